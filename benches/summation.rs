@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+use ocelotter::exec_bytecode_method;
+use ocelotter_runtime::code_builder::CodeBuilder;
+use ocelotter_runtime::vm_context::VmContext;
+use ocelotter_runtime::{InterpLocalVars, JvmValue};
+
+//////////// SUMMATION LOOP BENCHMARK
+
+// Times the interpreter's dispatch loop on the bytecode javac emits for
+//   int sum = 0; for (int i = 0; i < n; i++) sum += i; return sum;
+// with n in local 0. Run with cargo bench
+const N: i32 = 1_000_000;
+const RUNS: u32 = 20;
+
+fn summation() -> Vec<u8> {
+    let mut c = CodeBuilder::new();
+    let top = c.new_label();
+    let end = c.new_label();
+    c.iconst(0).istore(1).iconst(0).istore(2);
+    c.bind(top).iload(2).iload(0).if_icmpge(end);
+    c.iload(1).iload(2).iadd().istore(1);
+    c.iinc(2, 1).goto(top);
+    c.bind(end).iload(1).ireturn();
+    c.build()
+}
+
+fn run_once(context: &mut VmContext, code: &Vec<u8>) -> (i32, Duration) {
+    let mut lvt = InterpLocalVars::of(3);
+    lvt.store(0, JvmValue::Int { val: N });
+    let start = Instant::now();
+    let ret = exec_bytecode_method(context, "Summation".to_string(), code, &mut lvt);
+    let elapsed = start.elapsed();
+    match ret {
+        Ok(Some(JvmValue::Int { val })) => (val, elapsed),
+        other => panic!("Unexpected result from summation loop: {:?}", other),
+    }
+}
+
+fn main() {
+    let code = summation();
+    let expected = (0..N).fold(0i32, |acc, i| acc.wrapping_add(i));
+    let mut context = VmContext::of();
+
+    // One untimed run to warm up caches
+    run_once(&mut context, &code);
+    let mut times = Vec::new();
+    for _ in 0..RUNS {
+        let (sum, elapsed) = run_once(&mut context, &code);
+        assert_eq!(expected, sum);
+        times.push(elapsed);
+    }
+    times.sort();
+
+    let median = times[times.len() / 2];
+    let per_iter = median.as_nanos() as f64 / N as f64;
+    println!(
+        "summation of {} ints: median {:?} over {} runs ({:.2} ns per loop iteration, min {:?})",
+        N, median, RUNS, per_iter, times[0]
+    );
+}
@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.AbstractMethodError, which the VM throws itself
+public class AbstractMethodError extends IncompatibleClassChangeError {
+    public AbstractMethodError() {
+    }
+}
@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.ArithmeticException, which the VM throws itself
+public class ArithmeticException extends RuntimeException {
+    public ArithmeticException() {
+    }
+}
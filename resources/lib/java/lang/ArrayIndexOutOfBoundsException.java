@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.ArrayIndexOutOfBoundsException, which the VM throws itself
+public class ArrayIndexOutOfBoundsException extends IndexOutOfBoundsException {
+    public ArrayIndexOutOfBoundsException() {
+    }
+}
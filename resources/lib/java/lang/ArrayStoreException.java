@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.ArrayStoreException, which the VM throws itself
+public class ArrayStoreException extends RuntimeException {
+    public ArrayStoreException() {
+    }
+}
@@ -0,0 +1,19 @@
+package java.lang;
+
+// A stub java.lang.Boolean. Boxing and unboxing are VM intrinsics, so these bodies
+// don't run, but the klass and its value field must exist
+public final class Boolean {
+    private final boolean value;
+
+    public Boolean(boolean value) {
+        this.value = value;
+    }
+
+    public static Boolean valueOf(boolean b) {
+        return new Boolean(b);
+    }
+
+    public boolean booleanValue() {
+        return value;
+    }
+}
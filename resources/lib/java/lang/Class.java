@@ -0,0 +1,10 @@
+package java.lang;
+
+// A stub java.lang.Class. Instances are created by the VM, one per klass, and
+// only carry the klass's identity
+public final class Class<T> {
+    private Class() {
+    }
+
+    public native String getName();
+}
@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.ClassCastException, which the VM throws itself
+public class ClassCastException extends RuntimeException {
+    public ClassCastException() {
+    }
+}
@@ -0,0 +1,19 @@
+package java.lang;
+
+// A stub java.lang.Double. Boxing and unboxing are VM intrinsics, so these bodies
+// don't run, but the klass and its value field must exist
+public final class Double {
+    private final double value;
+
+    public Double(double value) {
+        this.value = value;
+    }
+
+    public static Double valueOf(double d) {
+        return new Double(d);
+    }
+
+    public double doubleValue() {
+        return value;
+    }
+}
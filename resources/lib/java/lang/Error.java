@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.Error, the super of the linkage errors the VM throws
+public class Error extends Throwable {
+    public Error() {
+    }
+}
@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.Exception, so that code can throw and catch one
+public class Exception extends Throwable {
+    public Exception() {
+    }
+}
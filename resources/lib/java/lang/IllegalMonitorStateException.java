@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.IllegalMonitorStateException, which the VM throws itself
+public class IllegalMonitorStateException extends RuntimeException {
+    public IllegalMonitorStateException() {
+    }
+}
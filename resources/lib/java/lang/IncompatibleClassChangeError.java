@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.IncompatibleClassChangeError
+public class IncompatibleClassChangeError extends LinkageError {
+    public IncompatibleClassChangeError() {
+    }
+}
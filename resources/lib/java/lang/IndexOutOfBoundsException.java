@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.IndexOutOfBoundsException, the super of ArrayIndexOutOfBoundsException
+public class IndexOutOfBoundsException extends RuntimeException {
+    public IndexOutOfBoundsException() {
+    }
+}
@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.LinkageError
+public class LinkageError extends Error {
+    public LinkageError() {
+    }
+}
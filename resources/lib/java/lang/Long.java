@@ -0,0 +1,19 @@
+package java.lang;
+
+// A stub java.lang.Long. Boxing and unboxing are VM intrinsics, so these bodies
+// don't run, but the klass and its value field must exist
+public final class Long {
+    private final long value;
+
+    public Long(long value) {
+        this.value = value;
+    }
+
+    public static Long valueOf(long l) {
+        return new Long(l);
+    }
+
+    public long longValue() {
+        return value;
+    }
+}
@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.NegativeArraySizeException, which the VM throws itself
+public class NegativeArraySizeException extends RuntimeException {
+    public NegativeArraySizeException() {
+    }
+}
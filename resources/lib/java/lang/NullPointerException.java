@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.NullPointerException, which the VM throws itself
+public class NullPointerException extends RuntimeException {
+    public NullPointerException() {
+    }
+}
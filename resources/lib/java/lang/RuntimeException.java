@@ -0,0 +1,7 @@
+package java.lang;
+
+// A stub java.lang.RuntimeException, the super of those the VM throws itself
+public class RuntimeException extends Exception {
+    public RuntimeException() {
+    }
+}
@@ -0,0 +1,13 @@
+package java.lang;
+
+// A stub java.lang.Throwable. The VM records each exception's stack trace itself,
+// as it unwinds, so there's nothing to fill in here
+public class Throwable {
+    public Throwable() {
+    }
+
+    // An intrinsic, which knows the messages of the exceptions the VM throws
+    public String getMessage() {
+        return null;
+    }
+}
@@ -0,0 +1,12 @@
+public class Aliaser {
+    public static int instances;
+    public int count;
+
+    public static int run() {
+        Aliaser a = new Aliaser();
+        Aliaser b = a;
+        a.count = 5;
+        b.count = b.count + 3;
+        return a.count;
+    }
+}
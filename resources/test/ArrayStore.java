@@ -0,0 +1,43 @@
+public class ArrayStore {
+
+    public static Object storeString() {
+        Object[] a = new Object[1];
+        a[0] = "s";
+        return a[0];
+    }
+
+    public static Object storeInteger() {
+        Object[] a = new String[1];
+        a[0] = Integer.valueOf(1);
+        return a[0];
+    }
+
+    // Hands back the exception, so that it can be looked at
+    public static Object catchStore() {
+        Object[] a = new String[1];
+        try {
+            a[0] = Integer.valueOf(1);
+            return null;
+        } catch (ArrayStoreException e) {
+            return e;
+        }
+    }
+
+    public static Object storeRow() {
+        int[][] a = new int[2][];
+        a[1] = new int[] {4, 5};
+        return a[1];
+    }
+
+    public static Object storeStrings() {
+        String[][] a = new String[1][];
+        a[0] = new String[] {"s"};
+        return a[0][0];
+    }
+
+    public static Object storeNull() {
+        Object[] a = new String[1];
+        a[0] = null;
+        return a[0];
+    }
+}
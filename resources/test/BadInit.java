@@ -0,0 +1,8 @@
+public class BadInit {
+    // Not folded by javac, so <clinit> throws an ArithmeticException
+    static int x = 1 / 0;
+
+    public static int get() {
+        return x;
+    }
+}
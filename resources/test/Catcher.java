@@ -0,0 +1,30 @@
+public class Catcher {
+    static int inner() throws Exception {
+        throw new Exception();
+    }
+
+    // Hands back what it catches, so that the exception can be looked at
+    public static Object outer() {
+        try {
+            inner();
+        } catch (Exception e) {
+            return e;
+        }
+        return null;
+    }
+
+    public static int uncaught() throws Exception {
+        return inner();
+    }
+
+    // Only the handler for a klass the exception is an instance of catches it
+    public static int picky() {
+        try {
+            return inner();
+        } catch (IllegalStateException e) {
+            return 1;
+        } catch (Exception e) {
+            return 2;
+        }
+    }
+}
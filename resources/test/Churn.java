@@ -0,0 +1,13 @@
+public class Churn {
+    static int[] held;
+
+    // Only what a local or a static still refers to has to outlive the loop
+    public static int churn(int n) {
+        int[] kept = new int[] {42};
+        for (int i = 0; i < n; i++) {
+            // Each array replaces the last, which becomes garbage
+            held = new int[] {i};
+        }
+        return kept[0] + held[0];
+    }
+}
@@ -0,0 +1,14 @@
+public class Concat {
+
+    public static String xEquals(int x) {
+        return "x=" + x;
+    }
+
+    public static String greet(String name) {
+        return new StringBuilder("Hello, ").append(name).append("!").toString();
+    }
+
+    public static String constant() {
+        return "x=";
+    }
+}
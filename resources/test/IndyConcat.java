@@ -0,0 +1,23 @@
+public class IndyConcat {
+
+    public static String describe(String name, int age, long id, boolean ok, char grade, double score) {
+        return "name=" + name + ", age=" + age + ", id=" + id + ", ok=" + ok + ", grade=" + grade + ", score=" + score;
+    }
+
+    // Literal tag chars can't go in the recipe, so javac passes them as constants
+    public static String withTags(int x) {
+        return "\u0001" + x + "\u0002";
+    }
+
+    public static String count(int n) {
+        String s = "";
+        for (int i = 0; i < n; i++) {
+            s = s + i;
+        }
+        return s;
+    }
+
+    public static String join(String a, String c, float b) {
+        return a + b + c;
+    }
+}
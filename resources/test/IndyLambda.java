@@ -0,0 +1,6 @@
+public class IndyLambda {
+    // Bootstrapped by LambdaMetafactory, which the VM doesn't link
+    public static Runnable make() {
+        return () -> {};
+    }
+}
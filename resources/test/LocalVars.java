@@ -0,0 +1,6 @@
+public class LocalVars {
+    public static int sum(int first, int second) {
+        int total = first + second;
+        return total;
+    }
+}
@@ -0,0 +1,14 @@
+public class LongConstants {
+
+    public static long big() {
+        return 1234567890123L;
+    }
+
+    public static double pi() {
+        return 2.5;
+    }
+
+    public static int answer() {
+        return 100000;
+    }
+}
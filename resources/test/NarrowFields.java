@@ -0,0 +1,17 @@
+public class NarrowFields {
+    public boolean z;
+    public byte b;
+    public short s;
+    public char c;
+    public static byte sb;
+
+    public static int main2(String[] args) {
+        NarrowFields nf = new NarrowFields();
+        nf.b = 7;
+        nf.s = 1;
+        nf.c = 'a';
+        nf.z = true;
+        sb = 2;
+        return nf.b + nf.s + nf.c + sb;
+    }
+}
@@ -0,0 +1,23 @@
+public class StackMaps {
+    public static int loop(int n) {
+        int total = 0;
+        for (int i = 0; i < n; i++) {
+            total += i;
+        }
+        return total;
+    }
+
+    public static long pick(boolean b, long x) {
+        long y;
+        if (b) {
+            y = x;
+        } else {
+            y = 0L;
+        }
+        return y;
+    }
+
+    public static int choose(boolean b) {
+        return b ? 1 : 2;
+    }
+}
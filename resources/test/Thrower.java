@@ -0,0 +1,11 @@
+import java.io.IOException;
+
+public class Thrower {
+    public static void fail() throws IOException, InterruptedException {
+        throw new IOException("Failed");
+    }
+
+    public static int succeed() {
+        return 1;
+    }
+}
@@ -0,0 +1,63 @@
+// Catches the exceptions that the VM raises itself, rather than an athrow
+public class VmExceptions {
+    public static int divide(int d) {
+        try {
+            return 10 / d;
+        } catch (ArithmeticException e) {
+            return -1;
+        }
+    }
+
+    public static String divideMessage(int d) {
+        try {
+            d = 1 / d;
+            return null;
+        } catch (ArithmeticException e) {
+            // Inherited methods aren't resolved, so this names Throwable's
+            return ((Throwable) e).getMessage();
+        }
+    }
+
+    public static int length(int[] a) {
+        try {
+            return a.length;
+        } catch (NullPointerException e) {
+            return -1;
+        }
+    }
+
+    // Caught by a handler for the super, as well
+    public static int at(int i) {
+        try {
+            return (new int[] {1, 2})[i];
+        } catch (IndexOutOfBoundsException e) {
+            return -1;
+        }
+    }
+
+    public static int allocate(int n) {
+        try {
+            return new int[n].length;
+        } catch (NegativeArraySizeException e) {
+            return -1;
+        }
+    }
+
+    public static int cast(Object o) {
+        try {
+            String s = (String) o;
+            return 1;
+        } catch (ClassCastException e) {
+            return -1;
+        }
+    }
+
+    // A RuntimeException handler catches each of its subclasses
+    public static int lengthOrDivide(int[] a) {
+        try {
+            return 10 / a.length;
+        } catch (RuntimeException e) {
+            return -1;
+        }
+    }
+}
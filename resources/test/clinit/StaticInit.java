@@ -0,0 +1,22 @@
+public class StaticInit {
+    static int value;
+    static int initCount;
+
+    static {
+        initCount = initCount + 1;
+        // Calls back into the klass while it is still being initialized
+        value = base() * 3 + 1;
+    }
+
+    static int base() {
+        return 7;
+    }
+
+    public static int read() {
+        return value;
+    }
+
+    public static int timesInitialized() {
+        return initCount;
+    }
+}
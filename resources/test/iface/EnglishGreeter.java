@@ -0,0 +1,5 @@
+public class EnglishGreeter implements Greeter {
+    public int greet() {
+        return 21;
+    }
+}
@@ -0,0 +1,7 @@
+public interface Greeter {
+    int greet();
+
+    default int greetTwice() {
+        return greet() * 2;
+    }
+}
@@ -0,0 +1,20 @@
+public class GreeterCaller {
+    public static int run() {
+        Greeter g = new EnglishGreeter();
+        return g.greet();
+    }
+
+    public static int runDefault() {
+        Greeter g = new EnglishGreeter();
+        return g.greetTwice();
+    }
+
+    public static int runMute() {
+        Greeter g = new MuteGreeter();
+        try {
+            return g.greet();
+        } catch (AbstractMethodError e) {
+            return -1;
+        }
+    }
+}
@@ -0,0 +1,3 @@
+// Compiled against a Greeter without greet(), so has no body for it
+public class MuteGreeter implements Greeter {
+}
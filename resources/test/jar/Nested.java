@@ -0,0 +1,4 @@
+package pkg.sub;
+
+public class Nested {
+}
@@ -0,0 +1,5 @@
+public class LazyCallee {
+    public static int value() {
+        return 41;
+    }
+}
@@ -0,0 +1,5 @@
+public class LazyCaller {
+    public static int run() {
+        return LazyCallee.value() + 1;
+    }
+}
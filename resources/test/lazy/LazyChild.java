@@ -0,0 +1,2 @@
+public class LazyChild extends LazyParent {
+}
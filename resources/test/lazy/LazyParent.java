@@ -0,0 +1,5 @@
+public class LazyParent {
+    public int parentValue() {
+        return 1;
+    }
+}
@@ -0,0 +1,6 @@
+public class HelloMain {
+    public static void main(String[] args) {
+        System.out.println(args[0]);
+        System.out.println(args.length);
+    }
+}
@@ -0,0 +1,9 @@
+public class GetClassName {
+    public static String name() {
+        return new GetClassName().getClass().getName();
+    }
+
+    public static boolean sameClass() {
+        return new GetClassName().getClass() == new GetClassName().getClass();
+    }
+}
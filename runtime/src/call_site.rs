@@ -0,0 +1,80 @@
+use crate::constant_pool::CpEntry;
+use crate::descriptor;
+use crate::otklass::OtKlass;
+
+//////////// CALL SITES
+
+const STRING_CONCAT_BSM: &str = "java/lang/invoke/StringConcatFactory.makeConcatWithConstants:(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/String;[Ljava/lang/Object;)Ljava/lang/invoke/CallSite;";
+
+// A piece of a linked string concatenation
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConcatPart {
+    // Text from the recipe, with its constants already substituted in
+    Literal(String),
+    // The next of the call's arguments, with its descriptor type
+    Arg(String),
+}
+
+// What an invokedynamic instruction is linked to once its bootstrap method has run.
+// There's no method handle machinery, so StringConcatFactory is the only bootstrap
+// that can be linked, and is done here rather than by running it
+#[derive(Clone, Debug)]
+pub enum CallSite {
+    StringConcat { name_desc: String, parts: Vec<ConcatPart> },
+}
+
+impl CallSite {
+    // Links the invokedynamic at cp_idx in klass, or None if its bootstrap isn't supported
+    pub fn link(klass: &OtKlass, cp_idx: u16) -> Option<CallSite> {
+        let bsm_idx = match klass.lookup_cp(cp_idx) {
+            CpEntry::invoke_dynamic { bsm_idx, nt_idx: _ } => bsm_idx,
+            _ => panic!(
+                "Non-invokedynamic entry found in {} at CP index {}",
+                klass.get_name(),
+                cp_idx
+            ),
+        };
+        let bsm = match klass.get_bootstrap_method(bsm_idx) {
+            Some(bsm) => bsm,
+            None => panic!("No bootstrap method {} found in {}", bsm_idx, klass.get_name()),
+        };
+        if klass.cp_as_string(bsm.method_ref) != STRING_CONCAT_BSM {
+            return None;
+        }
+
+        // In the recipe \u{1} stands for the next argument and \u{2} for the next constant
+        let name_desc = klass.cp_as_string(cp_idx);
+        let recipe = klass.cp_as_string(bsm.args[0]);
+        let mut arg_types = descriptor::arg_types(&name_desc).into_iter();
+        let mut constants = bsm.args[1..].iter();
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        for c in recipe.chars() {
+            match c {
+                '\u{1}' => {
+                    let ty = match arg_types.next() {
+                        Some(ty) => ty,
+                        None => panic!("Too few arguments for concat recipe {:?}", recipe),
+                    };
+                    if !literal.is_empty() {
+                        parts.push(ConcatPart::Literal(literal.clone()));
+                        literal.clear();
+                    }
+                    parts.push(ConcatPart::Arg(ty));
+                }
+                '\u{2}' => match constants.next() {
+                    Some(&idx) => match klass.lookup_cp(idx) {
+                        CpEntry::integer { val } => literal.push_str(&val.to_string()),
+                        _ => literal.push_str(&klass.cp_as_string(idx)),
+                    },
+                    None => panic!("Too few constants for concat recipe {:?}", recipe),
+                },
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(ConcatPart::Literal(literal));
+        }
+        Some(CallSite::StringConcat { name_desc, parts })
+    }
+}
@@ -0,0 +1,77 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ocelotter_util::file_to_bytes;
+
+use crate::jar_loader::{load_klass_from_jar, parse_entry, LoadError};
+use crate::OtKlass;
+
+//////////// CLASS LOADING
+
+#[derive(Debug, Clone)]
+pub enum ClasspathEntry {
+    Dir { path: PathBuf },
+    Jar { path: PathBuf },
+}
+
+// Finds klasses on demand, searching the classpath entries in order
+#[derive(Debug, Clone)]
+pub struct ClassLoader {
+    classpath: Vec<ClasspathEntry>,
+}
+
+impl ClassLoader {
+    pub fn of() -> ClassLoader {
+        ClassLoader {
+            classpath: Vec::new(),
+        }
+    }
+
+    pub fn add_dir(&mut self, path: &Path) -> () {
+        self.classpath.push(ClasspathEntry::Dir {
+            path: path.to_path_buf(),
+        });
+    }
+
+    pub fn add_jar(&mut self, path: &Path) -> () {
+        self.classpath.push(ClasspathEntry::Jar {
+            path: path.to_path_buf(),
+        });
+    }
+
+    // A klass missing from one entry is looked for in the next, but any other
+    // problem reading or parsing it stops the search
+    pub fn load_klass(&self, klass_name: &str) -> Result<Option<OtKlass>, LoadError> {
+        for entry in &self.classpath {
+            match entry {
+                ClasspathEntry::Dir { path } => {
+                    let entry_name = klass_name.to_string() + ".class";
+                    let fq_path = path.join(&entry_name);
+                    let bytes = match file_to_bytes(&fq_path) {
+                        Ok(bytes) => bytes,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                        Err(e) => {
+                            return Err(LoadError::Io {
+                                path: fq_path.to_string_lossy().to_string(),
+                                msg: e.to_string(),
+                            })
+                        }
+                    };
+                    return match parse_entry(&entry_name, bytes) {
+                        Ok(k) => Ok(Some(k)),
+                        Err(msg) => Err(LoadError::MalformedEntries {
+                            loaded: 0,
+                            errors: vec![(entry_name, msg)],
+                        }),
+                    };
+                }
+                ClasspathEntry::Jar { path } => match load_klass_from_jar(path, klass_name) {
+                    Ok(k) => return Ok(Some(k)),
+                    Err(LoadError::MissingEntry { path: _, entry: _ }) => (),
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+        Ok(None)
+    }
+}
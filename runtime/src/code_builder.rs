@@ -0,0 +1,131 @@
+use crate::opcode::Opcode;
+
+//////////// CODE BUILDER
+
+// Assembles method bytecode, so that tests needn't spell out raw bytes and branch
+// offsets. Branches name a Label, which is resolved to an offset by build()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Label(usize);
+
+#[derive(Default)]
+pub struct CodeBuilder {
+    code: Vec<u8>,
+    // The pc each label was bound at, if it has been yet
+    labels: Vec<Option<usize>>,
+    // (pc of the branch opcode, label) for every branch emitted
+    fixups: Vec<(usize, Label)>,
+}
+
+impl CodeBuilder {
+    pub fn new() -> CodeBuilder {
+        CodeBuilder::default()
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    // Marks the next instruction as the target of label
+    pub fn bind(&mut self, label: Label) -> &mut CodeBuilder {
+        if self.labels[label.0].is_some() {
+            panic!("Error: label {:?} bound twice", label);
+        }
+        self.labels[label.0] = Some(self.code.len());
+        self
+    }
+
+    // Any single opcode that takes no operands
+    pub fn op(&mut self, opcode: u8) -> &mut CodeBuilder {
+        self.code.push(opcode);
+        self
+    }
+
+    // Uses the shortest encoding, as javac does
+    pub fn iconst(&mut self, v: i32) -> &mut CodeBuilder {
+        match v {
+            -1..=5 => self.op((Opcode::ICONST_0 as i32 + v) as u8),
+            -128..=127 => self.op(Opcode::BIPUSH).op(v as u8),
+            -32768..=32767 => self.op(Opcode::SIPUSH).op((v >> 8) as u8).op(v as u8),
+            _ => panic!("Error: {} needs an LDC, which CodeBuilder can't emit", v),
+        }
+    }
+
+    pub fn iload(&mut self, idx: u8) -> &mut CodeBuilder {
+        match idx {
+            0..=3 => self.op(Opcode::ILOAD_0 + idx),
+            _ => self.op(Opcode::ILOAD).op(idx),
+        }
+    }
+
+    pub fn istore(&mut self, idx: u8) -> &mut CodeBuilder {
+        match idx {
+            0..=3 => self.op(Opcode::ISTORE_0 + idx),
+            _ => self.op(Opcode::ISTORE).op(idx),
+        }
+    }
+
+    pub fn iinc(&mut self, idx: u8, incr: i8) -> &mut CodeBuilder {
+        self.op(Opcode::IINC).op(idx).op(incr as u8)
+    }
+
+    pub fn iadd(&mut self) -> &mut CodeBuilder {
+        self.op(Opcode::IADD)
+    }
+
+    pub fn isub(&mut self) -> &mut CodeBuilder {
+        self.op(Opcode::ISUB)
+    }
+
+    pub fn imul(&mut self) -> &mut CodeBuilder {
+        self.op(Opcode::IMUL)
+    }
+
+    pub fn ireturn(&mut self) -> &mut CodeBuilder {
+        self.op(Opcode::IRETURN)
+    }
+
+    pub fn goto(&mut self, target: Label) -> &mut CodeBuilder {
+        self.branch(Opcode::GOTO, target)
+    }
+
+    pub fn ifeq(&mut self, target: Label) -> &mut CodeBuilder {
+        self.branch(Opcode::IFEQ, target)
+    }
+
+    pub fn ifne(&mut self, target: Label) -> &mut CodeBuilder {
+        self.branch(Opcode::IFNE, target)
+    }
+
+    pub fn if_icmplt(&mut self, target: Label) -> &mut CodeBuilder {
+        self.branch(Opcode::IF_ICMPLT, target)
+    }
+
+    pub fn if_icmpge(&mut self, target: Label) -> &mut CodeBuilder {
+        self.branch(Opcode::IF_ICMPGE, target)
+    }
+
+    // Any branch with a 16-bit offset. The offset is filled in by build()
+    pub fn branch(&mut self, opcode: u8, target: Label) -> &mut CodeBuilder {
+        self.fixups.push((self.code.len(), target));
+        self.op(opcode).op(0).op(0)
+    }
+
+    // Offsets are relative to the branch opcode itself
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = self.code.clone();
+        for (pc, label) in &self.fixups {
+            let target = match self.labels[label.0] {
+                Some(target) => target,
+                None => panic!("Error: label {:?} used at {} but never bound", label, pc),
+            };
+            let offset = target as isize - *pc as isize;
+            if offset < i16::MIN as isize || offset > i16::MAX as isize {
+                panic!("Error: branch at {} to {} needs goto_w", pc, target);
+            }
+            out[pc + 1] = (offset >> 8) as u8;
+            out[pc + 2] = offset as u8;
+        }
+        out
+    }
+}
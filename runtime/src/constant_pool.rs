@@ -1,3 +1,6 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 pub const ACC_PUBLIC: u16 = 0x0001; // Declared public; may be accessed from outside its package.
 pub const ACC_PRIVATE: u16 = 0x0002; // Declared private; usable only within the defining class.
 pub const ACC_PROTECTED: u16 = 0x0004; // Declared protected; may be accessed within subclasses.
@@ -21,6 +24,51 @@ pub const ACC_NATIVE: u16 = 0x0100; // (Method) Declared native; implemented in
 pub const ACC_ABSTRACT_M: u16 = 0x0400; // (Method) Declared abstract; no implementation is provided.
 pub const ACC_STRICT: u16 = 0x0800; // (Method) Declared strictfp; floating-point mode is FP-strict.
 
+// The same bits mean different things on a class and on a method, e.g. 0x0020 is
+// ACC_SUPER on one and ACC_SYNCHRONIZED on the other, hence a table for each. Both
+// are in bit order, which for the common cases is the order javac writes them in
+const CLASS_FLAG_NAMES: [(u16, &str); 8] = [
+    (ACC_PUBLIC, "public"),
+    (ACC_FINAL, "final"),
+    (ACC_SUPER, "super"),
+    (ACC_INTERFACE, "interface"),
+    (ACC_ABSTRACT, "abstract"),
+    (ACC_SYNTHETIC, "synthetic"),
+    (ACC_ANNOTATION, "annotation"),
+    (ACC_ENUM, "enum"),
+];
+
+const METHOD_FLAG_NAMES: [(u16, &str); 12] = [
+    (ACC_PUBLIC, "public"),
+    (ACC_PRIVATE, "private"),
+    (ACC_PROTECTED, "protected"),
+    (ACC_STATIC, "static"),
+    (ACC_FINAL, "final"),
+    (ACC_SYNCHRONIZED, "synchronized"),
+    (ACC_BRIDGE, "bridge"),
+    (ACC_VARARGS, "varargs"),
+    (ACC_NATIVE, "native"),
+    (ACC_ABSTRACT_M, "abstract"),
+    (ACC_STRICT, "strictfp"),
+    (ACC_SYNTHETIC, "synthetic"),
+];
+
+fn decode_flags(flags: u16, names: &[(u16, &'static str)]) -> Vec<&'static str> {
+    names
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+pub fn decode_class_flags(flags: u16) -> Vec<&'static str> {
+    decode_flags(flags, &CLASS_FLAG_NAMES)
+}
+
+pub fn decode_method_flags(flags: u16) -> Vec<&'static str> {
+    decode_flags(flags, &METHOD_FLAG_NAMES)
+}
+
 //////////// CONSTANT POOL HANDLING
 
 // CPType constants
@@ -39,8 +87,12 @@ pub const CP_METHODHANDLE: u8 = 15;
 pub const CP_METHODTYPE: u8 = 16;
 pub const CP_INVOKEDYNAMIC: u8 = 18;
 
+// CP indices are 1-based, so slot 0 of the entries Vec holds an unusable
+// placeholder, as does the slot after each (double-width) long or double
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum CpEntry {
+    unusable {},
     utf8 { val: String },
     integer { val: i32 },
     float { val: f32 },
@@ -52,6 +104,11 @@ pub enum CpEntry {
     methodref { clz_idx: u16, nt_idx: u16 },
     interface_methodref { clz_idx: u16, nt_idx: u16 },
     name_and_type { name_idx: u16, type_idx: u16 },
+    // kind is the reference kind, e.g. 6 for invokestatic, and ref_idx its methodref
+    method_handle { kind: u8, ref_idx: u16 },
+    method_type { desc_idx: u16 },
+    // bsm_idx indexes the klass's BootstrapMethods attribute, not the CP
+    invoke_dynamic { bsm_idx: u16, nt_idx: u16 },
 }
 
 impl CpEntry {
@@ -66,6 +123,7 @@ impl CpEntry {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CpAttr {
     name_idx: u16,
 }
@@ -74,6 +132,20 @@ impl CpAttr {
     pub fn of(name_idx: u16) -> CpAttr {
         CpAttr { name_idx: name_idx }
     }
+
+    pub fn get_name_idx(&self) -> u16 {
+        self.name_idx
+    }
+}
+
+// An entry of the BootstrapMethods attribute, which invokedynamic refers to by index
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BootstrapMethod {
+    // The CP index of a method_handle
+    pub method_ref: u16,
+    // The CP indices of the static arguments passed to the bootstrap method
+    pub args: Vec<u16>,
 }
 
 pub fn split_name_desc(name_desc: String) -> (String, String) {
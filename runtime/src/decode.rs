@@ -0,0 +1,70 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::klass_parser::{branch_offset, scan_code};
+use crate::opcode::Opcode;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+//////////// PRE-DECODED BYTECODE
+
+// One instruction with its operands already read out of the code array, so that
+// running a method again doesn't have to re-parse them
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Instruction {
+    pub opcode: u8,
+    // Where the opcode sits in the original code, for traces and error messages
+    pub pc: usize,
+    // A local var index, a constant, a CP index or, for a branch, the index of the
+    // target instruction. 0 for anything without operands
+    pub operand: i32,
+    // The increment of an iinc, otherwise 0
+    pub operand2: i32,
+}
+
+// Fails, with the pc at fault, on code that the klass parser would reject. Operands
+// that don't fit the one or two fields (wide, the switches) are left in the code, to
+// be read from pc
+pub fn decode(code: &[u8]) -> Result<Vec<Instruction>, (usize, String)> {
+    let (starts, _) = scan_code(code)?;
+    // The index of the instruction starting at each pc, if one does
+    let mut index = vec![None; code.len()];
+    let pcs: Vec<usize> = (0..code.len()).filter(|pc| starts[*pc]).collect();
+    for (i, pc) in pcs.iter().enumerate() {
+        index[*pc] = Some(i);
+    }
+
+    let mut out = Vec::with_capacity(pcs.len());
+    for pc in pcs {
+        let opcode = code[pc];
+        let (operand, operand2) = match (opcode, branch_offset(code, pc)) {
+            (_, Some(offset)) => {
+                // A negative target wraps to a usize far past the end, so isn't found
+                let target = pc as i64 + offset;
+                match index.get(target as usize) {
+                    Some(Some(i)) => (*i as i32, 0),
+                    _ => return Err((pc, format!("branch target {} is not an instruction", target))),
+                }
+            }
+            (Opcode::BIPUSH, _) => (code[pc + 1] as i8 as i32, 0),
+            (Opcode::SIPUSH, _) => (BigEndian::read_i16(&code[pc + 1..]) as i32, 0),
+            (Opcode::IINC, _) => (code[pc + 1] as i32, code[pc + 2] as i8 as i32),
+            (Opcode::WIDE, _) | (Opcode::TABLESWITCH, _) | (Opcode::LOOKUPSWITCH, _) => (0, 0),
+            // Everything else has an unsigned index, if anything, first. The extra
+            // bytes of invokeinterface, invokedynamic and multianewarray aren't needed
+            _ => match Opcode::num_params(opcode) {
+                0 => (0, 0),
+                1 => (code[pc + 1] as i32, 0),
+                _ => (BigEndian::read_u16(&code[pc + 1..]) as i32, 0),
+            },
+        };
+        out.push(Instruction {
+            opcode,
+            pc,
+            operand,
+            operand2,
+        });
+    }
+    Ok(out)
+}
@@ -0,0 +1,117 @@
+use crate::JvmValue;
+use crate::ObjId;
+
+//////////// TYPE DESCRIPTORS
+
+// Helpers for method descriptors such as (IJLjava/lang/String;)V. A name_desc, e.g.
+// foo:(I)V, is fine too, as only the part from the ( onwards is looked at
+
+// Splits the field type at the start of desc, e.g. [[Ljava/lang/String;I gives
+// ([[Ljava/lang/String;, I)
+fn split_field_type(desc: &str) -> Result<(&str, &str), String> {
+    let dims = desc.chars().take_while(|c| *c == '[').count();
+    let end = match desc[dims..].chars().next() {
+        Some('L') => match desc[dims..].find(';') {
+            Some(semi) => dims + semi + 1,
+            None => return Err(format!("Unterminated class name in descriptor {}", desc)),
+        },
+        Some('Z') | Some('B') | Some('C') | Some('S') | Some('I') | Some('J') | Some('F') | Some('D') => dims + 1,
+        Some(c) => return Err(format!("Illegal type descriptor character {}", c)),
+        None => return Err(format!("Missing element type in descriptor {}", desc)),
+    };
+    Ok(desc.split_at(end))
+}
+
+// The parameter list and whatever follows its closing )
+fn split_params(desc: &str) -> Result<(&str, &str), String> {
+    match (desc.find('('), desc.find(')')) {
+        (Some(open), Some(close)) if open < close => Ok((&desc[open + 1..close], &desc[close + 1..])),
+        _ => Err(format!("Malformed method descriptor {}", desc)),
+    }
+}
+
+// The try_ versions are for descriptors that haven't been checked yet, e.g. by the
+// verifier. The others panic, as they're used on code that has been verified
+
+pub fn try_arg_types(desc: &str) -> Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    let (mut rest, _) = split_params(desc)?;
+    while !rest.is_empty() {
+        let (arg, tail) = split_field_type(rest)?;
+        out.push(arg.to_string());
+        rest = tail;
+    }
+    Ok(out)
+}
+
+// The descriptor of each argument, in order
+pub fn arg_types(desc: &str) -> Vec<String> {
+    try_arg_types(desc).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// The local var slots taken by each argument, 2 for a long or double and 1 for
+// anything else, including any array
+pub fn arg_slot_sizes(desc: &str) -> Vec<u16> {
+    arg_types(desc)
+        .iter()
+        .map(|t| if t == "J" || t == "D" { 2 } else { 1 })
+        .collect()
+}
+
+// Excludes the receiver, if any
+pub fn arg_slot_count(desc: &str) -> usize {
+    arg_slot_sizes(desc).iter().map(|s| *s as usize).sum()
+}
+
+pub fn try_return_type(desc: &str) -> Result<Option<char>, String> {
+    let (_, ret) = split_params(desc)?;
+    if ret == "V" {
+        return Ok(None);
+    }
+    try_field_type(ret).map(Some)
+}
+
+// The first character of the return type, so L for any object and [ for any
+// array, or None for void
+pub fn return_type(desc: &str) -> Option<char> {
+    try_return_type(desc).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// Checks desc is a single field type, e.g. [J, and returns its first character
+pub fn try_field_type(desc: &str) -> Result<char, String> {
+    match split_field_type(desc)? {
+        (ty, "") => Ok(ty.chars().next().unwrap_or('V')),
+        (_, rest) => Err(format!("Unexpected {} after type descriptor {}", rest, desc)),
+    }
+}
+
+// The value a field of type desc holds before anything is stored to it, for both
+// newly allocated objects and statics before <clinit> runs
+pub fn default_value_for_descriptor(desc: &str) -> JvmValue {
+    match desc {
+        "Z" => JvmValue::Boolean { val: false },
+        "B" => JvmValue::Byte { val: 0 },
+        "S" => JvmValue::Short { val: 0 },
+        "C" => JvmValue::Char { val: '\0' },
+        "I" => JvmValue::Int { val: 0i32 },
+        "J" => JvmValue::Long { val: 0i64 },
+        "F" => JvmValue::Float { val: 0.0 },
+        "D" => JvmValue::Double { val: 0.0 },
+        _ if desc.starts_with('L') || desc.starts_with('[') => {
+            JvmValue::ObjRef { val: ObjId::NULL }
+        }
+        _ => panic!("Not a field descriptor: {}", desc),
+    }
+}
+
+// Narrows an Int from the stack to the type of a boolean, byte, short or char field.
+// Any other value is returned as it is
+pub fn narrow_for_descriptor(desc: &str, v: JvmValue) -> JvmValue {
+    match (desc, v) {
+        ("Z", JvmValue::Int { val }) => JvmValue::narrow_to_boolean(val),
+        ("B", JvmValue::Int { val }) => JvmValue::narrow_to_byte(val),
+        ("S", JvmValue::Int { val }) => JvmValue::narrow_to_short(val),
+        ("C", JvmValue::Int { val }) => JvmValue::narrow_to_char(val),
+        (_, v) => v,
+    }
+}
@@ -0,0 +1,162 @@
+#![deny(unreachable_patterns)]
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::constant_pool::CpEntry;
+use crate::opcode::Opcode;
+use crate::opcode::Opcode::*;
+use crate::OtKlass;
+use crate::OtMethod;
+
+//////////// BYTECODE DISASSEMBLY
+
+// Produces javap-style output, one instruction per line, prefixed by its pc
+pub fn disassemble(klass: &OtKlass, method: &OtMethod) -> String {
+    let code = method.get_code();
+    let mut out = String::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let (text, len) = decode_instruction(klass, &code, pc);
+        out.push_str(&format!("{}: {}\n", pc, text));
+        pc += len;
+    }
+    out
+}
+
+// Returns the rendered instruction at pc and its length in bytes
+fn decode_instruction(klass: &OtKlass, code: &[u8], pc: usize) -> (String, usize) {
+    let op = code[pc];
+    let name = Opcode::mnemonic(op);
+    match op {
+        TABLESWITCH => {
+            // Operands are aligned to a 4-byte boundary relative to the start of the code
+            let base = pc + 1 + switch_padding(pc);
+            let default = BigEndian::read_i32(&code[base..]);
+            let low = BigEndian::read_i32(&code[base + 4..]);
+            let high = BigEndian::read_i32(&code[base + 8..]);
+            let mut cases = Vec::new();
+            let mut pos = base + 12;
+            for key in low..=high {
+                let offset = BigEndian::read_i32(&code[pos..]);
+                cases.push(format!("{}: {}", key, branch_target(pc, offset)));
+                pos += 4;
+            }
+            cases.push(format!("default: {}", branch_target(pc, default)));
+            (format!("{} {{ {} }}", name, cases.join(", ")), pos - pc)
+        }
+        LOOKUPSWITCH => {
+            let base = pc + 1 + switch_padding(pc);
+            let default = BigEndian::read_i32(&code[base..]);
+            let npairs = BigEndian::read_i32(&code[base + 4..]);
+            let mut cases = Vec::new();
+            let mut pos = base + 8;
+            for _i in 0..npairs {
+                let key = BigEndian::read_i32(&code[pos..]);
+                let offset = BigEndian::read_i32(&code[pos + 4..]);
+                cases.push(format!("{}: {}", key, branch_target(pc, offset)));
+                pos += 8;
+            }
+            cases.push(format!("default: {}", branch_target(pc, default)));
+            (format!("{} {{ {} }}", name, cases.join(", ")), pos - pc)
+        }
+        WIDE => {
+            let widened = code[pc + 1];
+            let idx = BigEndian::read_u16(&code[pc + 2..]);
+            if widened == IINC {
+                let incr = BigEndian::read_i16(&code[pc + 4..]);
+                (format!("{} iinc {}, {}", name, idx, incr), 6)
+            } else {
+                (format!("{} {} {}", name, Opcode::mnemonic(widened), idx), 4)
+            }
+        }
+        _ => {
+            let len = 1 + Opcode::num_params(op) as usize;
+            match decode_operands(klass, op, &code[pc + 1..pc + len], pc) {
+                Some(operands) => (format!("{} {}", name, operands), len),
+                None => (name.to_string(), len),
+            }
+        }
+    }
+}
+
+fn decode_operands(klass: &OtKlass, op: u8, params: &[u8], pc: usize) -> Option<String> {
+    match op {
+        BIPUSH => Some(format!("{}", params[0] as i8)),
+        SIPUSH => Some(format!("{}", BigEndian::read_i16(params))),
+        LDC => Some(cp_operand(klass, params[0] as u16)),
+        LDC_W | LDC2_W | GETSTATIC | PUTSTATIC | GETFIELD | PUTFIELD | INVOKEVIRTUAL
+        | INVOKESPECIAL | INVOKESTATIC | NEW | ANEWARRAY | CHECKCAST | INSTANCEOF => {
+            Some(cp_operand(klass, BigEndian::read_u16(params)))
+        }
+        INVOKEINTERFACE => {
+            let idx = BigEndian::read_u16(params);
+            Some(format!(
+                "#{}, {} // {}",
+                idx,
+                params[2],
+                describe_cp(klass, idx)
+            ))
+        }
+        INVOKEDYNAMIC => Some(format!("#{}", BigEndian::read_u16(params))),
+        MULTIANEWARRAY => {
+            let idx = BigEndian::read_u16(params);
+            Some(format!(
+                "#{}, {} // {}",
+                idx,
+                params[2],
+                describe_cp(klass, idx)
+            ))
+        }
+        ILOAD | LLOAD | FLOAD | DLOAD | ALOAD | ISTORE | LSTORE | FSTORE | DSTORE | ASTORE
+        | RET => Some(format!("{}", params[0])),
+        IINC => Some(format!("{}, {}", params[0], params[1] as i8)),
+        IFEQ | IFNE | IFLT | IFGE | IFGT | IFLE | IF_ICMPEQ | IF_ICMPNE | IF_ICMPLT
+        | IF_ICMPGE | IF_ICMPGT | IF_ICMPLE | IF_ACMPEQ | IF_ACMPNE | GOTO | JSR | IFNULL
+        | IFNONNULL => Some(format!(
+            "{}",
+            branch_target(pc, BigEndian::read_i16(params) as i32)
+        )),
+        GOTO_W | JSR_W => Some(format!(
+            "{}",
+            branch_target(pc, BigEndian::read_i32(params))
+        )),
+        NEWARRAY => Some(
+            match params[0] {
+                4 => "boolean",
+                5 => "char",
+                6 => "float",
+                7 => "double",
+                8 => "byte",
+                9 => "short",
+                10 => "int",
+                11 => "long",
+                _ => "<illegal>",
+            }
+            .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+fn cp_operand(klass: &OtKlass, idx: u16) -> String {
+    format!("#{} // {}", idx, describe_cp(klass, idx))
+}
+
+fn describe_cp(klass: &OtKlass, idx: u16) -> String {
+    match klass.lookup_cp(idx) {
+        CpEntry::integer { val } => format!("{}", val),
+        CpEntry::float { val } => format!("{}f", val),
+        CpEntry::long { val } => format!("{}l", val),
+        CpEntry::double { val } => format!("{}d", val),
+        CpEntry::string { idx: _ } => format!("\"{}\"", klass.cp_as_string(idx)),
+        _ => klass.cp_as_string(idx),
+    }
+}
+
+pub(crate) fn switch_padding(pc: usize) -> usize {
+    (4 - (pc + 1) % 4) % 4
+}
+
+fn branch_target(pc: usize, offset: i32) -> i64 {
+    pc as i64 + offset as i64
+}
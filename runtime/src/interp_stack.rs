@@ -1,33 +1,145 @@
 #![deny(unreachable_patterns)]
 
+use std::fmt;
+
+use crate::runtime_error::RuntimeError;
 use crate::JvmValue;
+use crate::ObjId;
 
 pub struct InterpEvalStack {
     stack: Vec<JvmValue>,
+    // Longs and doubles take two slots each, as max_stack counts them
+    slots: usize,
+    max_stack: usize,
+    // The first overflow or underflow seen, reported by check()
+    fault: Option<RuntimeError>,
 }
 
 impl InterpEvalStack {
     pub fn of() -> InterpEvalStack {
-        InterpEvalStack { stack: Vec::new() }
+        InterpEvalStack::with_max_stack(u16::MAX)
+    }
+
+    // A stack bounded by the max_stack declared in a method's Code attribute
+    pub fn with_max_stack(max_stack: u16) -> InterpEvalStack {
+        InterpEvalStack {
+            stack: Vec::new(),
+            slots: 0,
+            max_stack: max_stack as usize,
+            fault: None,
+        }
+    }
+
+    // Unbounded, like of(), but with room for n values before the stack reallocates
+    pub fn with_capacity(n: usize) -> InterpEvalStack {
+        let mut out = InterpEvalStack::of();
+        out.stack.reserve_exact(n);
+        out
+    }
+
+    pub fn max_stack(&self) -> usize {
+        self.max_stack
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.stack.capacity()
     }
 
+    // Empties the stack for reuse by another frame, keeping its allocation and
+    // max_stack. Any fault not yet reported by check() is dropped
+    pub fn clear(&mut self) -> () {
+        self.stack.clear();
+        self.slots = 0;
+        self.fault = None;
+    }
+
+    // Pushing past max_stack doesn't stop the current instruction, but the
+    // overflow is recorded and reported by the next call to check(). A boolean,
+    // byte, short or char is pushed as the Int it promotes to
     pub fn push(&mut self, val: JvmValue) -> () {
+        let val = val.promote();
+        let slots = self.slots + slot_size(&val);
+        if slots > self.max_stack && self.fault.is_none() {
+            self.fault = Some(RuntimeError::VerifyError {
+                msg: format!(
+                    "Operand stack depth {} exceeds max_stack {}",
+                    slots, self.max_stack
+                ),
+            });
+        }
+        self.slots = slots;
         let s = &mut self.stack;
         s.push(val);
     }
 
+    // Reports (and clears) any overflow or underflow since the last check
+    pub fn check(&mut self) -> Result<(), RuntimeError> {
+        match self.fault.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    pub fn try_pop(&mut self) -> Result<JvmValue, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => {
+                self.slots -= slot_size(&value);
+                Ok(value)
+            }
+            None => Err(RuntimeError::VerifyError {
+                msg: "Operand stack underflow".to_string(),
+            }),
+        }
+    }
+
+    // The number of values on the stack
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    // The number of slots those values take up, which is what max_stack limits
+    pub fn slot_depth(&self) -> usize {
+        self.slots
+    }
+
+    // Bottom of the stack first
+    pub fn as_slice(&self) -> &[JvmValue] {
+        &self.stack
+    }
+
+    pub fn peek(&self) -> Option<&JvmValue> {
+        self.peek_n(0)
+    }
+
+    // n counts down from the top, so peek_n(0) is the top value
+    pub fn peek_n(&self, n: usize) -> Option<&JvmValue> {
+        let len = self.stack.len();
+        if n >= len {
+            return None;
+        }
+        self.stack.get(len - 1 - n)
+    }
+
+    // Popping an empty stack records an underflow, to be reported by check(),
+    // and yields a default value so the current instruction can complete
     pub fn pop(&mut self) -> JvmValue {
-        let s = &mut self.stack;
-        match s.pop() {
-            Some(value) => value,
-            None => panic!("pop() on empty stack"),
+        match self.try_pop() {
+            Ok(value) => value,
+            Err(e) => {
+                if self.fault.is_none() {
+                    self.fault = Some(e);
+                }
+                JvmValue::default()
+            }
         }
     }
 
     pub fn aconst_null(&mut self) -> () {
-        self.push(JvmValue::ObjRef {
-            val: 0, // OtObj::get_null(),
-        });
+        self.push(JvmValue::ObjRef { val: ObjId::NULL });
     }
 
     pub fn iconst(&mut self, v: i32) -> () {
@@ -45,7 +157,7 @@ impl InterpEvalStack {
             _ => panic!("Unexpected, non-integer value encountered"),
         };
 
-        self.push(JvmValue::Int { val: i1 + i2 });
+        self.push(JvmValue::Int { val: i1.wrapping_add(i2) });
     }
 
     pub fn isub(&mut self) -> () {
@@ -59,7 +171,8 @@ impl InterpEvalStack {
             _ => panic!("Unexpected, non-integer value encountered"),
         };
 
-        self.push(JvmValue::Int { val: i1 - i2 });
+        // i1 was on top, so is the subtrahend
+        self.push(JvmValue::Int { val: i2.wrapping_sub(i1) });
     }
     pub fn imul(&mut self) -> () {
         // For a runtime checking interpreter - type checks would go here...
@@ -72,10 +185,10 @@ impl InterpEvalStack {
             _ => panic!("Unexpected, non-integer value encountered"),
         };
 
-        self.push(JvmValue::Int { val: i1 * i2 });
+        self.push(JvmValue::Int { val: i1.wrapping_mul(i2) });
     }
 
-    pub fn irem(&mut self) -> () {
+    pub fn irem(&mut self) -> Result<(), RuntimeError> {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = match self.pop() {
             JvmValue::Int { val: i } => i,
@@ -86,10 +199,15 @@ impl InterpEvalStack {
             _ => panic!("Unexpected, non-integer value encountered"),
         };
 
-        self.push(JvmValue::Int { val: i2 % i1 });
+        if i1 == 0 {
+            return Err(RuntimeError::divide_by_zero());
+        }
+        // MIN % -1 overflows in Rust, but is just 0
+        self.push(JvmValue::Int { val: i2.wrapping_rem(i1) });
+        Ok(())
     }
     pub fn ixor(&self) -> () {}
-    pub fn idiv(&mut self) -> () {
+    pub fn idiv(&mut self) -> Result<(), RuntimeError> {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = match self.pop() {
             JvmValue::Int { val: i } => i,
@@ -100,7 +218,12 @@ impl InterpEvalStack {
             _ => panic!("Unexpected, non-integer value encountered"),
         };
 
-        self.push(JvmValue::Int { val: i2 / i1 });
+        if i1 == 0 {
+            return Err(RuntimeError::divide_by_zero());
+        }
+        // MIN / -1 overflows, and the JVM gives back MIN
+        self.push(JvmValue::Int { val: i2.wrapping_div(i1) });
+        Ok(())
     }
     pub fn iand(&self) -> () {}
     pub fn ineg(&mut self) -> () {
@@ -108,10 +231,89 @@ impl InterpEvalStack {
             JvmValue::Int { val: i } => i,
             _ => panic!("Unexpected, non-integer value encountered"),
         };
-        self.push(JvmValue::Int { val: -i1 });
+        self.push(JvmValue::Int { val: i1.wrapping_neg() });
     }
     pub fn ior(&self) -> () {}
 
+    pub fn ldiv(&mut self) -> Result<(), RuntimeError> {
+        let l1 = match self.pop() {
+            JvmValue::Long { val: l } => l,
+            _ => panic!("Unexpected, non-long value encountered"),
+        };
+        let l2 = match self.pop() {
+            JvmValue::Long { val: l } => l,
+            _ => panic!("Unexpected, non-long value encountered"),
+        };
+
+        if l1 == 0 {
+            return Err(RuntimeError::divide_by_zero());
+        }
+        // As for idiv, MIN / -1 is MIN
+        self.push(JvmValue::Long { val: l2.wrapping_div(l1) });
+        Ok(())
+    }
+
+    pub fn lrem(&mut self) -> Result<(), RuntimeError> {
+        let l1 = match self.pop() {
+            JvmValue::Long { val: l } => l,
+            _ => panic!("Unexpected, non-long value encountered"),
+        };
+        let l2 = match self.pop() {
+            JvmValue::Long { val: l } => l,
+            _ => panic!("Unexpected, non-long value encountered"),
+        };
+
+        if l1 == 0 {
+            return Err(RuntimeError::divide_by_zero());
+        }
+        self.push(JvmValue::Long { val: l2.wrapping_rem(l1) });
+        Ok(())
+    }
+
+    // (value1, value2) in the spec's naming, so value2 is the one that was on top
+    fn pop_two_ints(&mut self) -> (i32, i32) {
+        let v2 = match self.pop() {
+            JvmValue::Int { val: i } => i,
+            _ => panic!("Unexpected, non-integer value encountered"),
+        };
+        let v1 = match self.pop() {
+            JvmValue::Int { val: i } => i,
+            _ => panic!("Unexpected, non-integer value encountered"),
+        };
+        (v1, v2)
+    }
+
+    // The if_icmp* comparisons, true if the branch is taken
+    pub fn if_icmpeq(&mut self) -> bool {
+        let (v1, v2) = self.pop_two_ints();
+        v1 == v2
+    }
+
+    pub fn if_icmpne(&mut self) -> bool {
+        let (v1, v2) = self.pop_two_ints();
+        v1 != v2
+    }
+
+    pub fn if_icmplt(&mut self) -> bool {
+        let (v1, v2) = self.pop_two_ints();
+        v1 < v2
+    }
+
+    pub fn if_icmpge(&mut self) -> bool {
+        let (v1, v2) = self.pop_two_ints();
+        v1 >= v2
+    }
+
+    pub fn if_icmpgt(&mut self) -> bool {
+        let (v1, v2) = self.pop_two_ints();
+        v1 > v2
+    }
+
+    pub fn if_icmple(&mut self) -> bool {
+        let (v1, v2) = self.pop_two_ints();
+        v1 <= v2
+    }
+
     pub fn dadd(&mut self) -> () {
         // For a runtime checking interpreter - type checks would go here...
         let i1 = match self.pop() {
@@ -156,18 +358,47 @@ impl InterpEvalStack {
         self.push(JvmValue::Double { val: v });
     }
 
+    pub fn fconst(&mut self, v: f32) -> () {
+        self.push(JvmValue::Float { val: v });
+    }
+
+    pub fn lconst(&mut self, v: i64) -> () {
+        self.push(JvmValue::Long { val: v });
+    }
+
     pub fn i2d(&self) -> () {}
     pub fn dup(&mut self) -> () {
-        let i1 = self.pop();
-        self.push(i1.to_owned());
-        self.push(i1.to_owned());
-    }
-    pub fn dupX1(&mut self) -> () {
-        let i1 = self.pop();
-        let i1c = i1.clone();
-        let i2 = self.pop();
-        self.push(i1);
-        self.push(i2);
-        self.push(i1c);
+        match self.peek().cloned() {
+            Some(i1) => self.push(i1),
+            // Let pop record the underflow
+            None => {
+                let i1 = self.pop();
+                self.push(i1.to_owned());
+                self.push(i1);
+            }
+        }
+    }
+    // ..., v2, v1 -> ..., v1, v2, v1
+    pub fn dup_x1(&mut self) -> () {
+        let v1 = self.pop();
+        let v2 = self.pop();
+        self.push(v1.clone());
+        self.push(v2);
+        self.push(v1);
+    }
+}
+
+fn slot_size(val: &JvmValue) -> usize {
+    if crate::is_category_2(val) {
+        2
+    } else {
+        1
+    }
+}
+
+// Lists the stack contents with the top of the stack first
+impl fmt::Debug for InterpEvalStack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.stack.iter().rev()).finish()
     }
 }
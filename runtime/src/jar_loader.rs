@@ -0,0 +1,123 @@
+use std::fmt;
+use std::panic;
+use std::path::Path;
+
+use ocelotter_util::{zip_entry_to_bytes, ZipError, ZipFiles};
+
+use crate::klass_parser::OtKlassParser;
+use crate::klass_repo::SharedKlassRepo;
+use crate::OtKlass;
+
+//////////// JAR LOADING
+
+#[derive(Debug)]
+pub enum LoadError {
+    // The jar itself could not be opened or read
+    Io { path: String, msg: String },
+    // Some entries could not be parsed - the others have still been loaded
+    MalformedEntries {
+        loaded: usize,
+        errors: Vec<(String, String)>,
+    },
+    // The jar has no entry of that name
+    MissingEntry { path: String, entry: String },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, msg } => write!(f, "Error reading {}: {}", path, msg),
+            LoadError::MalformedEntries { loaded, errors } => {
+                write!(f, "Loaded {} classes, skipped {}:", loaded, errors.len())?;
+                for (name, msg) in errors {
+                    write!(f, " {} ({})", name, msg)?;
+                }
+                Ok(())
+            }
+            LoadError::MissingEntry { path, entry } => {
+                write!(f, "No entry {} found in {}", entry, path)
+            }
+        }
+    }
+}
+
+// Adds every *.class entry in the jar to the repo, returning how many were loaded
+pub fn load_jar(path: &Path, repo: &mut SharedKlassRepo) -> Result<usize, LoadError> {
+    let path_str = path.to_string_lossy().to_string();
+    let entries = match ZipFiles::open(&path_str) {
+        Ok(z) => z,
+        Err(e) => {
+            return Err(LoadError::Io {
+                path: path_str,
+                msg: e.to_string(),
+            })
+        }
+    };
+
+    let mut loaded = 0;
+    let mut errors = Vec::new();
+    for entry in entries {
+        let (name, bytes) = match entry {
+            Ok(value) => value,
+            Err(e) => {
+                errors.push((path_str.clone(), e.to_string()));
+                continue;
+            }
+        };
+        if !name.ends_with(".class") {
+            continue;
+        }
+        match parse_entry(&name, bytes) {
+            Ok(k) => {
+                repo.add_klass(&k);
+                loaded += 1;
+            }
+            Err(msg) => errors.push((name, msg)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(loaded)
+    } else {
+        Err(LoadError::MalformedEntries { loaded, errors })
+    }
+}
+
+// Loads a single klass from the jar. klass_name is in internal form, e.g. a/b/C,
+// which is found at the entry a/b/C.class
+pub fn load_klass_from_jar(path: &Path, klass_name: &str) -> Result<OtKlass, LoadError> {
+    let path_str = path.to_string_lossy().to_string();
+    let entry = klass_name.to_string() + ".class";
+    let bytes = match zip_entry_to_bytes(&path_str, &entry) {
+        Ok(bytes) => bytes,
+        Err(ZipError::FileNotFound) => return Err(LoadError::MissingEntry { path: path_str, entry }),
+        Err(e) => {
+            return Err(LoadError::Io {
+                path: path_str,
+                msg: e.to_string(),
+            })
+        }
+    };
+    parse_entry(&entry, bytes).map_err(|msg| LoadError::MalformedEntries {
+        loaded: 0,
+        errors: vec![(entry.clone(), msg)],
+    })
+}
+
+// Shared with the class loader, for klasses found in a classpath directory
+pub(crate) fn parse_entry(name: &str, bytes: Vec<u8>) -> Result<OtKlass, String> {
+    if bytes.len() < 4 || bytes[0..4] != [0xca, 0xfe, 0xba, 0xbe] {
+        return Err("Not a class file".to_string());
+    }
+    // FIXME The parser panics on malformed input, so contain that here until it returns errors
+    let fname = name.to_string();
+    let parsed = panic::catch_unwind(move || {
+        let mut parser = OtKlassParser::of(bytes, fname);
+        parser.try_parse().map(|_| parser.klass())
+    });
+    match parsed {
+        Ok(Ok(k)) => Ok(k),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("Class file could not be parsed".to_string()),
+    }
+}
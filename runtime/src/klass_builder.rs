@@ -0,0 +1,127 @@
+use crate::constant_pool::CpEntry;
+use crate::constant_pool::{ACC_PUBLIC, ACC_SUPER};
+use crate::otfield::OtField;
+use crate::otklass::OtKlass;
+use crate::otmethod::OtMethod;
+
+//////////// KLASS BUILDER
+
+// Assembles an OtKlass without a class file, allocating the constant pool
+// entries that the methods need. Mostly useful for setting up tests
+pub struct KlassBuilder {
+    name: String,
+    super_name: String,
+    interfaces: Vec<String>,
+    flags: u16,
+    cp_entries: Vec<CpEntry>,
+    methods: Vec<OtMethod>,
+}
+
+impl KlassBuilder {
+    pub fn new(name: &str) -> KlassBuilder {
+        let mut out = KlassBuilder {
+            name: name.to_string(),
+            super_name: "java/lang/Object".to_string(),
+            interfaces: Vec::new(),
+            flags: ACC_PUBLIC | ACC_SUPER,
+            // CP indices are 1-based
+            cp_entries: vec![CpEntry::unusable {}],
+            methods: Vec::new(),
+        };
+        out.add_class(name);
+        out
+    }
+
+    pub fn with_super(mut self, super_name: &str) -> KlassBuilder {
+        self.super_name = super_name.to_string();
+        self
+    }
+
+    pub fn with_interface(mut self, interface_name: &str) -> KlassBuilder {
+        self.interfaces.push(interface_name.to_string());
+        self
+    }
+
+    pub fn with_flags(mut self, flags: u16) -> KlassBuilder {
+        self.flags = flags;
+        self
+    }
+
+    // Appends entry at next_cp_index(), e.g. for an LDC operand. Longs and doubles
+    // take two slots, as in a class file
+    pub fn add_constant(mut self, entry: CpEntry) -> KlassBuilder {
+        let double_width = match entry {
+            CpEntry::long { val: _ } | CpEntry::double { val: _ } => true,
+            _ => false,
+        };
+        self.cp_entries.push(entry);
+        if double_width {
+            self.cp_entries.push(CpEntry::unusable {});
+        }
+        self
+    }
+
+    // The CP index the next add_constant() will use
+    pub fn next_cp_index(&self) -> u16 {
+        self.cp_entries.len() as u16
+    }
+
+    pub fn add_method(mut self, name: &str, desc: &str, flags: u16, code: Vec<u8>) -> KlassBuilder {
+        let name_idx = self.add_utf8(name);
+        let desc_idx = self.add_utf8(desc);
+        let mut meth = OtMethod::of(
+            self.name.clone(),
+            name.to_string(),
+            desc.to_string(),
+            flags,
+            name_idx,
+            desc_idx,
+        );
+        meth.set_code(code);
+        self.methods.push(meth);
+        self
+    }
+
+    pub fn build(mut self) -> OtKlass {
+        let super_name = self.super_name.clone();
+        self.add_class(&super_name);
+        for interface_name in self.interfaces.clone() {
+            self.add_class(&interface_name);
+        }
+        OtKlass::of(
+            self.name,
+            self.super_name,
+            &self.interfaces,
+            self.flags,
+            &self.cp_entries,
+            &self.methods,
+            &Vec::<OtField>::new(),
+        )
+    }
+
+    // Reuses an existing entry for the same string, as javac does
+    fn add_utf8(&mut self, s: &str) -> u16 {
+        for (i, entry) in self.cp_entries.iter().enumerate() {
+            if let CpEntry::utf8 { val } = entry {
+                if val == s {
+                    return i as u16;
+                }
+            }
+        }
+        self.cp_entries.push(CpEntry::utf8 { val: s.to_string() });
+        (self.cp_entries.len() - 1) as u16
+    }
+
+    fn add_class(&mut self, name: &str) -> u16 {
+        let utf_idx = self.add_utf8(name);
+        for (i, entry) in self.cp_entries.iter().enumerate() {
+            if let CpEntry::class { idx } = entry {
+                if *idx == utf_idx {
+                    return i as u16;
+                }
+            }
+        }
+        self.cp_entries.push(CpEntry::class { idx: utf_idx });
+        (self.cp_entries.len() - 1) as u16
+    }
+}
@@ -1,14 +1,53 @@
 #![deny(unreachable_patterns)]
 
 use byteorder::{BigEndian, ByteOrder};
+use std::fmt;
 use std::io::Read;
 use std::str;
 
 use crate::constant_pool::*;
+use crate::opcode::Opcode;
+use crate::verifier;
+use crate::verifier::{StackMapFrame, VType};
 
 use crate::OtField;
 use crate::OtKlass;
 use crate::OtMethod;
+use crate::otmethod::ExceptionHandler;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    // A Code attribute that would let the interpreter run off the end of, or out of, the method
+    MalformedCode {
+        method: String,
+        pc: usize,
+        reason: String,
+    },
+    // A class file newer than the parser was told to accept
+    UnsupportedClassVersion { major: u16, minor: u16, max_major: u16 },
+}
+
+// Java 14, the newest class file version that has been tried against this VM
+pub const MAX_MAJOR_VERSION: u16 = 58;
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::MalformedCode { method, pc, reason } => {
+                write!(f, "Malformed code in {} at {}: {}", method, pc, reason)
+            }
+            ParseError::UnsupportedClassVersion {
+                major,
+                minor,
+                max_major,
+            } => write!(
+                f,
+                "Class file version {}.{} is newer than the highest supported, {}.0",
+                major, minor, max_major
+            ),
+        }
+    }
+}
 
 pub struct OtKlassParser {
     clz_read: Vec<u8>,
@@ -16,6 +55,7 @@ pub struct OtKlassParser {
     current: usize,
     major: u16,
     minor: u16,
+    max_major: u16,
 
     pool_item_count: u16,
     flags: u16,
@@ -25,6 +65,7 @@ pub struct OtKlassParser {
     interfaces: Vec<u16>,
     fields: Vec<OtField>,
     methods: Vec<OtMethod>,
+    bootstrap_methods: Vec<BootstrapMethod>,
     // attributes: Vec<CpAttr>,
 }
 
@@ -36,6 +77,7 @@ impl OtKlassParser {
             current: 0,
             major: 0,
             minor: 0,
+            max_major: MAX_MAJOR_VERSION,
             pool_item_count: 0,
             flags: 0,
             cp_index_this: 0,
@@ -44,18 +86,34 @@ impl OtKlassParser {
             interfaces: Vec::new(),
             fields: Vec::new(),
             methods: Vec::new(),
+            bootstrap_methods: Vec::new(),
         }
     }
 
+    // Class files with a higher major version are rejected by try_parse()
+    pub fn with_max_major_version(mut self, max_major: u16) -> OtKlassParser {
+        self.max_major = max_major;
+        self
+    }
+
     pub fn klass(&mut self) -> OtKlass {
-        OtKlass::of(
+        let interfaces = self
+            .interfaces
+            .iter()
+            .map(|i| self.class_name_from_cp(*i).to_string())
+            .collect();
+        let mut k = OtKlass::of(
             self.klass_name().to_string(),
             self.super_name().to_string(),
+            &interfaces,
             self.flags,
             &self.cp_entries,
             &self.methods,
             &self.fields,
-        )
+        );
+        k.set_version(self.major, self.minor);
+        k.set_bootstrap_methods(self.bootstrap_methods.clone());
+        k
     }
 
     fn klass_name(&self) -> &String {
@@ -97,6 +155,22 @@ impl OtKlassParser {
         }
     }
 
+    fn class_name_from_cp(&self, idx: u16) -> &String {
+        match self.cp_entries[idx as usize] {
+            CpEntry::class { idx: icl } => match &self.cp_entries[icl as usize] {
+                CpEntry::utf8 { val: s } => s,
+                _ => panic!(
+                    "Class index {} does not point at utf8 string in constant pool",
+                    icl
+                ),
+            },
+            _ => panic!(
+                "Index {} does not point at class element in constant pool",
+                idx
+            ),
+        }
+    }
+
     fn stringref_from_cp(&mut self, idx: u16) -> &String {
         match &self.cp_entries[idx as usize] {
             CpEntry::utf8 { val: s } => s,
@@ -108,12 +182,19 @@ impl OtKlassParser {
     }
 
     pub fn parse(&mut self) -> () {
-        self.parse_header();
+        if let Err(e) = self.try_parse() {
+            panic!("Error parsing {}: {}", self.filename, e);
+        }
+    }
+
+    pub fn try_parse(&mut self) -> Result<(), ParseError> {
+        self.parse_header()?;
         self.parse_constant_pool();
         self.parse_basic_type_info();
         self.parse_fields();
-        self.parse_methods();
-        //        self.parseAttributes();
+        self.parse_methods()?;
+        self.parse_klass_attributes();
+        Ok(())
     }
 
     // CP is 1-indexed
@@ -122,7 +203,7 @@ impl OtKlassParser {
     }
 
     // Impl methods
-    fn parse_header(&mut self) -> () {
+    fn parse_header(&mut self) -> Result<(), ParseError> {
         if self.clz_read[0] != 0xca
             || self.clz_read[1] != 0xfe
             || self.clz_read[2] != 0xba
@@ -136,17 +217,23 @@ impl OtKlassParser {
 
         self.minor = ((self.clz_read[4] as u16) << 8) + self.clz_read[5] as u16;
         self.major = ((self.clz_read[6] as u16) << 8) + self.clz_read[7] as u16;
+        if self.major > self.max_major {
+            return Err(ParseError::UnsupportedClassVersion {
+                major: self.major,
+                minor: self.minor,
+                max_major: self.max_major,
+            });
+        }
         self.pool_item_count = ((self.clz_read[8] as u16) << 8) + self.clz_read[9] as u16;
+        Ok(())
     }
 
     fn parse_constant_pool(&mut self) -> () {
         self.current = 10;
         // dbg!("Pool size:");
         // dbg!(self.get_pool_size());
-        self.cp_entries.resize(
-            (self.pool_item_count as usize) + 1,
-            CpEntry::integer { val: 0 },
-        );
+        self.cp_entries
+            .resize(self.pool_item_count as usize, CpEntry::unusable {});
         let mut current_cp = 1;
         while current_cp < self.pool_item_count {
             let tag = self.clz_read[self.current];
@@ -211,8 +298,6 @@ impl OtKlassParser {
                     let b7 = self.clz_read[self.current + 6];
                     let b8 = self.clz_read[self.current + 7];
                     self.current += 8;
-                    // Longs are double width
-                    current_cp += 1;
 
                     let buf = &[b1, b2, b3, b4, b5, b6, b7, b8];
                     CpEntry::long {
@@ -230,8 +315,6 @@ impl OtKlassParser {
                     let b7 = self.clz_read[self.current + 6];
                     let b8 = self.clz_read[self.current + 7];
                     self.current += 8;
-                    // Doubles are double width
-                    current_cp += 1;
 
                     let buf = &[b1, b2, b3, b4, b5, b6, b7, b8];
                     CpEntry::double {
@@ -308,10 +391,30 @@ impl OtKlassParser {
                         type_idx: ((b3 as u16) << 8) + b4 as u16,
                     }
                 }
+                CP_METHODHANDLE => {
+                    let kind = self.clz_read[self.current];
+                    self.current += 1;
+                    CpEntry::method_handle {
+                        kind,
+                        ref_idx: self.read_u16(),
+                    }
+                }
+                CP_METHODTYPE => CpEntry::method_type {
+                    desc_idx: self.read_u16(),
+                },
+                CP_INVOKEDYNAMIC => CpEntry::invoke_dynamic {
+                    bsm_idx: self.read_u16(),
+                    nt_idx: self.read_u16(),
+                },
                 _ => panic!("Unsupported Constant Pool type {} at {}", tag, self.current),
             };
+            // Longs and doubles are double width, the following slot is unusable
+            let width = match item {
+                CpEntry::long { val: _ } | CpEntry::double { val: _ } => 2,
+                _ => 1,
+            };
             self.cp_entries[current_cp as usize] = item;
-            current_cp += 1;
+            current_cp += width;
         }
     }
 
@@ -423,7 +526,7 @@ impl OtKlassParser {
         CpAttr::of(name_idx)
     }
 
-    fn parse_methods(&mut self) -> () {
+    fn parse_methods(&mut self) -> Result<(), ParseError> {
         let mcount =
             ((self.clz_read[self.current] as u16) << 8) + self.clz_read[self.current + 1] as u16;
         self.current += 2;
@@ -465,14 +568,15 @@ impl OtKlassParser {
                 desc_idx,
             );
             for aidx in 0..attr_count {
-                let att = self.parse_method_attribute(&mut m);
+                let att = self.parse_method_attribute(&mut m)?;
                 m.set_attr(aidx, att.clone());
             }
             self.methods.push(m);
         }
+        Ok(())
     }
 
-    fn parse_method_attribute(&mut self, method: &mut OtMethod) -> CpAttr {
+    fn parse_method_attribute(&mut self, method: &mut OtMethod) -> Result<CpAttr, ParseError> {
         let name_idx =
             ((self.clz_read[self.current] as u16) << 8) + self.clz_read[self.current + 1] as u16;
         let b1 = self.clz_read[self.current + 2];
@@ -491,8 +595,10 @@ impl OtKlassParser {
             "Code" => {
                 //    u2 max_stack;
                 //    u2 max_locals;
-                //    FIXME: Currently Don't care about stack depth or locals
-                self.current += 4;
+                let max_stack = self.read_u16();
+                method.set_max_stack(max_stack);
+                let max_locals = self.read_u16();
+                method.set_max_locals(max_locals);
                 // //    u4 code_length;
                 // //    u1 code[code_length];
                 let b1 = self.clz_read[self.current];
@@ -511,26 +617,70 @@ impl OtKlassParser {
                 match chunk.read_to_end(&mut bytecode) {
                     Ok(v) => {
                         self.current += code_len as usize;
-                        method.set_code(bytecode);
                     }
                     Err(e) => panic!("error parsing file: {:?}", e),
                 };
+                if bytecode.len() != code_len as usize {
+                    return Err(ParseError::MalformedCode {
+                        method: method.get_fq_name_desc(),
+                        pc: bytecode.len(),
+                        reason: format!("code_length is {} but class file is truncated", code_len),
+                    });
+                }
+                validate_code(&bytecode).map_err(|(pc, reason)| ParseError::MalformedCode {
+                    method: method.get_fq_name_desc(),
+                    pc,
+                    reason,
+                })?;
+                method.set_code(bytecode);
+
+                //    u2 exception_table_length;
+                //    {   u2 start_pc;
+                //        u2 end_pc;
+                //        u2 handler_pc;
+                //        u2 catch_type;
+                //    } exception_table[exception_table_length];
+                let exception_table_len = self.read_u16();
+                let mut exception_table = Vec::new();
+                for _i in 0..exception_table_len {
+                    let start_pc = self.read_u16();
+                    let end_pc = self.read_u16();
+                    let handler_pc = self.read_u16();
+                    let catch_idx = self.read_u16();
+                    let catch_type = match catch_idx {
+                        0 => None,
+                        _ => Some(self.class_name_from_cp(catch_idx).clone()),
+                    };
+                    exception_table.push(ExceptionHandler {
+                        start_pc,
+                        end_pc,
+                        handler_pc,
+                        catch_type,
+                    });
+                }
+                method.set_exception_table(exception_table);
+
+                //    u2 attributes_count;
+                //    attribute_info attributes[attributes_count];
+                let code_attr_count = self.read_u16();
+                for _i in 0..code_attr_count {
+                    self.parse_code_attribute(method);
+                }
             }
             "Signature" => {
                 dbg!("Encountered signature in bytecode - skipping");
                 ()
             }
-            //    u2 exception_table_length;
-            //    {   u2 start_pc;
-            //        u2 end_pc;
-            //        u2 handler_pc;
-            //        u2 catch_type;
-            //    } exception_table[exception_table_length];
-            //    u2 attributes_count;
-            //    attribute_info attributes[attributes_count];
             "Exceptions" => {
-                dbg!("Encountered exception handlers in bytecode - skipping");
-                ()
+                //    u2 number_of_exceptions;
+                //    u2 exception_index_table[number_of_exceptions];
+                let count = self.read_u16();
+                let mut declared = Vec::new();
+                for _i in 0..count {
+                    let class_idx = self.read_u16();
+                    declared.push(self.class_name_from_cp(class_idx).clone());
+                }
+                method.set_declared_exceptions(declared);
             }
             "Deprecated" => {
                 dbg!("Encountered Deprecated attribute in bytecode - skipping");
@@ -551,7 +701,166 @@ impl OtKlassParser {
         //         self.current, end_index
         //     )
         // }
-        CpAttr::of(name_idx)
+        Ok(CpAttr::of(name_idx))
+    }
+
+    // Attributes nested inside a Code attribute. All of these are optional debug info
+    fn parse_code_attribute(&mut self, method: &mut OtMethod) -> () {
+        let name_idx = self.read_u16();
+        let attr_len = BigEndian::read_u32(&self.clz_read[self.current..]);
+        self.current += 4;
+        let end_index = self.current + attr_len as usize;
+
+        let s = self.stringref_from_cp(name_idx).clone();
+        match s.as_str() {
+            "LineNumberTable" => {
+                //    u2 line_number_table_length;
+                //    {   u2 start_pc;
+                //        u2 line_number;
+                //    } line_number_table[line_number_table_length];
+                let table_len = self.read_u16();
+                let mut line_numbers = Vec::new();
+                for _i in 0..table_len {
+                    let start_pc = self.read_u16();
+                    let line_number = self.read_u16();
+                    line_numbers.push((start_pc, line_number));
+                }
+                method.set_line_numbers(line_numbers);
+            }
+            "LocalVariableTable" => {
+                //    u2 local_variable_table_length;
+                //    {   u2 start_pc;
+                //        u2 length;
+                //        u2 name_index;
+                //        u2 descriptor_index;
+                //        u2 index;
+                //    } local_variable_table[local_variable_table_length];
+                let table_len = self.read_u16();
+                let mut local_vars = Vec::new();
+                for _i in 0..table_len {
+                    let start_pc = self.read_u16();
+                    let length = self.read_u16();
+                    let name_idx = self.read_u16();
+                    let desc_idx = self.read_u16();
+                    let slot = self.read_u16();
+                    let name = self.stringref_from_cp(name_idx).clone();
+                    let desc = self.stringref_from_cp(desc_idx).clone();
+                    local_vars.push((start_pc, length, name, desc, slot));
+                }
+                method.set_local_vars(local_vars);
+            }
+            "StackMapTable" => {
+                //    u2 number_of_entries;
+                //    stack_map_frame entries[number_of_entries];
+                // Each frame is a delta against the one before, starting from the
+                // frame implied by the method descriptor
+                let entry_count = self.read_u16();
+                let mut locals = verifier::initial_locals(method)
+                    .unwrap_or_else(|msg| panic!("{} in {}", msg, method));
+                let mut frames = Vec::new();
+                let mut pc: i32 = -1;
+                for _i in 0..entry_count {
+                    let frame_type = self.clz_read[self.current];
+                    self.current += 1;
+                    let (offset_delta, stack) = match frame_type {
+                        // same_frame
+                        0..=63 => (frame_type as u16, vec![]),
+                        // same_locals_1_stack_item_frame
+                        64..=127 => ((frame_type - 64) as u16, vec![self.read_verification_type()]),
+                        // same_locals_1_stack_item_frame_extended
+                        247 => {
+                            let delta = self.read_u16();
+                            (delta, vec![self.read_verification_type()])
+                        }
+                        // chop_frame, dropping the last 251 - frame_type locals
+                        248..=250 => {
+                            let delta = self.read_u16();
+                            let keep = locals.len().saturating_sub((251 - frame_type) as usize);
+                            locals.truncate(keep);
+                            (delta, vec![])
+                        }
+                        // same_frame_extended
+                        251 => (self.read_u16(), vec![]),
+                        // append_frame, adding frame_type - 251 locals
+                        252..=254 => {
+                            let delta = self.read_u16();
+                            for _j in 0..(frame_type - 251) {
+                                locals.push(self.read_verification_type());
+                            }
+                            (delta, vec![])
+                        }
+                        // full_frame
+                        255 => {
+                            let delta = self.read_u16();
+                            let local_count = self.read_u16();
+                            locals = (0..local_count).map(|_| self.read_verification_type()).collect();
+                            let stack_count = self.read_u16();
+                            let stack = (0..stack_count).map(|_| self.read_verification_type()).collect();
+                            (delta, stack)
+                        }
+                        _ => panic!("Reserved StackMapTable frame type {} in {}", frame_type, method),
+                    };
+                    // The first frame's offset is absolute, later ones are relative to the previous frame + 1
+                    pc = pc + offset_delta as i32 + 1;
+                    frames.push(StackMapFrame::of(pc as u16, &locals, stack));
+                }
+                method.set_stack_map(frames);
+            }
+            // Other attributes are skipped
+            _ => (),
+        };
+        self.current = end_index;
+    }
+
+    // A verification_type_info. Nulls, uninitialized objects and class references are
+    // all just references to the verifier
+    fn read_verification_type(&mut self) -> VType {
+        let tag = self.clz_read[self.current];
+        self.current += 1;
+        match tag {
+            0 => VType::Top,
+            1 => VType::Int,
+            2 => VType::Float,
+            3 => VType::Double,
+            4 => VType::Long,
+            5 | 6 => VType::Reference,
+            // Object carries a CP class index, Uninitialized the pc of its NEW
+            7 | 8 => {
+                self.current += 2;
+                VType::Reference
+            }
+            _ => panic!("Unknown verification type tag {} in {}", tag, self.filename),
+        }
+    }
+
+    // Only BootstrapMethods is kept, the rest are skipped
+    fn parse_klass_attributes(&mut self) -> () {
+        let attr_count = self.read_u16();
+        for _i in 0..attr_count {
+            let name_idx = self.read_u16();
+            let attr_len = BigEndian::read_u32(&self.clz_read[self.current..]);
+            self.current += 4;
+            let end_index = self.current + attr_len as usize;
+
+            if let CpEntry::utf8 { val: s } = &self.cp_entries[name_idx as usize] {
+                if s == "BootstrapMethods" {
+                    let count = self.read_u16();
+                    for _j in 0..count {
+                        let method_ref = self.read_u16();
+                        let arg_count = self.read_u16();
+                        let args = (0..arg_count).map(|_| self.read_u16()).collect();
+                        self.bootstrap_methods.push(BootstrapMethod { method_ref, args });
+                    }
+                }
+            }
+            self.current = end_index;
+        }
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let out = BigEndian::read_u16(&self.clz_read[self.current..]);
+        self.current += 2;
+        out
     }
 
     //         int nameCPIdx = ((int) clzBytes[current++] << 8) + (int) clzBytes[current++];
@@ -611,3 +920,95 @@ impl OtKlassParser {
 
     //         return new CPAttr(nameCPIdx);
 }
+
+// Walks the instructions, checking that every operand lies within the code array
+// and every branch target is the start of an instruction
+// The start of each instruction, and (pc, target) for every branch. Fails on any
+// instruction whose operands run past the end of the code
+pub(crate) fn scan_code(code: &[u8]) -> Result<(Vec<bool>, Vec<(usize, i64)>), (usize, String)> {
+    let mut starts = vec![false; code.len()];
+    let mut targets = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        starts[pc] = true;
+        let op = code[pc];
+        let len = match op {
+            Opcode::TABLESWITCH | Opcode::LOOKUPSWITCH => {
+                let base = pc + 1 + (4 - (pc + 1) % 4) % 4;
+                check_operands(code, pc, base + 8)?;
+                let default = BigEndian::read_i32(&code[base..]);
+                targets.push((pc, pc as i64 + default as i64));
+                let (count, entry_size, first) = if op == Opcode::TABLESWITCH {
+                    check_operands(code, pc, base + 12)?;
+                    let low = BigEndian::read_i32(&code[base + 4..]) as i64;
+                    let high = BigEndian::read_i32(&code[base + 8..]) as i64;
+                    if high < low {
+                        return Err((pc, "tableswitch high is less than low".to_string()));
+                    }
+                    ((high - low + 1) as usize, 4, base + 12)
+                } else {
+                    let npairs = BigEndian::read_i32(&code[base + 4..]);
+                    if npairs < 0 {
+                        return Err((pc, "lookupswitch has negative npairs".to_string()));
+                    }
+                    (npairs as usize, 8, base + 8)
+                };
+                let end = first + count * entry_size;
+                check_operands(code, pc, end)?;
+                for i in 0..count {
+                    // The offset is the last 4 bytes of each entry
+                    let offset = BigEndian::read_i32(&code[first + i * entry_size + entry_size - 4..]);
+                    targets.push((pc, pc as i64 + offset as i64));
+                }
+                end - pc
+            }
+            Opcode::WIDE => {
+                check_operands(code, pc, pc + 2)?;
+                if code[pc + 1] == Opcode::IINC {
+                    6
+                } else {
+                    4
+                }
+            }
+            _ => 1 + Opcode::num_params(op) as usize,
+        };
+        check_operands(code, pc, pc + len)?;
+        if let Some(offset) = branch_offset(code, pc) {
+            targets.push((pc, pc as i64 + offset));
+        }
+        pc += len;
+    }
+    Ok((starts, targets))
+}
+
+// The offset of the single target of a branch at pc, or None if it isn't one. The
+// switches have several targets, so aren't included
+pub(crate) fn branch_offset(code: &[u8], pc: usize) -> Option<i64> {
+    match code[pc] {
+        Opcode::IFEQ | Opcode::IFNE | Opcode::IFLT | Opcode::IFGE | Opcode::IFGT
+        | Opcode::IFLE | Opcode::IF_ICMPEQ | Opcode::IF_ICMPNE | Opcode::IF_ICMPLT
+        | Opcode::IF_ICMPGE | Opcode::IF_ICMPGT | Opcode::IF_ICMPLE | Opcode::IF_ACMPEQ
+        | Opcode::IF_ACMPNE | Opcode::GOTO | Opcode::JSR | Opcode::IFNULL
+        | Opcode::IFNONNULL => Some(BigEndian::read_i16(&code[pc + 1..]) as i64),
+        Opcode::GOTO_W | Opcode::JSR_W => Some(BigEndian::read_i32(&code[pc + 1..]) as i64),
+        _ => None,
+    }
+}
+
+fn validate_code(code: &[u8]) -> Result<(), (usize, String)> {
+    let (starts, targets) = scan_code(code)?;
+    for (pc, target) in targets {
+        if target < 0 || target >= code.len() as i64 || !starts[target as usize] {
+            return Err((pc, format!("branch target {} is not an instruction", target)));
+        }
+    }
+    Ok(())
+}
+
+fn check_operands(code: &[u8], pc: usize, end: usize) -> Result<(), (usize, String)> {
+    if end > code.len() {
+        Err((pc, format!("operands run past the end of the code at {}", end)))
+    } else {
+        Ok(())
+    }
+}
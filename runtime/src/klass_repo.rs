@@ -2,11 +2,14 @@ use std::fmt;
 use std::path::Path;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use regex::Regex;
 
+use crate::constant_pool::CpEntry;
 use crate::JvmValue;
-use crate::InterpLocalVars;
+use crate::ObjId;
 use crate::otfield::OtField;
 use crate::otmethod::OtMethod;
 use crate::otklass::OtKlass;
@@ -19,12 +22,24 @@ use ocelotter_util::file_to_bytes;
 pub enum KlassLoadingStatus {
     Mentioned {},
     Loaded { klass: OtKlass },
+    // <clinit> is running, the klass may be used by the initializing code itself
+    Initializing { klass: OtKlass },
+    // <clinit> failed, so the klass can't be used again (JVMS 5.5)
+    Erroneous { klass: OtKlass },
     Live { klass: OtKlass }
 }
 
 #[derive(Debug)]
 pub struct SharedKlassRepo {
     klass_lookup: HashMap<String, RefCell<KlassLoadingStatus>>,
+    // Resolved methods, keyed by the calling klass's id and the CP index of the methodref.
+    // Shared, so that a hit doesn't copy the method's code
+    method_cache: RefCell<HashMap<(usize, u16), Rc<OtMethod>>>,
+    // Heap objects only record their klass id, so keep the way back to the name
+    klass_names_by_id: HashMap<usize, String>,
+    next_klass_id: usize,
+    // Whether klasses have their methods' constants folded as they're added
+    fold_constants: bool,
 }
 
 impl SharedKlassRepo {
@@ -33,7 +48,7 @@ impl SharedKlassRepo {
     // Static methods
 
     // FIXME This is effectively static
-    fn parse_bootstrap_class(&self, cl_name: String) -> OtKlass {
+    pub fn parse_bootstrap_class(&self, cl_name: String) -> OtKlass {
         let fq_klass_fname = "./resources/lib/".to_owned() + &cl_name + ".class";
         let bytes = match file_to_bytes(Path::new(&fq_klass_fname)) {
             Ok(buf) => buf,
@@ -69,23 +84,84 @@ impl SharedKlassRepo {
     pub fn of() -> SharedKlassRepo {
         SharedKlassRepo {
             klass_lookup: HashMap::new(),
+            method_cache: RefCell::new(HashMap::new()),
+            klass_names_by_id: HashMap::new(),
+            // klass id 0 means "not loaded into a repo"
+            next_klass_id: 1,
+            fold_constants: false,
         }
     }
 
-    pub fn lookup_klass(&self, klass_name: &String) -> OtKlass {
-        // let s = format!("{}", self);
-        // dbg!(s);
-
+    // Returns None if the klass is unknown, or has only been mentioned by another klass
+    pub fn try_lookup_klass(&self, klass_name: &String) -> Option<OtKlass> {
         match self.klass_lookup.get(klass_name) {
             Some(cell) => match &*(cell.borrow()) {
-                KlassLoadingStatus::Mentioned {} => panic!("Klass with ID {} is not loaded yet", klass_name),
-                KlassLoadingStatus::Loaded { klass : k } => k.clone(),
-                KlassLoadingStatus::Live { klass : k } => k.clone()
+                KlassLoadingStatus::Mentioned {} => None,
+                KlassLoadingStatus::Loaded { klass : k } => Some(k.clone()),
+                KlassLoadingStatus::Initializing { klass : k } => Some(k.clone()),
+                KlassLoadingStatus::Erroneous { klass : k } => Some(k.clone()),
+                KlassLoadingStatus::Live { klass : k } => Some(k.clone())
             },
-            None => panic!("No klass called {} found in repo", klass_name),
+            None => None,
+        }
+    }
+
+    pub fn lookup_klass(&self, klass_name: &String) -> OtKlass {
+        match self.try_lookup_klass(klass_name) {
+            Some(k) => k,
+            None => panic!("No loaded klass called {} found in repo", klass_name),
         }
     }
 
+    // Every non-null reference held in a static field of a loaded klass
+    pub fn static_refs(&self) -> Vec<ObjId> {
+        let mut out = Vec::new();
+        for cell in self.klass_lookup.values() {
+            let status = cell.borrow();
+            let k = match &*status {
+                KlassLoadingStatus::Mentioned {} => continue,
+                KlassLoadingStatus::Loaded { klass: k } => k,
+                KlassLoadingStatus::Initializing { klass: k } => k,
+                KlassLoadingStatus::Erroneous { klass: k } => k,
+                KlassLoadingStatus::Live { klass: k } => k,
+            };
+            for v in k.get_static_field_values() {
+                match v {
+                    JvmValue::ObjRef { val } if !val.is_null() => out.push(*val),
+                    _ => (),
+                }
+            }
+        }
+        out
+    }
+
+    pub fn try_lookup_klass_by_id(&self, klass_id: usize) -> Option<OtKlass> {
+        let klass_name = self.klass_names_by_id.get(&klass_id)?;
+        self.try_lookup_klass(klass_name)
+    }
+
+    // Sorted. Klasses that have only been mentioned by another klass aren't loaded
+    pub fn loaded_class_names(&self) -> Vec<String> {
+        let mut out: Vec<String> = self
+            .klass_lookup
+            .iter()
+            .filter(|(_, cell)| !matches!(&*cell.borrow(), KlassLoadingStatus::Mentioned {}))
+            .map(|(name, _)| name.clone())
+            .collect();
+        out.sort();
+        out
+    }
+
+    // The number of loaded klasses
+    pub fn class_count(&self) -> usize {
+        self.klass_names_by_id.len()
+    }
+
+    // Only klasses added afterwards are affected
+    pub fn set_constant_folding(&mut self, on: bool) -> () {
+        self.fold_constants = on;
+    }
+
     pub fn add_klass(&mut self, k: &OtKlass) -> () {
         // First check to see if we already have this class and which state it's in
         let klass_name = k.get_name();
@@ -93,10 +169,18 @@ impl SharedKlassRepo {
             Some(value) => match &*(value.borrow()) {
                 KlassLoadingStatus::Mentioned {} => true,
                 KlassLoadingStatus::Loaded { klass : _ } => false, 
+                KlassLoadingStatus::Initializing { klass : _ } => false, 
+                KlassLoadingStatus::Erroneous { klass : _ } => false, 
                 KlassLoadingStatus::Live { klass : _ } => false 
             },
             None => {
-                let k2: OtKlass = (*k).to_owned();
+                let mut k2: OtKlass = (*k).to_owned();
+                k2.set_id(self.next_klass_id);
+                if self.fold_constants {
+                    k2.fold_constants();
+                }
+                self.klass_names_by_id.insert(self.next_klass_id, klass_name.clone());
+                self.next_klass_id = self.next_klass_id + 1;
                 // Scan for every other class the newcomer mentions
                 let klasses_mentioned = k2.get_mentioned_klasses();
 
@@ -107,7 +191,13 @@ impl SharedKlassRepo {
             }
         };
         if upgrade {
-            let k2 = (*k).to_owned();
+            let mut k2 = (*k).to_owned();
+            k2.set_id(self.next_klass_id);
+            if self.fold_constants {
+                k2.fold_constants();
+            }
+            self.klass_names_by_id.insert(self.next_klass_id, klass_name.clone());
+            self.next_klass_id = self.next_klass_id + 1;
             // Set kid & Load k into map
             self.klass_lookup.get(&klass_name).unwrap().replace(KlassLoadingStatus::Loaded{ klass: k2 });
         }
@@ -130,89 +220,6 @@ impl SharedKlassRepo {
         }
     }
 
-    fn run_clinit_method(&mut self, k : &OtKlass, i_callback: fn(&mut SharedKlassRepo, &OtMethod, &mut InterpLocalVars) -> Option<JvmValue>) {
-        let klass_name = k.get_name();
-        let m_str: String = klass_name.clone() + ".<clinit>:()V";
-        let clinit = match k.get_method_by_name_and_desc(&m_str) {
-            Some(value) => value.clone(),
-            // FIXME Make this a clean exit
-            None => panic!("Error: Clinit method not found {}", klass_name),
-        };
-        // FIXME Parameter passing
-        let mut vars = InterpLocalVars::of(5);
-        i_callback(self, &clinit, &mut vars);
-    }
-
-    // FIXME This should be changed to read in an ocelot-rt.jar (a cut down full RT)
-    // and add each class one by one before fixing up the native code that we have working
-//  (repo: SharedKlassRepo, meth: &OtMethod, lvt: &mut InterpLocalVars) -> Option<JvmValue>
-    pub fn bootstrap(&mut self, i_callback: fn(&mut SharedKlassRepo, &OtMethod, &mut InterpLocalVars) -> Option<JvmValue>) -> () {
-        // Add java.lang.Object
-        let k_obj = self.parse_bootstrap_class("java/lang/Object".to_string());
-        // let s = format!("{}", self);
-        // dbg!(s);
-
-        // Add j.l.O native methods (e.g. hashCode())
-        k_obj.set_native_method(
-            "java/lang/Object.hashCode:()I".to_string(),
-            crate::native_methods::java_lang_Object__hashcode,
-        );
-        k_obj.set_native_method(
-            "java/lang/Object.registerNatives:()V".to_string(),
-            crate::native_methods::java_lang_Object__registerNatives,
-        );
-        self.add_klass(&k_obj);
-        // FIXME Must reset the value set for the klass repo before clinit
-        self.run_clinit_method(&k_obj, i_callback);
-
-        // FIXME Add primitive arrays
-
-        // FIXME Add java.lang.Class
-
-        // Add wrapper classes
-        let k_jli = self.parse_bootstrap_class("java/lang/Integer".to_string());
-        self.add_klass(&k_jli);
-        // Needs j.l.Class to run (set up primitive type .class object)
-        // self.run_clinit_method(&k_jli, i_callback);
-
-        let k_jlic = self.parse_bootstrap_class("java/lang/Integer$IntegerCache".to_string());
-        self.add_klass(&k_jlic);
-        // Needs j.l.Class and uses sun.* classes to do VM-protected stuff
-        // self.run_clinit_method(&k_jlic, i_callback);
-
-        // FIXME Other classes
-
-        // Add java.lang.String
-        let k_jls = self.parse_bootstrap_class("java/lang/String".to_string());
-        // FIXME String only has intern() as a native method, skip for now
-        self.add_klass(&k_jls);
-
-        // Add java.lang.StringBuilder
-        let k_jlsb = self.parse_bootstrap_class("java/lang/StringBuilder".to_string());
-        self.add_klass(&k_jlsb);
-
-        // FIXME Add class objects for already bootstrapped classes
-
-        // Add java.lang.System
-        let k_sys = self.parse_bootstrap_class("java/lang/System".to_string());
-        k_sys.set_native_method(
-            "java/lang/System.currentTimeMillis:()J".to_string(),
-            crate::native_methods::java_lang_System__currentTimeMillis,
-        );
-        self.add_klass(&k_sys);
-
-        // TODO Dummy up enough of java.io.PrintStream to get System.out.println() to work
-        // By faking up the class so that println(Ljava/lang/Object;) fwds to native code
-        // k_obj = self.parse_bootstrap_class("java/io/PrintStream".to_string());
-        // k_obj.set_native_method(
-        //     "println:(Ljava/lang/Object;)V".to_string(),
-        //     crate::native_methods::java_io_PrintStream__println,
-        // );
-
-        let s = format!("{:?}", self.klass_lookup);
-        dbg!(s);
-    }
-
     pub fn lookup_static_field(&self, klass_name: &String, idx: u16) -> OtField {
         let current_klass = self.lookup_klass(klass_name);
 
@@ -259,17 +266,144 @@ impl SharedKlassRepo {
     }
 
     pub fn put_static(&self, klass_name: String, f: OtField, v: JvmValue) -> () {
-        // FIXME Handle storage properly
+        let cell = match self.klass_lookup.get(&klass_name) {
+            Some(cell) => cell,
+            None => panic!("No klass with ID {} found in repo", klass_name),
+        };
+        match &mut *(cell.borrow_mut()) {
+            KlassLoadingStatus::Mentioned {} => panic!("Klass with ID {} is not loaded yet", klass_name),
+            KlassLoadingStatus::Loaded { klass : k } => k.set_static_field_value(&f, v),
+            KlassLoadingStatus::Initializing { klass : k } => k.set_static_field_value(&f, v),
+            KlassLoadingStatus::Erroneous { klass : k } => k.set_static_field_value(&f, v),
+            KlassLoadingStatus::Live { klass : k } => k.set_static_field_value(&f, v),
+        }
     }
 
-    pub fn lookup_method_exact(&self, klass_name: &String, fq_name_desc: String) -> OtMethod {
+    // Moves a loaded klass to Initializing. Returns false, and changes nothing, if the
+    // klass is already initialized, is part way through (i.e. a re-entrant request) or
+    // failed to initialize
+    pub fn begin_initialization(&self, klass_name: &String) -> bool {
+        let cell = match self.klass_lookup.get(klass_name) {
+            Some(cell) => cell,
+            None => panic!("No loaded klass called {} found in repo", klass_name),
+        };
+        let k = match &*(cell.borrow()) {
+            KlassLoadingStatus::Mentioned {} => panic!("Klass with ID {} is not loaded yet", klass_name),
+            KlassLoadingStatus::Loaded { klass : k } => k.clone(),
+            KlassLoadingStatus::Initializing { klass : _ } => return false,
+            KlassLoadingStatus::Erroneous { klass : _ } => return false,
+            KlassLoadingStatus::Live { klass : _ } => return false,
+        };
+        cell.replace(KlassLoadingStatus::Initializing { klass: k });
+        true
+    }
+
+    // Marks the klass as Live, whether or not its <clinit> was run
+    pub fn set_initialized(&self, klass_name: &String) -> () {
+        let k = self.lookup_klass(klass_name);
+        self.klass_lookup
+            .get(klass_name)
+            .unwrap()
+            .replace(KlassLoadingStatus::Live { klass: k });
+    }
+
+    // Once erroneous, a klass never becomes Live
+    pub fn set_erroneous(&self, klass_name: &String) -> () {
+        let k = self.lookup_klass(klass_name);
+        self.klass_lookup
+            .get(klass_name)
+            .unwrap()
+            .replace(KlassLoadingStatus::Erroneous { klass: k });
+    }
+
+    pub fn is_erroneous(&self, klass_name: &String) -> bool {
+        match self.klass_lookup.get(klass_name) {
+            Some(cell) => matches!(&*(cell.borrow()), KlassLoadingStatus::Erroneous { klass: _ }),
+            None => false,
+        }
+    }
+
+    pub fn is_initialized(&self, klass_name: &String) -> bool {
+        match self.klass_lookup.get(klass_name) {
+            Some(cell) => matches!(&*(cell.borrow()), KlassLoadingStatus::Live { klass: _ }),
+            None => false,
+        }
+    }
+
+    // Is a reference to sub usable where sup is expected? Walks the superclass chain and
+    // every implemented interface. A klass that isn't loaded can't be examined, so it is
+    // only assignable to itself and java/lang/Object
+    pub fn is_assignable_from(&self, sub: &str, sup: &str) -> bool {
+        assignable(&|n| self.try_lookup_klass(n), sub, sup)
+    }
+
+    pub fn try_lookup_method_exact(&self, klass_name: &String, fq_name_desc: String) -> Option<OtMethod> {
         match self.klass_lookup.get(klass_name) {
             Some(cell) => match &*(cell.borrow()) {
-                KlassLoadingStatus::Mentioned {} => panic!("Klass with ID {} is not loaded yet", klass_name),
-                KlassLoadingStatus::Loaded { klass : k } => k.get_method_by_name_and_desc(&fq_name_desc).unwrap().clone(),
-                KlassLoadingStatus::Live { klass : k } => k.get_method_by_name_and_desc(&fq_name_desc).unwrap().clone(),
+                KlassLoadingStatus::Mentioned {} => None,
+                KlassLoadingStatus::Loaded { klass : k } => k.try_get_method_by_name_and_desc(&fq_name_desc).cloned(),
+                KlassLoadingStatus::Initializing { klass : k } => k.try_get_method_by_name_and_desc(&fq_name_desc).cloned(),
+                KlassLoadingStatus::Erroneous { klass : k } => k.try_get_method_by_name_and_desc(&fq_name_desc).cloned(),
+                KlassLoadingStatus::Live { klass : k } => k.try_get_method_by_name_and_desc(&fq_name_desc).cloned(),
             },
-            None => panic!("No klass with ID {} found in repo", klass_name),
+            None => None,
+        }
+    }
+
+    pub fn lookup_method_exact(&self, klass_name: &String, fq_name_desc: String) -> OtMethod {
+        match self.try_lookup_method_exact(klass_name, fq_name_desc.clone()) {
+            Some(m) => m,
+            None => panic!("No method {} found on klass {} in repo", fq_name_desc, klass_name),
+        }
+    }
+
+    // cp_idx is IDX in CP of current class. The first resolution of each call site
+    // is cached, so repeated invokes skip building and hashing the name strings
+    pub fn lookup_method_by_cp(&self, current_klass: &OtKlass, cp_idx: u16) -> Rc<OtMethod> {
+        let key = (current_klass.get_id(), cp_idx);
+        if let Some(m) = self.method_cache.borrow().get(&key) {
+            return Rc::clone(m);
+        }
+
+        let fq_name_desc = current_klass.cp_as_string(cp_idx);
+        let klz_idx = match current_klass.lookup_cp(cp_idx) {
+            CpEntry::methodref { clz_idx, nt_idx: _ } => clz_idx,
+            _ => panic!(
+                "Non-methodref found in {} at CP index {}",
+                current_klass.get_name(),
+                cp_idx
+            ),
+        };
+        let dispatch_klass_name = current_klass.cp_as_string(klz_idx);
+        let callee = Rc::new(self.lookup_method_exact(&dispatch_klass_name, fq_name_desc));
+
+        // Klasses that are not in the repo don't have a unique id to key on
+        if current_klass.get_id() > 0 {
+            self.method_cache.borrow_mut().insert(key, Rc::clone(&callee));
+        }
+        callee
+    }
+
+    pub fn is_call_site_cached(&self, current_klass: &OtKlass, cp_idx: u16) -> bool {
+        self.method_cache
+            .borrow()
+            .contains_key(&(current_klass.get_id(), cp_idx))
+    }
+
+    pub fn cached_method_count(&self) -> usize {
+        self.method_cache.borrow().len()
+    }
+
+    // name_desc is unqualified, e.g. greet:()I. The receiver's klass and its supers
+    // are searched first, then any default method from the interfaces they implement
+    pub fn try_lookup_method_interface(&self, receiver_klass_name: &String, name_desc: &str) -> Option<OtMethod> {
+        method_interface(&|n| self.try_lookup_klass(n), receiver_klass_name, name_desc)
+    }
+
+    pub fn lookup_method_interface(&self, receiver_klass_name: &String, name_desc: &str) -> OtMethod {
+        match self.try_lookup_method_interface(receiver_klass_name, name_desc) {
+            Some(m) => m,
+            None => panic!("No implementation of {} found for klass {} in repo", name_desc, receiver_klass_name),
         }
     }
 
@@ -279,11 +413,32 @@ impl SharedKlassRepo {
             Some(cell) => match &*(cell.borrow()) {
                 KlassLoadingStatus::Mentioned {} => panic!("Klass with ID {} is not loaded yet", klass_name),
                 KlassLoadingStatus::Loaded { klass : k } => k.get_method_by_offset_virtual(m_idx),
+                KlassLoadingStatus::Initializing { klass : k } => k.get_method_by_offset_virtual(m_idx),
+                KlassLoadingStatus::Erroneous { klass : k } => k.get_method_by_offset_virtual(m_idx),
                 KlassLoadingStatus::Live { klass : k } => k.get_method_by_offset_virtual(m_idx),
             }
             None => panic!("No klass with ID {} found in repo", klass_name),
         }
     }
+
+    // Klasses that were only ever mentioned are left out
+    pub fn freeze(self) -> Arc<FrozenRepo> {
+        let klasses = self
+            .klass_lookup
+            .into_iter()
+            .filter_map(|(name, cell)| match cell.into_inner() {
+                KlassLoadingStatus::Mentioned {} => None,
+                KlassLoadingStatus::Loaded { klass: k } => Some((name, k)),
+                KlassLoadingStatus::Initializing { klass: k } => Some((name, k)),
+                KlassLoadingStatus::Erroneous { klass: k } => Some((name, k)),
+                KlassLoadingStatus::Live { klass: k } => Some((name, k)),
+            })
+            .collect();
+        Arc::new(FrozenRepo {
+            klasses,
+            klass_names_by_id: self.klass_names_by_id,
+        })
+    }
 }
 
 impl fmt::Display for SharedKlassRepo {
@@ -300,8 +455,141 @@ impl Clone for SharedKlassRepo {
     fn clone(&self) -> SharedKlassRepo {
         SharedKlassRepo {
             klass_lookup: self.klass_lookup.clone(),
+            method_cache: self.method_cache.clone(),
+            klass_names_by_id: self.klass_names_by_id.clone(),
+            next_klass_id: self.next_klass_id,
+            fold_constants: self.fold_constants,
+        }
+    }
+}
+
+//////////// FROZEN KLASS REPO
+
+// A read-only snapshot of a fully loaded repo. It holds no cells, so one copy can be
+// shared by any number of interpreter threads without locking. Static field values
+// are copied as they stood at freeze time, so statics still belong in the live repo
+#[derive(Debug)]
+pub struct FrozenRepo {
+    klasses: HashMap<String, OtKlass>,
+    klass_names_by_id: HashMap<usize, String>,
+}
+
+impl FrozenRepo {
+    pub fn try_lookup_klass(&self, klass_name: &String) -> Option<OtKlass> {
+        self.klasses.get(klass_name).cloned()
+    }
+
+    pub fn lookup_klass(&self, klass_name: &String) -> OtKlass {
+        match self.try_lookup_klass(klass_name) {
+            Some(k) => k,
+            None => panic!("No loaded klass called {} found in repo", klass_name),
+        }
+    }
+
+    pub fn try_lookup_klass_by_id(&self, klass_id: usize) -> Option<OtKlass> {
+        let klass_name = self.klass_names_by_id.get(&klass_id)?;
+        self.try_lookup_klass(klass_name)
+    }
+
+    pub fn is_assignable_from(&self, sub: &str, sup: &str) -> bool {
+        assignable(&|n| self.try_lookup_klass(n), sub, sup)
+    }
+
+    pub fn try_lookup_method_exact(&self, klass_name: &String, fq_name_desc: String) -> Option<OtMethod> {
+        self.klasses
+            .get(klass_name)?
+            .try_get_method_by_name_and_desc(&fq_name_desc)
+            .cloned()
+    }
+
+    pub fn lookup_method_exact(&self, klass_name: &String, fq_name_desc: String) -> OtMethod {
+        match self.try_lookup_method_exact(klass_name, fq_name_desc.clone()) {
+            Some(m) => m,
+            None => panic!("No method {} found on klass {} in repo", fq_name_desc, klass_name),
+        }
+    }
+
+    // Unlike the live repo there's no call-site cache, as that would need a lock
+    pub fn lookup_method_by_cp(&self, current_klass: &OtKlass, cp_idx: u16) -> OtMethod {
+        let fq_name_desc = current_klass.cp_as_string(cp_idx);
+        let klz_idx = match current_klass.lookup_cp(cp_idx) {
+            CpEntry::methodref { clz_idx, nt_idx: _ } => clz_idx,
+            _ => panic!(
+                "Non-methodref found in {} at CP index {}",
+                current_klass.get_name(),
+                cp_idx
+            ),
+        };
+        let dispatch_klass_name = current_klass.cp_as_string(klz_idx);
+        self.lookup_method_exact(&dispatch_klass_name, fq_name_desc)
+    }
+
+    pub fn try_lookup_method_interface(&self, receiver_klass_name: &String, name_desc: &str) -> Option<OtMethod> {
+        method_interface(&|n| self.try_lookup_klass(n), receiver_klass_name, name_desc)
+    }
+
+    pub fn lookup_method_interface(&self, receiver_klass_name: &String, name_desc: &str) -> OtMethod {
+        match self.try_lookup_method_interface(receiver_klass_name, name_desc) {
+            Some(m) => m,
+            None => panic!("No implementation of {} found for klass {} in repo", name_desc, receiver_klass_name),
+        }
+    }
+}
+
+//////////// LOOKUPS SHARED BY BOTH REPOS
+
+type KlassLookup<'a> = &'a dyn Fn(&String) -> Option<OtKlass>;
+
+fn assignable(lookup: KlassLookup, sub: &str, sup: &str) -> bool {
+    if sub == sup || sup == "java/lang/Object" {
+        return true;
+    }
+    let k = match lookup(&sub.to_string()) {
+        Some(k) => k,
+        None => return false,
+    };
+    for interface_name in k.get_interfaces() {
+        if assignable(lookup, interface_name, sup) {
+            return true;
+        }
+    }
+    // java/lang/Object is its own super
+    let super_name = k.get_super_name();
+    super_name != sub && assignable(lookup, &super_name, sup)
+}
+
+fn method_interface(lookup: KlassLookup, receiver_klass_name: &String, name_desc: &str) -> Option<OtMethod> {
+    let mut interfaces = Vec::new();
+    let mut klass_name = receiver_klass_name.clone();
+    while let Some(k) = lookup(&klass_name) {
+        let fq_name_desc = klass_name.clone() + "." + name_desc;
+        if let Some(m) = k.try_get_method_by_name_and_desc(&fq_name_desc) {
+            if !m.is_abstract() {
+                return Some(m.clone());
+            }
+        }
+        interfaces.extend(k.get_interfaces().iter().cloned());
+        // java/lang/Object is its own super
+        if k.get_super_name() == klass_name {
+            break;
+        }
+        klass_name = k.get_super_name();
+    }
+
+    while let Some(interface_name) = interfaces.pop() {
+        let k = match lookup(&interface_name) {
+            Some(k) => k,
+            None => continue,
+        };
+        let fq_name_desc = interface_name.clone() + "." + name_desc;
+        if let Some(m) = k.try_get_method_by_name_and_desc(&fq_name_desc) {
+            if !m.is_abstract() {
+                return Some(m.clone());
+            }
         }
+        interfaces.extend(k.get_interfaces().iter().cloned());
     }
+    None
 }
 
 /////////////////////////////////////////////////////////////////
@@ -1,19 +1,35 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::sync::{Mutex};
 
 #[macro_use]
 extern crate lazy_static;
 
+pub mod call_site;
+pub mod class_loader;
+#[cfg(any(test, feature = "test_util"))]
+pub mod code_builder;
 pub mod constant_pool;
+pub mod decode;
+pub mod descriptor;
+pub mod disasm;
 pub mod interp_stack;
+pub mod jar_loader;
+pub mod klass_builder;
 pub mod klass_parser;
 pub mod klass_repo;
 pub mod native_methods;
 pub mod object;
+pub mod opcode;
 pub mod otfield;
 pub mod otklass;
 pub mod otmethod;
+pub mod peephole;
+pub mod profile;
+pub mod runtime_error;
 pub mod simple_heap;
+pub mod verifier;
+pub mod vm_context;
 
 use crate::simple_heap::SharedSimpleHeap;
 use object::OtObj;
@@ -21,6 +37,10 @@ use otfield::OtField;
 use otklass::OtKlass;
 use otmethod::OtMethod;
 use klass_repo::SharedKlassRepo;
+use runtime_error::RuntimeError;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 lazy_static! {
     pub static ref HEAP: Mutex<SharedSimpleHeap> = Mutex::new(SharedSimpleHeap::of());
@@ -28,7 +48,35 @@ lazy_static! {
 
 //////////// RUNTIME JVM VALUES
 
-#[derive(Clone, Debug)]
+// A handle to an object on the heap. Id 0 is never allocated, so stands for null
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObjId(usize);
+
+impl ObjId {
+    pub const NULL: ObjId = ObjId(0);
+
+    pub fn of(id: usize) -> ObjId {
+        ObjId(id)
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.0
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == ObjId::NULL
+    }
+}
+
+impl fmt::Display for ObjId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum JvmValue {
     Boolean { val: bool },
     Byte { val: i8 },
@@ -38,7 +86,7 @@ pub enum JvmValue {
     Float { val: f32 },
     Double { val: f64 },
     Char { val: char },
-    ObjRef { val: usize }, // Access objects by id
+    ObjRef { val: ObjId }, // Access objects by id
 }
 
 impl JvmValue {
@@ -55,6 +103,226 @@ impl JvmValue {
             JvmValue::ObjRef { val: _ } => 'A',
         }
     }
+
+    // A tag byte, which is the name() char, followed by the value in little-endian
+    // order. A char takes 4 bytes, as a Rust char may not fit in a Java one, and an
+    // ObjRef is just its id, as 8 bytes
+    pub fn to_tagged_bytes(&self) -> Vec<u8> {
+        let mut out = vec![self.name() as u8];
+        match *self {
+            JvmValue::Boolean { val } => out.push(val as u8),
+            JvmValue::Byte { val } => out.extend_from_slice(&val.to_le_bytes()),
+            JvmValue::Short { val } => out.extend_from_slice(&val.to_le_bytes()),
+            JvmValue::Int { val } => out.extend_from_slice(&val.to_le_bytes()),
+            JvmValue::Long { val } => out.extend_from_slice(&val.to_le_bytes()),
+            JvmValue::Float { val } => out.extend_from_slice(&val.to_bits().to_le_bytes()),
+            JvmValue::Double { val } => out.extend_from_slice(&val.to_bits().to_le_bytes()),
+            JvmValue::Char { val } => out.extend_from_slice(&(val as u32).to_le_bytes()),
+            JvmValue::ObjRef { val } => out.extend_from_slice(&(val.get_id() as u64).to_le_bytes()),
+        }
+        out
+    }
+
+    // Decodes the value at the start of buf, returning it with the number of bytes read
+    pub fn from_tagged_bytes(buf: &[u8]) -> Result<(JvmValue, usize), RuntimeError> {
+        let tag = match buf.first() {
+            Some(tag) => *tag as char,
+            None => return Err(malformed("no tag byte".to_string())),
+        };
+        let len = match tag {
+            'Z' | 'B' => 1,
+            'S' => 2,
+            'I' | 'F' | 'C' => 4,
+            'J' | 'D' | 'A' => 8,
+            _ => return Err(malformed(format!("unknown tag {:?}", tag))),
+        };
+        if buf.len() < 1 + len {
+            return Err(malformed(format!("{} needs {} bytes, found {}", tag, len, buf.len() - 1)));
+        }
+        let mut payload = [0u8; 8];
+        payload[..len].copy_from_slice(&buf[1..1 + len]);
+        let bits = u64::from_le_bytes(payload);
+        let val = match tag {
+            'Z' => match bits {
+                0 | 1 => JvmValue::Boolean { val: bits == 1 },
+                _ => return Err(malformed(format!("{} is not a boolean", bits))),
+            },
+            'B' => JvmValue::Byte { val: bits as i8 },
+            'S' => JvmValue::Short { val: bits as i16 },
+            'I' => JvmValue::Int { val: bits as i32 },
+            'J' => JvmValue::Long { val: bits as i64 },
+            'F' => JvmValue::Float { val: f32::from_bits(bits as u32) },
+            'D' => JvmValue::Double { val: f64::from_bits(bits) },
+            'C' => match std::char::from_u32(bits as u32) {
+                Some(c) => JvmValue::Char { val: c },
+                None => return Err(malformed(format!("{:#x} is not a char", bits))),
+            },
+            _ => JvmValue::ObjRef { val: ObjId::of(bits as usize) },
+        };
+        Ok((val, 1 + len))
+    }
+}
+
+// Booleans, bytes, shorts and chars follow the JVM's own model. On the operand stack
+// and in local vars they are only ever Ints, and they take their own variant only
+// inside a field. A store to a field narrows the Int, which is where any truncation
+// happens, and InterpEvalStack::push() promotes anything narrow straight back to an
+// Int, so the int opcodes never see the other variants
+impl JvmValue {
+    // Only the low bit is kept, as the JVM does for a boolean field or array
+    pub fn narrow_to_boolean(v: i32) -> JvmValue {
+        JvmValue::Boolean { val: v & 1 == 1 }
+    }
+
+    pub fn narrow_to_byte(v: i32) -> JvmValue {
+        JvmValue::Byte { val: v as i8 }
+    }
+
+    pub fn narrow_to_short(v: i32) -> JvmValue {
+        JvmValue::Short { val: v as i16 }
+    }
+
+    // A char is a UTF-16 code unit, so a lone surrogate has no Rust char to map to
+    // and is replaced by U+FFFD
+    pub fn narrow_to_char(v: i32) -> JvmValue {
+        let unit = v as u16 as u32;
+        JvmValue::Char {
+            val: std::char::from_u32(unit).unwrap_or(std::char::REPLACEMENT_CHARACTER),
+        }
+    }
+
+    // The form a value takes on the operand stack. Anything wider than an int is unchanged
+    pub fn promote(self) -> JvmValue {
+        match self {
+            JvmValue::Boolean { val } => JvmValue::Int { val: val as i32 },
+            JvmValue::Byte { val } => JvmValue::Int { val: val as i32 },
+            JvmValue::Short { val } => JvmValue::Int { val: val as i32 },
+            JvmValue::Char { val } => JvmValue::Int { val: val as u32 as u16 as i32 },
+            _ => self,
+        }
+    }
+
+    // Whether the value may be assigned to a slot of type ty, which is the first char
+    // of a field descriptor or A for any reference. Booleans, bytes, shorts, chars
+    // and ints are all one type to the JVM outside of fields, so each conforms to
+    // any of Z, B, S, C and I
+    pub fn conforms_to(&self, ty: char) -> bool {
+        match (self, ty) {
+            (
+                JvmValue::Boolean { val: _ }
+                | JvmValue::Byte { val: _ }
+                | JvmValue::Short { val: _ }
+                | JvmValue::Char { val: _ }
+                | JvmValue::Int { val: _ },
+                'Z' | 'B' | 'S' | 'C' | 'I',
+            ) => true,
+            (JvmValue::Long { val: _ }, 'J') => true,
+            (JvmValue::Float { val: _ }, 'F') => true,
+            (JvmValue::Double { val: _ }, 'D') => true,
+            (JvmValue::ObjRef { val: _ }, 'L' | '[' | 'A') => true,
+            _ => false,
+        }
+    }
+}
+
+//////////// CONVERSIONS
+
+// A Rust bool becomes an Int, as a boolean only takes its own variant inside a field
+impl From<bool> for JvmValue {
+    fn from(v: bool) -> JvmValue {
+        JvmValue::Int { val: v as i32 }
+    }
+}
+
+impl From<i32> for JvmValue {
+    fn from(v: i32) -> JvmValue {
+        JvmValue::Int { val: v }
+    }
+}
+
+impl From<i64> for JvmValue {
+    fn from(v: i64) -> JvmValue {
+        JvmValue::Long { val: v }
+    }
+}
+
+impl From<f32> for JvmValue {
+    fn from(v: f32) -> JvmValue {
+        JvmValue::Float { val: v }
+    }
+}
+
+impl From<f64> for JvmValue {
+    fn from(v: f64) -> JvmValue {
+        JvmValue::Double { val: v }
+    }
+}
+
+fn mismatch(expected: char, found: JvmValue) -> RuntimeError {
+    RuntimeError::ValueTypeMismatch { expected, found }
+}
+
+// An Int holding anything but 0 or 1 isn't a boolean
+impl TryFrom<JvmValue> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(v: JvmValue) -> Result<bool, RuntimeError> {
+        match v {
+            JvmValue::Boolean { val } => Ok(val),
+            JvmValue::Int { val: 0 } => Ok(false),
+            JvmValue::Int { val: 1 } => Ok(true),
+            _ => Err(mismatch('Z', v)),
+        }
+    }
+}
+
+// The narrow types convert too, as they would be promoted on the stack
+impl TryFrom<JvmValue> for i32 {
+    type Error = RuntimeError;
+
+    fn try_from(v: JvmValue) -> Result<i32, RuntimeError> {
+        match v.promote() {
+            JvmValue::Int { val } => Ok(val),
+            v => Err(mismatch('I', v)),
+        }
+    }
+}
+
+impl TryFrom<JvmValue> for i64 {
+    type Error = RuntimeError;
+
+    fn try_from(v: JvmValue) -> Result<i64, RuntimeError> {
+        match v {
+            JvmValue::Long { val } => Ok(val),
+            _ => Err(mismatch('J', v)),
+        }
+    }
+}
+
+impl TryFrom<JvmValue> for f32 {
+    type Error = RuntimeError;
+
+    fn try_from(v: JvmValue) -> Result<f32, RuntimeError> {
+        match v {
+            JvmValue::Float { val } => Ok(val),
+            _ => Err(mismatch('F', v)),
+        }
+    }
+}
+
+impl TryFrom<JvmValue> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(v: JvmValue) -> Result<f64, RuntimeError> {
+        match v {
+            JvmValue::Double { val } => Ok(val),
+            _ => Err(mismatch('D', v)),
+        }
+    }
+}
+
+fn malformed(msg: String) -> RuntimeError {
+    RuntimeError::MalformedValue { msg }
 }
 
 impl fmt::Display for JvmValue {
@@ -68,7 +336,33 @@ impl fmt::Display for JvmValue {
             JvmValue::Float { val: v } => write!(f, "{}", v),
             JvmValue::Double { val: v } => write!(f, "{}", v),
             JvmValue::Char { val: v } => write!(f, "{}", v),
-            JvmValue::ObjRef { val: v } => write!(f, "{}", v.clone()),
+            JvmValue::ObjRef { val: v } if v.is_null() => write!(f, "null"),
+            JvmValue::ObjRef { val: v } => object::fmt_obj(f, v.get_id(), None),
+        }
+    }
+}
+
+impl fmt::Debug for JvmValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.name(), self)
+    }
+}
+
+// Floating point values use IEEE equality (so NaN != NaN) and object
+// references compare by identity, as for the JVM's == operator
+impl PartialEq for JvmValue {
+    fn eq(&self, other: &JvmValue) -> bool {
+        match (self, other) {
+            (JvmValue::Boolean { val: v1 }, JvmValue::Boolean { val: v2 }) => v1 == v2,
+            (JvmValue::Byte { val: v1 }, JvmValue::Byte { val: v2 }) => v1 == v2,
+            (JvmValue::Short { val: v1 }, JvmValue::Short { val: v2 }) => v1 == v2,
+            (JvmValue::Int { val: v1 }, JvmValue::Int { val: v2 }) => v1 == v2,
+            (JvmValue::Long { val: v1 }, JvmValue::Long { val: v2 }) => v1 == v2,
+            (JvmValue::Float { val: v1 }, JvmValue::Float { val: v2 }) => v1 == v2,
+            (JvmValue::Double { val: v1 }, JvmValue::Double { val: v2 }) => v1 == v2,
+            (JvmValue::Char { val: v1 }, JvmValue::Char { val: v2 }) => v1 == v2,
+            (JvmValue::ObjRef { val: v1 }, JvmValue::ObjRef { val: v2 }) => v1 == v2,
+            _ => false,
         }
     }
 }
@@ -79,39 +373,157 @@ impl Default for JvmValue {
     }
 }
 
+fn is_category_2(val: &JvmValue) -> bool {
+    matches!(val, JvmValue::Long { val: _ } | JvmValue::Double { val: _ })
+}
+
 //////////// LOCAL VARS
 
 // Keep this here for now, move to separate file as and when it gets bigger
 
 pub struct InterpLocalVars {
     lvt: Vec<JvmValue>,
+    // Marks the second slot of each long or double, which mustn't be read on its own
+    upper_half: Vec<bool>,
 }
 
 impl InterpLocalVars {
-    pub fn of(var_count: u8) -> InterpLocalVars {
-        let mut out = InterpLocalVars { lvt: Vec::new() };
-        for i in 0..var_count {
-            out.lvt.push(JvmValue::default());
+    // Indices are u16 so that slots past 255 can be reached via WIDE
+    pub fn of(var_count: u16) -> InterpLocalVars {
+        InterpLocalVars::with_capacity(var_count as usize)
+    }
+
+    // Allocates exactly var_count slots, normally the method's max_locals
+    pub fn with_capacity(var_count: usize) -> InterpLocalVars {
+        InterpLocalVars {
+            lvt: vec![JvmValue::default(); var_count],
+            upper_half: vec![false; var_count],
         }
+    }
 
-        out
+    pub fn len(&self) -> usize {
+        self.lvt.len()
+    }
+
+    pub fn load(&self, idx: u16) -> JvmValue {
+        self.try_load(idx).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn store(&mut self, idx: u16, val: JvmValue) -> () {
+        self.try_store(idx, val).unwrap_or_else(|e| panic!("{}", e))
     }
 
-    pub fn load(&self, idx: u8) -> JvmValue {
-        self.lvt[idx as usize].clone()
+    pub fn try_load(&self, idx: u16) -> Result<JvmValue, RuntimeError> {
+        self.check_idx(idx)?;
+        Ok(self.lvt[idx as usize].clone())
+    }
+
+    // Longs and doubles take two slots, idx and idx + 1
+    pub fn try_store(&mut self, idx: u16, val: JvmValue) -> Result<(), RuntimeError> {
+        self.check_idx(idx)?;
+        if is_category_2(&val) {
+            self.check_idx(idx + 1)?;
+            self.upper_half[idx as usize + 1] = true;
+        }
+        self.upper_half[idx as usize] = false;
+        self.lvt[idx as usize] = val;
+        Ok(())
+    }
+
+    // Typed accessors, for the [ailfd]load and [ailfd]store families. An index past
+    // max_locals is an error, but a slot (or value being stored) of the wrong type
+    // can only come from a VM bug, so panics
+
+    pub fn load_int(&self, idx: u16) -> Result<JvmValue, RuntimeError> {
+        self.load_checked(idx, 'I', "int")
+    }
+
+    pub fn load_long(&self, idx: u16) -> Result<JvmValue, RuntimeError> {
+        self.load_checked(idx, 'J', "long")
+    }
+
+    pub fn load_float(&self, idx: u16) -> Result<JvmValue, RuntimeError> {
+        self.load_checked(idx, 'F', "float")
+    }
+
+    pub fn load_double(&self, idx: u16) -> Result<JvmValue, RuntimeError> {
+        self.load_checked(idx, 'D', "double")
+    }
+
+    pub fn load_ref(&self, idx: u16) -> Result<JvmValue, RuntimeError> {
+        self.load_checked(idx, 'A', "reference")
+    }
+
+    pub fn store_int(&mut self, idx: u16, val: JvmValue) -> Result<(), RuntimeError> {
+        self.store_checked(idx, 'I', "int", val)
+    }
+
+    pub fn store_long(&mut self, idx: u16, val: JvmValue) -> Result<(), RuntimeError> {
+        self.store_checked(idx, 'J', "long", val)
+    }
+
+    pub fn store_float(&mut self, idx: u16, val: JvmValue) -> Result<(), RuntimeError> {
+        self.store_checked(idx, 'F', "float", val)
+    }
+
+    pub fn store_double(&mut self, idx: u16, val: JvmValue) -> Result<(), RuntimeError> {
+        self.store_checked(idx, 'D', "double", val)
+    }
+
+    pub fn store_ref(&mut self, idx: u16, val: JvmValue) -> Result<(), RuntimeError> {
+        self.store_checked(idx, 'A', "reference", val)
+    }
+
+    fn load_checked(&self, idx: u16, ty: char, kind: &str) -> Result<JvmValue, RuntimeError> {
+        self.check_idx(idx)?;
+        if self.upper_half[idx as usize] {
+            panic!("Local var {} is the second half of a long or double, expected {}", idx, kind);
+        }
+        let val = &self.lvt[idx as usize];
+        if !val.conforms_to(ty) {
+            panic!("Local var {} holds {:?}, expected {}", idx, val, kind);
+        }
+        Ok(val.clone())
+    }
+
+    fn store_checked(
+        &mut self,
+        idx: u16,
+        ty: char,
+        kind: &str,
+        val: JvmValue,
+    ) -> Result<(), RuntimeError> {
+        if !val.conforms_to(ty) {
+            panic!("Non-{} value {:?} stored as {} in local var {}", kind, val, kind, idx);
+        }
+        self.try_store(idx, val)
+    }
+
+    fn check_idx(&self, idx: u16) -> Result<(), RuntimeError> {
+        if idx as usize >= self.lvt.len() {
+            return Err(RuntimeError::LocalVarOutOfRange {
+                idx,
+                max_locals: self.lvt.len(),
+            });
+        }
+        Ok(())
     }
 
-    pub fn store(&mut self, idx: u8, val: JvmValue) -> () {
-        self.lvt[idx as usize] = val
+    pub fn as_slice(&self) -> &[JvmValue] {
+        &self.lvt
     }
 
-    pub fn iinc(&mut self, idx: u8, incr: u8) -> () {
+    pub fn iinc(&mut self, idx: u16, incr: i16) -> Result<(), RuntimeError> {
+        self.check_idx(idx)?;
         match self.lvt[idx as usize] {
             JvmValue::Int { val: v } => {
-                self.lvt[idx as usize] = JvmValue::Int { val: v + 1 };
+                self.lvt[idx as usize] = JvmValue::Int {
+                    val: v.wrapping_add(incr as i32),
+                };
             }
             _ => panic!("Non-integer value encountered in IINC of local var {}", idx),
         }
+        Ok(())
     }
 }
 
@@ -1,36 +1,256 @@
 use std::time::SystemTime;
 
-use crate::InterpLocalVars;
+use crate::runtime_error::RuntimeError;
+use crate::vm_context::VmContext;
 use crate::JvmValue;
+use crate::ObjId;
+use crate::OtField;
+use crate::OtKlass;
+use crate::HEAP;
 
-pub fn java_lang_Object__hashcode(args: &InterpLocalVars) -> Option<JvmValue> {
-    // FIXME Proper hashCode algorithm
-    Some(JvmValue::Int { val: 255 })
+pub fn java_lang_Object__hashcode(_ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let hash = match args[0] {
+        JvmValue::ObjRef { val: id } => HEAP.lock().unwrap().get_hash_code(id.get_id()),
+        _ => panic!("Non-reference receiver {:?} found for hashCode()", args[0]),
+    };
+    Ok(Some(JvmValue::Int { val: hash }))
+}
+
+pub fn java_lang_Object__getClass(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let klass_name = match args[0] {
+        JvmValue::ObjRef { val: ObjId::NULL } => panic!("Null receiver found for getClass()"),
+        JvmValue::ObjRef { val: id } => match ctx.klass_name_of_obj(id) {
+            Some(name) => name,
+            None => panic!("No klass found for receiver {} of getClass()", id),
+        },
+        _ => panic!("Non-reference receiver {:?} found for getClass()", args[0]),
+    };
+    Ok(Some(JvmValue::ObjRef {
+        val: ctx.get_class_object(&klass_name)?,
+    }))
 }
 
-pub fn java_lang_Object__registerNatives(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_Object__registerNatives(_ctx: &mut VmContext, _args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
     // NO-OP for now - this is needed so <clinit> will run
-    None
+    Ok(None)
 }
 
 
 // FIXME System -> Runtime -> Shutdown
-pub fn java_lang_Shutdown__exit(args: &InterpLocalVars) -> Option<JvmValue> {
-    Some(JvmValue::Int { val: 255 })
+pub fn java_lang_Shutdown__exit(_ctx: &mut VmContext, _args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    Ok(Some(JvmValue::Int { val: 255 }))
 }
 
-pub fn java_lang_System__currentTimeMillis(args: &InterpLocalVars) -> Option<JvmValue> {
+pub fn java_lang_System__currentTimeMillis(_ctx: &mut VmContext, _args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
     let millis = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
         Ok(n) => n.as_millis(),
         Err(_) => panic!("SystemTime before UNIX EPOCH!"),
     };
-    Some(JvmValue::Long { val: millis as i64 })
+    Ok(Some(JvmValue::Long { val: millis as i64 }))
 }
 
-// pub fn java_lang_System__nanoTime(args: &InterpLocalVars) -> Option<JvmValue> {
+// pub fn java_lang_System__nanoTime(_ctx: &mut VmContext, _args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
 //     let millis = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
 //         Ok(n) => n.as_millis(),
 //         Err(_) => panic!("SystemTime before UNIX EPOCH!"),
 //     };
 //     Some(JvmValue::Long { val: millis as i64})
 // }
+
+// Only the VM's own exceptions have a message, as the stubs' constructors don't take one
+pub fn java_lang_Throwable__getMessage(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let id = match args[0] {
+        JvmValue::ObjRef { val: id } => id,
+        _ => panic!("Non-reference receiver {:?} found for getMessage()", args[0]),
+    };
+    let msg = match ctx.get_exception_message(id) {
+        Some(msg) => msg.clone(),
+        None => return Ok(Some(JvmValue::ObjRef { val: ObjId::NULL })),
+    };
+    let k_jls = ctx.lookup_klass(&"java/lang/String".to_string());
+    let s_id = ctx.allocate(&[id], |heap| heap.try_allocate_str(&k_jls, &msg))?;
+    Ok(Some(JvmValue::ObjRef { val: ObjId::of(s_id) }))
+}
+
+// Class names use dots, e.g. java.lang.String, rather than the internal form
+pub fn java_lang_Class__getName(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let klass_name = match args[0] {
+        JvmValue::ObjRef { val: id } => match ctx.klass_name_of_class_object(id) {
+            Some(name) => name.replace('/', "."),
+            None => panic!("Receiver {} of getName() is not a Class object", id),
+        },
+        _ => panic!("Non-reference receiver {:?} found for getName()", args[0]),
+    };
+    let k_jls = ctx.lookup_klass(&"java/lang/String".to_string());
+    let s_id = ctx.allocate(&[], |heap| heap.try_allocate_str(&k_jls, &klass_name))?;
+    Ok(Some(JvmValue::ObjRef { val: ObjId::of(s_id) }))
+}
+
+// args[0] is the PrintStream receiver, which is ignored - all output goes to the VM's writer
+pub fn java_io_PrintStream__println_string(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let s = match args[1] {
+        JvmValue::ObjRef { val: ObjId::NULL } => "null".to_string(),
+        JvmValue::ObjRef { val: id } => HEAP.lock().unwrap().get_obj(id.get_id()).get_str_value(),
+        _ => panic!("Non-reference value {:?} passed to println(String)", args[1]),
+    };
+    writeln!(ctx.get_out(), "{}", s).expect("Error: println failed to write");
+    Ok(None)
+}
+
+pub fn java_io_PrintStream__println_int(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let i = match args[1] {
+        JvmValue::Int { val: i } => i,
+        _ => panic!("Non-int value {:?} passed to println(int)", args[1]),
+    };
+    writeln!(ctx.get_out(), "{}", i).expect("Error: println failed to write");
+    Ok(None)
+}
+
+//////////// BOXING
+
+// Wraps v in a new instance of klass_name, in the value field every wrapper has
+fn box_value(ctx: &mut VmContext, klass_name: &str, v: JvmValue) -> Result<ObjId, RuntimeError> {
+    let k = ctx.lookup_klass(&klass_name.to_string());
+    let value_f = value_field(&k);
+    let obj_id = ctx.allocate(&[], |heap| heap.try_allocate_obj(&k))?;
+    HEAP.lock().unwrap().put_field(obj_id, k.get_instance_field_offset(&value_f), v);
+    Ok(ObjId::of(obj_id))
+}
+
+// As valueOf() does, handing out the same box each time for a value in the cached range
+fn box_cached(ctx: &mut VmContext, klass_name: &str, ty: char, key: i64, v: JvmValue) -> Result<ObjId, RuntimeError> {
+    if let Some(boxed) = ctx.get_cached_box(ty, key) {
+        return Ok(boxed);
+    }
+    let boxed = box_value(ctx, klass_name, v)?;
+    ctx.cache_box(ty, key, boxed);
+    Ok(boxed)
+}
+
+fn value_field(k: &OtKlass) -> OtField {
+    match k.fields_iter().find(|f| !f.is_static() && f.get_name() == "value") {
+        Some(f) => f.clone(),
+        None => panic!("Error: no value field found on {}", k.get_name()),
+    }
+}
+
+// Integer.valueOf(int), which caches -128 to 127 as Java does
+pub fn java_lang_Integer__valueOf(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let i = match args[0] {
+        JvmValue::Int { val: i } => i,
+        _ => panic!("Non-int value {:?} passed to Integer.valueOf(int)", args[0]),
+    };
+    let boxed = match i {
+        -128..=127 => box_cached(ctx, "java/lang/Integer", 'I', i as i64, args[0].clone())?,
+        _ => box_value(ctx, "java/lang/Integer", args[0].clone())?,
+    };
+    Ok(Some(JvmValue::ObjRef { val: boxed }))
+}
+
+// Long.valueOf(long), which caches the same range as Integer
+pub fn java_lang_Long__valueOf(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let l = match args[0] {
+        JvmValue::Long { val: l } => l,
+        _ => panic!("Non-long value {:?} passed to Long.valueOf(long)", args[0]),
+    };
+    let boxed = match l {
+        -128..=127 => box_cached(ctx, "java/lang/Long", 'J', l, args[0].clone())?,
+        _ => box_value(ctx, "java/lang/Long", args[0].clone())?,
+    };
+    Ok(Some(JvmValue::ObjRef { val: boxed }))
+}
+
+// Double.valueOf(double), which never caches
+pub fn java_lang_Double__valueOf(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    if !args[0].conforms_to('D') {
+        panic!("Non-double value {:?} passed to Double.valueOf(double)", args[0]);
+    }
+    let boxed = box_value(ctx, "java/lang/Double", args[0].clone())?;
+    Ok(Some(JvmValue::ObjRef { val: boxed }))
+}
+
+// Boolean.valueOf(boolean), which only ever hands out Boolean.TRUE or Boolean.FALSE
+pub fn java_lang_Boolean__valueOf(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let b = match args[0] {
+        JvmValue::Int { val: i } => i & 1,
+        _ => panic!("Non-boolean value {:?} passed to Boolean.valueOf(boolean)", args[0]),
+    };
+    let boxed = box_cached(ctx, "java/lang/Boolean", 'Z', b as i64, JvmValue::narrow_to_boolean(b))?;
+    Ok(Some(JvmValue::ObjRef { val: boxed }))
+}
+
+// intValue(), longValue(), doubleValue() and booleanValue(), which all just read the
+// receiver's value field
+pub fn unbox(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let obj_id = match args[0] {
+        JvmValue::ObjRef { val: ObjId::NULL } => panic!("Null receiver found for unboxing"),
+        JvmValue::ObjRef { val: id } => id,
+        _ => panic!("Non-reference receiver {:?} found for unboxing", args[0]),
+    };
+    let klass_name = match ctx.klass_name_of_obj(obj_id) {
+        Some(name) => name,
+        None => panic!("No klass found for receiver {} of unboxing", obj_id),
+    };
+    let k = ctx.lookup_klass(&klass_name);
+    let offset = k.get_instance_field_offset(&value_field(&k));
+    Ok(Some(HEAP.lock().unwrap().get_field(obj_id.get_id(), offset).promote()))
+}
+
+//////////// STRINGBUILDER
+
+fn receiver_id(args: &[JvmValue], meth: &str) -> usize {
+    match args[0] {
+        JvmValue::ObjRef { val: ObjId::NULL } => panic!("Null receiver found for {}", meth),
+        JvmValue::ObjRef { val: id } => id.get_id(),
+        _ => panic!("Non-reference receiver {:?} found for {}", args[0], meth),
+    }
+}
+
+// A null String reads as "null", as it does when appended
+fn str_arg(v: &JvmValue, meth: &str) -> String {
+    match v {
+        JvmValue::ObjRef { val: ObjId::NULL } => "null".to_string(),
+        JvmValue::ObjRef { val: id } => HEAP.lock().unwrap().get_obj(id.get_id()).get_str_value(),
+        _ => panic!("Non-reference value {:?} passed to {}", v, meth),
+    }
+}
+
+pub fn java_lang_StringBuilder__init(_ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let id = receiver_id(args, "StringBuilder()");
+    HEAP.lock().unwrap().put_str_value(id, String::new());
+    Ok(None)
+}
+
+pub fn java_lang_StringBuilder__init_string(_ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let id = receiver_id(args, "StringBuilder(String)");
+    let s = str_arg(&args[1], "StringBuilder(String)");
+    HEAP.lock().unwrap().put_str_value(id, s);
+    Ok(None)
+}
+
+pub fn java_lang_StringBuilder__append_string(_ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let id = receiver_id(args, "append(String)");
+    let s = str_arg(&args[1], "append(String)");
+    HEAP.lock().unwrap().append_str(id, &s);
+    Ok(Some(args[0].clone()))
+}
+
+pub fn java_lang_StringBuilder__append_int(_ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let id = receiver_id(args, "append(int)");
+    let i = match args[1] {
+        JvmValue::Int { val: i } => i,
+        _ => panic!("Non-int value {:?} passed to append(int)", args[1]),
+    };
+    HEAP.lock().unwrap().append_str(id, &i.to_string());
+    Ok(Some(args[0].clone()))
+}
+
+// Always a new String, never an interned one
+pub fn java_lang_StringBuilder__toString(ctx: &mut VmContext, args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    let id = receiver_id(args, "toString()");
+    let k_jls = ctx.lookup_klass(&"java/lang/String".to_string());
+    let value = HEAP.lock().unwrap().get_obj(id).get_str_value();
+    // The receiver has to survive a collection, in case nothing else refers to it
+    let s_id = ctx.allocate(&[ObjId::of(id)], |heap| heap.try_allocate_str(&k_jls, &value))?;
+    Ok(Some(JvmValue::ObjRef { val: ObjId::of(s_id) }))
+}
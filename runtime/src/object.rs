@@ -1,9 +1,19 @@
 use std::fmt;
 use std::sync::Mutex;
 
+use crate::klass_repo::SharedKlassRepo;
 use crate::JvmValue;
+use crate::ObjId;
 use crate::OtField;
 
+// Mark word layout, from the least significant bit:
+//   bits 0-15   monitor recursion count
+//   bits 16-46  identity hash code, 0 until first requested
+//   bits 47-63  unused
+pub const MARK_LOCK_COUNT_BITS: u64 = 0xffff;
+pub const MARK_HASH_SHIFT: u64 = 16;
+pub const MARK_HASH_BITS: u64 = 0x7fff_ffff << MARK_HASH_SHIFT;
+
 #[derive(Debug)]
 pub enum OtObj {
     vm_obj {
@@ -26,6 +36,30 @@ pub enum OtObj {
         length: i32,
         elements: Vec<i64>,
     },
+    // byte[] and boolean[] share baload and bastore, so boolean says which this is
+    vm_arr_byte {
+        id: usize,
+        mark: u64,
+        klassid: usize,
+        length: i32,
+        elements: Vec<i8>,
+        boolean: bool,
+    },
+    // Reference arrays hold the ids of their elements, 0 for null
+    vm_arr_obj {
+        id: usize,
+        mark: u64,
+        klassid: usize,
+        length: i32,
+        elements: Vec<usize>,
+    },
+    // FIXME Strings are held natively until char[] is supported
+    vm_str {
+        id: usize,
+        mark: u64,
+        klassid: usize,
+        value: String,
+    },
 }
 
 impl OtObj {
@@ -51,6 +85,36 @@ impl OtObj {
         }
     }
 
+    pub fn byte_arr_of(size: i32, obj_id: usize, boolean: bool) -> OtObj {
+        OtObj::vm_arr_byte {
+            id: obj_id,
+            mark: 0u64,
+            klassid: 2, // FIXME As for int_arr_of()
+            length: size,
+            elements: vec![0; size as usize],
+            boolean,
+        }
+    }
+
+    pub fn obj_arr_of(klass_id: usize, obj_id: usize, elements: Vec<usize>) -> OtObj {
+        OtObj::vm_arr_obj {
+            id: obj_id,
+            mark: 0u64,
+            klassid: klass_id,
+            length: elements.len() as i32,
+            elements,
+        }
+    }
+
+    pub fn str_of(klass_id: usize, obj_id: usize, value: &str) -> OtObj {
+        OtObj::vm_str {
+            id: obj_id,
+            mark: 0u64,
+            klassid: klass_id,
+            value: value.to_string(),
+        }
+    }
+
     pub fn put_field(&self, offset : usize, val: JvmValue) -> () {
         let (kid, fields) = match self {
             OtObj::vm_obj {
@@ -142,6 +206,27 @@ impl OtObj {
                 length: _,
                 elements: _,
             } => i,
+            OtObj::vm_arr_byte {
+                id: i,
+                mark: _,
+                klassid: _,
+                length: _,
+                elements: _,
+                boolean: _,
+            } => i,
+            OtObj::vm_arr_obj {
+                id: i,
+                mark: _,
+                klassid: _,
+                length: _,
+                elements: _,
+            } => i,
+            OtObj::vm_str {
+                id: i,
+                mark: _,
+                klassid: _,
+                value: _,
+            } => i,
         }
     }
 
@@ -167,9 +252,112 @@ impl OtObj {
                 length: _,
                 elements: _,
             } => m,
+            OtObj::vm_arr_byte {
+                id: _,
+                mark: m,
+                klassid: _,
+                length: _,
+                elements: _,
+                boolean: _,
+            } => m,
+            OtObj::vm_arr_obj {
+                id: _,
+                mark: m,
+                klassid: _,
+                length: _,
+                elements: _,
+            } => m,
+            OtObj::vm_str {
+                id: _,
+                mark: m,
+                klassid: _,
+                value: _,
+            } => m,
         }
     }
 
+    pub fn set_mark(&mut self, new_mark: u64) -> () {
+        match self {
+            OtObj::vm_obj {
+                id: _,
+                mark: m,
+                klassid: _,
+                fields: _,
+            } => *m = new_mark,
+            OtObj::vm_arr_int {
+                id: _,
+                mark: m,
+                klassid: _,
+                length: _,
+                elements: _,
+            } => *m = new_mark,
+            OtObj::vm_arr_long {
+                id: _,
+                mark: m,
+                klassid: _,
+                length: _,
+                elements: _,
+            } => *m = new_mark,
+            OtObj::vm_arr_byte {
+                id: _,
+                mark: m,
+                klassid: _,
+                length: _,
+                elements: _,
+                boolean: _,
+            } => *m = new_mark,
+            OtObj::vm_arr_obj {
+                id: _,
+                mark: m,
+                klassid: _,
+                length: _,
+                elements: _,
+            } => *m = new_mark,
+            OtObj::vm_str {
+                id: _,
+                mark: m,
+                klassid: _,
+                value: _,
+            } => *m = new_mark,
+        }
+    }
+
+    pub fn get_lock_count(&self) -> u64 {
+        self.get_mark() & MARK_LOCK_COUNT_BITS
+    }
+
+    // The identity hash is computed on first use and then kept in the mark word,
+    // so it is stable for the lifetime of the object
+    pub fn get_hash_code(&mut self) -> i32 {
+        let hash = self.peek_hash_code();
+        let mark = (self.get_mark() & !MARK_HASH_BITS) | ((hash as u64) << MARK_HASH_SHIFT);
+        self.set_mark(mark);
+        hash
+    }
+
+    // The hash get_hash_code() returns, without recording it in the mark word
+    fn peek_hash_code(&self) -> i32 {
+        let mut hash = (self.get_mark() & MARK_HASH_BITS) >> MARK_HASH_SHIFT;
+        if hash == 0 {
+            // Scramble the id so that neighbouring objects get unrelated hashes
+            hash = ((self.get_id() as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 33) & 0x7fff_ffff;
+            if hash == 0 {
+                hash = 1;
+            }
+        }
+        hash as i32
+    }
+
+    // As Object.toString() would have it, e.g. java.lang.String@1b6d3586. The Display
+    // impl is for when there's no repo to hand to turn the klass id into a name
+    pub fn describe(&self, repo: &SharedKlassRepo) -> String {
+        let klass_name = match repo.try_lookup_klass_by_id(self.get_klassid()) {
+            Some(k) => k.get_name().replace('/', "."),
+            None => format!("<klass {}>", self.get_klassid()),
+        };
+        format!("{}@{:x}", klass_name, self.peek_hash_code())
+    }
+
     pub fn get_klassid(&self) -> usize {
         match *self {
             OtObj::vm_obj {
@@ -192,6 +380,27 @@ impl OtObj {
                 length: _,
                 elements: _,
             } => k,
+            OtObj::vm_arr_byte {
+                id: _,
+                mark: _,
+                klassid: k,
+                length: _,
+                elements: _,
+                boolean: _,
+            } => k,
+            OtObj::vm_arr_obj {
+                id: _,
+                mark: _,
+                klassid: k,
+                length: _,
+                elements: _,
+            } => k,
+            OtObj::vm_str {
+                id: _,
+                mark: _,
+                klassid: k,
+                value: _,
+            } => k,
         }
     }
 
@@ -217,17 +426,73 @@ impl OtObj {
                 length: l,
                 elements: _,
             } => l,
+            OtObj::vm_arr_byte {
+                id: _,
+                mark: _,
+                klassid: _,
+                length: l,
+                elements: _,
+                boolean: _,
+            } => l,
+            OtObj::vm_arr_obj {
+                id: _,
+                mark: _,
+                klassid: _,
+                length: l,
+                elements: _,
+            } => l,
+            OtObj::vm_str {
+                id: _,
+                mark: _,
+                klassid: _,
+                value: ref v,
+            } => v.chars().count() as i32,
         }
     }
+
+    pub fn get_str_value(&self) -> String {
+        match self {
+            OtObj::vm_str {
+                id: _,
+                mark: _,
+                klassid: _,
+                value: v,
+            } => v.clone(),
+            _ => panic!("Attempted to read the string value of a non-string object!"),
+        }
+    }
+
+    // The non-null objects this one refers to, which the collector must keep alive
+    pub fn references(&self) -> Vec<ObjId> {
+        match self {
+            OtObj::vm_obj { fields: fs, .. } => fs
+                .iter()
+                .filter_map(|f| match *f.lock().unwrap() {
+                    JvmValue::ObjRef { val } if !val.is_null() => Some(val),
+                    _ => None,
+                })
+                .collect(),
+            OtObj::vm_arr_obj { elements: elts, .. } => elts
+                .iter()
+                .filter(|e| **e != 0)
+                .map(|e| ObjId::of(*e))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Objects and references to them print alike, so the two can be matched up in traces.
+// A reference alone can't say more than the id without going to the heap
+pub(crate) fn fmt_obj(f: &mut fmt::Formatter, id: usize, mark_and_klass: Option<(u64, usize)>) -> fmt::Result {
+    match mark_and_klass {
+        Some((mark, klass)) => write!(f, "OtObj{{id={}, mark={:#x}, klass={}}}", id, mark, klass),
+        None => write!(f, "OtObj{{id={}}}", id),
+    }
 }
 
 impl fmt::Display for OtObj {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "MarK: {} ; Klass: {}",
-            self.get_mark(),
-            self.get_klassid()
-        )
+        fmt_obj(f, self.get_id(), Some((self.get_mark(), self.get_klassid())))
     }
 }
@@ -2,9 +2,14 @@ use std::fmt;
 
 use crate::constant_pool::CpAttr;
 use crate::constant_pool::ACC_STATIC;
+use crate::descriptor::{default_value_for_descriptor, narrow_for_descriptor};
 use crate::JvmValue;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OtField {
     // We store the klass_name rather than the klass's id because when
     // the OtField is created, it is too early - the klass doesn't have
@@ -65,17 +70,12 @@ impl OtField {
     }
 
     pub fn get_default(&self) -> JvmValue {
-        match self.desc.as_str() {
-            "Z" => JvmValue::Boolean { val: false },
-            "B" => JvmValue::Byte { val: 0 },
-            "S" => JvmValue::Short { val: 0 },
-            "C" => JvmValue::Char { val: '\0' },
-            "I" => JvmValue::Int { val: 0i32 },
-            "J" => JvmValue::Long { val: 0i64 },
-            "F" => JvmValue::Float { val: 0.0 },
-            "D" => JvmValue::Double { val: 0.0 },
-            _ => JvmValue::ObjRef { val: 0 },
-        }
+        default_value_for_descriptor(&self.desc)
+    }
+
+    // The value a store of v leaves in this field
+    pub fn narrow(&self, v: JvmValue) -> JvmValue {
+        narrow_for_descriptor(&self.desc, v)
     }
 }
 
@@ -1,22 +1,30 @@
-use std::cell::Cell;
 use std::collections::HashMap;
 use std::fmt;
 use std::sync::Mutex;
 
-use crate::constant_pool::CpEntry;
+use crate::constant_pool::{BootstrapMethod, CpEntry};
+use crate::constant_pool::decode_class_flags;
+use crate::constant_pool::{ACC_ABSTRACT, ACC_INTERFACE};
 use crate::otfield::OtField;
 use crate::otmethod::OtMethod;
-use crate::InterpLocalVars;
 use crate::JvmValue;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 //////////// RUNTIME KLASS AND RELATED HANDLING
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OtKlass {
-    id: Cell<usize>,
+    id: usize,
     name: String,
     super_name: String,
+    interfaces: Vec<String>,
     flags: u16,
+    // Both 0 for a klass that didn't come from a class file
+    major_version: u16,
+    minor_version: u16,
     cp_entries: Vec<CpEntry>,
     methods: Vec<OtMethod>,
     i_fields: Vec<OtField>,
@@ -24,12 +32,14 @@ pub struct OtKlass {
     s_field_vals: Vec<JvmValue>,
     m_name_desc_lookup: HashMap<String, usize>,
     f_name_desc_lookup: HashMap<String, usize>,
+    bootstrap_methods: Vec<BootstrapMethod>,
 }
 
 impl OtKlass {
     pub fn of(
         klass_name: String,
         super_klass: String,
+        interfaces: &Vec<String>,
         flags: u16,
         cp_entries: &Vec<CpEntry>,
         methods: &Vec<OtMethod>,
@@ -38,11 +48,11 @@ impl OtKlass {
         let mut m_lookup = HashMap::new();
         let mut i = 0;
         while i < methods.len() {
-            let meth = match methods.get(i).clone() {
-                Some(val) => val.clone(),
+            let meth = match methods.get(i) {
+                Some(val) => val,
                 None => panic!("Error: method {} not found on {}", i, klass_name),
             };
-            m_lookup.insert(meth.get_fq_name_desc().clone(), i);
+            m_lookup.insert(meth.get_fq_name_desc(), i);
             i = i + 1;
         }
         i = 0;
@@ -55,29 +65,33 @@ impl OtKlass {
                 None => panic!("Error: field {} not found on {}", i, klass_name),
             };
             let f_name = f.get_fq_name_desc();
+            // Indexes into whichever of the static or instance fields holds f
             if f.is_static() {
+                f_lookup.insert(f_name, s_fields.len());
                 s_fields.push(f);
             } else {
+                f_lookup.insert(f_name, i_fields.len());
                 i_fields.push(f);
             }
-            f_lookup.insert(f_name, i);
             i = i + 1;
         }
-        // dbg!(m_lookup.clone());
-        // dbg!(f_lookup.clone());
         OtKlass {
-            id: Cell::new(0), // This indicates that the class has not yet been loaded into a repo
+            id: 0, // This indicates that the class has not yet been loaded into a repo
             name: klass_name,
             super_name: super_klass,
+            interfaces: interfaces.to_vec(),
             flags: flags,
+            major_version: 0,
+            minor_version: 0,
             cp_entries: cp_entries.to_vec(),
             methods: methods.to_vec(),
             i_fields: i_fields.to_vec(),
-            s_fields: s_fields.to_vec(),
-            s_field_vals: Vec::new(),
+            s_field_vals: s_fields.iter().map(|f| f.get_default()).collect(),
+            s_fields,
             // FIXME
             m_name_desc_lookup: m_lookup,
             f_name_desc_lookup: f_lookup,
+            bootstrap_methods: Vec::new(),
         }
     }
 
@@ -94,41 +108,81 @@ impl OtKlass {
         out
     }
 
-    pub fn set_id(&self, new_id: usize) -> () {
-        self.id.set(new_id)
+    pub fn set_id(&mut self, new_id: usize) -> () {
+        self.id = new_id;
     }
 
     pub fn get_id(&self) -> usize {
-        self.id.get()
+        self.id
     }
 
     pub fn get_name(&self) -> String {
         self.name.to_owned()
     }
 
+    pub fn set_version(&mut self, major: u16, minor: u16) -> () {
+        self.major_version = major;
+        self.minor_version = minor;
+    }
+
+    pub fn set_bootstrap_methods(&mut self, bootstrap_methods: Vec<BootstrapMethod>) -> () {
+        self.bootstrap_methods = bootstrap_methods;
+    }
+
+    // idx is the bsm_idx of an invoke_dynamic entry
+    pub fn get_bootstrap_method(&self, idx: u16) -> Option<&BootstrapMethod> {
+        self.bootstrap_methods.get(idx as usize)
+    }
+
+    pub fn get_major_version(&self) -> u16 {
+        self.major_version
+    }
+
+    pub fn get_minor_version(&self) -> u16 {
+        self.minor_version
+    }
+
+    pub fn get_flags(&self) -> u16 {
+        self.flags
+    }
+
+    pub fn is_interface(&self) -> bool {
+        self.flags & ACC_INTERFACE == ACC_INTERFACE
+    }
+
+    // Interfaces are always abstract too
+    pub fn is_abstract(&self) -> bool {
+        self.flags & ACC_ABSTRACT == ACC_ABSTRACT
+    }
+
     pub fn get_super_name(&self) -> String {
         self.super_name.to_owned()
     }
 
+    pub fn get_interfaces(&self) -> &Vec<String> {
+        &self.interfaces
+    }
+
     pub fn get_methods(&self) -> Vec<OtMethod> {
         self.methods.clone()
     }
 
-    pub fn set_native_method(
-        &self,
-        name_desc: String,
-        n_code: fn(&InterpLocalVars) -> Option<JvmValue>,
-    ) {
-        // dbg!("Setting native code");
-        // dbg!(name_desc.clone());
-        match self.get_method_by_name_and_desc(&name_desc) {
-            Some(m2) => m2.set_native_code(n_code),
-            None => {
-                panic!("Should be unreachable - trying to store native code in a regular method")
-            }
+    pub fn fold_constants(&mut self) -> () {
+        for m in self.methods.iter_mut() {
+            m.fold_constants();
         }
     }
 
+    // Read-only traversal, without the copy that get_methods() makes
+    pub fn methods_iter(&self) -> impl Iterator<Item = &OtMethod> {
+        self.methods.iter()
+    }
+
+    // Instance fields first, then statics
+    pub fn fields_iter(&self) -> impl Iterator<Item = &OtField> {
+        self.i_fields.iter().chain(self.s_fields.iter())
+    }
+
     pub fn get_mentioned_klasses(&self) -> Vec<String> {
         let mut i = 0;
         let mut out = Vec::new();
@@ -182,6 +236,16 @@ impl OtKlass {
         self.s_field_vals.get(idx).unwrap()
     }
 
+    // In the same order as the static fields
+    pub fn get_static_field_values(&self) -> &[JvmValue] {
+        &self.s_field_vals
+    }
+
+    pub fn set_static_field_value(&mut self, f: &OtField, v: JvmValue) -> () {
+        let idx = self.get_static_field_offset(f);
+        self.s_field_vals[idx] = v;
+    }
+
     pub fn get_method_by_offset_virtual(&self, m_idx: u16) -> OtMethod {
         // If present, return value at specific offset
         // let offset = self.get_method_offset(f);
@@ -200,9 +264,7 @@ impl OtKlass {
     }
 
     // NOTE: This is fully-qualified
-    pub fn get_method_by_name_and_desc(&self, name_desc: &String) -> Option<&OtMethod> {
-        // dbg!(&self.m_name_desc_lookup);
-        dbg!(&name_desc);
+    pub fn try_get_method_by_name_and_desc(&self, name_desc: &String) -> Option<&OtMethod> {
         let opt_idx = self.m_name_desc_lookup.get(name_desc);
         let idx: usize = match opt_idx {
             Some(value) => value.clone(),
@@ -211,31 +273,34 @@ impl OtKlass {
         self.methods.get(idx)
     }
 
+    // Already non-panicking, so this is simply an alias of the try_ form
+    pub fn get_method_by_name_and_desc(&self, name_desc: &String) -> Option<&OtMethod> {
+        self.try_get_method_by_name_and_desc(name_desc)
+    }
+
     // NOTE: This is fully-qualified
     pub fn get_static_field_by_name_and_desc(&self, name_desc: &String) -> Option<&OtField> {
-        // dbg!(&self.f_name_desc_lookup);
-        dbg!(&name_desc);
         let opt_idx = self.f_name_desc_lookup.get(name_desc);
         let idx: usize = match opt_idx {
             Some(value) => value.clone(),
             None => return None,
         };
-        self.s_fields.get(idx)
+        self.s_fields.get(idx).filter(|f| f.get_fq_name_desc() == *name_desc)
     }
 
     // NOTE: This is fully-qualified
     pub fn get_instance_field_by_name_and_desc(&self, name_desc: &String) -> Option<&OtField> {
-        // dbg!(&self.f_name_desc_lookup);
-        dbg!(&name_desc);
         let opt_idx = self.f_name_desc_lookup.get(name_desc);
         let idx: usize = match opt_idx {
             Some(value) => value.clone(),
             None => return None,
         };
-        self.i_fields.get(idx)
+        self.i_fields.get(idx).filter(|f| f.get_fq_name_desc() == *name_desc)
     }
 
+    // CP indices are 1-based, slot 0 is never a valid reference
     pub fn lookup_cp(&self, cp_idx: u16) -> CpEntry {
+        assert!(cp_idx != 0, "Error: CP index 0 looked up on {}", self.name);
         let idx = cp_idx as usize;
         match self.cp_entries.get(idx).clone() {
             Some(val) => val.clone(),
@@ -246,20 +311,104 @@ impl OtKlass {
         }
     }
 
+    // One more than the highest CP index, as constant_pool_count in the class file
+    pub fn cp_len(&self) -> u16 {
+        self.cp_entries.len() as u16
+    }
+
+    // None for index 0, the slot after a long or double, or anything out of range
+    pub fn cp_entry_at(&self, cp_idx: u16) -> Option<&CpEntry> {
+        match self.cp_entries.get(cp_idx as usize) {
+            Some(CpEntry::unusable {}) | None => None,
+            Some(entry) => Some(entry),
+        }
+    }
+
+    // Every usable entry with its CP index, in index order
+    pub fn cp_iter(&self) -> impl Iterator<Item = (u16, &CpEntry)> {
+        (1..self.cp_len()).filter_map(move |i| self.cp_entry_at(i).map(|e| (i, e)))
+    }
+
+    // The (klass, name, desc) that a methodref or interface methodref names
+    pub fn resolve_methodref(&self, cp_idx: u16) -> Result<(String, String, String), String> {
+        match self.cp_entry_at(cp_idx) {
+            Some(CpEntry::methodref { clz_idx, nt_idx })
+            | Some(CpEntry::interface_methodref { clz_idx, nt_idx }) => {
+                self.resolve_member(*clz_idx, *nt_idx)
+            }
+            e => Err(format!(
+                "Expected a methodref on {} at CP index {}, found {:?}",
+                self.name, cp_idx, e
+            )),
+        }
+    }
+
+    // The (klass, name, desc) that a fieldref names
+    pub fn resolve_fieldref(&self, cp_idx: u16) -> Result<(String, String, String), String> {
+        match self.cp_entry_at(cp_idx) {
+            Some(CpEntry::fieldref { clz_idx, nt_idx }) => self.resolve_member(*clz_idx, *nt_idx),
+            e => Err(format!(
+                "Expected a fieldref on {} at CP index {}, found {:?}",
+                self.name, cp_idx, e
+            )),
+        }
+    }
+
+    fn resolve_member(&self, clz_idx: u16, nt_idx: u16) -> Result<(String, String, String), String> {
+        let klass_name = match self.cp_entry_at(clz_idx) {
+            Some(CpEntry::class { idx }) => self.resolve_utf8(*idx)?,
+            e => {
+                return Err(format!(
+                    "Expected a class on {} at CP index {}, found {:?}",
+                    self.name, clz_idx, e
+                ))
+            }
+        };
+        match self.cp_entry_at(nt_idx) {
+            Some(CpEntry::name_and_type { name_idx, type_idx }) => Ok((
+                klass_name,
+                self.resolve_utf8(*name_idx)?,
+                self.resolve_utf8(*type_idx)?,
+            )),
+            e => Err(format!(
+                "Expected a name_and_type on {} at CP index {}, found {:?}",
+                self.name, nt_idx, e
+            )),
+        }
+    }
+
+    fn resolve_utf8(&self, cp_idx: u16) -> Result<String, String> {
+        match self.cp_entry_at(cp_idx) {
+            Some(CpEntry::utf8 { val }) => Ok(val.clone()),
+            e => Err(format!(
+                "Expected a utf8 on {} at CP index {}, found {:?}",
+                self.name, cp_idx, e
+            )),
+        }
+    }
+
     pub fn cp_as_string(&self, i: u16) -> String {
         match self.lookup_cp(i) {
             CpEntry::utf8 { val: s } => s,
             CpEntry::class { idx: utf_idx } => self.cp_as_string(utf_idx),
+            CpEntry::string { idx: utf_idx } => self.cp_as_string(utf_idx),
             CpEntry::fieldref { clz_idx, nt_idx } => {
                 self.cp_as_string(clz_idx) + "." + &self.cp_as_string(nt_idx)
             }
             CpEntry::methodref { clz_idx, nt_idx } => {
                 self.cp_as_string(clz_idx) + "." + &self.cp_as_string(nt_idx)
             }
+            CpEntry::interface_methodref { clz_idx, nt_idx } => {
+                self.cp_as_string(clz_idx) + "." + &self.cp_as_string(nt_idx)
+            }
             CpEntry::name_and_type {
                 name_idx: nidx,
                 type_idx: tidx,
             } => self.cp_as_string(nidx) + ":" + &self.cp_as_string(tidx),
+            CpEntry::method_handle { kind: _, ref_idx } => self.cp_as_string(ref_idx),
+            CpEntry::method_type { desc_idx } => self.cp_as_string(desc_idx),
+            // Just the name and type, as there's no klass until the call site is linked
+            CpEntry::invoke_dynamic { bsm_idx: _, nt_idx } => self.cp_as_string(nt_idx),
             _ => panic!(
                 "Unimplemented stringify of CP entry found in {} at index {}",
                 self.name, i
@@ -271,12 +420,23 @@ impl OtKlass {
 // flags: u16,
 // cp_entries: Vec<CpEntry>,
 // name_desc_lookup: HashMap<String, usize>,
+// A one-line summary in the style of javap's header, e.g.
+// public super class Foo extends java/lang/Object (2 methods, 15 constant pool entries)
 impl fmt::Display for OtKlass {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for name in decode_class_flags(self.flags) {
+            write!(f, "{} ", name)?;
+        }
+        if !self.is_interface() {
+            write!(f, "class ")?;
+        }
         write!(
             f,
-            "{} ISA {} with methods {:?}",
-            self.name, self.super_name, self.m_name_desc_lookup
+            "{} extends {} ({} methods, {} constant pool entries)",
+            self.name,
+            self.super_name,
+            self.methods.len(),
+            self.cp_len().saturating_sub(1)
         )
     }
 }
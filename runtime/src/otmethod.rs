@@ -1,22 +1,62 @@
-use std::cell::Cell;
 use std::fmt;
 
 use crate::constant_pool::CpAttr;
-use crate::constant_pool::ACC_NATIVE;
-use crate::InterpLocalVars;
-use crate::JvmValue;
+use crate::constant_pool::{ACC_ABSTRACT_M, ACC_NATIVE, ACC_STATIC};
+use crate::decode;
+use crate::decode::Instruction;
+use crate::disasm;
+use crate::otklass::OtKlass;
+use crate::peephole;
+use crate::verifier::StackMapFrame;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OtMethod {
     klass_name: String,
     flags: u16,
     name: String,
     name_desc: String,
+    // klass_name.name_desc, built once as it's the key for most method lookups
+    fq_name_desc: String,
     name_idx: u16,
     desc_idx: u16,
     code: Vec<u8>,
-    native_code: Cell<Option<fn(&InterpLocalVars) -> Option<JvmValue>>>,
+    // The code after constant folding, if that was asked for and changed anything.
+    // code itself is kept as it was, for the verifier and disassembly
+    folded_code: Option<Vec<u8>>,
+    // The code the interpreter runs, decoded once here rather than on every call.
+    // None if it doesn't decode, which the interpreter reports when it's run
+    decoded: Option<Vec<Instruction>>,
+    // Declared in the Code attribute, so unlimited for methods without one
+    max_stack: u16,
+    // Also from the Code attribute, otherwise the most a one-byte index can reach
+    max_locals: u16,
     attrs: Vec<CpAttr>,
+    // (start_pc, line_number) pairs from the LineNumberTable, if compiled with debug info
+    line_numbers: Vec<(u16, u16)>,
+    // (start_pc, length, name, desc, slot) entries from the LocalVariableTable
+    local_vars: Vec<(u16, u16, String, String, u16)>,
+    // Frames from the StackMapTable, in pc order. Empty for pre-Java 7 class files
+    stack_map: Vec<StackMapFrame>,
+    // The klasses named in the throws clause, from the Exceptions attribute. Nothing
+    // checks these at runtime, that's the job of the Code attribute's exception table
+    declared_exceptions: Vec<String>,
+    // From the Code attribute, in class file order, which is the order they're tried in
+    exception_table: Vec<ExceptionHandler>,
+}
+
+// Catches an exception thrown at a pc in [start_pc, end_pc) and jumps to handler_pc
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExceptionHandler {
+    pub start_pc: u16,
+    pub end_pc: u16,
+    pub handler_pc: u16,
+    // None catches anything, as for a finally block
+    pub catch_type: Option<String>,
 }
 
 impl OtMethod {
@@ -29,40 +69,171 @@ impl OtMethod {
         desc_idx: u16,
     ) -> OtMethod {
         let name_and_desc = name.clone() + ":" + &desc.clone();
+        let fq_name_desc = klass_name.clone() + "." + &name_and_desc;
         OtMethod {
             klass_name: klass_name.to_string(),
             flags: flags,
             name: name.clone(),
             name_desc: name_and_desc,
+            fq_name_desc,
             attrs: Vec::new(),
             code: Vec::new(),
-            native_code: Cell::new(None),
-            // FIXME
-            name_idx: desc_idx,
-            desc_idx: desc_idx,
+            folded_code: None,
+            decoded: Some(Vec::new()),
+            max_stack: u16::MAX,
+            max_locals: 256,
+            line_numbers: Vec::new(),
+            local_vars: Vec::new(),
+            stack_map: Vec::new(),
+            declared_exceptions: Vec::new(),
+            exception_table: Vec::new(),
+            name_idx,
+            desc_idx,
         }
     }
 
-    pub fn set_attr(&self, _index: u16, _attr: CpAttr) -> () {}
+    // Attributes are added in class file order, so the index is implied
+    pub fn set_attr(&mut self, _index: u16, attr: CpAttr) -> () {
+        self.attrs.push(attr);
+    }
+
+    // Attribute names live in the constant pool, so are resolved through klass, which
+    // should be the one declaring this method
+    pub fn get_attr(&self, klass: &OtKlass, name: &str) -> Option<&CpAttr> {
+        self.attrs
+            .iter()
+            .find(|a| klass.cp_as_string(a.get_name_idx()) == name)
+    }
 
     pub fn set_code(&mut self, code: Vec<u8>) -> () {
         self.code = code;
+        self.folded_code = None;
+        self.decoded = decode::decode(&self.code).ok();
+    }
+
+    pub fn fold_constants(&mut self) -> () {
+        let folded = peephole::fold_constants(&self.code);
+        self.folded_code = if folded != self.code { Some(folded) } else { None };
+        self.decoded = decode::decode(&self.get_exec_code()).ok();
+    }
+
+    pub fn get_decoded(&self) -> Option<&Vec<Instruction>> {
+        self.decoded.as_ref()
+    }
+
+    // The code the interpreter should run, folded if it has been
+    pub fn get_exec_code(&self) -> Vec<u8> {
+        match &self.folded_code {
+            Some(folded) => folded.clone(),
+            None => self.code.clone(),
+        }
     }
 
     pub fn get_code(&self) -> Vec<u8> {
         self.code.clone()
     }
 
+    pub fn set_max_stack(&mut self, max_stack: u16) -> () {
+        self.max_stack = max_stack;
+    }
+
+    pub fn get_max_stack(&self) -> u16 {
+        self.max_stack
+    }
+
+    pub fn set_max_locals(&mut self, max_locals: u16) -> () {
+        self.max_locals = max_locals;
+    }
+
+    pub fn get_max_locals(&self) -> u16 {
+        self.max_locals
+    }
+
+    // The class file doesn't promise any order, so the table is sorted by pc here
+    pub fn set_line_numbers(&mut self, mut line_numbers: Vec<(u16, u16)>) -> () {
+        line_numbers.sort_by_key(|(start_pc, _)| *start_pc);
+        self.line_numbers = line_numbers;
+    }
+
+    // The source line is that of the closest entry starting at or before pc
+    pub fn line_for_pc(&self, pc: u16) -> Option<u16> {
+        let after = self.line_numbers.partition_point(|(start_pc, _)| *start_pc <= pc);
+        if after == 0 {
+            return None;
+        }
+        Some(self.line_numbers[after - 1].1)
+    }
+
+    pub fn set_local_vars(&mut self, local_vars: Vec<(u16, u16, String, String, u16)>) -> () {
+        self.local_vars = local_vars;
+    }
+
+    // A slot can be reused for different variables, so the pc must fall within the var's range
+    pub fn local_name(&self, slot: u16, pc: u16) -> Option<String> {
+        self.local_vars
+            .iter()
+            .find(|(start_pc, length, _, _, var_slot)| {
+                *var_slot == slot && *start_pc <= pc && (pc as u32) < *start_pc as u32 + *length as u32
+            })
+            .map(|(_, _, name, _, _)| name.clone())
+    }
+
+    pub fn set_stack_map(&mut self, stack_map: Vec<StackMapFrame>) -> () {
+        self.stack_map = stack_map;
+    }
+
+    pub fn get_stack_map(&self) -> &Vec<StackMapFrame> {
+        &self.stack_map
+    }
+
+    pub fn set_declared_exceptions(&mut self, declared_exceptions: Vec<String>) -> () {
+        self.declared_exceptions = declared_exceptions;
+    }
+
+    pub fn get_declared_exceptions(&self) -> &Vec<String> {
+        &self.declared_exceptions
+    }
+
+    pub fn set_exception_table(&mut self, exception_table: Vec<ExceptionHandler>) -> () {
+        self.exception_table = exception_table;
+    }
+
+    pub fn get_exception_table(&self) -> &Vec<ExceptionHandler> {
+        &self.exception_table
+    }
+
+    // CP references are resolved through klass, which should be the one declaring this method
+    pub fn disassemble(&self, klass: &OtKlass) -> String {
+        disasm::disassemble(klass, self)
+    }
+
     pub fn get_klass_name(&self) -> String {
         self.klass_name.clone()
     }
 
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    // CP index of the method's name, in the declaring klass's constant pool
+    pub fn get_name_idx(&self) -> u16 {
+        self.name_idx
+    }
+
+    pub fn get_desc_idx(&self) -> u16 {
+        self.desc_idx
+    }
+
+    pub fn get_attrs(&self) -> &Vec<CpAttr> {
+        &self.attrs
+    }
+
     pub fn get_desc(&self) -> String {
         self.name_desc.clone()
     }
 
     pub fn get_fq_name_desc(&self) -> String {
-        self.klass_name.clone() + "." + &self.name_desc.clone()
+        self.fq_name_desc.clone()
     }
 
     pub fn get_flags(&self) -> u16 {
@@ -73,17 +244,17 @@ impl OtMethod {
         self.flags & ACC_NATIVE == ACC_NATIVE
     }
 
-    pub fn set_native_code(&self, n_code: fn(&InterpLocalVars) -> Option<JvmValue>) {
-        self.native_code.set(Some(n_code));
+    pub fn is_abstract(&self) -> bool {
+        self.flags & ACC_ABSTRACT_M == ACC_ABSTRACT_M
     }
 
-    pub fn get_native_code(&self) -> Option<fn(&InterpLocalVars) -> Option<JvmValue>> {
-        self.native_code.get()
+    pub fn is_static(&self) -> bool {
+        self.flags & ACC_STATIC == ACC_STATIC
     }
 
-    // HACK Replace with proper local var size by parsing class attributes properly
-    pub fn get_local_var_size(&self) -> u8 {
-        255
+    // How many local var slots a frame for this method needs
+    pub fn get_local_var_size(&self) -> u16 {
+        self.max_locals
     }
 }
 
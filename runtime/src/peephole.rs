@@ -0,0 +1,94 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::klass_parser::scan_code;
+use crate::opcode::Opcode;
+
+//////////// CONSTANT FOLDING
+
+// A peephole pass over method bytecode. Two int constants feeding straight into an
+// iadd, isub, imul, idiv or irem are replaced by the one constant they produce, padded
+// out with nops so that every other instruction keeps its pc. Branch offsets, line
+// numbers and stack maps all still hold. Nothing is folded across a branch target, or
+// where the op would throw, i.e. a division by zero
+pub fn fold_constants(code: &[u8]) -> Vec<u8> {
+    let targets = match scan_code(code) {
+        Ok((_, targets)) => targets,
+        // Malformed code is left for the verifier to reject
+        Err(_) => return code.to_vec(),
+    };
+    let mut is_target = vec![false; code.len()];
+    for (_, target) in targets {
+        if target >= 0 && (target as usize) < code.len() {
+            is_target[target as usize] = true;
+        }
+    }
+
+    let mut out = code.to_vec();
+    loop {
+        let starts = match scan_code(&out) {
+            Ok((starts, _)) => starts,
+            Err((pc, reason)) => panic!("Error: folding broke the code at {}: {}", pc, reason),
+        };
+        // Padding from earlier folds is skipped over, so that folds can chain
+        let insns: Vec<usize> = (0..out.len())
+            .filter(|pc| starts[*pc] && (out[*pc] != Opcode::NOP || is_target[*pc]))
+            .collect();
+        let folded = insns
+            .windows(3)
+            .find_map(|w| try_fold(&out, &is_target, w[0], w[1], w[2]));
+        match folded {
+            Some((start, end, replacement)) => {
+                out[start..start + replacement.len()].copy_from_slice(&replacement);
+                for b in &mut out[start + replacement.len()..end] {
+                    *b = Opcode::NOP;
+                }
+            }
+            None => break,
+        }
+    }
+    out
+}
+
+// Returns the span to overwrite and the constant to write at its start
+fn try_fold(
+    code: &[u8],
+    is_target: &[bool],
+    first: usize,
+    second: usize,
+    op: usize,
+) -> Option<(usize, usize, Vec<u8>)> {
+    if is_target[second] || is_target[op] {
+        return None;
+    }
+    let v1 = int_constant(code, first)?;
+    let v2 = int_constant(code, second)?;
+    // The same wrapping as the interpreter, which is what the JVM specifies
+    let result = match code[op] {
+        Opcode::IADD => v1.wrapping_add(v2),
+        Opcode::ISUB => v1.wrapping_sub(v2),
+        Opcode::IMUL => v1.wrapping_mul(v2),
+        Opcode::IDIV if v2 != 0 => v1.wrapping_div(v2),
+        Opcode::IREM if v2 != 0 => v1.wrapping_rem(v2),
+        _ => return None,
+    };
+    Some((first, op + 1, encode_int(result)?))
+}
+
+fn int_constant(code: &[u8], pc: usize) -> Option<i32> {
+    match code[pc] {
+        op @ Opcode::ICONST_M1..=Opcode::ICONST_5 => Some(op as i32 - Opcode::ICONST_0 as i32),
+        Opcode::BIPUSH => Some(code[pc + 1] as i8 as i32),
+        Opcode::SIPUSH => Some(BigEndian::read_i16(&code[pc + 1..]) as i32),
+        _ => None,
+    }
+}
+
+// The shortest push of v, or None if it would need an LDC
+fn encode_int(v: i32) -> Option<Vec<u8>> {
+    match v {
+        -1..=5 => Some(vec![(Opcode::ICONST_0 as i32 + v) as u8]),
+        -128..=127 => Some(vec![Opcode::BIPUSH, v as u8]),
+        -32768..=32767 => Some(vec![Opcode::SIPUSH, (v >> 8) as u8, v as u8]),
+        _ => None,
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::opcode::Opcode;
+
+//////////// PROFILING
+
+// Execution counts, collected by the VmContext while profiling is switched on
+pub struct Profile {
+    instructions: u64,
+    opcode_counts: Vec<u64>,
+    // Keyed by fully-qualified name and descriptor
+    invocations: HashMap<String, u64>,
+}
+
+impl Profile {
+    pub fn of() -> Profile {
+        Profile {
+            instructions: 0,
+            opcode_counts: vec![0; 256],
+            invocations: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn count_instruction(&mut self, opcode: u8) -> () {
+        self.instructions += 1;
+        self.opcode_counts[opcode as usize] += 1;
+    }
+
+    pub fn count_invocation(&mut self, fq_name_desc: &str) -> () {
+        match self.invocations.get_mut(fq_name_desc) {
+            Some(count) => *count += 1,
+            None => {
+                self.invocations.insert(fq_name_desc.to_string(), 1);
+            }
+        }
+    }
+
+    pub fn get_opcode_count(&self, opcode: u8) -> u64 {
+        self.opcode_counts[opcode as usize]
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let mut opcodes: Vec<(String, u64)> = (0..=255u8)
+            .filter(|op| self.opcode_counts[*op as usize] > 0)
+            .map(|op| (Opcode::mnemonic(op).to_string(), self.opcode_counts[op as usize]))
+            .collect();
+        let mut methods: Vec<(String, u64)> = self
+            .invocations
+            .iter()
+            .map(|(name, count)| (name.clone(), *count))
+            .collect();
+        // Most frequent first, ties broken by name so the order is stable
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        methods.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ProfileReport {
+            instructions: self.instructions,
+            opcodes,
+            methods,
+        }
+    }
+}
+
+pub struct ProfileReport {
+    pub instructions: u64,
+    // (mnemonic, count) for each opcode that was executed
+    pub opcodes: Vec<(String, u64)>,
+    // (method, count) for each method that was invoked
+    pub methods: Vec<(String, u64)>,
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} instructions", self.instructions)?;
+        for (mnemonic, count) in &self.opcodes {
+            writeln!(f, "  {:>10} {}", count, mnemonic)?;
+        }
+        writeln!(f, "{} methods invoked", self.methods.len())?;
+        for (meth, count) in &self.methods {
+            writeln!(f, "  {:>10} {}", count, meth)?;
+        }
+        Ok(())
+    }
+}
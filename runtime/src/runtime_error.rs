@@ -0,0 +1,212 @@
+use std::fmt;
+
+use crate::opcode::Opcode;
+use crate::JvmValue;
+use crate::ObjId;
+
+//////////// RUNTIME ERRORS
+
+// Conditions raised by the interpreter that a Java program could observe,
+// as opposed to VM bugs, which still panic
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    // A monitorexit on an object whose monitor is not held
+    IllegalMonitorState { obj_id: usize },
+    // A checkcast of an object that isn't an instance of the target klass
+    ClassCastException { from: String, to: String },
+    // The code breaks a constraint that a verifier would have rejected
+    VerifyError { msg: String },
+    // A local var index at or beyond the method's max_locals
+    LocalVarOutOfRange { idx: u16, max_locals: usize },
+    // A legal opcode that this VM deliberately doesn't implement
+    UnsupportedOpcodeError { opcode: u8, pc: usize },
+    // A reserved opcode (breakpoint, impdep1 or impdep2), which mustn't appear in a
+    // class file, or a byte that isn't an opcode at all
+    IllegalOpcode { opcode: u8, pc: usize },
+    // Thrown as a java/lang/ArithmeticException object, as are the other Java exceptions
+    // that as_exception() names, once they reach the frame they happened in
+    ArithmeticException { msg: String },
+    // A field access, array access, call or monitor op through a null reference
+    NullPointerException { msg: String },
+    // An array load or store at an index below 0 or at or beyond the length
+    ArrayIndexOutOfBoundsException { index: i32, length: i32 },
+    // An array allocation with a negative length
+    NegativeArraySizeException { size: i32 },
+    // An aastore of an object that isn't an instance of the array's component klass
+    ArrayStoreException { from: String, to: String },
+    // Dispatch arrived at a method with no body, named by its fq name and descriptor
+    AbstractMethodError { method: String },
+    // An allocation that would take the heap past its object limit
+    OutOfMemoryError { max_objects: usize },
+    // Execution went past a limit set on the VmContext, named as "steps" or "frames".
+    // Not a Java exception, so no handler can catch it
+    ExecutionLimitExceeded { limit: &'static str, max: u64 },
+    // A klass found on the classpath that couldn't be read or parsed
+    NoClassDefFoundError { klass_name: String, msg: String },
+    // Bytes that don't decode to a JvmValue
+    MalformedValue { msg: String },
+    // A JvmValue converted to a Rust type it doesn't hold, named by its descriptor char
+    ValueTypeMismatch { expected: char, found: JvmValue },
+    // An athrow that no handler has caught yet, so is unwinding the stack
+    Thrown { exception: ThrownException },
+}
+
+// One frame of a stack trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackTraceElement {
+    pub klass_name: String,
+    // Empty for bytecode run outside of any method
+    pub method_name: String,
+    // None if the method has no LineNumberTable
+    pub line: Option<u16>,
+}
+
+// Formatted as Java does, e.g. at com.example.Foo.bar(Foo.java:12). The SourceFile
+// attribute isn't parsed, so javac's naming of the file after the outermost klass is
+// assumed
+impl fmt::Display for StackTraceElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dotted = self.klass_name.replace('/', ".");
+        if self.method_name.is_empty() {
+            return write!(f, "at {}(Unknown Source)", dotted);
+        }
+        let simple_name = match self.klass_name.rfind('/') {
+            Some(slash) => &self.klass_name[slash + 1..],
+            None => &self.klass_name[..],
+        };
+        let outermost = simple_name.split('$').next().unwrap_or(simple_name);
+        match self.line {
+            Some(line) => write!(f, "at {}.{}({}.java:{})", dotted, self.method_name, outermost, line),
+            None => write!(f, "at {}.{}({}.java)", dotted, self.method_name, outermost),
+        }
+    }
+}
+
+// A thrown exception object, with the stack as it was at the athrow, innermost first
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrownException {
+    pub obj_id: ObjId,
+    pub klass_name: String,
+    // Only known for exceptions that the VM raised itself
+    pub msg: Option<String>,
+    pub trace: Vec<StackTraceElement>,
+}
+
+impl RuntimeError {
+    // What idiv, irem, ldiv and lrem raise for a zero divisor
+    pub fn divide_by_zero() -> RuntimeError {
+        RuntimeError::ArithmeticException {
+            msg: "/ by zero".to_string(),
+        }
+    }
+
+    // action describes what was done with the reference, e.g. "load from int array"
+    pub fn null_pointer(action: &str) -> RuntimeError {
+        RuntimeError::NullPointerException {
+            msg: format!("Cannot {} because the reference is null", action),
+        }
+    }
+
+    // The klass and message of the Java exception this is thrown as, for the errors
+    // that a handler can catch
+    pub fn as_exception(&self) -> Option<(&'static str, String)> {
+        let klass_name = match self {
+            RuntimeError::ArithmeticException { .. } => "java/lang/ArithmeticException",
+            RuntimeError::NullPointerException { .. } => "java/lang/NullPointerException",
+            RuntimeError::ArrayIndexOutOfBoundsException { .. } => "java/lang/ArrayIndexOutOfBoundsException",
+            RuntimeError::NegativeArraySizeException { .. } => "java/lang/NegativeArraySizeException",
+            RuntimeError::ArrayStoreException { .. } => "java/lang/ArrayStoreException",
+            RuntimeError::ClassCastException { .. } => "java/lang/ClassCastException",
+            RuntimeError::IllegalMonitorState { .. } => "java/lang/IllegalMonitorStateException",
+            RuntimeError::AbstractMethodError { .. } => "java/lang/AbstractMethodError",
+            _ => return None,
+        };
+        // Each is displayed as its klass name, then ": " and the message
+        let msg = self.to_string()[klass_name.len() + 2..].to_string();
+        Some((klass_name, msg))
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::IllegalMonitorState { obj_id } => {
+                write!(f, "java/lang/IllegalMonitorStateException: object {}", obj_id)
+            }
+            RuntimeError::ClassCastException { from, to } => write!(
+                f,
+                "java/lang/ClassCastException: {} cannot be cast to {}",
+                from, to
+            ),
+            RuntimeError::VerifyError { msg } => write!(f, "java/lang/VerifyError: {}", msg),
+            RuntimeError::LocalVarOutOfRange { idx, max_locals } => write!(
+                f,
+                "java/lang/VerifyError: local var {} out of range for max_locals {}",
+                idx, max_locals
+            ),
+            RuntimeError::UnsupportedOpcodeError { opcode, pc } => match *opcode {
+                Opcode::JSR | Opcode::JSR_W | Opcode::RET => write!(
+                    f,
+                    "Unsupported opcode {} at {}: jsr/ret subroutines are deprecated, recompile for Java 6 or later",
+                    Opcode::mnemonic(*opcode),
+                    pc
+                ),
+                _ => write!(f, "Unsupported opcode {} at {}", Opcode::mnemonic(*opcode), pc),
+            },
+            RuntimeError::IllegalOpcode { opcode, pc } => write!(
+                f,
+                "java/lang/VerifyError: illegal opcode {:#04x} ({}) at {}",
+                opcode,
+                Opcode::mnemonic(*opcode),
+                pc
+            ),
+            RuntimeError::ArithmeticException { msg } => {
+                write!(f, "java/lang/ArithmeticException: {}", msg)
+            }
+            RuntimeError::NullPointerException { msg } => {
+                write!(f, "java/lang/NullPointerException: {}", msg)
+            }
+            RuntimeError::ArrayIndexOutOfBoundsException { index, length } => write!(
+                f,
+                "java/lang/ArrayIndexOutOfBoundsException: Index {} out of bounds for length {}",
+                index, length
+            ),
+            RuntimeError::NegativeArraySizeException { size } => {
+                write!(f, "java/lang/NegativeArraySizeException: {}", size)
+            }
+            RuntimeError::ArrayStoreException { from, to } => write!(
+                f,
+                "java/lang/ArrayStoreException: {} cannot be stored in an array of {}",
+                from, to
+            ),
+            RuntimeError::AbstractMethodError { method } => {
+                write!(f, "java/lang/AbstractMethodError: {}", method)
+            }
+            RuntimeError::ExecutionLimitExceeded { limit, max } => {
+                write!(f, "Execution limit exceeded: more than {} {}", max, limit)
+            }
+            RuntimeError::OutOfMemoryError { max_objects } => write!(
+                f,
+                "java/lang/OutOfMemoryError: heap is limited to {} objects",
+                max_objects
+            ),
+            RuntimeError::NoClassDefFoundError { klass_name, msg } => {
+                write!(f, "java/lang/NoClassDefFoundError: {} ({})", klass_name, msg)
+            }
+            RuntimeError::MalformedValue { msg } => write!(f, "Malformed value encoding: {}", msg),
+            RuntimeError::ValueTypeMismatch { expected, found } => {
+                write!(f, "Expected a value of type {}, found {:?}", expected, found)
+            }
+            RuntimeError::Thrown { exception } => {
+                write!(f, "{}", exception.klass_name)?;
+                if let Some(msg) = &exception.msg {
+                    write!(f, ": {}", msg)?;
+                }
+                for element in &exception.trace {
+                    write!(f, "\n\t{}", element)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,95 +1,392 @@
 #![deny(unreachable_patterns)]
 
 use crate::JvmValue;
-use crate::OtField;
+use crate::ObjId;
 use crate::OtKlass;
 use crate::OtObj;
+use crate::object::MARK_LOCK_COUNT_BITS;
+use crate::runtime_error::RuntimeError;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub struct SharedSimpleHeap {
     obj_count: AtomicUsize,
-    // Free list
-    // Alloc table
-    alloc: Vec<OtObj>,
+    // Free list, the ids of slots emptied by gc() and not yet reused
+    free: Vec<usize>,
+    // Alloc table, indexed by id. None for a slot that has been freed
+    alloc: Vec<Option<OtObj>>,
+    max_objects: Option<usize>,
 }
 
 impl SharedSimpleHeap {
     pub fn of() -> SharedSimpleHeap {
         let mut out = SharedSimpleHeap {
             obj_count: AtomicUsize::new(1),
+            free: Vec::new(),
             alloc: Vec::new(),
+            max_objects: None,
         };
         let null_obj = OtObj::get_null();
-        out.alloc.push(null_obj);
+        out.alloc.push(Some(null_obj));
         out
     }
 
-    pub fn allocate_obj(&mut self, klass: &OtKlass) -> usize {
+    // No more than max_objects may be live at once, if set
+    pub fn set_max_objects(&mut self, max_objects: Option<usize>) -> () {
+        self.max_objects = max_objects;
+    }
+
+    // Excludes the null object
+    pub fn live_count(&self) -> usize {
+        self.alloc.len() - 1 - self.free.len()
+    }
+
+    fn check_capacity(&self) -> Result<(), RuntimeError> {
+        match self.max_objects {
+            Some(max_objects) if self.live_count() >= max_objects => {
+                Err(RuntimeError::OutOfMemoryError { max_objects })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Reuses a freed slot if there is one, otherwise grows the table
+    fn next_id(&mut self) -> usize {
+        match self.free.pop() {
+            Some(id) => id,
+            None => {
+                self.alloc.push(None);
+                self.obj_count.fetch_add(1, Ordering::SeqCst)
+            }
+        }
+    }
+
+    pub fn try_allocate_obj(&mut self, klass: &OtKlass) -> Result<usize, RuntimeError> {
+        self.check_capacity()?;
         let klass_id = klass.get_id();
-        let obj_id: usize = self.obj_count.fetch_add(1, Ordering::SeqCst);
+        let obj_id = self.next_id();
         let out = OtObj::obj_of(klass_id, obj_id, klass.make_default());
-        self.alloc.push(out);
-        obj_id
+        self.alloc[obj_id] = Some(out);
+        Ok(obj_id)
     }
 
-    pub fn allocate_int_arr(&mut self, size: i32) -> usize {
-        let obj_id = self.obj_count.fetch_add(1, Ordering::SeqCst);
+    pub fn allocate_obj(&mut self, klass: &OtKlass) -> usize {
+        self.try_allocate_obj(klass).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_allocate_int_arr(&mut self, size: i32) -> Result<usize, RuntimeError> {
+        if size < 0 {
+            return Err(RuntimeError::NegativeArraySizeException { size });
+        }
+        self.check_capacity()?;
+        let obj_id = self.next_id();
         let out = OtObj::int_arr_of(size, obj_id);
-        self.alloc.push(out);
-        obj_id
+        self.alloc[obj_id] = Some(out);
+        Ok(obj_id)
+    }
+
+    pub fn allocate_int_arr(&mut self, size: i32) -> usize {
+        self.try_allocate_int_arr(size).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // A boolean[] if boolean is set, otherwise a byte[]
+    pub fn try_allocate_byte_arr(&mut self, size: i32, boolean: bool) -> Result<usize, RuntimeError> {
+        if size < 0 {
+            return Err(RuntimeError::NegativeArraySizeException { size });
+        }
+        self.check_capacity()?;
+        let obj_id = self.next_id();
+        let out = OtObj::byte_arr_of(size, obj_id, boolean);
+        self.alloc[obj_id] = Some(out);
+        Ok(obj_id)
+    }
+
+    // FIXME There are no array klasses yet, so the element klass stands in
+    pub fn try_allocate_obj_arr(&mut self, elt_klass: &OtKlass, elements: Vec<usize>) -> Result<usize, RuntimeError> {
+        self.check_capacity()?;
+        let obj_id = self.next_id();
+        let out = OtObj::obj_arr_of(elt_klass.get_id(), obj_id, elements);
+        self.alloc[obj_id] = Some(out);
+        Ok(obj_id)
+    }
+
+    // All null, as anewarray makes them
+    pub fn try_allocate_null_obj_arr(&mut self, elt_klass: &OtKlass, size: i32) -> Result<usize, RuntimeError> {
+        if size < 0 {
+            return Err(RuntimeError::NegativeArraySizeException { size });
+        }
+        self.try_allocate_obj_arr(elt_klass, vec![0; size as usize])
+    }
+
+    pub fn allocate_obj_arr(&mut self, elt_klass: &OtKlass, elements: Vec<usize>) -> usize {
+        self.try_allocate_obj_arr(elt_klass, elements)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_allocate_str(&mut self, klass: &OtKlass, value: &str) -> Result<usize, RuntimeError> {
+        self.check_capacity()?;
+        let obj_id = self.next_id();
+        let out = OtObj::str_of(klass.get_id(), obj_id, value);
+        self.alloc[obj_id] = Some(out);
+        Ok(obj_id)
+    }
+
+    pub fn allocate_str(&mut self, klass: &OtKlass, value: &str) -> usize {
+        self.try_allocate_str(klass, value).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn slot(&self, id: usize) -> Option<&OtObj> {
+        self.alloc.get(id).and_then(|o| o.as_ref())
+    }
+
+    fn slot_mut(&mut self, id: usize) -> Option<&mut OtObj> {
+        self.alloc.get_mut(id).and_then(|o| o.as_mut())
     }
 
     pub fn get_obj(&self, id: usize) -> &OtObj {
-        match self.alloc.get(id) {
+        match self.slot(id) {
+            Some(val) => val,
+            None => panic!("Error: object {} not found", id),
+        }
+    }
+
+    // None for null, or for a handle that doesn't name an allocated object
+    pub fn try_get_obj(&self, r: ObjId) -> Option<&OtObj> {
+        if r.is_null() {
+            return None;
+        }
+        self.slot(r.get_id())
+    }
+
+    pub fn is_valid(&self, r: ObjId) -> bool {
+        r.is_null() || self.try_get_obj(r).is_some()
+    }
+
+    pub fn get_hash_code(&mut self, id: usize) -> i32 {
+        match self.slot_mut(id) {
+            Some(val) => val.get_hash_code(),
+            None => panic!("Error: object {} not found", id),
+        }
+    }
+
+    // Returns the new recursion count. Single-threaded, so the monitor is always available
+    pub fn monitor_enter(&mut self, id: usize) -> u64 {
+        let obj = match self.slot_mut(id) {
             Some(val) => val,
             None => panic!("Error: object {} not found", id),
+        };
+        let count = obj.get_lock_count() + 1;
+        if count > MARK_LOCK_COUNT_BITS {
+            panic!("Error: monitor recursion count overflow on object {}", id);
         }
+        obj.set_mark((obj.get_mark() & !MARK_LOCK_COUNT_BITS) | count);
+        count
     }
 
-    // FIXME Handle storage properly
-    pub fn put_field(&self, id: usize, f: OtField, v: JvmValue) -> () {
-        // Get object from heap
-        match self.alloc.get(id) {
-            Some(val) => val.put_field(f.get_offset() as usize, v),
+    // Returns the new recursion count, or None if the monitor was not held
+    pub fn monitor_exit(&mut self, id: usize) -> Option<u64> {
+        let obj = match self.slot_mut(id) {
+            Some(val) => val,
             None => panic!("Error: object {} not found", id),
         };
+        let count = obj.get_lock_count();
+        if count == 0 {
+            return None;
+        }
+        obj.set_mark((obj.get_mark() & !MARK_LOCK_COUNT_BITS) | (count - 1));
+        Some(count - 1)
     }
 
-    pub fn get_field(&self, id: usize, offset: u16) -> JvmValue {
-        // Get object from heap
-        let obj = match self.alloc.get(id) {
+    // Makes id a string-backed object holding value, keeping its klass. This is how a
+    // StringBuilder gets its buffer, as it starts out as a plain object
+    pub fn put_str_value(&mut self, id: usize, value: String) -> () {
+        let slot = match self.slot_mut(id) {
             Some(val) => val,
             None => panic!("Error: object {} not found", id),
         };
-        obj.get_field_value(offset as usize)
+        *slot = OtObj::str_of(slot.get_klassid(), id, &value);
     }
 
-    pub fn iastore(&mut self, id: usize, pos: i32, v: i32) -> () {
-        let p = pos as usize;
-        let obj = match self.alloc.get(id) {
+    // Adds s to the end of a string-backed object
+    pub fn append_str(&mut self, id: usize, s: &str) -> () {
+        match self.slot_mut(id) {
+            Some(OtObj::vm_str {
+                id: _,
+                mark: _,
+                klassid: _,
+                value: v,
+            }) => v.push_str(s),
+            _ => panic!("Error: object {} is not string-backed", id),
+        }
+    }
+
+    // Objects are only ever reached through their id, so every copy of an ObjRef
+    // sees writes made through any other. offset is into the klass's instance fields
+    pub fn put_field(&self, id: usize, offset: usize, v: JvmValue) -> () {
+        match self.slot(id) {
+            Some(val) => val.put_field(offset, v),
+            None => panic!("Error: object {} not found", id),
+        };
+    }
+
+    pub fn get_field(&self, id: usize, offset: usize) -> JvmValue {
+        let obj = match self.slot(id) {
             Some(val) => val,
             None => panic!("Error: object {} not found", id),
         };
-        let t = match obj {
+        obj.get_field_value(offset)
+    }
+
+    //////////// ARRAYS
+
+    // Every array opcode goes through these, so each makes the JVM's checks: a
+    // NullPointerException for a null array, then an ArrayIndexOutOfBoundsException
+    // for a bad index. action describes the access for the NullPointerException
+
+    fn array(&self, r: ObjId, action: &str) -> Result<&OtObj, RuntimeError> {
+        if r.is_null() {
+            return Err(RuntimeError::null_pointer(action));
+        }
+        Ok(self.get_obj(r.get_id()))
+    }
+
+    pub fn array_length(&self, r: ObjId) -> Result<i32, RuntimeError> {
+        Ok(self.array(r, "read the array length")?.length())
+    }
+
+    pub fn iaload(&self, r: ObjId, pos: i32) -> Result<i32, RuntimeError> {
+        match self.array(r, "load from int array")? {
             OtObj::vm_arr_int {
-                id: i,
-                mark: m,
-                klassid: kid,
+                id: _,
+                mark: _,
+                klassid: _,
                 length: _,
                 elements: elts,
-            } => (i, m, kid, elts),
-            _ => panic!("Non-int[] seen in heap during IASTORE at {}", id),
+            } => Ok(elts[check_index(pos, elts.len())?]),
+            _ => panic!("Non-int[] {} seen in heap during IALOAD", r),
+        }
+    }
+
+    pub fn iastore(&mut self, r: ObjId, pos: i32, v: i32) -> Result<(), RuntimeError> {
+        let length = self.array(r, "store to int array")?.length();
+        let idx = check_index(pos, length as usize)?;
+        match self.slot_mut(r.get_id()) {
+            Some(OtObj::vm_arr_int {
+                id: _,
+                mark: _,
+                klassid: _,
+                length: _,
+                elements: elts,
+            }) => elts[idx] = v,
+            _ => panic!("Non-int[] {} seen in heap during IASTORE", r),
         };
-        let mut elts = t.3.clone();
-        elts[pos as usize] = v;
-        let obj = OtObj::vm_arr_int {
-            id: *t.0,
-            mark: *t.1,
-            klassid: *t.2,
-            length: elts.len() as i32,
-            elements: elts,
+        Ok(())
+    }
+
+    // Bytes are sign-extended, and a boolean is always 0 or 1
+    pub fn baload(&self, r: ObjId, pos: i32) -> Result<i32, RuntimeError> {
+        match self.array(r, "load from byte/boolean array")? {
+            OtObj::vm_arr_byte {
+                id: _,
+                mark: _,
+                klassid: _,
+                length: _,
+                elements: elts,
+                boolean: _,
+            } => Ok(elts[check_index(pos, elts.len())?] as i32),
+            _ => panic!("Non-byte[] {} seen in heap during BALOAD", r),
+        }
+    }
+
+    // Truncates v to a byte, or to its low bit for a boolean[], as the JVM does
+    pub fn bastore(&mut self, r: ObjId, pos: i32, v: i32) -> Result<(), RuntimeError> {
+        let length = self.array(r, "store to byte/boolean array")?.length();
+        let idx = check_index(pos, length as usize)?;
+        match self.slot_mut(r.get_id()) {
+            Some(OtObj::vm_arr_byte {
+                id: _,
+                mark: _,
+                klassid: _,
+                length: _,
+                elements: elts,
+                boolean,
+            }) => elts[idx] = if *boolean { (v & 1) as i8 } else { v as i8 },
+            _ => panic!("Non-byte[] {} seen in heap during BASTORE", r),
         };
-        self.alloc[id] = obj;
+        Ok(())
+    }
+
+    // Arrays are covariant, so check_store is passed the id of the array's component
+    // klass, to raise an ArrayStoreException if v can't be stored. It's only called
+    // once the null and bounds checks have passed
+    pub fn aastore<F>(&mut self, r: ObjId, pos: i32, v: ObjId, check_store: F) -> Result<(), RuntimeError>
+    where
+        F: FnOnce(usize) -> Result<(), RuntimeError>,
+    {
+        let arr = self.array(r, "store to object array")?;
+        let idx = check_index(pos, arr.length() as usize)?;
+        check_store(arr.get_klassid())?;
+        match self.slot_mut(r.get_id()) {
+            Some(OtObj::vm_arr_obj {
+                id: _,
+                mark: _,
+                klassid: _,
+                length: _,
+                elements: elts,
+            }) => elts[idx] = v.get_id(),
+            _ => panic!("Non-reference array {} seen in heap during AASTORE", r),
+        };
+        Ok(())
+    }
+
+    pub fn aaload(&self, r: ObjId, pos: i32) -> Result<ObjId, RuntimeError> {
+        match self.array(r, "load from object array")? {
+            OtObj::vm_arr_obj {
+                id: _,
+                mark: _,
+                klassid: _,
+                length: _,
+                elements: elts,
+            } => Ok(ObjId::of(elts[check_index(pos, elts.len())?])),
+            _ => panic!("Non-reference array {} seen in heap during AALOAD", r),
+        }
+    }
+
+    // Marks everything reachable from roots and frees the rest, returning how many
+    // objects were freed. Freed ids are handed out again, so callers must pass every
+    // reference they still hold
+    pub fn gc(&mut self, roots: &[ObjId]) -> usize {
+        let mut marked = vec![false; self.alloc.len()];
+        marked[0] = true;
+        let mut grey: Vec<usize> = roots.iter().map(|r| r.get_id()).collect();
+        while let Some(id) = grey.pop() {
+            if marked[id] {
+                continue;
+            }
+            marked[id] = true;
+            if let Some(obj) = self.slot(id) {
+                grey.extend(obj.references().iter().map(|r| r.get_id()));
+            }
+        }
+
+        let mut freed = 0;
+        for (id, live) in marked.iter().enumerate() {
+            if !live && self.alloc[id].is_some() {
+                self.alloc[id] = None;
+                self.free.push(id);
+                freed += 1;
+            }
+        }
+        freed
+    }
+}
+
+fn check_index(pos: i32, length: usize) -> Result<usize, RuntimeError> {
+    if pos < 0 || pos as usize >= length {
+        return Err(RuntimeError::ArrayIndexOutOfBoundsException {
+            index: pos,
+            length: length as i32,
+        });
     }
+    Ok(pos as usize)
 }
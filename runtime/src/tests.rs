@@ -1,7 +1,19 @@
 use super::*;
 
+use crate::code_builder::CodeBuilder;
+use crate::constant_pool::CpEntry;
+use crate::constant_pool::{decode_class_flags, decode_method_flags};
+use crate::constant_pool::{ACC_FINAL, ACC_PUBLIC, ACC_STATIC, ACC_SUPER, ACC_SYNCHRONIZED};
+use crate::klass_builder::KlassBuilder;
+use crate::opcode::Opcode;
+use crate::peephole::fold_constants;
+use crate::vm_context::VmContext;
+
+use std::convert::TryFrom;
 use std::path::Path;
 
+use ocelotter_util::file_to_bytes;
+
 #[test]
 fn test_klass_name_from_fq() {
     let jli_value = "java/lang/Integer.valueOf:(I)Ljava/lang/Integer;".to_string();
@@ -40,6 +52,51 @@ fn test_read_simple_class() {
     assert_eq!(2, k.get_methods().len());
 }
 
+#[test]
+fn bootstrap_methods_are_parsed() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/IndyConcat.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading IndyConcat"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "IndyConcat.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    assert!(k.get_bootstrap_method(4).is_none());
+
+    // withTags() is the second concat in the file, and passes its tags as constants
+    let bsm = k.get_bootstrap_method(1).unwrap();
+    assert_eq!(
+        "java/lang/invoke/StringConcatFactory.makeConcatWithConstants",
+        k.cp_as_string(bsm.method_ref).split(':').next().unwrap()
+    );
+    assert_eq!(3, bsm.args.len());
+    assert_eq!("\u{2}\u{1}\u{2}", k.cp_as_string(bsm.args[0]));
+    assert_eq!("\u{1}", k.cp_as_string(bsm.args[1]));
+    assert_eq!("\u{2}", k.cp_as_string(bsm.args[2]));
+}
+
+#[test]
+fn max_stack_and_locals_come_from_the_code_attribute() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/IndyConcat.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading IndyConcat"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "IndyConcat.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+
+    // As javap -v reports them. The long and double args take two slots each
+    let describe = "IndyConcat.describe:(Ljava/lang/String;IJZCD)Ljava/lang/String;".to_string();
+    let meth = k.get_method_by_name_and_desc(&describe).unwrap();
+    assert_eq!(8, meth.get_max_stack());
+    assert_eq!(8, meth.get_max_locals());
+
+    let count = "IndyConcat.count:(I)Ljava/lang/String;".to_string();
+    let meth = k.get_method_by_name_and_desc(&count).unwrap();
+    assert_eq!(2, meth.get_max_stack());
+    assert_eq!(3, meth.get_max_locals());
+}
+
 #[test]
 fn check_simple_fields_methods() {
     let bytes = match file_to_bytes(Path::new(
@@ -73,6 +130,1946 @@ fn check_system_current_timemillis() {
     assert_eq!("java/lang/Object", k.get_super_name());
 }
 
+#[test]
+fn disassemble_simple_method() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/SampleInvoke.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading SampleInvoke"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "SampleInvoke.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    let meth = k
+        .get_method_by_name_and_desc(&"SampleInvoke.foo:()I".to_string())
+        .unwrap();
+
+    let out = disasm::disassemble(&k, meth);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(8, lines.len());
+    assert_eq!("0: iconst_2", lines[0]);
+    assert_eq!("3: invokestatic #2 // SampleInvoke.bar:()I", lines[3]);
+    assert_eq!("9: ireturn", lines[7]);
+}
+
+#[test]
+fn dup_x1_matches_spec() {
+    let mut eval = interp_stack::InterpEvalStack::of();
+    eval.iconst(1);
+    eval.iconst(2);
+    eval.dup_x1();
+
+    // ..., 1, 2 -> ..., 2, 1, 2
+    for expected in [2, 1, 2] {
+        match eval.pop() {
+            JvmValue::Int { val: i } => assert_eq!(expected, i),
+            v => panic!("Unexpected value {} on stack after DUP_X1", v),
+        }
+    }
+}
+
+#[test]
+fn narrow_values_are_pushed_as_ints() {
+    let mut eval = interp_stack::InterpEvalStack::of();
+    eval.push(JvmValue::Byte { val: -3 });
+    eval.push(JvmValue::Char { val: 'A' });
+    eval.iadd();
+    assert_eq!(JvmValue::Int { val: 62 }, eval.pop());
+
+    eval.push(JvmValue::Boolean { val: true });
+    eval.push(JvmValue::Short { val: 7 });
+    eval.imul();
+    assert_eq!(JvmValue::Int { val: 7 }, eval.pop());
+
+    eval.push(JvmValue::Long { val: 7 });
+    assert_eq!(JvmValue::Long { val: 7 }, eval.pop());
+}
+
+#[test]
+fn int_arithmetic_wraps() {
+    let int_result = |a: i32, b: i32, op: fn(&mut interp_stack::InterpEvalStack)| {
+        let mut eval = interp_stack::InterpEvalStack::of();
+        eval.iconst(a);
+        eval.iconst(b);
+        op(&mut eval);
+        match eval.pop() {
+            JvmValue::Int { val: i } => i,
+            v => panic!("Unexpected value {} on stack", v),
+        }
+    };
+
+    assert_eq!(i32::MIN, int_result(i32::MAX, 1, |e| e.iadd()));
+    assert_eq!(i32::MAX, int_result(i32::MIN, -1, |e| e.iadd()));
+    assert_eq!(i32::MAX, int_result(i32::MIN, 1, |e| e.isub()));
+    assert_eq!(i32::MIN, int_result(i32::MAX, -1, |e| e.isub()));
+    assert_eq!(3, int_result(5, 2, |e| e.isub()));
+    assert_eq!(-2, int_result(i32::MAX, 2, |e| e.imul()));
+    assert_eq!(i32::MIN, int_result(i32::MIN, -1, |e| e.imul()));
+
+    let mut eval = interp_stack::InterpEvalStack::of();
+    eval.iconst(i32::MIN);
+    eval.ineg();
+    assert_eq!(JvmValue::Int { val: i32::MIN }, eval.pop());
+}
+
+#[test]
+fn division_of_min_by_minus_one_wraps() {
+    let mut eval = interp_stack::InterpEvalStack::of();
+    eval.iconst(i32::MIN);
+    eval.iconst(-1);
+    eval.idiv().unwrap();
+    assert_eq!(JvmValue::Int { val: i32::MIN }, eval.pop());
+
+    eval.iconst(i32::MIN);
+    eval.iconst(-1);
+    eval.irem().unwrap();
+    assert_eq!(JvmValue::Int { val: 0 }, eval.pop());
+
+    eval.lconst(i64::MIN);
+    eval.lconst(-1);
+    eval.ldiv().unwrap();
+    assert_eq!(JvmValue::Long { val: i64::MIN }, eval.pop());
+
+    eval.lconst(-9);
+    eval.lconst(2);
+    eval.ldiv().unwrap();
+    assert_eq!(JvmValue::Long { val: -4 }, eval.pop());
+}
+
+#[test]
+fn int_comparisons_at_the_boundary() {
+    type Cmp = fn(&mut interp_stack::InterpEvalStack) -> bool;
+    // v1 is pushed first, as javac would for `v1 < v2`
+    let compare = |v1: i32, v2: i32, f: Cmp| {
+        let mut eval = interp_stack::InterpEvalStack::of();
+        eval.iconst(v1);
+        eval.iconst(v2);
+        let out = f(&mut eval);
+        assert_eq!(0, eval.depth());
+        out
+    };
+    // (comparison, result for 5 vs 5, 4 vs 5, 5 vs 4)
+    let cases: Vec<(Cmp, bool, bool, bool)> = vec![
+        (|e| e.if_icmpeq(), true, false, false),
+        (|e| e.if_icmpne(), false, true, true),
+        (|e| e.if_icmplt(), false, true, false),
+        (|e| e.if_icmpge(), true, false, true),
+        (|e| e.if_icmpgt(), false, false, true),
+        (|e| e.if_icmple(), true, true, false),
+    ];
+    for (f, equal, less, greater) in cases {
+        assert_eq!(equal, compare(5, 5, f));
+        assert_eq!(less, compare(4, 5, f));
+        assert_eq!(greater, compare(5, 4, f));
+    }
+}
+
+#[test]
+fn eval_stack_peek() {
+    let mut stack = interp_stack::InterpEvalStack::of();
+    assert!(stack.peek().is_none());
+    stack.iconst(1);
+    stack.lconst(2);
+    stack.dconst(3.0);
+
+    assert_eq!(Some(&JvmValue::Double { val: 3.0 }), stack.peek());
+    assert_eq!(Some(&JvmValue::Double { val: 3.0 }), stack.peek_n(0));
+    assert_eq!(Some(&JvmValue::Long { val: 2 }), stack.peek_n(1));
+    assert_eq!(Some(&JvmValue::Int { val: 1 }), stack.peek_n(2));
+    assert!(stack.peek_n(3).is_none());
+    assert_eq!(3, stack.depth());
+    assert_eq!(5, stack.slot_depth());
+
+    stack.dup();
+    assert_eq!(4, stack.depth());
+    assert_eq!(Some(&JvmValue::Double { val: 3.0 }), stack.peek_n(1));
+}
+
+#[test]
+fn eval_stack_clear_keeps_capacity() {
+    let mut stack = interp_stack::InterpEvalStack::with_capacity(16);
+    let capacity = stack.capacity();
+    assert!(capacity >= 16);
+
+    for i in 0..10 {
+        stack.iconst(i);
+    }
+    stack.lconst(1);
+    assert_eq!(12, stack.slot_depth());
+
+    assert!(!stack.is_empty());
+    stack.clear();
+    assert!(stack.is_empty());
+    assert_eq!(0, stack.depth());
+    assert_eq!(0, stack.slot_depth());
+    assert_eq!(capacity, stack.capacity());
+
+    stack.iconst(42);
+    assert_eq!(JvmValue::Int { val: 42 }, stack.pop());
+    assert!(stack.check().is_ok());
+    assert_eq!(capacity, stack.capacity());
+}
+
+#[test]
+fn constants_fold_in_place() {
+    let code = vec![Opcode::ICONST_2, Opcode::ICONST_3, Opcode::IADD, Opcode::IRETURN];
+    assert_eq!(
+        vec![Opcode::ICONST_5, Opcode::NOP, Opcode::NOP, Opcode::IRETURN],
+        fold_constants(&code)
+    );
+
+    // Division by zero must still throw at runtime
+    let code = vec![Opcode::ICONST_1, Opcode::ICONST_0, Opcode::IDIV, Opcode::IRETURN];
+    assert_eq!(code, fold_constants(&code));
+    let code = vec![Opcode::ICONST_1, Opcode::ICONST_0, Opcode::IREM, Opcode::IRETURN];
+    assert_eq!(code, fold_constants(&code));
+
+    // Folds chain through the padding left by earlier ones
+    let code = CodeBuilder::new()
+        .iconst(300)
+        .iconst(10)
+        .imul()
+        .iconst(3)
+        .op(Opcode::IDIV)
+        .ireturn()
+        .build();
+    let mut expected = vec![Opcode::SIPUSH, 0x03, 0xe8];
+    expected.extend(vec![Opcode::NOP; 5]);
+    expected.push(Opcode::IRETURN);
+    assert_eq!(expected, fold_constants(&code));
+
+    // 32768 would need an LDC, for which there's no constant pool entry
+    let code = CodeBuilder::new().iconst(-32768).iconst(-1).imul().ireturn().build();
+    assert_eq!(code, fold_constants(&code));
+
+    // The second constant is a branch target, so may be reached without the first
+    let mut c = CodeBuilder::new();
+    let (skip, join) = (c.new_label(), c.new_label());
+    c.iload(0)
+        .ifeq(skip)
+        .iconst(1)
+        .goto(join)
+        .bind(skip)
+        .iconst(2)
+        .bind(join)
+        .iconst(3)
+        .iadd()
+        .ireturn();
+    let code = c.build();
+    assert_eq!(code, fold_constants(&code));
+}
+
+#[test]
+fn decode_keeps_opcodes_and_maps_branches() {
+    let mut c = CodeBuilder::new();
+    let (top, done) = (c.new_label(), c.new_label());
+    c.iconst(0)
+        .istore(1)
+        .bind(top)
+        .iload(1)
+        .iconst(1000)
+        .if_icmpge(done)
+        .iinc(1, -3)
+        .goto(top)
+        .bind(done)
+        .iload(1)
+        .ireturn();
+    let code = c.build();
+    let insns = decode::decode(&code).unwrap();
+
+    // Walking the raw code gives the same opcodes at the same pcs
+    let mut pc = 0;
+    let mut raw = Vec::new();
+    while pc < code.len() {
+        raw.push((code[pc], pc));
+        pc += 1 + Opcode::num_params(code[pc]) as usize;
+    }
+    let decoded: Vec<(u8, usize)> = insns.iter().map(|i| (i.opcode, i.pc)).collect();
+    assert_eq!(raw, decoded);
+
+    // Operands are decoded, and branches point at instructions rather than pcs
+    assert_eq!(1000, insns[3].operand);
+    assert_eq!((1, -3), (insns[5].operand, insns[5].operand2));
+    assert_eq!(Opcode::GOTO, insns[6].opcode);
+    assert_eq!(2, insns[6].operand);
+    assert_eq!(Opcode::IF_ICMPGE, insns[4].opcode);
+    assert_eq!(Opcode::ILOAD_1, insns[insns[4].operand as usize].opcode);
+    assert_eq!(7, insns[4].operand);
+}
+
+#[test]
+fn decode_rejects_branch_into_an_instruction() {
+    // The goto lands on sipush's operand
+    let code = vec![Opcode::SIPUSH, 0, 1, Opcode::GOTO, 0xff, 0xfe, Opcode::IRETURN];
+    assert!(decode::decode(&code).is_err());
+}
+
+#[test]
+fn debug_eval_stack() {
+    let mut eval = interp_stack::InterpEvalStack::of();
+    eval.iconst(7);
+    eval.dconst(2.5);
+
+    assert_eq!("I(7)", format!("{:?}", JvmValue::Int { val: 7 }));
+    assert_eq!("[D(2.5), I(7)]", format!("{:?}", eval));
+}
+
+#[test]
+fn eval_stack_reports_overflow_and_underflow() {
+    let mut eval = interp_stack::InterpEvalStack::with_max_stack(1);
+    eval.iconst(1);
+    assert_eq!(Ok(()), eval.check());
+
+    eval.iconst(2);
+    match eval.check() {
+        Err(RuntimeError::VerifyError { msg }) => {
+            assert_eq!("Operand stack depth 2 exceeds max_stack 1", msg)
+        }
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+    // Reporting a fault clears it
+    assert_eq!(Ok(()), eval.check());
+
+    eval.pop();
+    eval.pop();
+    assert_eq!(JvmValue::default(), eval.pop());
+    match eval.check() {
+        Err(RuntimeError::VerifyError { msg }) => assert_eq!("Operand stack underflow", msg),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+    assert!(eval.try_pop().is_err());
+}
+
+#[test]
+fn eval_stack_counts_wide_values_as_two_slots() {
+    let mut eval = interp_stack::InterpEvalStack::with_max_stack(4);
+    eval.iconst(1);
+    eval.dconst(2.0);
+    assert_eq!(2, eval.depth());
+    assert_eq!(3, eval.slot_depth());
+
+    // One more slot fits, but a long doesn't
+    eval.lconst(3);
+    match eval.check() {
+        Err(RuntimeError::VerifyError { msg }) => {
+            assert_eq!("Operand stack depth 5 exceeds max_stack 4", msg)
+        }
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+
+    eval.pop();
+    eval.pop();
+    assert_eq!(1, eval.slot_depth());
+    eval.pop();
+    eval.pop();
+    assert_eq!(0, eval.slot_depth());
+}
+
+#[test]
+fn obj_id_null_and_round_trip() {
+    assert!(ObjId::NULL.is_null());
+    assert!(!ObjId::of(3).is_null());
+    assert_eq!(3, ObjId::of(3).get_id());
+
+    let mut eval = interp_stack::InterpEvalStack::of();
+    eval.aconst_null();
+    eval.push(JvmValue::ObjRef { val: ObjId::of(3) });
+    match eval.pop() {
+        JvmValue::ObjRef { val } => assert_eq!(ObjId::of(3), val),
+        v => panic!("Unexpected value {} on stack", v),
+    }
+    match eval.pop() {
+        JvmValue::ObjRef { val } => assert!(val.is_null()),
+        v => panic!("Unexpected value {} on stack", v),
+    }
+}
+
+#[test]
+fn heap_validates_handles() {
+    let k = KlassBuilder::new("Handled").build();
+    let mut heap = simple_heap::SharedSimpleHeap::of();
+    let id = ObjId::of(heap.allocate_obj(&k));
+
+    assert!(heap.is_valid(id));
+    assert!(heap.try_get_obj(id).is_some());
+    assert!(heap.is_valid(ObjId::NULL));
+    assert!(heap.try_get_obj(ObjId::NULL).is_none());
+    assert!(!heap.is_valid(ObjId::of(id.get_id() + 1)));
+}
+
+#[test]
+fn parse_declared_exceptions() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/Thrower.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading Thrower"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Thrower.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+
+    let fail = k
+        .get_method_by_name_and_desc(&"Thrower.fail:()V".to_string())
+        .unwrap();
+    assert_eq!(
+        &vec!["java/io/IOException".to_string(), "java/lang/InterruptedException".to_string()],
+        fail.get_declared_exceptions()
+    );
+    let succeed = k
+        .get_method_by_name_and_desc(&"Thrower.succeed:()I".to_string())
+        .unwrap();
+    assert!(succeed.get_declared_exceptions().is_empty());
+}
+
+#[test]
+fn parse_exception_table() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/Catcher.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading Catcher"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Catcher.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+
+    let picky = k
+        .get_method_by_name_and_desc(&"Catcher.picky:()I".to_string())
+        .unwrap();
+    assert_eq!(
+        &vec![
+            otmethod::ExceptionHandler {
+                start_pc: 0,
+                end_pc: 3,
+                handler_pc: 4,
+                catch_type: Some("java/lang/IllegalStateException".to_string()),
+            },
+            otmethod::ExceptionHandler {
+                start_pc: 0,
+                end_pc: 3,
+                handler_pc: 7,
+                catch_type: Some("java/lang/Exception".to_string()),
+            },
+        ],
+        picky.get_exception_table()
+    );
+    let inner = k
+        .get_method_by_name_and_desc(&"Catcher.inner:()I".to_string())
+        .unwrap();
+    assert!(inner.get_exception_table().is_empty());
+}
+
+#[test]
+fn stack_trace_elements_display_as_java_does() {
+    let element = |klass_name: &str, method_name: &str, line| runtime_error::StackTraceElement {
+        klass_name: klass_name.to_string(),
+        method_name: method_name.to_string(),
+        line,
+    };
+    assert_eq!("at Foo.bar(Foo.java:12)", element("Foo", "bar", Some(12)).to_string());
+    assert_eq!(
+        "at com.example.Foo$Inner.run(Foo.java:7)",
+        element("com/example/Foo$Inner", "run", Some(7)).to_string()
+    );
+    assert_eq!("at Foo.bar(Foo.java)", element("Foo", "bar", None).to_string());
+    assert_eq!("at DUMMY(Unknown Source)", element("DUMMY", "", None).to_string());
+}
+
+#[test]
+fn stack_trace_follows_frames() {
+    let mut context = VmContext::of();
+    assert!(context.build_stack_trace().is_empty());
+
+    context.enter_frame("Outer".to_string(), "run".to_string());
+    context.set_frame_line(Some(5));
+    context.enter_frame("Inner".to_string(), "call".to_string());
+    context.set_frame_line(Some(9));
+    let trace: Vec<String> = context.build_stack_trace().iter().map(|e| e.to_string()).collect();
+    assert_eq!(vec!["at Inner.call(Inner.java:9)", "at Outer.run(Outer.java:5)"], trace);
+
+    context.leave_frame();
+    assert_eq!(1, context.build_stack_trace().len());
+}
+
+#[test]
+fn method_lookup_borrows_from_klass() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/SampleInvoke.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading SampleInvoke"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "SampleInvoke.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+
+    // Every lookup hands back the method the klass holds, not a copy of it
+    let fq_meth = "SampleInvoke.bar:()I".to_string();
+    let held = k
+        .methods_iter()
+        .find(|m| m.get_fq_name_desc() == fq_meth)
+        .expect("SampleInvoke.bar:()I not held");
+    for _ in 0..3 {
+        assert!(std::ptr::eq(held, k.get_method_by_name_and_desc(&fq_meth).unwrap()));
+    }
+    assert!(k
+        .get_method_by_name_and_desc(&"SampleInvoke.baz:()I".to_string())
+        .is_none());
+}
+
+#[test]
+fn cached_call_sites_share_the_method() {
+    let b = KlassBuilder::new("Caller");
+    let base = b.next_cp_index();
+    let utf8 = |s: &str| CpEntry::utf8 { val: s.to_string() };
+    let k = b
+        .add_constant(utf8("run"))
+        .add_constant(utf8("()V"))
+        .add_constant(CpEntry::name_and_type { name_idx: base, type_idx: base + 1 })
+        .add_constant(CpEntry::methodref { clz_idx: 2, nt_idx: base + 2 })
+        .add_method("run", "()V", ACC_PUBLIC | ACC_STATIC, vec![Opcode::RETURN])
+        .build();
+    let mut repo = klass_repo::SharedKlassRepo::of();
+    repo.add_klass(&k);
+    let k = repo.lookup_klass(&"Caller".to_string());
+
+    // A hit hands back the cached method itself rather than a copy of its code
+    let first = repo.lookup_method_by_cp(&k, base + 3);
+    let second = repo.lookup_method_by_cp(&k, base + 3);
+    assert_eq!("Caller.run:()V", first.get_fq_name_desc());
+    assert!(std::rc::Rc::ptr_eq(&first, &second));
+}
+
+#[test]
+fn heap_limit_raises_out_of_memory() {
+    let k = KlassBuilder::new("Small").build();
+    // A private heap, so the limit doesn't affect other tests
+    let mut heap = simple_heap::SharedSimpleHeap::of();
+    heap.set_max_objects(Some(2));
+    assert_eq!(0, heap.live_count());
+
+    assert!(heap.try_allocate_obj(&k).is_ok());
+    assert!(heap.try_allocate_int_arr(3).is_ok());
+    assert_eq!(2, heap.live_count());
+    assert_eq!(
+        Err(RuntimeError::OutOfMemoryError { max_objects: 2 }),
+        heap.try_allocate_obj(&k)
+    );
+    assert_eq!(2, heap.live_count());
+
+    heap.set_max_objects(None);
+    assert!(heap.try_allocate_obj(&k).is_ok());
+    assert_eq!(3, heap.live_count());
+}
+
+#[test]
+fn repo_lists_loaded_classes() {
+    let mut repo = SharedKlassRepo::of();
+    assert_eq!(0, repo.class_count());
+    repo.add_klass(&KlassBuilder::new("org/example/Zebra").build());
+    repo.add_klass(&KlassBuilder::new("Apple").build());
+    repo.add_klass(&KlassBuilder::new("org/example/Mango").build());
+    // Adding a klass again doesn't load it twice
+    repo.add_klass(&KlassBuilder::new("Apple").build());
+
+    // java/lang/Object is mentioned as the super of each, but never loaded
+    assert_eq!(
+        vec!["Apple", "org/example/Mango", "org/example/Zebra"],
+        repo.loaded_class_names()
+    );
+    assert_eq!(3, repo.class_count());
+}
+
+#[test]
+fn frozen_repo_resolves_across_threads() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/SampleInvoke.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading SampleInvoke"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "SampleInvoke.class".to_string());
+    parser.parse();
+    let mut repo = SharedKlassRepo::of();
+    repo.add_klass(&parser.klass());
+    let frozen = repo.freeze();
+
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let frozen = std::sync::Arc::clone(&frozen);
+            std::thread::spawn(move || {
+                let m = frozen.lookup_method_exact(
+                    &"SampleInvoke".to_string(),
+                    "SampleInvoke.bar:()I".to_string(),
+                );
+                let k = frozen.lookup_klass(&"SampleInvoke".to_string());
+                (m.get_fq_name_desc(), k.get_id())
+            })
+        })
+        .collect();
+    for h in handles {
+        let (fq_name, id) = h.join().unwrap();
+        assert_eq!("SampleInvoke.bar:()I", fq_name);
+        assert_eq!(1, id);
+    }
+    // Only mentioned, never loaded
+    assert!(frozen.try_lookup_klass(&"java/lang/Object".to_string()).is_none());
+}
+
+#[test]
+fn obj_describe_names_its_klass() {
+    let mut repo = SharedKlassRepo::of();
+    repo.add_klass(&KlassBuilder::new("org/example/Described").build());
+    let k = repo.lookup_klass(&"org/example/Described".to_string());
+    let mut heap = simple_heap::SharedSimpleHeap::of();
+    let id = heap.allocate_obj(&k);
+
+    let shown = heap.get_obj(id).describe(&repo);
+    assert!(shown.starts_with("org.example.Described@"));
+    // Describing doesn't change the hash the object goes on to report
+    let hash = heap.get_hash_code(id);
+    assert_eq!(format!("org.example.Described@{:x}", hash), shown);
+    assert_eq!(shown, heap.get_obj(id).describe(&repo));
+}
+
+#[test]
+fn gc_frees_unreachable_objects() {
+    let k = KlassBuilder::new("Garbage").build();
+    let mut heap = simple_heap::SharedSimpleHeap::of();
+    let kept = heap.allocate_obj(&k);
+    let held = heap.allocate_int_arr(4);
+    // Reachable only through the array
+    let arr = heap.allocate_obj_arr(&k, vec![held, 0]);
+    for _ in 0..10 {
+        heap.allocate_obj(&k);
+    }
+    assert_eq!(13, heap.live_count());
+
+    let roots = vec![ObjId::of(kept), ObjId::of(arr)];
+    assert_eq!(10, heap.gc(&roots));
+    assert_eq!(3, heap.live_count());
+    assert!(heap.is_valid(ObjId::of(held)));
+    assert!(!heap.is_valid(ObjId::of(arr + 1)));
+
+    // Freed slots are reused before the heap grows
+    let reused = heap.allocate_obj(&k);
+    assert!(reused > arr && reused <= arr + 10);
+    assert_eq!(4, heap.live_count());
+
+    // Dropping the array takes its element with it
+    assert_eq!(3, heap.gc(&[ObjId::of(kept)]));
+    assert_eq!(1, heap.live_count());
+}
+
+#[test]
+fn jvm_values_round_trip_through_tagged_bytes() {
+    let values = vec![
+        JvmValue::Boolean { val: true },
+        JvmValue::Byte { val: -7 },
+        JvmValue::Short { val: -300 },
+        JvmValue::Int { val: i32::MIN },
+        JvmValue::Long { val: 1 << 40 },
+        JvmValue::Float { val: -1.5 },
+        JvmValue::Double { val: std::f64::consts::PI },
+        JvmValue::Char { val: 'é' },
+        JvmValue::ObjRef { val: ObjId::of(1234) },
+        JvmValue::ObjRef { val: ObjId::NULL },
+    ];
+    let mut buf = Vec::new();
+    for v in &values {
+        let bytes = v.to_tagged_bytes();
+        assert_eq!(Ok((v.clone(), bytes.len())), JvmValue::from_tagged_bytes(&bytes));
+        buf.extend(bytes);
+    }
+    assert_eq!(vec![b'I', 0, 0, 0, 0x80], JvmValue::Int { val: i32::MIN }.to_tagged_bytes());
+
+    // Values can be read back one after another from a single buffer
+    let mut pos = 0;
+    for v in &values {
+        let (read, len) = JvmValue::from_tagged_bytes(&buf[pos..]).unwrap();
+        assert_eq!(*v, read);
+        pos += len;
+    }
+    assert_eq!(buf.len(), pos);
+
+    assert!(JvmValue::from_tagged_bytes(&[]).is_err());
+    assert!(JvmValue::from_tagged_bytes(&[b'Q', 0]).is_err());
+    assert!(JvmValue::from_tagged_bytes(&[b'J', 1, 2, 3]).is_err());
+    assert!(JvmValue::from_tagged_bytes(&[b'Z', 2]).is_err());
+}
+
+#[test]
+fn narrow_values_truncate_and_promote() {
+    assert_eq!(JvmValue::Byte { val: 44 }, JvmValue::narrow_to_byte(300));
+    assert_eq!(JvmValue::Byte { val: -1 }, JvmValue::narrow_to_byte(255));
+    assert_eq!(JvmValue::Short { val: -32768 }, JvmValue::narrow_to_short(32768));
+    assert_eq!(JvmValue::Boolean { val: true }, JvmValue::narrow_to_boolean(3));
+    assert_eq!(JvmValue::Char { val: 'A' }, JvmValue::narrow_to_char(0x10041));
+
+    assert_eq!(JvmValue::Int { val: -1 }, JvmValue::Byte { val: -1 }.promote());
+    assert_eq!(JvmValue::Int { val: 65 }, JvmValue::Char { val: 'A' }.promote());
+    assert_eq!(JvmValue::Int { val: 1 }, JvmValue::Boolean { val: true }.promote());
+    assert_eq!(JvmValue::Long { val: 5 }, JvmValue::Long { val: 5 }.promote());
+
+    assert_eq!(
+        JvmValue::Byte { val: 44 },
+        descriptor::narrow_for_descriptor("B", JvmValue::Int { val: 300 })
+    );
+    assert_eq!(
+        JvmValue::Int { val: 300 },
+        descriptor::narrow_for_descriptor("I", JvmValue::Int { val: 300 })
+    );
+}
+
+#[test]
+fn obj_display_format() {
+    assert_eq!("OtObj{id=0, mark=0x0, klass=0}", format!("{}", OtObj::get_null()));
+
+    let k = KlassBuilder::new("Shown").build();
+    let mut heap = simple_heap::SharedSimpleHeap::of();
+    let id = heap.allocate_obj(&k);
+    heap.monitor_enter(id);
+    assert_eq!(
+        format!("OtObj{{id={}, mark=0x1, klass=0}}", id),
+        format!("{}", heap.get_obj(id))
+    );
+
+    let r = JvmValue::ObjRef { val: ObjId::of(id) };
+    assert_eq!(format!("OtObj{{id={}}}", id), format!("{}", r));
+    assert_eq!("null", format!("{}", JvmValue::ObjRef { val: ObjId::NULL }));
+}
+
+#[test]
+fn jvm_value_equality() {
+    assert_eq!(JvmValue::Int { val: 3 }, JvmValue::Int { val: 3 });
+    assert_ne!(JvmValue::Int { val: 3 }, JvmValue::Long { val: 3 });
+
+    let nan = JvmValue::Double { val: f64::NAN };
+    assert_ne!(nan, nan.clone());
+    let fnan = JvmValue::Float { val: f32::NAN };
+    assert_ne!(fnan, fnan.clone());
+
+    assert_eq!(JvmValue::ObjRef { val: ObjId::NULL }, JvmValue::ObjRef { val: ObjId::NULL });
+    assert_ne!(JvmValue::ObjRef { val: ObjId::NULL }, JvmValue::ObjRef { val: ObjId::of(1) });
+}
+
+#[test]
+fn jvm_value_from_primitives() {
+    assert_eq!(JvmValue::Int { val: 7 }, JvmValue::from(7));
+    assert_eq!(JvmValue::Long { val: 7 }, JvmValue::from(7i64));
+    assert_eq!(JvmValue::Float { val: 1.5 }, JvmValue::from(1.5f32));
+    assert_eq!(JvmValue::Double { val: 1.5 }, JvmValue::from(1.5));
+    assert_eq!(JvmValue::Int { val: 1 }, JvmValue::from(true));
+    assert_eq!(JvmValue::Int { val: 0 }, JvmValue::from(false));
+}
+
+#[test]
+fn jvm_value_try_into_primitives() {
+    assert_eq!(Ok(7), i32::try_from(JvmValue::Int { val: 7 }));
+    assert_eq!(Ok(-2), i32::try_from(JvmValue::Byte { val: -2 }));
+    assert_eq!(Ok(7), i64::try_from(JvmValue::Long { val: 7 }));
+    assert_eq!(Ok(1.5), f32::try_from(JvmValue::Float { val: 1.5 }));
+    assert_eq!(Ok(1.5), f64::try_from(JvmValue::Double { val: 1.5 }));
+    assert_eq!(Ok(true), bool::try_from(JvmValue::Boolean { val: true }));
+    assert_eq!(Ok(true), bool::try_from(JvmValue::from(true)));
+    assert_eq!(Ok(false), bool::try_from(JvmValue::Int { val: 0 }));
+}
+
+#[test]
+fn jvm_value_try_into_wrong_type_fails() {
+    assert_eq!(
+        Err(RuntimeError::ValueTypeMismatch {
+            expected: 'I',
+            found: JvmValue::Long { val: 7 },
+        }),
+        i32::try_from(JvmValue::Long { val: 7 })
+    );
+    assert!(i64::try_from(JvmValue::Int { val: 7 }).is_err());
+    assert!(f32::try_from(JvmValue::Double { val: 1.5 }).is_err());
+    assert!(f64::try_from(JvmValue::ObjRef { val: ObjId::NULL }).is_err());
+    assert!(bool::try_from(JvmValue::Int { val: 2 }).is_err());
+
+    let err = i32::try_from(JvmValue::Long { val: 7 }).unwrap_err();
+    assert_eq!("Expected a value of type I, found J(7)", err.to_string());
+}
+
+#[test]
+fn jvm_values_conform_to_descriptor_types() {
+    let i = JvmValue::Int { val: 1 };
+    assert!(i.conforms_to('I'));
+    assert!(i.conforms_to('B'));
+    assert!(i.conforms_to('Z'));
+    assert!(JvmValue::Char { val: 'a' }.conforms_to('I'));
+    assert!(JvmValue::Long { val: 1 }.conforms_to('J'));
+    assert!(JvmValue::ObjRef { val: ObjId::NULL }.conforms_to('L'));
+    assert!(JvmValue::ObjRef { val: ObjId::NULL }.conforms_to('['));
+
+    assert!(!JvmValue::Double { val: 1.0 }.conforms_to('I'));
+    assert!(!JvmValue::Float { val: 1.0 }.conforms_to('D'));
+    assert!(!i.conforms_to('J'));
+    assert!(!i.conforms_to('L'));
+    assert!(!JvmValue::ObjRef { val: ObjId::NULL }.conforms_to('I'));
+}
+
+fn parse_long_constants() -> OtKlass {
+    let bytes = match file_to_bytes(Path::new("../resources/test/LongConstants.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading LongConstants"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "LongConstants.class".to_string());
+    parser.parse();
+    assert_eq!(23, parser.get_pool_size());
+    parser.klass()
+}
+
+#[test]
+fn cp_indices_skip_wide_entries() {
+    let k = parse_long_constants();
+
+    match k.lookup_cp(6) {
+        CpEntry::utf8 { val: s } => assert_eq!("()V", s),
+        e => panic!("Unexpected CP entry {:?} at 6", e),
+    }
+    match k.lookup_cp(7) {
+        CpEntry::long { val: l } => assert_eq!(1234567890123, l),
+        e => panic!("Unexpected CP entry {:?} at 7", e),
+    }
+    match k.lookup_cp(8) {
+        CpEntry::unusable {} => (),
+        e => panic!("Unexpected CP entry {:?} at 8", e),
+    }
+    match k.lookup_cp(9) {
+        CpEntry::double { val: d } => assert_eq!(2.5, d),
+        e => panic!("Unexpected CP entry {:?} at 9", e),
+    }
+    match k.lookup_cp(11) {
+        CpEntry::integer { val: i } => assert_eq!(100000, i),
+        e => panic!("Unexpected CP entry {:?} at 11", e),
+    }
+    assert_eq!("LongConstants", k.cp_as_string(12));
+}
+
+#[test]
+#[should_panic(expected = "CP index 0")]
+fn cp_index_zero_is_rejected() {
+    let k = parse_long_constants();
+    k.lookup_cp(0);
+}
+
+#[test]
+fn klass_builder_two_methods() {
+    let k = KlassBuilder::new("Built")
+        .add_method("one", "()I", ACC_PUBLIC | ACC_STATIC, vec![Opcode::ICONST_1, Opcode::IRETURN])
+        .add_method("two", "(I)I", ACC_PUBLIC | ACC_STATIC, vec![Opcode::ICONST_2, Opcode::IRETURN])
+        .build();
+    assert_eq!("Built", k.get_name());
+    assert_eq!("java/lang/Object", k.get_super_name());
+
+    let one = k
+        .get_method_by_name_and_desc(&"Built.one:()I".to_string())
+        .expect("Built.one:()I not found");
+    assert_eq!(vec![Opcode::ICONST_1, Opcode::IRETURN], one.get_code());
+    let two = k
+        .get_method_by_name_and_desc(&"Built.two:(I)I".to_string())
+        .expect("Built.two:(I)I not found");
+    assert_eq!(ACC_PUBLIC | ACC_STATIC, two.get_flags());
+
+    // The klass entry and the method names and descriptors are in the pool
+    assert_eq!("Built", k.cp_as_string(2));
+    assert_eq!("one", k.cp_as_string(3));
+    assert_eq!("(I)I", k.cp_as_string(6));
+}
+
+#[test]
+fn identity_hash_is_stable() {
+    let k = KlassBuilder::new("Hashed").build();
+    let mut heap = simple_heap::SharedSimpleHeap::of();
+    let id1 = heap.allocate_obj(&k);
+    let id2 = heap.allocate_obj(&k);
+
+    let h1 = heap.get_hash_code(id1);
+    assert_eq!(h1, heap.get_hash_code(id1));
+    assert_ne!(h1, heap.get_hash_code(id2));
+
+    // Taking the monitor must not disturb the stored hash
+    heap.monitor_enter(id1);
+    assert_eq!(h1, heap.get_hash_code(id1));
+    assert_eq!(1, heap.get_obj(id1).get_lock_count());
+}
+
+#[test]
+fn line_numbers_map_pc_to_source_line() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/SampleInvoke.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading SampleInvoke"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "SampleInvoke.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    let meth = k
+        .get_method_by_name_and_desc(&"SampleInvoke.foo:()I".to_string())
+        .expect("SampleInvoke.foo:()I not found");
+
+    assert_eq!(Some(8), meth.line_for_pc(0));
+    assert_eq!(Some(9), meth.line_for_pc(2));
+    assert_eq!(Some(9), meth.line_for_pc(5));
+    assert_eq!(Some(10), meth.line_for_pc(8));
+
+    // No debug info
+    let built = KlassBuilder::new("Built")
+        .add_method("one", "()I", ACC_PUBLIC | ACC_STATIC, vec![Opcode::ICONST_1, Opcode::IRETURN])
+        .build();
+    let one = built
+        .get_method_by_name_and_desc(&"Built.one:()I".to_string())
+        .expect("Built.one:()I not found");
+    assert_eq!(None, one.line_for_pc(0));
+
+    // Entries can come in any order, and pcs before the first entry have no line
+    let mut m = one.clone();
+    m.set_line_numbers(vec![(13, 4), (2, 4), (19, 7), (9, 5), (4, 3)]);
+    assert_eq!(None, m.line_for_pc(1));
+    assert_eq!(Some(4), m.line_for_pc(2));
+    assert_eq!(Some(3), m.line_for_pc(6));
+    assert_eq!(Some(5), m.line_for_pc(12));
+    assert_eq!(Some(4), m.line_for_pc(16));
+    assert_eq!(Some(7), m.line_for_pc(400));
+}
+
+#[test]
+fn try_lookups_hit_and_miss() {
+    let mut repo = SharedKlassRepo::of();
+    let k = KlassBuilder::new("Present")
+        .add_method("one", "()I", ACC_PUBLIC | ACC_STATIC, vec![Opcode::ICONST_1, Opcode::IRETURN])
+        .build();
+    repo.add_klass(&k);
+
+    let found = repo
+        .try_lookup_klass(&"Present".to_string())
+        .expect("Present not found");
+    assert_eq!("Present", found.get_name());
+    assert!(repo.try_lookup_klass(&"Absent".to_string()).is_none());
+    // The superclass has been mentioned, but not loaded
+    assert!(repo.try_lookup_klass(&"java/lang/Object".to_string()).is_none());
+
+    assert!(k
+        .try_get_method_by_name_and_desc(&"Present.one:()I".to_string())
+        .is_some());
+    assert!(k
+        .try_get_method_by_name_and_desc(&"Present.two:()I".to_string())
+        .is_none());
+    let m = repo
+        .try_lookup_method_exact(&"Present".to_string(), "Present.one:()I".to_string())
+        .expect("Present.one:()I not found");
+    assert_eq!("Present.one:()I", m.get_fq_name_desc());
+    assert!(repo
+        .try_lookup_method_exact(&"Present".to_string(), "Present.one:()J".to_string())
+        .is_none());
+    assert!(repo
+        .try_lookup_method_exact(&"Absent".to_string(), "Absent.one:()I".to_string())
+        .is_none());
+}
+
+#[test]
+#[should_panic(expected = "No loaded klass called Absent found in repo")]
+fn lookup_klass_panics_on_miss() {
+    SharedKlassRepo::of().lookup_klass(&"Absent".to_string());
+}
+
+fn local_vars_sum() -> OtMethod {
+    let bytes = match file_to_bytes(Path::new("../resources/test/LocalVars.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading LocalVars"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "LocalVars.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    k.get_method_by_name_and_desc(&"LocalVars.sum:(II)I".to_string())
+        .expect("LocalVars.sum:(II)I not found")
+        .clone()
+}
+
+#[test]
+fn local_vars_sized_from_max_locals() {
+    let meth = local_vars_sum();
+    assert_eq!(3, meth.get_local_var_size());
+
+    let mut lvt = InterpLocalVars::with_capacity(meth.get_local_var_size() as usize);
+    assert_eq!(3, lvt.len());
+    lvt.store(2, JvmValue::Int { val: 7 });
+    assert_eq!(JvmValue::Int { val: 7 }, lvt.load(2));
+}
+
+#[test]
+fn local_vars_store_past_max_locals_is_an_error() {
+    let meth = local_vars_sum();
+    let mut lvt = InterpLocalVars::with_capacity(meth.get_local_var_size() as usize);
+    let out_of_range = RuntimeError::LocalVarOutOfRange {
+        idx: 3,
+        max_locals: 3,
+    };
+    assert_eq!(Err(out_of_range.clone()), lvt.try_store(3, JvmValue::Int { val: 7 }));
+    assert_eq!(Err(out_of_range.clone()), lvt.store_int(3, JvmValue::Int { val: 7 }));
+    assert_eq!(Err(out_of_range), lvt.load_int(3));
+    // A long in the last slot would spill past the end
+    assert_eq!(
+        Err(RuntimeError::LocalVarOutOfRange {
+            idx: 3,
+            max_locals: 3
+        }),
+        lvt.store_long(2, JvmValue::Long { val: 7 })
+    );
+}
+
+#[test]
+fn local_variable_names() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/LocalVars.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading LocalVars"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "LocalVars.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    let meth = k
+        .get_method_by_name_and_desc(&"LocalVars.sum:(II)I".to_string())
+        .expect("LocalVars.sum:(II)I not found");
+
+    assert_eq!(Some("first".to_string()), meth.local_name(0, 0));
+    assert_eq!(Some("second".to_string()), meth.local_name(1, 5));
+    // total is only in scope once it has been stored
+    assert_eq!(None, meth.local_name(2, 3));
+    assert_eq!(Some("total".to_string()), meth.local_name(2, 4));
+    assert_eq!(None, meth.local_name(3, 0));
+}
+
+#[test]
+fn load_jar_adds_every_class() {
+    let mut repo = SharedKlassRepo::of();
+    let count = jar_loader::load_jar(Path::new("../resources/test/jar/foo.jar"), &mut repo)
+        .expect("Error loading foo.jar");
+    assert_eq!(2, count);
+    assert!(repo.try_lookup_klass(&"Foo".to_string()).is_some());
+    assert!(repo.try_lookup_klass(&"Bar".to_string()).is_some());
+}
+
+#[test]
+fn load_jar_skips_malformed_entries() {
+    let mut repo = SharedKlassRepo::of();
+    match jar_loader::load_jar(Path::new("../resources/test/jar/broken.jar"), &mut repo) {
+        Err(jar_loader::LoadError::MalformedEntries { loaded, errors }) => {
+            assert_eq!(2, loaded);
+            let names: Vec<String> = errors.into_iter().map(|(name, _)| name).collect();
+            assert_eq!(vec!["Truncated.class", "NotAClass.class"], names);
+        }
+        other => panic!("Unexpected result loading broken.jar: {:?}", other),
+    }
+    assert!(repo.try_lookup_klass(&"Foo".to_string()).is_some());
+    assert!(repo.try_lookup_klass(&"Bar".to_string()).is_some());
+
+    match jar_loader::load_jar(Path::new("../resources/test/jar/missing.jar"), &mut repo) {
+        Err(jar_loader::LoadError::Io { path: _, msg: _ }) => (),
+        other => panic!("Unexpected result loading missing.jar: {:?}", other),
+    }
+}
+
+#[test]
+fn branch_out_of_range_is_malformed() {
+    let mut bytes = match file_to_bytes(Path::new("../resources/test/Iffer.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading Iffer"),
+    };
+    // Retarget the if_icmpne in Iffer.baz (iadd; iconst_2; if_icmpne +5) past the end
+    let branch = [Opcode::IADD, Opcode::ICONST_2, Opcode::IF_ICMPNE, 0, 5];
+    let pos = bytes
+        .windows(branch.len())
+        .position(|w| w == branch)
+        .expect("Branch not found in Iffer");
+    bytes[pos + 4] = 0x40;
+
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Iffer.class".to_string());
+    match parser.try_parse() {
+        Err(klass_parser::ParseError::MalformedCode { method, pc, reason: _ }) => {
+            assert_eq!("Iffer.baz:()I", method);
+            assert_eq!(8, pc);
+        }
+        Err(e) => panic!("Unexpected error parsing Iffer: {}", e),
+        Ok(_) => panic!("Out of range branch was not rejected"),
+    }
+}
+
+#[test]
+fn class_file_version_is_kept_and_checked() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/AddLdc.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading AddLdc"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes.clone(), "AddLdc.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    assert_eq!(58, k.get_major_version());
+    assert_eq!(0, k.get_minor_version());
+
+    // The same file, but from some future Java
+    let mut too_new = bytes.clone();
+    too_new[7] = 99;
+    let mut parser = klass_parser::OtKlassParser::of(too_new, "AddLdc.class".to_string());
+    assert_eq!(
+        Err(klass_parser::ParseError::UnsupportedClassVersion {
+            major: 99,
+            minor: 0,
+            max_major: klass_parser::MAX_MAJOR_VERSION
+        }),
+        parser.try_parse()
+    );
+
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "AddLdc.class".to_string())
+        .with_max_major_version(52);
+    match parser.try_parse() {
+        Err(e) => assert_eq!(
+            "Class file version 58.0 is newer than the highest supported, 52.0",
+            e.to_string()
+        ),
+        Ok(_) => panic!("Java 14 class file accepted with a Java 8 maximum"),
+    }
+}
+
+#[test]
+fn method_disassembles_switch() {
+    let code = vec![
+        Opcode::ILOAD_0,
+        Opcode::TABLESWITCH,
+        0, 0, // padding to a 4-byte boundary
+        0, 0, 0, 27, // default
+        0, 0, 0, 0, // low
+        0, 0, 0, 1, // high
+        0, 0, 0, 23,
+        0, 0, 0, 25,
+        Opcode::ICONST_1,
+        Opcode::IRETURN,
+        Opcode::ICONST_2,
+        Opcode::IRETURN,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+    ];
+    let k = KlassBuilder::new("Switcher")
+        .add_method("pick", "(I)I", ACC_PUBLIC | ACC_STATIC, code)
+        .build();
+    let meth = k
+        .get_method_by_name_and_desc(&"Switcher.pick:(I)I".to_string())
+        .expect("Switcher.pick:(I)I not found");
+
+    let out = meth.disassemble(&k);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(8, lines.len());
+    assert_eq!("0: iload_0", lines[0]);
+    assert_eq!("1: tableswitch { 0: 24, 1: 26, default: 28 }", lines[1]);
+    assert_eq!("24: iconst_1", lines[2]);
+    assert_eq!("29: ireturn", lines[7]);
+}
+
+#[test]
+fn assignability_follows_supers_and_interfaces() {
+    let mut repo = SharedKlassRepo::of();
+    repo.add_klass(&KlassBuilder::new("Pet").build());
+    repo.add_klass(&KlassBuilder::new("Animal").build());
+    repo.add_klass(&KlassBuilder::new("Dog").with_super("Animal").with_interface("Pet").build());
+    repo.add_klass(&KlassBuilder::new("Puppy").with_super("Dog").build());
+
+    assert!(repo.is_assignable_from("Puppy", "Puppy"));
+    assert!(repo.is_assignable_from("Puppy", "Dog"));
+    assert!(repo.is_assignable_from("Puppy", "Animal"));
+    assert!(repo.is_assignable_from("Dog", "Pet"));
+    // Interfaces are inherited from the superclass
+    assert!(repo.is_assignable_from("Puppy", "Pet"));
+    assert!(repo.is_assignable_from("Pet", "java/lang/Object"));
+
+    assert!(!repo.is_assignable_from("Animal", "Dog"));
+    assert!(!repo.is_assignable_from("Animal", "Pet"));
+    // Unloaded klasses
+    assert!(!repo.is_assignable_from("Cat", "Animal"));
+    assert!(repo.is_assignable_from("Cat", "java/lang/Object"));
+}
+
+#[test]
+fn typed_local_vars_round_trip() {
+    let mut lvt = InterpLocalVars::with_capacity(8);
+    lvt.store_int(0, JvmValue::Int { val: -3 }).unwrap();
+    lvt.store_long(1, JvmValue::Long { val: 1 << 40 }).unwrap();
+    lvt.store_float(3, JvmValue::Float { val: 1.5 }).unwrap();
+    lvt.store_double(4, JvmValue::Double { val: -2.25 }).unwrap();
+    lvt.store_ref(6, JvmValue::ObjRef { val: ObjId::of(17) }).unwrap();
+
+    assert_eq!(JvmValue::Int { val: -3 }, lvt.load_int(0).unwrap());
+    assert_eq!(JvmValue::Long { val: 1 << 40 }, lvt.load_long(1).unwrap());
+    assert_eq!(JvmValue::Float { val: 1.5 }, lvt.load_float(3).unwrap());
+    assert_eq!(JvmValue::Double { val: -2.25 }, lvt.load_double(4).unwrap());
+    assert_eq!(JvmValue::ObjRef { val: ObjId::of(17) }, lvt.load_ref(6).unwrap());
+}
+
+#[test]
+#[should_panic(expected = "Local var 0 holds J(7), expected int")]
+fn typed_local_vars_reject_long_read_as_int() {
+    let mut lvt = InterpLocalVars::with_capacity(2);
+    lvt.store_long(0, JvmValue::Long { val: 7 }).unwrap();
+    lvt.load_int(0).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Local var 1 is the second half of a long or double")]
+fn typed_local_vars_reject_second_half_of_double() {
+    let mut lvt = InterpLocalVars::with_capacity(2);
+    lvt.store_double(0, JvmValue::Double { val: 7.0 }).unwrap();
+    lvt.load_int(1).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Non-reference value I(7) stored as reference in local var 0")]
+fn typed_local_vars_reject_store_of_wrong_type() {
+    let mut lvt = InterpLocalVars::with_capacity(1);
+    lvt.store_ref(0, JvmValue::Int { val: 7 }).unwrap();
+}
+
+#[test]
+fn class_loader_resolves_unseen_klass_and_its_super() {
+    let mut context = VmContext::of();
+    context
+        .get_class_loader_mut()
+        .add_dir(Path::new("../resources/test/lazy"));
+    let child = "LazyChild".to_string();
+    let parent = "LazyParent".to_string();
+    assert!(context.get_repo().try_lookup_klass(&child).is_none());
+
+    let k = context
+        .try_lookup_klass(&child)
+        .unwrap()
+        .expect("LazyChild not found on classpath");
+    assert_eq!(parent, k.get_super_name());
+
+    // The superclass came along with it, without being asked for
+    assert!(context.get_repo().try_lookup_klass(&parent).is_some());
+    assert!(context.get_repo().is_assignable_from(&child, &parent));
+    assert!(context.try_lookup_klass(&"NotOnClasspath".to_string()).unwrap().is_none());
+}
+
+#[test]
+fn class_loader_reads_klasses_from_jar() {
+    let jar = Path::new("../resources/test/jar/two.jar");
+    let mut loader = class_loader::ClassLoader::of();
+    loader.add_jar(jar);
+
+    for klass_name in &["Top", "pkg/sub/Nested"] {
+        let k = loader
+            .load_klass(klass_name)
+            .unwrap()
+            .unwrap_or_else(|| panic!("{} not found in jar", klass_name));
+        assert_eq!(*klass_name, k.get_name());
+    }
+    assert!(loader.load_klass("pkg/sub/Absent").unwrap().is_none());
+
+    match jar_loader::load_klass_from_jar(jar, "pkg/sub/Absent") {
+        Err(e) => assert_eq!(
+            "No entry pkg/sub/Absent.class found in ../resources/test/jar/two.jar",
+            e.to_string()
+        ),
+        Ok(_) => panic!("pkg/sub/Absent should not be in the jar"),
+    }
+}
+
+#[test]
+fn class_loader_reports_unreadable_klasses() {
+    let mut loader = class_loader::ClassLoader::of();
+    loader.add_dir(Path::new("../resources/test/badcp"));
+    match loader.load_klass("Broken") {
+        Err(jar_loader::LoadError::MalformedEntries { loaded: 0, errors }) => {
+            assert_eq!(vec![("Broken.class".to_string(), "Not a class file".to_string())], errors)
+        }
+        other => panic!("Unexpected result loading Broken: {:?}", other),
+    }
+
+    // A jar that can't be opened stops the search rather than being skipped
+    let mut loader = class_loader::ClassLoader::of();
+    loader.add_jar(Path::new("../resources/test/jar/missing.jar"));
+    loader.add_dir(Path::new("../resources/test/lazy"));
+    match loader.load_klass("LazyChild") {
+        Err(jar_loader::LoadError::Io { path, msg: _ }) => {
+            assert_eq!("../resources/test/jar/missing.jar", path)
+        }
+        other => panic!("Unexpected result loading LazyChild: {:?}", other),
+    }
+
+    let mut context = VmContext::of();
+    context.get_class_loader_mut().add_dir(Path::new("../resources/test/badcp"));
+    match context.try_lookup_klass(&"Broken".to_string()) {
+        Err(RuntimeError::NoClassDefFoundError { klass_name, msg: _ }) => assert_eq!("Broken", klass_name),
+        other => panic!("Unexpected result looking up Broken: {:?}", other),
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn klass_serde_round_trip() {
+    let k = KlassBuilder::new("Cached")
+        .with_interface("Cacheable")
+        .add_method("answer", "()I", ACC_PUBLIC | ACC_STATIC, vec![Opcode::BIPUSH, 42, Opcode::IRETURN])
+        .build();
+    let bytes = bincode::serialize(&k).expect("Cached could not be serialized");
+    let k2: OtKlass = bincode::deserialize(&bytes).expect("Cached could not be deserialized");
+
+    assert_eq!("Cached", k2.get_name());
+    assert_eq!(&vec!["Cacheable".to_string()], k2.get_interfaces());
+    let answer = k2
+        .get_method_by_name_and_desc(&"Cached.answer:()I".to_string())
+        .expect("Cached.answer:()I not found after round trip");
+    assert_eq!(vec![Opcode::BIPUSH, 42, Opcode::IRETURN], answer.get_code());
+    assert_eq!(ACC_PUBLIC | ACC_STATIC, answer.get_flags());
+    assert_eq!("Cacheable", k2.cp_as_string(8));
+}
+
+#[test]
+fn iterate_methods_and_fields() {
+    let k = KlassBuilder::new("Three")
+        .add_method("a", "()V", ACC_PUBLIC, vec![Opcode::RETURN])
+        .add_method("b", "()V", ACC_PUBLIC, vec![Opcode::RETURN])
+        .add_method("c", "()I", ACC_PUBLIC | ACC_STATIC, vec![Opcode::ICONST_0, Opcode::IRETURN])
+        .build();
+    assert_eq!(3, k.methods_iter().count());
+    assert_eq!(1, k.methods_iter().filter(|m| m.get_flags() & ACC_STATIC == ACC_STATIC).count());
+    assert_eq!(0, k.fields_iter().count());
+
+    let bytes = match file_to_bytes(Path::new("../resources/test/Aliaser.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading Aliaser"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Aliaser.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    let names: Vec<String> = k.fields_iter().map(|f| f.get_name()).collect();
+    assert_eq!(vec!["count".to_string(), "instances".to_string()], names);
+}
+
+#[test]
+fn verifier_accepts_well_typed_methods() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/SampleInvoke.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading SampleInvoke"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "SampleInvoke.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    for m in k.methods_iter() {
+        assert_eq!(Ok(()), verifier::verify_method(&k, m), "{}", m.get_fq_name_desc());
+    }
+}
+
+#[test]
+fn verifier_rejects_iadd_on_doubles() {
+    let k = KlassBuilder::new("IllTyped")
+        .add_method(
+            "add",
+            "()I",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![Opcode::DCONST_1, Opcode::DCONST_1, Opcode::IADD, Opcode::IRETURN],
+        )
+        .build();
+    let m = k
+        .get_method_by_name_and_desc(&"IllTyped.add:()I".to_string())
+        .expect("IllTyped.add:()I not found");
+    match verifier::verify_method(&k, m) {
+        Err(RuntimeError::VerifyError { msg }) => assert_eq!(
+            "Expected Int on the stack, found Double at pc 2 in IllTyped.add:()I",
+            msg
+        ),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+}
+
+#[test]
+fn verifier_merges_locals_at_branch_targets() {
+    // static int pick(int):
+    //   iload_0; ifeq +8; fconst_1; fstore_1; goto +5; iconst_1; istore_1; iload_1; ireturn
+    let code = vec![
+        Opcode::ILOAD_0,
+        Opcode::IFEQ,
+        0,
+        8,
+        Opcode::FCONST_1,
+        Opcode::FSTORE_1,
+        Opcode::GOTO,
+        0,
+        5,
+        Opcode::ICONST_1,
+        Opcode::ISTORE_1,
+        Opcode::ILOAD_1,
+        Opcode::IRETURN,
+    ];
+    let k = KlassBuilder::new("Merge")
+        .add_method("pick", "(I)I", ACC_PUBLIC | ACC_STATIC, code.clone())
+        .build();
+    let m = k
+        .get_method_by_name_and_desc(&"Merge.pick:(I)I".to_string())
+        .expect("Merge.pick:(I)I not found");
+
+    // Local 1 is an int on one path and a float on the other, so can't be loaded
+    match verifier::verify_method(&k, m) {
+        Err(RuntimeError::VerifyError { msg }) => assert_eq!(
+            "Local var 1 holds Top, expected Int at pc 11 in Merge.pick:(I)I",
+            msg
+        ),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+
+    // With an int stored on both paths the merge is fine
+    let mut fixed = code;
+    fixed[4] = Opcode::ICONST_2;
+    fixed[5] = Opcode::ISTORE_1;
+    let k = KlassBuilder::new("Merge")
+        .add_method("pick", "(I)I", ACC_PUBLIC | ACC_STATIC, fixed)
+        .build();
+    let m = k
+        .get_method_by_name_and_desc(&"Merge.pick:(I)I".to_string())
+        .expect("Merge.pick:(I)I not found");
+    assert_eq!(Ok(()), verifier::verify_method(&k, m));
+}
+
+// KlassBuilder can't add an exception table, so it's set on a copy of the method
+fn method_with_handlers(code: Vec<u8>, desc: &str, handlers: Vec<otmethod::ExceptionHandler>) -> (OtKlass, OtMethod) {
+    let k = KlassBuilder::new("Handled")
+        .add_method("run", desc, ACC_PUBLIC | ACC_STATIC, code)
+        .build();
+    let mut m = k
+        .get_method_by_name_and_desc(&format!("Handled.run:{}", desc))
+        .expect("Handled.run not found")
+        .clone();
+    m.set_exception_table(handlers);
+    (k, m)
+}
+
+fn catch_all(start_pc: u16, end_pc: u16, handler_pc: u16) -> otmethod::ExceptionHandler {
+    otmethod::ExceptionHandler {
+        start_pc,
+        end_pc,
+        handler_pc,
+        catch_type: None,
+    }
+}
+
+#[test]
+fn verifier_checks_exception_handlers() {
+    // iconst_1; ireturn; then a handler that adds a double to the exception
+    let code = vec![Opcode::ICONST_1, Opcode::IRETURN, Opcode::DCONST_1, Opcode::IADD, Opcode::IRETURN];
+    let (k, m) = method_with_handlers(code, "()I", vec![catch_all(0, 2, 2)]);
+    match verifier::verify_method(&k, &m) {
+        Err(RuntimeError::VerifyError { msg }) => assert_eq!(
+            "Expected Int on the stack, found Double at pc 3 in Handled.run:()I",
+            msg
+        ),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+
+    // aconst_null; areturn; then a handler that returns the exception
+    let code = vec![Opcode::ACONST_NULL, Opcode::ARETURN, Opcode::ARETURN];
+    let (k, m) = method_with_handlers(code, "()Ljava/lang/Object;", vec![catch_all(0, 2, 2)]);
+    assert_eq!(Ok(()), verifier::verify_method(&k, &m));
+
+    // javac's handlers, with their stack map frames
+    for name in &["Catcher", "VmExceptions"] {
+        let path = format!("../resources/test/{}.class", name);
+        let bytes = file_to_bytes(Path::new(&path)).unwrap_or_else(|_| panic!("Error reading {}", name));
+        let mut parser = klass_parser::OtKlassParser::of(bytes, format!("{}.class", name));
+        parser.parse();
+        let k = parser.klass();
+        for m in k.methods_iter() {
+            assert_eq!(Ok(()), verifier::verify_method(&k, m), "{}", m.get_fq_name_desc());
+        }
+    }
+}
+
+#[test]
+fn verifier_rejects_handlers_between_instructions() {
+    // sipush 1; ireturn, so pc 1 is in the middle of the sipush
+    let code = vec![Opcode::SIPUSH, 0, 1, Opcode::IRETURN];
+    for (handler, msg) in &[
+        (catch_all(0, 3, 1), "Exception handler is not the start of an instruction at pc 1"),
+        (catch_all(0, 3, 9), "Exception handler is not the start of an instruction at pc 9"),
+        (catch_all(1, 3, 3), "Exception range 1 to 3 is not a run of instructions at pc 1"),
+        (catch_all(0, 2, 3), "Exception range 0 to 2 is not a run of instructions at pc 2"),
+        (catch_all(3, 3, 3), "Exception range 3 to 3 is not a run of instructions at pc 3"),
+    ] {
+        let (k, m) = method_with_handlers(code.clone(), "()I", vec![handler.clone()]);
+        assert_eq!(
+            Err(RuntimeError::VerifyError {
+                msg: format!("{} in Handled.run:()I", msg)
+            }),
+            verifier::verify_method(&k, &m)
+        );
+    }
+
+    // A range may run to the end of the code, here with a handler that drops the
+    // exception and returns 2
+    let mut code = code;
+    code.extend(vec![Opcode::POP, Opcode::ICONST_2, Opcode::IRETURN]);
+    let (k, m) = method_with_handlers(code, "()I", vec![catch_all(0, 7, 4)]);
+    assert_eq!(Ok(()), verifier::verify_method(&k, &m));
+}
+
+#[test]
+fn method_lookup_map_for_many_methods() {
+    let mut builder = KlassBuilder::new("Wide");
+    for i in 0..2000 {
+        builder = builder.add_method(
+            &format!("m{}", i),
+            "()I",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![Opcode::ICONST_0, Opcode::IRETURN],
+        );
+    }
+    let k = builder.build();
+
+    assert_eq!(2000, k.methods_iter().count());
+    for i in 0..2000 {
+        let fq_name = format!("Wide.m{}:()I", i);
+        let m = k
+            .get_method_by_name_and_desc(&fq_name)
+            .unwrap_or_else(|| panic!("{} not found", fq_name));
+        assert_eq!(fq_name, m.get_fq_name_desc());
+    }
+    assert!(k.try_get_method_by_name_and_desc(&"Wide.m2000:()I".to_string()).is_none());
+}
+
+#[test]
+fn stack_map_frames_decode() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/StackMaps.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading StackMaps"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "StackMaps.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    let frames_of = |name_desc: &str| {
+        k.get_method_by_name_and_desc(&name_desc.to_string())
+            .unwrap_or_else(|| panic!("{} not found", name_desc))
+            .get_stack_map()
+            .clone()
+    };
+    use crate::verifier::StackMapFrame;
+    use crate::verifier::VType::*;
+
+    // append_frame of two ints at the loop head, then chop_frame of one after the loop
+    assert_eq!(
+        vec![
+            StackMapFrame { pc: 4, locals: vec![Int, Int, Int], stack: vec![] },
+            StackMapFrame { pc: 19, locals: vec![Int, Int], stack: vec![] },
+        ],
+        frames_of("StackMaps.loop:(I)I")
+    );
+    // A long takes two slots in the locals
+    assert_eq!(
+        vec![
+            StackMapFrame { pc: 9, locals: vec![Int, Long, Top], stack: vec![] },
+            StackMapFrame { pc: 11, locals: vec![Int, Long, Top, Long, Top], stack: vec![] },
+        ],
+        frames_of("StackMaps.pick:(ZJ)J")
+    );
+    // same_frame, then same_locals_1_stack_item_frame
+    assert_eq!(
+        vec![
+            StackMapFrame { pc: 8, locals: vec![Int], stack: vec![] },
+            StackMapFrame { pc: 9, locals: vec![Int], stack: vec![Int] },
+        ],
+        frames_of("StackMaps.choose:(Z)I")
+    );
+
+    for m in k.methods_iter() {
+        assert_eq!(Ok(()), verifier::verify_method(&k, m), "{}", m.get_fq_name_desc());
+    }
+}
+
+#[test]
+fn verifier_checks_against_stack_map() {
+    // static int choose(boolean): iload_0; ifeq +7; iconst_1; goto +4; iconst_2; ireturn
+    let k = KlassBuilder::new("Choose")
+        .add_method(
+            "choose",
+            "(Z)I",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![
+                Opcode::ILOAD_0,
+                Opcode::IFEQ,
+                0,
+                7,
+                Opcode::ICONST_1,
+                Opcode::GOTO,
+                0,
+                4,
+                Opcode::ICONST_2,
+                Opcode::IRETURN,
+            ],
+        )
+        .build();
+    let mut m = k
+        .get_method_by_name_and_desc(&"Choose.choose:(Z)I".to_string())
+        .expect("Choose.choose:(Z)I not found")
+        .clone();
+
+    m.set_stack_map(vec![
+        verifier::StackMapFrame::of(8, &[verifier::VType::Int], vec![]),
+        verifier::StackMapFrame::of(9, &[verifier::VType::Int], vec![verifier::VType::Int]),
+    ]);
+    assert_eq!(Ok(()), verifier::verify_method(&k, &m));
+
+    m.set_stack_map(vec![
+        verifier::StackMapFrame::of(8, &[verifier::VType::Int], vec![]),
+        verifier::StackMapFrame::of(9, &[verifier::VType::Int], vec![verifier::VType::Float]),
+    ]);
+    match verifier::verify_method(&k, &m) {
+        Err(RuntimeError::VerifyError { msg }) => assert_eq!(
+            "Operand stack [Int] doesn't match the stack map's [Float] at pc 9 in Choose.choose:(Z)I",
+            msg
+        ),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+}
+
+#[test]
+fn method_getters_return_constructor_args() {
+    let m = otmethod::OtMethod::of(
+        "Getters".to_string(),
+        "calc".to_string(),
+        "(I)J".to_string(),
+        ACC_PUBLIC | ACC_STATIC,
+        7,
+        9,
+    );
+    assert_eq!("Getters", m.get_klass_name());
+    assert_eq!("calc", m.get_name());
+    assert_eq!("calc:(I)J", m.get_desc());
+    assert_eq!(7, m.get_name_idx());
+    assert_eq!(9, m.get_desc_idx());
+    assert_eq!(ACC_PUBLIC | ACC_STATIC, m.get_flags());
+    assert!(m.get_attrs().is_empty());
+
+    // Parsed methods point back into their klass's constant pool
+    let bytes = match file_to_bytes(Path::new("../resources/test/SampleInvoke.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading SampleInvoke"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "SampleInvoke.class".to_string());
+    parser.parse();
+    let k = parser.klass();
+    let m = k
+        .get_method_by_name_and_desc(&"SampleInvoke.bar:()I".to_string())
+        .expect("SampleInvoke.bar:()I not found");
+    assert_eq!("bar", k.cp_as_string(m.get_name_idx()));
+    assert_eq!("()I", k.cp_as_string(m.get_desc_idx()));
+}
+
+#[test]
+fn method_attrs_are_kept_and_found_by_name() {
+    let bytes = match file_to_bytes(Path::new("../resources/test/SampleInvoke.class")) {
+        Ok(buf) => buf,
+        _ => panic!("Error reading SampleInvoke"),
+    };
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "SampleInvoke.class".to_string());
+    parser.parse();
+    let pool_size = parser.get_pool_size();
+    let k = parser.klass();
+    let utf8_idx = |s: &str| {
+        (1..pool_size)
+            .find(|i| matches!(k.lookup_cp(*i), CpEntry::utf8 { val } if val == s))
+            .unwrap_or_else(|| panic!("No utf8 entry {} in SampleInvoke", s))
+    };
+
+    let mut m = otmethod::OtMethod::of(
+        "SampleInvoke".to_string(),
+        "baz".to_string(),
+        "()V".to_string(),
+        ACC_PUBLIC,
+        0,
+        0,
+    );
+    m.set_attr(0, constant_pool::CpAttr::of(utf8_idx("Code")));
+    m.set_attr(1, constant_pool::CpAttr::of(utf8_idx("LineNumberTable")));
+
+    assert_eq!(2, m.get_attrs().len());
+    let attr = m
+        .get_attr(&k, "LineNumberTable")
+        .expect("LineNumberTable attribute not found");
+    assert_eq!(utf8_idx("LineNumberTable"), attr.get_name_idx());
+    assert!(m.get_attr(&k, "Exceptions").is_none());
+
+    // Parsed methods keep theirs too
+    let bar = k
+        .get_method_by_name_and_desc(&"SampleInvoke.bar:()I".to_string())
+        .expect("SampleInvoke.bar:()I not found");
+    assert!(bar.get_attr(&k, "Code").is_some());
+}
+
+#[test]
+fn klass_builder_wires_up_methods_and_constants() {
+    let builder = KlassBuilder::new("Pair").with_super("Base");
+    let long_idx = builder.next_cp_index();
+    let builder = builder.add_constant(CpEntry::long { val: 1 << 40 });
+    let int_idx = builder.next_cp_index();
+    let k = builder
+        .add_constant(CpEntry::integer { val: 42 })
+        .add_method(
+            "first",
+            "()I",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![Opcode::LDC, int_idx as u8, Opcode::IRETURN],
+        )
+        .add_method("second", "(J)V", ACC_PUBLIC, vec![Opcode::RETURN])
+        .build();
+
+    assert_eq!("Pair", k.get_name());
+    assert_eq!("Base", k.get_super_name());
+    for fq_name in &["Pair.first:()I", "Pair.second:(J)V"] {
+        let m = k
+            .get_method_by_name_and_desc(&fq_name.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fq_name));
+        assert_eq!(*fq_name, m.get_fq_name_desc());
+    }
+
+    // The long's second slot is skipped
+    assert_eq!(long_idx + 2, int_idx);
+    match k.lookup_cp(long_idx) {
+        CpEntry::long { val } => assert_eq!(1 << 40, val),
+        e => panic!("Expected a long at CP index {}, found {:?}", long_idx, e),
+    }
+    match k.lookup_cp(int_idx) {
+        CpEntry::integer { val } => assert_eq!(42, val),
+        e => panic!("Expected an int at CP index {}, found {:?}", int_idx, e),
+    }
+}
+
+#[test]
+fn cp_can_be_walked_without_panicking() {
+    let k = KlassBuilder::new("Small")
+        .add_constant(CpEntry::long { val: 7 })
+        .add_method("run", "()V", ACC_PUBLIC, vec![Opcode::RETURN])
+        .build();
+
+    // Small, run, ()V and java/lang/Object
+    let utf8_count = k
+        .cp_iter()
+        .filter(|(_, e)| matches!(e, CpEntry::utf8 { val: _ }))
+        .count();
+    assert_eq!(4, utf8_count);
+
+    // Slot 0 and the long's second half are left out
+    assert_eq!(9, k.cp_len());
+    assert_eq!(7, k.cp_iter().count());
+    assert!(k.cp_iter().all(|(i, _)| i != 0 && i != 4));
+
+    assert!(k.cp_entry_at(0).is_none());
+    assert!(k.cp_entry_at(4).is_none());
+    assert!(k.cp_entry_at(k.cp_len()).is_none());
+    match k.cp_entry_at(2) {
+        Some(CpEntry::class { idx }) => assert_eq!("Small", k.cp_as_string(*idx)),
+        e => panic!("Expected a class at CP index 2, found {:?}", e),
+    }
+}
+
+#[test]
+fn methodrefs_and_fieldrefs_resolve_to_their_parts() {
+    let b = KlassBuilder::new("Caller");
+    let base = b.next_cp_index();
+    let (math, math_clz, max_nt, max_ref) = (base, base + 1, base + 4, base + 5);
+    let (pi_nt, pi_ref, broken) = (base + 8, base + 9, base + 10);
+    let utf8 = |s: &str| CpEntry::utf8 { val: s.to_string() };
+    let k = b
+        .add_constant(utf8("java/lang/Math"))
+        .add_constant(CpEntry::class { idx: math })
+        .add_constant(utf8("max"))
+        .add_constant(utf8("(II)I"))
+        .add_constant(CpEntry::name_and_type { name_idx: base + 2, type_idx: base + 3 })
+        .add_constant(CpEntry::methodref { clz_idx: math_clz, nt_idx: max_nt })
+        .add_constant(utf8("PI"))
+        .add_constant(utf8("D"))
+        .add_constant(CpEntry::name_and_type { name_idx: base + 6, type_idx: base + 7 })
+        .add_constant(CpEntry::fieldref { clz_idx: math_clz, nt_idx: pi_nt })
+        // Points at the utf8 rather than the class
+        .add_constant(CpEntry::methodref { clz_idx: math, nt_idx: max_nt })
+        .build();
+
+    let parts = |a: &str, b: &str, c: &str| (a.to_string(), b.to_string(), c.to_string());
+    assert_eq!(
+        Ok(parts("java/lang/Math", "max", "(II)I")),
+        k.resolve_methodref(max_ref)
+    );
+    assert_eq!(Ok(parts("java/lang/Math", "PI", "D")), k.resolve_fieldref(pi_ref));
+
+    assert!(k.resolve_fieldref(max_ref).is_err());
+    assert!(k.resolve_methodref(pi_ref).is_err());
+    assert!(k.resolve_methodref(broken).is_err());
+    assert!(k.resolve_methodref(k.cp_len()).is_err());
+}
+
+#[test]
+fn code_builder_resolves_branch_offsets() {
+    // int i = 0; while (i < 10) { i++; } return i;
+    let mut c = CodeBuilder::new();
+    let top = c.new_label();
+    let done = c.new_label();
+    c.iconst(0)
+        .istore(1)
+        .bind(top)
+        .iload(1)
+        .iconst(10)
+        .if_icmpge(done)
+        .iinc(1, 1)
+        .goto(top)
+        .bind(done)
+        .iload(1)
+        .ireturn();
+
+    let expected = vec![
+        Opcode::ICONST_0,
+        Opcode::ISTORE_1,
+        Opcode::ILOAD_1, // 2
+        Opcode::BIPUSH,
+        10,
+        Opcode::IF_ICMPGE, // 5, forward 9 to 14
+        0,
+        9,
+        Opcode::IINC,
+        1,
+        1,
+        Opcode::GOTO, // 11, back 9 to 2
+        0xff,
+        0xf7,
+        Opcode::ILOAD_1, // 14
+        Opcode::IRETURN,
+    ];
+    assert_eq!(expected, c.build());
+
+    let mut c = CodeBuilder::new();
+    c.iconst(-1).iconst(200).iconst(-20000).iload(7).istore(4);
+    assert_eq!(
+        vec![
+            Opcode::ICONST_M1,
+            Opcode::SIPUSH,
+            0,
+            200,
+            Opcode::SIPUSH,
+            0xb1,
+            0xe0,
+            Opcode::ILOAD,
+            7,
+            Opcode::ISTORE,
+            4
+        ],
+        c.build()
+    );
+}
+
+#[test]
+fn access_flags_decode_to_mnemonics() {
+    assert_eq!(
+        vec!["public", "static", "final"],
+        decode_method_flags(ACC_PUBLIC | ACC_STATIC | ACC_FINAL)
+    );
+    assert_eq!(vec!["public", "super"], decode_class_flags(ACC_PUBLIC | ACC_SUPER));
+
+    // 0x0020 and 0x0040 depend on what the flags belong to
+    assert_eq!(vec!["synchronized", "bridge"], decode_method_flags(0x0060));
+    assert_eq!(vec!["super"], decode_class_flags(ACC_SYNCHRONIZED));
+    assert!(decode_class_flags(0).is_empty());
+}
+
+#[test]
+fn klass_display_summarizes_shape() {
+    let bytes = file_to_bytes(Path::new("../resources/test/iface/Greeter.class"))
+        .expect("Error reading Greeter");
+    let mut parser = klass_parser::OtKlassParser::of(bytes, "Greeter.class".to_string());
+    parser.parse();
+    let greeter = parser.klass();
+    let shown = format!("{}", greeter);
+    assert!(shown.starts_with("public interface abstract Greeter extends java/lang/Object"));
+    assert!(shown.contains("(2 methods, "));
+
+    let k = KlassBuilder::new("Shown").with_flags(ACC_PUBLIC).build();
+    let shown = format!("{}", k);
+    assert!(shown.contains("Shown"));
+    assert!(shown.starts_with("public "));
+    assert!(shown.contains("class Shown extends java/lang/Object"));
+}
+
+#[test]
+fn klass_and_method_flags_decode() {
+    let parse = |name: &str| {
+        let path = format!("../resources/test/iface/{}.class", name);
+        let bytes = file_to_bytes(Path::new(&path)).unwrap_or_else(|_| panic!("Error reading {}", name));
+        let mut parser = klass_parser::OtKlassParser::of(bytes, name.to_string() + ".class");
+        parser.parse();
+        parser.klass()
+    };
+
+    let greeter = parse("Greeter");
+    assert!(greeter.is_interface());
+    assert!(greeter.is_abstract());
+    let greet = greeter
+        .get_method_by_name_and_desc(&"Greeter.greet:()I".to_string())
+        .expect("Greeter.greet:()I not found");
+    assert!(greet.is_abstract());
+    assert!(!greet.is_static());
+    assert!(!greet.is_native());
+    let twice = greeter
+        .get_method_by_name_and_desc(&"Greeter.greetTwice:()I".to_string())
+        .expect("Greeter.greetTwice:()I not found");
+    assert!(!twice.is_abstract());
+
+    let english = parse("EnglishGreeter");
+    assert!(!english.is_interface());
+    assert!(!english.is_abstract());
+
+    let caller = parse("GreeterCaller");
+    let run = caller
+        .get_method_by_name_and_desc(&"GreeterCaller.run:()I".to_string())
+        .expect("GreeterCaller.run:()I not found");
+    assert!(run.is_static());
+    assert!(!run.is_abstract());
+}
+
+#[test]
+fn malformed_descriptors_are_errors() {
+    assert_eq!(Ok(Some('[')), descriptor::try_return_type("foo:(I)[J"));
+    assert_eq!(Ok('L'), descriptor::try_field_type("Ljava/lang/String;"));
+    assert!(descriptor::try_arg_types("(Ljava/lang/String)V").is_err());
+    assert!(descriptor::try_arg_types("(IQ)V").is_err());
+    assert!(descriptor::try_arg_types("I)V").is_err());
+    assert!(descriptor::try_return_type("()").is_err());
+    assert!(descriptor::try_field_type("[").is_err());
+    assert!(descriptor::try_field_type("II").is_err());
+}
+
+#[test]
+fn descriptor_slots_and_return_types() {
+    let desc = "(IJLjava/lang/String;[[D[Ljava/lang/Object;)V";
+    assert_eq!(
+        vec!["I", "J", "Ljava/lang/String;", "[[D", "[Ljava/lang/Object;"],
+        descriptor::arg_types(desc)
+    );
+    assert_eq!(vec![1, 2, 1, 1, 1], descriptor::arg_slot_sizes(desc));
+    assert_eq!(6, descriptor::arg_slot_count(desc));
+    assert_eq!(None, descriptor::return_type(desc));
+
+    assert_eq!(0, descriptor::arg_slot_count("()I"));
+    assert_eq!(Some('I'), descriptor::return_type("()I"));
+    assert_eq!(4, descriptor::arg_slot_count("(DJ)J"));
+    assert_eq!(Some('J'), descriptor::return_type("(DJ)J"));
+    assert_eq!(
+        Some('L'),
+        descriptor::return_type("([Ljava/lang/String;)Ljava/lang/String;")
+    );
+    assert_eq!(Some('['), descriptor::return_type("(Ljava/util/List;I)[[I"));
+
+    // A name_desc works just as well
+    assert_eq!(3, descriptor::arg_slot_count("main:(JZ)V"));
+}
+
+#[test]
+fn default_values_follow_field_descriptors() {
+    let dflt = descriptor::default_value_for_descriptor;
+    assert_eq!(JvmValue::Int { val: 0 }, dflt("I"));
+    assert_eq!(JvmValue::Long { val: 0 }, dflt("J"));
+    assert_eq!(JvmValue::Double { val: 0.0 }, dflt("D"));
+    assert_eq!(JvmValue::Boolean { val: false }, dflt("Z"));
+    assert_eq!(JvmValue::ObjRef { val: ObjId::NULL }, dflt("Ljava/lang/Object;"));
+    assert_eq!(JvmValue::ObjRef { val: ObjId::NULL }, dflt("[I"));
+}
+
 // FIXME Convert to klass_parser tests
 // let k = simple_parse_klass("SampleInvoke".to_string());
 // assert_eq!(21, parser.get_pool_size());
@@ -0,0 +1,829 @@
+#![deny(unreachable_patterns)]
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::constant_pool::{CpEntry, ACC_STATIC};
+use crate::descriptor;
+use crate::disasm::switch_padding;
+use crate::opcode::Opcode;
+use crate::opcode::Opcode::*;
+use crate::runtime_error::RuntimeError;
+use crate::OtKlass;
+use crate::OtMethod;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+//////////// BYTECODE VERIFIER
+
+// Verification types. Booleans, bytes, chars and shorts are all ints once they're
+// on the stack, and references aren't told apart by klass. Top is an unset local,
+// the upper half of a long or double, or a local whose type differs between paths
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum VType {
+    Top,
+    Int,
+    Float,
+    Long,
+    Double,
+    Reference,
+}
+
+impl VType {
+    fn is_category_2(self) -> bool {
+        self == VType::Long || self == VType::Double
+    }
+
+    fn size(self) -> usize {
+        if self.is_category_2() {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+// A frame declared by a StackMapTable attribute. The locals are by slot, so a long or
+// double is followed by Top, and any slots past the end of locals are Top
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StackMapFrame {
+    pub pc: u16,
+    pub locals: Vec<VType>,
+    pub stack: Vec<VType>,
+}
+
+impl StackMapFrame {
+    // The StackMapTable lists each long or double local once, rather than per slot
+    pub fn of(pc: u16, local_entries: &[VType], stack: Vec<VType>) -> StackMapFrame {
+        let mut locals = Vec::new();
+        for t in local_entries {
+            locals.push(*t);
+            if t.is_category_2() {
+                locals.push(VType::Top);
+            }
+        }
+        StackMapFrame { pc, locals, stack }
+    }
+}
+
+// The types of the locals and the operand stack on entry to an instruction. A long
+// or double is a single entry on the stack, but two slots in the locals
+#[derive(Clone, Debug, PartialEq)]
+struct Frame {
+    locals: Vec<VType>,
+    stack: Vec<VType>,
+}
+
+impl Frame {
+    fn push(&mut self, t: VType, max_stack: usize) -> Result<(), String> {
+        self.stack.push(t);
+        let depth: usize = self.stack.iter().map(|t| t.size()).sum();
+        if depth > max_stack {
+            return Err(format!(
+                "Operand stack depth {} exceeds max_stack {}",
+                depth, max_stack
+            ));
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<VType, String> {
+        match self.stack.pop() {
+            Some(t) => Ok(t),
+            None => Err("Operand stack underflow".to_string()),
+        }
+    }
+
+    fn pop_expect(&mut self, expected: VType) -> Result<(), String> {
+        let t = self.pop()?;
+        if t != expected {
+            return Err(format!("Expected {:?} on the stack, found {:?}", expected, t));
+        }
+        Ok(())
+    }
+
+    // For the stack manipulation ops that only work on category 1 values
+    fn pop_category_1(&mut self) -> Result<VType, String> {
+        let t = self.pop()?;
+        if t.is_category_2() {
+            return Err(format!("Expected a category 1 value on the stack, found {:?}", t));
+        }
+        Ok(t)
+    }
+
+    fn load(&self, idx: usize, expected: VType) -> Result<(), String> {
+        match self.locals.get(idx) {
+            Some(t) if *t == expected => Ok(()),
+            Some(t) => Err(format!(
+                "Local var {} holds {:?}, expected {:?}",
+                idx, t, expected
+            )),
+            None => Err(format!(
+                "Local var {} out of range for max_locals {}",
+                idx,
+                self.locals.len()
+            )),
+        }
+    }
+
+    fn store(&mut self, idx: usize, t: VType) -> Result<(), String> {
+        if idx + t.size() > self.locals.len() {
+            return Err(format!(
+                "Local var {} out of range for max_locals {}",
+                idx,
+                self.locals.len()
+            ));
+        }
+        // Overwriting the upper half of a long or double invalidates the lower half
+        if idx > 0 && self.locals[idx - 1].is_category_2() {
+            self.locals[idx - 1] = VType::Top;
+        }
+        self.locals[idx] = t;
+        if t.is_category_2() {
+            self.locals[idx + 1] = VType::Top;
+        }
+        Ok(())
+    }
+
+    fn declared(sm: &StackMapFrame, max_locals: usize) -> Frame {
+        let mut locals = sm.locals.clone();
+        locals.resize(max_locals.max(locals.len()), VType::Top);
+        Frame {
+            locals,
+            stack: sm.stack.clone(),
+        }
+    }
+
+    fn check_assignable(&self, declared: &Frame) -> Result<(), String> {
+        if self.stack != declared.stack {
+            return Err(format!(
+                "Operand stack {:?} doesn't match the stack map's {:?}",
+                self.stack, declared.stack
+            ));
+        }
+        for (idx, expected) in declared.locals.iter().enumerate() {
+            let actual = self.locals.get(idx).cloned().unwrap_or(VType::Top);
+            if *expected != VType::Top && actual != *expected {
+                return Err(format!(
+                    "Local var {} holds {:?}, but the stack map expects {:?}",
+                    idx, actual, expected
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // What an exception handler starts with, if an instruction run in this frame throws
+    fn for_handler(&self) -> Frame {
+        Frame {
+            locals: self.locals.clone(),
+            stack: vec![VType::Reference],
+        }
+    }
+
+    // Folds in the frame arriving from another path, returning whether anything changed.
+    // Locals that disagree become unusable, but the stacks have to match exactly
+    fn merge(&mut self, other: &Frame) -> Result<bool, String> {
+        if self.stack != other.stack {
+            return Err(format!(
+                "Operand stacks differ at merge point: {:?} and {:?}",
+                self.stack, other.stack
+            ));
+        }
+        let mut changed = false;
+        for (mine, theirs) in self.locals.iter_mut().zip(other.locals.iter()) {
+            if *mine != *theirs && *mine != VType::Top {
+                *mine = VType::Top;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+}
+
+// Checks a method's bytecode by abstract interpretation, tracking the types of the
+// locals and stack through every path and merging them where paths meet. A handler
+// is reached from each instruction it covers, with just the exception on the stack
+pub fn verify_method(klass: &OtKlass, meth: &OtMethod) -> Result<(), RuntimeError> {
+    if meth.is_native() || meth.is_abstract() {
+        return Ok(());
+    }
+    let code = meth.get_code();
+    let fail = |pc: usize, msg: String| RuntimeError::VerifyError {
+        msg: format!("{} at pc {} in {}", msg, pc, meth.get_fq_name_desc()),
+    };
+    if code.is_empty() {
+        return Err(fail(0, "Method has no code".to_string()));
+    }
+
+    let mut starts = vec![false; code.len()];
+    let mut pc = 0;
+    while pc < code.len() {
+        starts[pc] = true;
+        // Called out separately, as old class files may legitimately contain them
+        let op = match code[pc] {
+            WIDE if code.get(pc + 1) == Some(&RET) => RET,
+            op => op,
+        };
+        if op == JSR || op == JSR_W || op == RET {
+            return Err(RuntimeError::UnsupportedOpcodeError { opcode: op, pc });
+        }
+        pc += instruction_length(&code, pc).map_err(|msg| fail(pc, msg))?;
+    }
+    if pc != code.len() {
+        return Err(fail(pc, "Last instruction runs past the end of the code".to_string()));
+    }
+
+    let (_, ret_type) = method_vtypes(&meth.get_desc()).map_err(|msg| fail(0, msg))?;
+    let max_locals = meth.get_max_locals() as usize;
+    let mut declared: Vec<Option<Frame>> = vec![None; code.len()];
+    for sm in meth.get_stack_map() {
+        match declared.get_mut(sm.pc as usize) {
+            Some(slot) if starts[sm.pc as usize] => *slot = Some(Frame::declared(sm, max_locals)),
+            _ => {
+                return Err(fail(
+                    sm.pc as usize,
+                    "Stack map frame is not at the start of an instruction".to_string(),
+                ))
+            }
+        }
+    }
+    let handlers = meth.get_exception_table();
+    for h in handlers {
+        let (start, end, handler) = (h.start_pc as usize, h.end_pc as usize, h.handler_pc as usize);
+        if start >= end || !starts.get(start).copied().unwrap_or(false) {
+            return Err(fail(start, format!("Exception range {} to {} is not a run of instructions", start, end)));
+        }
+        // The range is exclusive, so may end with the code
+        if end != code.len() && !starts.get(end).copied().unwrap_or(false) {
+            return Err(fail(end, format!("Exception range {} to {} is not a run of instructions", start, end)));
+        }
+        if !starts.get(handler).copied().unwrap_or(false) {
+            return Err(fail(handler, "Exception handler is not the start of an instruction".to_string()));
+        }
+    }
+
+    let mut frames: Vec<Option<Frame>> = vec![None; code.len()];
+    let entry = initial_frame(meth).map_err(|msg| fail(0, msg))?;
+    arrive(&mut frames, &declared, 0, &entry).map_err(|msg| fail(0, msg))?;
+    let mut worklist = vec![0];
+
+    while let Some(pc) = worklist.pop() {
+        let mut frame = match &frames[pc] {
+            Some(f) => f.clone(),
+            None => continue,
+        };
+        let covering: Vec<usize> = handlers
+            .iter()
+            .filter(|h| (h.start_pc as usize..h.end_pc as usize).contains(&pc))
+            .map(|h| h.handler_pc as usize)
+            .collect();
+        // The exception may be thrown before or after the instruction has changed the
+        // locals, so the handler sees both
+        let before = frame.for_handler();
+        let successors = step(klass, meth, &code, pc, ret_type, &mut frame)
+            .map_err(|msg| fail(pc, msg))?;
+        for handler in &covering {
+            for seen in &[&before, &frame.for_handler()] {
+                let changed =
+                    arrive(&mut frames, &declared, *handler, seen).map_err(|msg| fail(*handler, msg))?;
+                if changed {
+                    worklist.push(*handler);
+                }
+            }
+        }
+        for succ in successors {
+            if succ >= code.len() {
+                return Err(fail(pc, "Execution falls off the end of the code".to_string()));
+            }
+            if !starts[succ] {
+                return Err(fail(
+                    pc,
+                    format!("Branch target {} is not the start of an instruction", succ),
+                ));
+            }
+            let changed =
+                arrive(&mut frames, &declared, succ, &frame).map_err(|msg| fail(succ, msg))?;
+            if changed {
+                worklist.push(succ);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Records frame as reaching pc, returning whether pc needs (re)checking. Where the
+// StackMapTable declares a frame, the incoming one only has to be assignable to it,
+// so the declared frame is checked once and never needs iterating to a fixpoint
+fn arrive(
+    frames: &mut [Option<Frame>],
+    declared: &[Option<Frame>],
+    pc: usize,
+    frame: &Frame,
+) -> Result<bool, String> {
+    if let Some(expected) = &declared[pc] {
+        frame.check_assignable(expected)?;
+        if frames[pc].is_some() {
+            return Ok(false);
+        }
+        frames[pc] = Some(expected.clone());
+        return Ok(true);
+    }
+    match &mut frames[pc] {
+        Some(existing) => existing.merge(frame),
+        None => {
+            frames[pc] = Some(frame.clone());
+            Ok(true)
+        }
+    }
+}
+
+// The entry form of the locals, as implied by the descriptor: the receiver, if any,
+// then one entry per argument. This is the starting point for StackMapTable deltas
+pub fn initial_locals(meth: &OtMethod) -> Result<Vec<VType>, String> {
+    let (mut locals, _) = method_vtypes(&meth.get_desc())?;
+    if meth.get_flags() & ACC_STATIC == 0 {
+        locals.insert(0, VType::Reference);
+    }
+    Ok(locals)
+}
+
+// The locals on entry hold the receiver, if any, followed by the arguments
+fn initial_frame(meth: &OtMethod) -> Result<Frame, String> {
+    let mut frame = Frame {
+        locals: vec![VType::Top; meth.get_local_var_size() as usize],
+        stack: Vec::new(),
+    };
+    let mut slot = 0;
+    for t in initial_locals(meth)? {
+        frame.store(slot, t)?;
+        slot += t.size();
+    }
+    Ok(frame)
+}
+
+fn instruction_length(code: &[u8], pc: usize) -> Result<usize, String> {
+    let op = code[pc];
+    let len = match op {
+        TABLESWITCH => {
+            let base = pc + 1 + switch_padding(pc);
+            let low = read_i32(code, base + 4)?;
+            let high = read_i32(code, base + 8)?;
+            if high < low {
+                return Err(format!("tableswitch has high {} below low {}", high, low));
+            }
+            base + 12 + 4 * (high as i64 - low as i64 + 1) as usize - pc
+        }
+        LOOKUPSWITCH => {
+            let base = pc + 1 + switch_padding(pc);
+            let npairs = read_i32(code, base + 4)?;
+            if npairs < 0 {
+                return Err(format!("lookupswitch has negative npairs {}", npairs));
+            }
+            base + 8 + 8 * npairs as usize - pc
+        }
+        WIDE => match code.get(pc + 1) {
+            Some(&IINC) => 6,
+            Some(_) => 4,
+            None => return Err("Truncated wide instruction".to_string()),
+        },
+        _ => 1 + Opcode::num_params(op) as usize,
+    };
+    if pc + len > code.len() {
+        return Err(format!("Truncated {} instruction", Opcode::mnemonic(op)));
+    }
+    Ok(len)
+}
+
+fn read_i32(code: &[u8], pos: usize) -> Result<i32, String> {
+    match code.get(pos..pos + 4) {
+        Some(bytes) => Ok(BigEndian::read_i32(bytes)),
+        None => Err("Truncated switch instruction".to_string()),
+    }
+}
+
+fn relative_target(pc: usize, offset: i32) -> Result<usize, String> {
+    let target = pc as i64 + offset as i64;
+    if target < 0 {
+        return Err(format!("Branch target {} is before the start of the code", target));
+    }
+    Ok(target as usize)
+}
+
+// Applies the instruction at pc to frame, returning the pcs that can follow it
+fn step(
+    klass: &OtKlass,
+    meth: &OtMethod,
+    code: &[u8],
+    pc: usize,
+    ret_type: Option<VType>,
+    frame: &mut Frame,
+) -> Result<Vec<usize>, String> {
+    let max_stack = meth.get_max_stack() as usize;
+    let op = code[pc];
+    let next = pc + instruction_length(code, pc)?;
+    let u8_operand = || code[pc + 1] as usize;
+    let u16_operand = || BigEndian::read_u16(&code[pc + 1..]);
+    let branch = || relative_target(pc, BigEndian::read_i16(&code[pc + 1..]) as i32);
+
+    match op {
+        NOP => (),
+        ACONST_NULL => frame.push(VType::Reference, max_stack)?,
+        ICONST_M1 | ICONST_0 | ICONST_1 | ICONST_2 | ICONST_3 | ICONST_4 | ICONST_5
+        | BIPUSH | SIPUSH => frame.push(VType::Int, max_stack)?,
+        LCONST_0 | LCONST_1 => frame.push(VType::Long, max_stack)?,
+        FCONST_0 | FCONST_1 | FCONST_2 => frame.push(VType::Float, max_stack)?,
+        DCONST_0 | DCONST_1 => frame.push(VType::Double, max_stack)?,
+        LDC | LDC_W => {
+            let idx = if op == LDC { u8_operand() as u16 } else { u16_operand() };
+            let t = match klass.lookup_cp(idx) {
+                CpEntry::integer { val: _ } => VType::Int,
+                CpEntry::float { val: _ } => VType::Float,
+                CpEntry::string { idx: _ } | CpEntry::class { idx: _ } => VType::Reference,
+                other => return Err(format!("ldc of unloadable CP entry {:?}", other)),
+            };
+            frame.push(t, max_stack)?;
+        }
+        LDC2_W => {
+            let t = match klass.lookup_cp(u16_operand()) {
+                CpEntry::long { val: _ } => VType::Long,
+                CpEntry::double { val: _ } => VType::Double,
+                other => return Err(format!("ldc2_w of unloadable CP entry {:?}", other)),
+            };
+            frame.push(t, max_stack)?;
+        }
+
+        ILOAD => load(frame, u8_operand(), VType::Int, max_stack)?,
+        LLOAD => load(frame, u8_operand(), VType::Long, max_stack)?,
+        FLOAD => load(frame, u8_operand(), VType::Float, max_stack)?,
+        DLOAD => load(frame, u8_operand(), VType::Double, max_stack)?,
+        ALOAD => load(frame, u8_operand(), VType::Reference, max_stack)?,
+        ILOAD_0 | ILOAD_1 | ILOAD_2 | ILOAD_3 => {
+            load(frame, (op - ILOAD_0) as usize, VType::Int, max_stack)?
+        }
+        LLOAD_0 | LLOAD_1 | LLOAD_2 | LLOAD_3 => {
+            load(frame, (op - LLOAD_0) as usize, VType::Long, max_stack)?
+        }
+        FLOAD_0 | FLOAD_1 | FLOAD_2 | FLOAD_3 => {
+            load(frame, (op - FLOAD_0) as usize, VType::Float, max_stack)?
+        }
+        DLOAD_0 | DLOAD_1 | DLOAD_2 | DLOAD_3 => {
+            load(frame, (op - DLOAD_0) as usize, VType::Double, max_stack)?
+        }
+        ALOAD_0 | ALOAD_1 | ALOAD_2 | ALOAD_3 => {
+            load(frame, (op - ALOAD_0) as usize, VType::Reference, max_stack)?
+        }
+
+        ISTORE => store(frame, u8_operand(), VType::Int)?,
+        LSTORE => store(frame, u8_operand(), VType::Long)?,
+        FSTORE => store(frame, u8_operand(), VType::Float)?,
+        DSTORE => store(frame, u8_operand(), VType::Double)?,
+        ASTORE => store(frame, u8_operand(), VType::Reference)?,
+        ISTORE_0 | ISTORE_1 | ISTORE_2 | ISTORE_3 => {
+            store(frame, (op - ISTORE_0) as usize, VType::Int)?
+        }
+        LSTORE_0 | LSTORE_1 | LSTORE_2 | LSTORE_3 => {
+            store(frame, (op - LSTORE_0) as usize, VType::Long)?
+        }
+        FSTORE_0 | FSTORE_1 | FSTORE_2 | FSTORE_3 => {
+            store(frame, (op - FSTORE_0) as usize, VType::Float)?
+        }
+        DSTORE_0 | DSTORE_1 | DSTORE_2 | DSTORE_3 => {
+            store(frame, (op - DSTORE_0) as usize, VType::Double)?
+        }
+        ASTORE_0 | ASTORE_1 | ASTORE_2 | ASTORE_3 => {
+            store(frame, (op - ASTORE_0) as usize, VType::Reference)?
+        }
+        IINC => frame.load(u8_operand(), VType::Int)?,
+        WIDE => {
+            let widened = code[pc + 1];
+            let idx = BigEndian::read_u16(&code[pc + 2..]) as usize;
+            match widened {
+                ILOAD => load(frame, idx, VType::Int, max_stack)?,
+                LLOAD => load(frame, idx, VType::Long, max_stack)?,
+                FLOAD => load(frame, idx, VType::Float, max_stack)?,
+                DLOAD => load(frame, idx, VType::Double, max_stack)?,
+                ALOAD => load(frame, idx, VType::Reference, max_stack)?,
+                ISTORE => store(frame, idx, VType::Int)?,
+                LSTORE => store(frame, idx, VType::Long)?,
+                FSTORE => store(frame, idx, VType::Float)?,
+                DSTORE => store(frame, idx, VType::Double)?,
+                ASTORE => store(frame, idx, VType::Reference)?,
+                IINC => frame.load(idx, VType::Int)?,
+                RET => panic!("wide ret at {} should have been rejected already", pc),
+                _ => return Err(format!("wide applied to {}", Opcode::mnemonic(widened))),
+            }
+        }
+
+        IALOAD | BALOAD | CALOAD | SALOAD => array_load(frame, VType::Int, max_stack)?,
+        LALOAD => array_load(frame, VType::Long, max_stack)?,
+        FALOAD => array_load(frame, VType::Float, max_stack)?,
+        DALOAD => array_load(frame, VType::Double, max_stack)?,
+        AALOAD => array_load(frame, VType::Reference, max_stack)?,
+        IASTORE | BASTORE | CASTORE | SASTORE => array_store(frame, VType::Int)?,
+        LASTORE => array_store(frame, VType::Long)?,
+        FASTORE => array_store(frame, VType::Float)?,
+        DASTORE => array_store(frame, VType::Double)?,
+        AASTORE => array_store(frame, VType::Reference)?,
+
+        POP => {
+            frame.pop_category_1()?;
+        }
+        POP2 => {
+            if !frame.pop()?.is_category_2() {
+                frame.pop_category_1()?;
+            }
+        }
+        DUP => {
+            let v1 = frame.pop_category_1()?;
+            push_all(frame, &[v1, v1], max_stack)?;
+        }
+        DUP_X1 => {
+            let v1 = frame.pop_category_1()?;
+            let v2 = frame.pop_category_1()?;
+            push_all(frame, &[v1, v2, v1], max_stack)?;
+        }
+        DUP_X2 => {
+            let v1 = frame.pop_category_1()?;
+            let v2 = frame.pop()?;
+            if v2.is_category_2() {
+                push_all(frame, &[v1, v2, v1], max_stack)?;
+            } else {
+                let v3 = frame.pop_category_1()?;
+                push_all(frame, &[v1, v3, v2, v1], max_stack)?;
+            }
+        }
+        DUP2 => {
+            let v1 = frame.pop()?;
+            if v1.is_category_2() {
+                push_all(frame, &[v1, v1], max_stack)?;
+            } else {
+                let v2 = frame.pop_category_1()?;
+                push_all(frame, &[v2, v1, v2, v1], max_stack)?;
+            }
+        }
+        DUP2_X1 => {
+            let v1 = frame.pop()?;
+            if v1.is_category_2() {
+                let v2 = frame.pop_category_1()?;
+                push_all(frame, &[v1, v2, v1], max_stack)?;
+            } else {
+                let v2 = frame.pop_category_1()?;
+                let v3 = frame.pop_category_1()?;
+                push_all(frame, &[v2, v1, v3, v2, v1], max_stack)?;
+            }
+        }
+        DUP2_X2 => {
+            let v1 = frame.pop()?;
+            if v1.is_category_2() {
+                let v2 = frame.pop()?;
+                if v2.is_category_2() {
+                    push_all(frame, &[v1, v2, v1], max_stack)?;
+                } else {
+                    let v3 = frame.pop_category_1()?;
+                    push_all(frame, &[v1, v3, v2, v1], max_stack)?;
+                }
+            } else {
+                let v2 = frame.pop_category_1()?;
+                let v3 = frame.pop()?;
+                if v3.is_category_2() {
+                    push_all(frame, &[v2, v1, v3, v2, v1], max_stack)?;
+                } else {
+                    let v4 = frame.pop_category_1()?;
+                    push_all(frame, &[v2, v1, v4, v3, v2, v1], max_stack)?;
+                }
+            }
+        }
+        SWAP => {
+            let v1 = frame.pop_category_1()?;
+            let v2 = frame.pop_category_1()?;
+            push_all(frame, &[v1, v2], max_stack)?;
+        }
+
+        IADD | ISUB | IMUL | IDIV | IREM | ISHL | ISHR | IUSHR | IAND | IOR | IXOR => {
+            binary_op(frame, VType::Int, VType::Int, VType::Int, max_stack)?
+        }
+        LADD | LSUB | LMUL | LDIV | LREM | LAND | LOR | LXOR => {
+            binary_op(frame, VType::Long, VType::Long, VType::Long, max_stack)?
+        }
+        // The shift distance is always an int
+        LSHL | LSHR | LUSHR => binary_op(frame, VType::Long, VType::Int, VType::Long, max_stack)?,
+        FADD | FSUB | FMUL | FDIV | FREM => {
+            binary_op(frame, VType::Float, VType::Float, VType::Float, max_stack)?
+        }
+        DADD | DSUB | DMUL | DDIV | DREM => {
+            binary_op(frame, VType::Double, VType::Double, VType::Double, max_stack)?
+        }
+        INEG => convert(frame, VType::Int, VType::Int, max_stack)?,
+        LNEG => convert(frame, VType::Long, VType::Long, max_stack)?,
+        FNEG => convert(frame, VType::Float, VType::Float, max_stack)?,
+        DNEG => convert(frame, VType::Double, VType::Double, max_stack)?,
+        LCMP => binary_op(frame, VType::Long, VType::Long, VType::Int, max_stack)?,
+        FCMPL | FCMPG => binary_op(frame, VType::Float, VType::Float, VType::Int, max_stack)?,
+        DCMPL | DCMPG => binary_op(frame, VType::Double, VType::Double, VType::Int, max_stack)?,
+
+        I2L => convert(frame, VType::Int, VType::Long, max_stack)?,
+        I2F => convert(frame, VType::Int, VType::Float, max_stack)?,
+        I2D => convert(frame, VType::Int, VType::Double, max_stack)?,
+        L2I => convert(frame, VType::Long, VType::Int, max_stack)?,
+        L2F => convert(frame, VType::Long, VType::Float, max_stack)?,
+        L2D => convert(frame, VType::Long, VType::Double, max_stack)?,
+        F2I => convert(frame, VType::Float, VType::Int, max_stack)?,
+        F2L => convert(frame, VType::Float, VType::Long, max_stack)?,
+        F2D => convert(frame, VType::Float, VType::Double, max_stack)?,
+        D2I => convert(frame, VType::Double, VType::Int, max_stack)?,
+        D2L => convert(frame, VType::Double, VType::Long, max_stack)?,
+        D2F => convert(frame, VType::Double, VType::Float, max_stack)?,
+        I2B | I2C | I2S => convert(frame, VType::Int, VType::Int, max_stack)?,
+
+        IFEQ | IFNE | IFLT | IFGE | IFGT | IFLE => {
+            frame.pop_expect(VType::Int)?;
+            return Ok(vec![next, branch()?]);
+        }
+        IF_ICMPEQ | IF_ICMPNE | IF_ICMPLT | IF_ICMPGE | IF_ICMPGT | IF_ICMPLE => {
+            frame.pop_expect(VType::Int)?;
+            frame.pop_expect(VType::Int)?;
+            return Ok(vec![next, branch()?]);
+        }
+        IF_ACMPEQ | IF_ACMPNE => {
+            frame.pop_expect(VType::Reference)?;
+            frame.pop_expect(VType::Reference)?;
+            return Ok(vec![next, branch()?]);
+        }
+        IFNULL | IFNONNULL => {
+            frame.pop_expect(VType::Reference)?;
+            return Ok(vec![next, branch()?]);
+        }
+        GOTO => return Ok(vec![branch()?]),
+        GOTO_W => return Ok(vec![relative_target(pc, BigEndian::read_i32(&code[pc + 1..]))?]),
+        TABLESWITCH | LOOKUPSWITCH => {
+            frame.pop_expect(VType::Int)?;
+            let base = pc + 1 + switch_padding(pc);
+            let mut targets = vec![relative_target(pc, read_i32(code, base)?)?];
+            if op == TABLESWITCH {
+                let mut pos = base + 12;
+                while pos < next {
+                    targets.push(relative_target(pc, read_i32(code, pos)?)?);
+                    pos += 4;
+                }
+            } else {
+                let mut pos = base + 8;
+                while pos < next {
+                    targets.push(relative_target(pc, read_i32(code, pos + 4)?)?);
+                    pos += 8;
+                }
+            }
+            return Ok(targets);
+        }
+        // Rejected before the data flow analysis starts
+        JSR | JSR_W | RET => panic!("{} at {} should have been rejected already", Opcode::mnemonic(op), pc),
+
+        IRETURN => return return_value(frame, ret_type, VType::Int),
+        LRETURN => return return_value(frame, ret_type, VType::Long),
+        FRETURN => return return_value(frame, ret_type, VType::Float),
+        DRETURN => return return_value(frame, ret_type, VType::Double),
+        ARETURN => return return_value(frame, ret_type, VType::Reference),
+        RETURN => {
+            if let Some(t) = ret_type {
+                return Err(format!("return from a method returning {:?}", t));
+            }
+            return Ok(vec![]);
+        }
+        ATHROW => {
+            frame.pop_expect(VType::Reference)?;
+            return Ok(vec![]);
+        }
+
+        GETSTATIC => frame.push(field_type(klass, u16_operand())?, max_stack)?,
+        PUTSTATIC => frame.pop_expect(field_type(klass, u16_operand())?)?,
+        GETFIELD => {
+            frame.pop_expect(VType::Reference)?;
+            frame.push(field_type(klass, u16_operand())?, max_stack)?;
+        }
+        PUTFIELD => {
+            frame.pop_expect(field_type(klass, u16_operand())?)?;
+            frame.pop_expect(VType::Reference)?;
+        }
+        INVOKEDYNAMIC if code[pc + 3] != 0 || code[pc + 4] != 0 => {
+            return Err("invokedynamic with non-zero trailing bytes".to_string())
+        }
+        INVOKEVIRTUAL | INVOKESPECIAL | INVOKESTATIC | INVOKEINTERFACE | INVOKEDYNAMIC => {
+            let (arg_types, ret) = method_vtypes(&klass.cp_as_string(u16_operand()))?;
+            for t in arg_types.iter().rev() {
+                frame.pop_expect(*t)?;
+            }
+            if op != INVOKESTATIC && op != INVOKEDYNAMIC {
+                frame.pop_expect(VType::Reference)?;
+            }
+            if let Some(t) = ret {
+                frame.push(t, max_stack)?;
+            }
+        }
+
+        NEW => frame.push(VType::Reference, max_stack)?,
+        NEWARRAY | ANEWARRAY => convert(frame, VType::Int, VType::Reference, max_stack)?,
+        MULTIANEWARRAY => {
+            for _i in 0..code[pc + 3] {
+                frame.pop_expect(VType::Int)?;
+            }
+            frame.push(VType::Reference, max_stack)?;
+        }
+        ARRAYLENGTH => convert(frame, VType::Reference, VType::Int, max_stack)?,
+        CHECKCAST => convert(frame, VType::Reference, VType::Reference, max_stack)?,
+        INSTANCEOF => convert(frame, VType::Reference, VType::Int, max_stack)?,
+        MONITORENTER | MONITOREXIT => frame.pop_expect(VType::Reference)?,
+
+        _ => return Err(format!("Illegal opcode byte {}", op)),
+    }
+    Ok(vec![next])
+}
+
+fn load(frame: &mut Frame, idx: usize, t: VType, max_stack: usize) -> Result<(), String> {
+    frame.load(idx, t)?;
+    frame.push(t, max_stack)
+}
+
+fn store(frame: &mut Frame, idx: usize, t: VType) -> Result<(), String> {
+    frame.pop_expect(t)?;
+    frame.store(idx, t)
+}
+
+fn push_all(frame: &mut Frame, ts: &[VType], max_stack: usize) -> Result<(), String> {
+    for t in ts {
+        frame.push(*t, max_stack)?;
+    }
+    Ok(())
+}
+
+// ..., arrayref, index -> ..., value
+fn array_load(frame: &mut Frame, t: VType, max_stack: usize) -> Result<(), String> {
+    frame.pop_expect(VType::Int)?;
+    frame.pop_expect(VType::Reference)?;
+    frame.push(t, max_stack)
+}
+
+// ..., arrayref, index, value -> ...
+fn array_store(frame: &mut Frame, t: VType) -> Result<(), String> {
+    frame.pop_expect(t)?;
+    frame.pop_expect(VType::Int)?;
+    frame.pop_expect(VType::Reference)
+}
+
+// ..., v1, v2 -> ..., result, where v2 has type t2
+fn binary_op(
+    frame: &mut Frame,
+    t1: VType,
+    t2: VType,
+    result: VType,
+    max_stack: usize,
+) -> Result<(), String> {
+    frame.pop_expect(t2)?;
+    frame.pop_expect(t1)?;
+    frame.push(result, max_stack)
+}
+
+fn convert(frame: &mut Frame, from: VType, to: VType, max_stack: usize) -> Result<(), String> {
+    frame.pop_expect(from)?;
+    frame.push(to, max_stack)
+}
+
+fn return_value(frame: &mut Frame, ret_type: Option<VType>, t: VType) -> Result<Vec<usize>, String> {
+    if ret_type != Some(t) {
+        return Err(format!("Returning {:?} from a method returning {:?}", t, ret_type));
+    }
+    frame.pop_expect(t)?;
+    Ok(vec![])
+}
+
+// A fieldref stringifies as klass.name:desc
+fn field_type(klass: &OtKlass, cp_idx: u16) -> Result<VType, String> {
+    let fq_name_desc = klass.cp_as_string(cp_idx);
+    let desc = match fq_name_desc.rfind(':') {
+        Some(colon) => &fq_name_desc[colon + 1..],
+        None => return Err(format!("Malformed field reference {}", fq_name_desc)),
+    };
+    Ok(vtype_of(descriptor::try_field_type(desc)?))
+}
+
+// Takes anything ending in a method descriptor, e.g. foo:(IJLjava/lang/String;)V,
+// and returns the argument types and the return type, which is None for void
+fn method_vtypes(name_desc: &str) -> Result<(Vec<VType>, Option<VType>), String> {
+    let args = descriptor::try_arg_types(name_desc)?
+        .iter()
+        .map(|t| vtype_of(t.chars().next().unwrap_or('V')))
+        .collect();
+    Ok((args, descriptor::try_return_type(name_desc)?.map(vtype_of)))
+}
+
+// ty is the first character of a field type
+fn vtype_of(ty: char) -> VType {
+    match ty {
+        'J' => VType::Long,
+        'F' => VType::Float,
+        'D' => VType::Double,
+        'L' | '[' => VType::Reference,
+        _ => VType::Int,
+    }
+}
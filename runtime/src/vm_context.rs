@@ -0,0 +1,680 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::call_site::CallSite;
+use crate::class_loader::ClassLoader;
+use crate::constant_pool::CpEntry;
+use crate::klass_repo::SharedKlassRepo;
+use crate::profile::{Profile, ProfileReport};
+use crate::runtime_error::{RuntimeError, StackTraceElement};
+use crate::simple_heap::SharedSimpleHeap;
+use crate::verifier;
+use crate::HEAP;
+use crate::InterpLocalVars;
+use crate::JvmValue;
+use crate::ObjId;
+use crate::OtKlass;
+use crate::OtMethod;
+
+// Native methods receive the VM and the call's arguments (receiver first, if any)
+pub type NativeMethod = fn(&mut VmContext, &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError>;
+
+// Called with (method, pc, opcode, stack_depth) before each instruction is executed.
+// method is the fully-qualified name and descriptor of the method being run, or just
+// the klass name for bytecode run outside of any method
+pub type TraceCallback = Box<dyn FnMut(&str, usize, u8, usize)>;
+
+// The interpreter entry point, passed in by the interpreter crate
+pub type InterpCallback =
+    fn(&mut VmContext, &OtMethod, &mut InterpLocalVars) -> Result<Option<JvmValue>, RuntimeError>;
+
+//////////// VM CONTEXT
+
+// What the context knows of a running interpreter frame, which it can't see into.
+// The frame brings both fields up to date before it calls out or throws
+struct ContextFrame {
+    // The references the frame holds, as the collector can't see the interpreter's stack
+    roots: Vec<ObjId>,
+    // Where the frame has got to
+    element: StackTraceElement,
+}
+
+pub struct VmContext {
+    repo: SharedKlassRepo,
+    // Native implementations, keyed by fully-qualified name and descriptor. These
+    // also stand in for methods that have a Java body, as intrinsics
+    natives: HashMap<String, NativeMethod>,
+    // Where System.out output is written, stdout unless replaced
+    out: Box<dyn Write>,
+    // Consulted when a klass is needed that the repo doesn't have yet
+    class_loader: ClassLoader,
+    trace_callback: Option<TraceCallback>,
+    // Only collected while profiling is switched on
+    profile: Option<Profile>,
+    // Set by bootstrap, for entry points that need to run bytecode themselves
+    interp: Option<InterpCallback>,
+    // Fully-qualified names of the methods that have passed verification
+    verified: HashSet<String>,
+    // The interned java.lang.Class object of each klass that's asked for one, both ways round
+    class_objects: HashMap<String, ObjId>,
+    class_object_klasses: HashMap<ObjId, String>,
+    // Each running interpreter frame, outermost first
+    frames: Vec<ContextFrame>,
+    // The trace of each exception that's been caught, keyed by the exception object,
+    // as a real Throwable would hold its own
+    stack_traces: HashMap<ObjId, Vec<StackTraceElement>>,
+    // The message of each exception the VM raised itself, as its detailMessage
+    exception_messages: HashMap<ObjId, String>,
+    // The boxes that valueOf() hands out the same one of each time, keyed by the
+    // primitive's descriptor char and value, e.g. ('I', 100)
+    box_cache: HashMap<(char, i64), ObjId>,
+    // The String object for each string constant that has been loaded, as ldc of
+    // equal constants must give the same object
+    interned: HashMap<String, ObjId>,
+    // The linked site of each invokedynamic that has been run, keyed by the
+    // fully-qualified name of its method and its pc
+    // None for a site whose bootstrap isn't supported, so it isn't tried again
+    call_sites: HashMap<(String, usize), Option<CallSite>>,
+    // How many invokedynamic bootstraps have been run
+    bootstraps_run: u64,
+    // Bounds on execution, for running untrusted code. Unlimited unless set
+    max_steps: u64,
+    max_frames: usize,
+    // Instructions run since max_steps was last set
+    steps: u64,
+}
+
+impl VmContext {
+    pub fn of() -> VmContext {
+        VmContext {
+            repo: SharedKlassRepo::of(),
+            natives: HashMap::new(),
+            out: Box::new(io::stdout()),
+            class_loader: ClassLoader::of(),
+            trace_callback: None,
+            profile: None,
+            interp: None,
+            verified: HashSet::new(),
+            class_objects: HashMap::new(),
+            class_object_klasses: HashMap::new(),
+            frames: Vec::new(),
+            stack_traces: HashMap::new(),
+            exception_messages: HashMap::new(),
+            box_cache: HashMap::new(),
+            interned: HashMap::new(),
+            call_sites: HashMap::new(),
+            bootstraps_run: 0,
+            max_steps: u64::MAX,
+            max_frames: usize::MAX,
+            steps: 0,
+        }
+    }
+
+    pub fn get_repo(&self) -> &SharedKlassRepo {
+        &self.repo
+    }
+
+    pub fn get_repo_mut(&mut self) -> &mut SharedKlassRepo {
+        &mut self.repo
+    }
+
+    pub fn get_out(&mut self) -> &mut dyn Write {
+        &mut *self.out
+    }
+
+    pub fn set_out(&mut self, out: Box<dyn Write>) -> () {
+        self.out = out;
+    }
+
+    pub fn set_trace_callback(&mut self, callback: Option<TraceCallback>) -> () {
+        self.trace_callback = callback;
+    }
+
+    #[inline]
+    pub fn trace(&mut self, meth_name: &str, pc: usize, opcode: u8, stack_depth: usize) -> () {
+        if let Some(callback) = &mut self.trace_callback {
+            callback(meth_name, pc, opcode, stack_depth);
+        }
+    }
+
+    // The step count starts again from zero, so each limit set is a fresh budget
+    pub fn set_max_steps(&mut self, max_steps: u64) -> () {
+        self.max_steps = max_steps;
+        self.steps = 0;
+    }
+
+    pub fn set_max_frames(&mut self, max_frames: usize) -> () {
+        self.max_frames = max_frames;
+    }
+
+    pub fn get_steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Called before each instruction is run
+    #[inline]
+    pub fn count_step(&mut self) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(RuntimeError::ExecutionLimitExceeded {
+                limit: "steps",
+                max: self.max_steps,
+            });
+        }
+        Ok(())
+    }
+
+    // Called before a frame is entered, so max_frames of them can run at once
+    pub fn check_frame_limit(&self) -> Result<(), RuntimeError> {
+        if self.frames.len() >= self.max_frames {
+            return Err(RuntimeError::ExecutionLimitExceeded {
+                limit: "frames",
+                max: self.max_frames as u64,
+            });
+        }
+        Ok(())
+    }
+
+    // Folding is done as klasses are loaded, so switch it on before loading any
+    pub fn set_constant_folding(&mut self, on: bool) -> () {
+        self.repo.set_constant_folding(on);
+    }
+
+    // Switching profiling on starts from fresh counts, switching it off discards them
+    pub fn set_profiling(&mut self, on: bool) -> () {
+        self.profile = if on { Some(Profile::of()) } else { None };
+    }
+
+    #[inline]
+    pub fn profile_instruction(&mut self, opcode: u8) -> () {
+        if let Some(profile) = &mut self.profile {
+            profile.count_instruction(opcode);
+        }
+    }
+
+    pub fn profile_invocation(&mut self, meth: &OtMethod) -> () {
+        if let Some(profile) = &mut self.profile {
+            profile.count_invocation(&meth.get_fq_name_desc());
+        }
+    }
+
+    // None unless profiling is switched on
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        self.profile.as_ref().map(|p| p.report())
+    }
+
+    // Methods are verified the first time they're run. Bytecode whose klass isn't in
+    // the repo has no constant pool to check against, so is let through unverified
+    pub fn verify(&mut self, meth: &OtMethod) -> Result<(), RuntimeError> {
+        let fq_name = meth.get_fq_name_desc();
+        if self.verified.contains(&fq_name) {
+            return Ok(());
+        }
+        if let Some(k) = self.repo.try_lookup_klass(&meth.get_klass_name()) {
+            verifier::verify_method(&k, meth)?;
+            self.verified.insert(fq_name);
+        }
+        Ok(())
+    }
+
+    // The java.lang.Class object for klass_name, allocated the first time it's asked for
+    pub fn get_class_object(&mut self, klass_name: &String) -> Result<ObjId, RuntimeError> {
+        if let Some(class_obj) = self.class_objects.get(klass_name) {
+            return Ok(*class_obj);
+        }
+        let k_jlc = self.lookup_klass(&"java/lang/Class".to_string());
+        let class_obj = ObjId::of(self.allocate(&[], |heap| heap.try_allocate_obj(&k_jlc))?);
+        self.class_objects.insert(klass_name.clone(), class_obj);
+        self.class_object_klasses.insert(class_obj, klass_name.clone());
+        Ok(class_obj)
+    }
+
+    pub fn get_cached_box(&self, ty: char, v: i64) -> Option<ObjId> {
+        self.box_cache.get(&(ty, v)).cloned()
+    }
+
+    pub fn cache_box(&mut self, ty: char, v: i64, boxed: ObjId) -> () {
+        self.box_cache.insert((ty, v), boxed);
+    }
+
+    // The String object for value, allocated the first time it's asked for
+    pub fn intern_string(&mut self, value: &str) -> Result<ObjId, RuntimeError> {
+        if let Some(s) = self.interned.get(value) {
+            return Ok(*s);
+        }
+        let k_jls = self.lookup_klass(&"java/lang/String".to_string());
+        let s = ObjId::of(self.allocate(&[], |heap| heap.try_allocate_str(&k_jls, value))?);
+        self.interned.insert(value.to_string(), s);
+        Ok(s)
+    }
+
+    // The call site of the invokedynamic at pc in meth_name, bootstrapped from the
+    // CP entry the first time it's reached. None if its bootstrap isn't supported
+    pub fn call_site(&mut self, meth_name: &str, pc: usize, klass: &OtKlass, cp_idx: u16) -> Option<&CallSite> {
+        let key = (meth_name.to_string(), pc);
+        if !self.call_sites.contains_key(&key) {
+            self.bootstraps_run += 1;
+            let site = CallSite::link(klass, cp_idx);
+            self.call_sites.insert(key.clone(), site);
+        }
+        self.call_sites.get(&key)?.as_ref()
+    }
+
+    pub fn get_bootstraps_run(&self) -> u64 {
+        self.bootstraps_run
+    }
+
+    // The klass that class_obj is the java.lang.Class object for, if it is one
+    pub fn klass_name_of_class_object(&self, class_obj: ObjId) -> Option<&String> {
+        self.class_object_klasses.get(&class_obj)
+    }
+
+    pub fn klass_name_of_obj(&self, obj: ObjId) -> Option<String> {
+        let klass_id = HEAP.lock().unwrap().try_get_obj(obj)?.get_klassid();
+        Some(self.repo.try_lookup_klass_by_id(klass_id)?.get_name())
+    }
+
+    // method_name is empty for bytecode run outside of any method
+    pub fn enter_frame(&mut self, klass_name: String, method_name: String) -> () {
+        self.frames.push(ContextFrame {
+            roots: Vec::new(),
+            element: StackTraceElement {
+                klass_name,
+                method_name,
+                line: None,
+            },
+        });
+    }
+
+    pub fn leave_frame(&mut self) -> () {
+        self.frames.pop();
+    }
+
+    // The running frames, innermost first, each at the line it last recorded
+    pub fn build_stack_trace(&self) -> Vec<StackTraceElement> {
+        self.frames.iter().rev().map(|f| f.element.clone()).collect()
+    }
+
+    // Called by the frame that catches an exception, with the trace it built up
+    pub fn record_stack_trace(&mut self, exception: ObjId, trace: Vec<StackTraceElement>) -> () {
+        self.stack_traces.insert(exception, trace);
+    }
+
+    pub fn get_stack_trace(&self, exception: ObjId) -> Option<&Vec<StackTraceElement>> {
+        self.stack_traces.get(&exception)
+    }
+
+    pub fn set_exception_message(&mut self, exception: ObjId, msg: String) -> () {
+        self.exception_messages.insert(exception, msg);
+    }
+
+    pub fn get_exception_message(&self, exception: ObjId) -> Option<&String> {
+        self.exception_messages.get(&exception)
+    }
+
+    // Replaces the roots of the innermost frame, which is the one running
+    pub fn set_frame_roots(&mut self, roots: Vec<ObjId>) -> () {
+        self.innermost_frame("roots").roots = roots;
+    }
+
+    pub fn set_frame_line(&mut self, line: Option<u16>) -> () {
+        self.innermost_frame("line").element.line = line;
+    }
+
+    fn innermost_frame(&mut self, what: &str) -> &mut ContextFrame {
+        match self.frames.last_mut() {
+            Some(frame) => frame,
+            None => panic!("Error: frame {} set with no frame running", what),
+        }
+    }
+
+    // Collects garbage, rooted in the statics, the interned class objects and strings,
+    // the cached boxes, the recorded frames and roots, which should hold whatever the
+    // caller has live that no frame has recorded yet. Returns how many objects were freed
+    pub fn gc(&mut self, roots: &[ObjId]) -> usize {
+        let mut all_roots = self.repo.static_refs();
+        all_roots.extend(self.class_objects.values());
+        all_roots.extend(self.interned.values());
+        all_roots.extend(self.box_cache.values());
+        for frame in &self.frames {
+            all_roots.extend(&frame.roots);
+        }
+        all_roots.extend(roots);
+        let mut heap = HEAP.lock().unwrap();
+        let freed = heap.gc(&all_roots);
+        // Freed ids are handed out again, so must take nothing of their old objects with them
+        self.exception_messages.retain(|id, _| heap.is_valid(*id));
+        self.stack_traces.retain(|id, _| heap.is_valid(*id));
+        freed
+    }
+
+    // Runs alloc, and if the heap is full collects garbage and tries once more. roots
+    // are whatever the caller holds that no frame has recorded, so must survive
+    pub fn allocate<F>(&mut self, roots: &[ObjId], alloc: F) -> Result<usize, RuntimeError>
+    where
+        F: Fn(&mut SharedSimpleHeap) -> Result<usize, RuntimeError>,
+    {
+        let first = alloc(&mut HEAP.lock().unwrap());
+        match first {
+            Err(RuntimeError::OutOfMemoryError { .. }) => {
+                self.gc(roots);
+                alloc(&mut HEAP.lock().unwrap())
+            }
+            _ => first,
+        }
+    }
+
+    pub fn get_class_loader_mut(&mut self) -> &mut ClassLoader {
+        &mut self.class_loader
+    }
+
+    // Like the repo's lookup, but a miss is loaded through the class loader. A newly
+    // loaded klass brings in its superclass and interfaces too, where they can be found,
+    // so that assignability checks can see the whole hierarchy. None if the klass isn't
+    // on the classpath, an error if it is there but can't be read
+    pub fn try_lookup_klass(&mut self, klass_name: &String) -> Result<Option<OtKlass>, RuntimeError> {
+        if let Some(k) = self.repo.try_lookup_klass(klass_name) {
+            return Ok(Some(k));
+        }
+        let k = match self.class_loader.load_klass(klass_name) {
+            Ok(Some(k)) => k,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                return Err(RuntimeError::NoClassDefFoundError {
+                    klass_name: klass_name.clone(),
+                    msg: e.to_string(),
+                })
+            }
+        };
+        self.repo.add_klass(&k);
+        let super_name = k.get_super_name();
+        if super_name != *klass_name {
+            self.try_lookup_klass(&super_name)?;
+        }
+        for interface_name in k.get_interfaces() {
+            self.try_lookup_klass(interface_name)?;
+        }
+        Ok(self.repo.try_lookup_klass(klass_name))
+    }
+
+    pub fn lookup_klass(&mut self, klass_name: &String) -> OtKlass {
+        match self.try_lookup_klass(klass_name) {
+            Ok(Some(k)) => k,
+            Ok(None) => panic!("No klass called {} found in repo or on classpath", klass_name),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // Makes sure the klass the methodref points at has been loaded before resolving it.
+    // Call sites that have already been resolved are known to be loaded
+    pub fn lookup_method_by_cp(&mut self, current_klass: &OtKlass, cp_idx: u16) -> Rc<OtMethod> {
+        if self.repo.is_call_site_cached(current_klass, cp_idx) {
+            return self.repo.lookup_method_by_cp(current_klass, cp_idx);
+        }
+        if let CpEntry::methodref { clz_idx, nt_idx: _ } = current_klass.lookup_cp(cp_idx) {
+            let dispatch_klass_name = current_klass.cp_as_string(clz_idx);
+            self.lookup_klass(&dispatch_klass_name);
+        }
+        self.repo.lookup_method_by_cp(current_klass, cp_idx)
+    }
+
+    // NOTE: This is fully-qualified, e.g. java/lang/Object.hashCode:()I
+    pub fn register_native(&mut self, fq_name_desc: &str, func: NativeMethod) -> () {
+        self.natives.insert(fq_name_desc.to_string(), func);
+    }
+
+    pub fn lookup_native(&self, fq_name_desc: &str) -> Option<NativeMethod> {
+        self.natives.get(fq_name_desc).cloned()
+    }
+
+    // Runs <clinit> (if there is one) the first time a klass is actively used, after
+    // initializing its superclass. Requests made while <clinit> is still running return
+    // straight away, so the initializer can use its own klass
+    pub fn ensure_initialized(&mut self, klass_name: &String, i_callback: InterpCallback) -> Result<(), RuntimeError> {
+        let k = self.lookup_klass(klass_name);
+        if self.repo.is_erroneous(klass_name) {
+            return Err(RuntimeError::NoClassDefFoundError {
+                klass_name: klass_name.clone(),
+                msg: "initialization failed earlier".to_string(),
+            });
+        }
+        if !self.repo.begin_initialization(klass_name) {
+            return Ok(());
+        }
+        // A klass whose super can't be initialized can't be either
+        let super_name = k.get_super_name();
+        let result = if super_name != *klass_name {
+            self.try_lookup_klass(&super_name).and_then(|sup| match sup {
+                Some(_) => self.ensure_initialized(&super_name, i_callback),
+                None => Ok(()),
+            })
+        } else {
+            Ok(())
+        }
+        .and_then(|_| self.run_clinit_method(&k, i_callback));
+        match result {
+            Ok(()) => self.repo.set_initialized(klass_name),
+            Err(_) => self.repo.set_erroneous(klass_name),
+        }
+        result
+    }
+
+    fn run_clinit_method(&mut self, k: &OtKlass, i_callback: InterpCallback) -> Result<(), RuntimeError> {
+        let klass_name = k.get_name();
+        let m_str: String = klass_name.clone() + ".<clinit>:()V";
+        let clinit = match k.get_method_by_name_and_desc(&m_str) {
+            Some(value) => value.clone(),
+            None => return Ok(()),
+        };
+        let mut vars = InterpLocalVars::with_capacity(clinit.get_local_var_size() as usize);
+        i_callback(self, &clinit, &mut vars)?;
+        Ok(())
+    }
+
+    // FIXME This should be changed to read in an ocelot-rt.jar (a cut down full RT)
+    // and add each class one by one before fixing up the native code that we have working
+    pub fn bootstrap(&mut self, i_callback: InterpCallback) -> () {
+        self.interp = Some(i_callback);
+
+        // Add java.lang.Object
+        let k_obj = self.repo.parse_bootstrap_class("java/lang/Object".to_string());
+
+        // Add j.l.O native methods (e.g. hashCode())
+        self.register_native(
+            "java/lang/Object.hashCode:()I",
+            crate::native_methods::java_lang_Object__hashcode,
+        );
+        self.register_native(
+            "java/lang/Object.registerNatives:()V",
+            crate::native_methods::java_lang_Object__registerNatives,
+        );
+        self.register_native(
+            "java/lang/Object.getClass:()Ljava/lang/Class;",
+            crate::native_methods::java_lang_Object__getClass,
+        );
+        self.repo.add_klass(&k_obj);
+        let obj_name = k_obj.get_name();
+        if let Err(e) = self.ensure_initialized(&obj_name, i_callback) {
+            panic!("Error: {} running <clinit> of {}", e, obj_name);
+        }
+
+        // FIXME Add primitive arrays
+
+        // A stub java.lang.Class. Class objects are only created on demand, by getClass()
+        let k_jlc = self.repo.parse_bootstrap_class("java/lang/Class".to_string());
+        self.repo.add_klass(&k_jlc);
+        self.repo.set_initialized(&k_jlc.get_name());
+        self.register_native(
+            "java/lang/Class.getName:()Ljava/lang/String;",
+            crate::native_methods::java_lang_Class__getName,
+        );
+
+        // Add wrapper classes
+        let k_jli = self.repo.parse_bootstrap_class("java/lang/Integer".to_string());
+        self.repo.add_klass(&k_jli);
+        // Needs j.l.Class to run (set up primitive type .class object)
+        // self.run_clinit_method(&k_jli, i_callback);
+        self.repo.set_initialized(&k_jli.get_name());
+
+        let k_jlic = self
+            .repo
+            .parse_bootstrap_class("java/lang/Integer$IntegerCache".to_string());
+        self.repo.add_klass(&k_jlic);
+        // Needs j.l.Class and uses sun.* classes to do VM-protected stuff
+        // self.run_clinit_method(&k_jlic, i_callback);
+        self.repo.set_initialized(&k_jlic.get_name());
+
+        // Stubs for the other wrappers, which only need to hold a value
+        for name in &["java/lang/Long", "java/lang/Double", "java/lang/Boolean"] {
+            let k = self.repo.parse_bootstrap_class(name.to_string());
+            self.repo.add_klass(&k);
+            self.repo.set_initialized(&k.get_name());
+        }
+
+        // Boxing and unboxing are intrinsics. Integer's own valueOf() would need
+        // IntegerCache, whose <clinit> can't run
+        let intrinsics: [(&str, NativeMethod); 8] = [
+            ("java/lang/Integer.valueOf:(I)Ljava/lang/Integer;", crate::native_methods::java_lang_Integer__valueOf),
+            ("java/lang/Integer.intValue:()I", crate::native_methods::unbox),
+            ("java/lang/Long.valueOf:(J)Ljava/lang/Long;", crate::native_methods::java_lang_Long__valueOf),
+            ("java/lang/Long.longValue:()J", crate::native_methods::unbox),
+            ("java/lang/Double.valueOf:(D)Ljava/lang/Double;", crate::native_methods::java_lang_Double__valueOf),
+            ("java/lang/Double.doubleValue:()D", crate::native_methods::unbox),
+            ("java/lang/Boolean.valueOf:(Z)Ljava/lang/Boolean;", crate::native_methods::java_lang_Boolean__valueOf),
+            ("java/lang/Boolean.booleanValue:()Z", crate::native_methods::unbox),
+        ];
+        for (fq_name_desc, func) in &intrinsics {
+            self.register_native(fq_name_desc, *func);
+        }
+
+        // FIXME Other classes
+
+        // Add java.lang.String
+        let k_jls = self.repo.parse_bootstrap_class("java/lang/String".to_string());
+        // FIXME String only has intern() as a native method, skip for now
+        self.repo.add_klass(&k_jls);
+        // The remaining bootstrap klasses' <clinit>s can't run yet either
+        self.repo.set_initialized(&k_jls.get_name());
+
+        // Add java.lang.StringBuilder
+        let k_jlsb = self
+            .repo
+            .parse_bootstrap_class("java/lang/StringBuilder".to_string());
+        self.repo.add_klass(&k_jlsb);
+        self.repo.set_initialized(&k_jlsb.get_name());
+
+        // Enough of StringBuilder for javac's string concatenation, as intrinsics. The
+        // text is held natively, as for a String
+        let intrinsics: [(&str, NativeMethod); 5] = [
+            ("java/lang/StringBuilder.<init>:()V", crate::native_methods::java_lang_StringBuilder__init),
+            ("java/lang/StringBuilder.<init>:(Ljava/lang/String;)V", crate::native_methods::java_lang_StringBuilder__init_string),
+            ("java/lang/StringBuilder.append:(Ljava/lang/String;)Ljava/lang/StringBuilder;", crate::native_methods::java_lang_StringBuilder__append_string),
+            ("java/lang/StringBuilder.append:(I)Ljava/lang/StringBuilder;", crate::native_methods::java_lang_StringBuilder__append_int),
+            ("java/lang/StringBuilder.toString:()Ljava/lang/String;", crate::native_methods::java_lang_StringBuilder__toString),
+        ];
+        for (fq_name_desc, func) in &intrinsics {
+            self.register_native(fq_name_desc, *func);
+        }
+
+        // Stubs, just enough to throw and catch an Exception, and those the VM throws
+        for name in &[
+            "java/lang/Throwable",
+            "java/lang/Exception",
+            "java/lang/RuntimeException",
+            "java/lang/ArithmeticException",
+            "java/lang/NullPointerException",
+            "java/lang/IndexOutOfBoundsException",
+            "java/lang/ArrayIndexOutOfBoundsException",
+            "java/lang/NegativeArraySizeException",
+            "java/lang/ArrayStoreException",
+            "java/lang/ClassCastException",
+            "java/lang/IllegalMonitorStateException",
+            "java/lang/Error",
+            "java/lang/LinkageError",
+            "java/lang/IncompatibleClassChangeError",
+            "java/lang/AbstractMethodError",
+        ] {
+            let k = self.repo.parse_bootstrap_class(name.to_string());
+            self.repo.add_klass(&k);
+            self.repo.set_initialized(&k.get_name());
+        }
+        self.register_native(
+            "java/lang/Throwable.getMessage:()Ljava/lang/String;",
+            crate::native_methods::java_lang_Throwable__getMessage,
+        );
+
+        // FIXME Add class objects for already bootstrapped classes
+
+        // Add java.lang.System
+        let k_sys = self.repo.parse_bootstrap_class("java/lang/System".to_string());
+        self.register_native(
+            "java/lang/System.currentTimeMillis:()J",
+            crate::native_methods::java_lang_System__currentTimeMillis,
+        );
+        self.repo.add_klass(&k_sys);
+        self.repo.set_initialized(&k_sys.get_name());
+
+        // A stub java.io.PrintStream, just enough to get System.out.println() to work
+        let k_ps = self.repo.parse_bootstrap_class("java/io/PrintStream".to_string());
+        self.repo.add_klass(&k_ps);
+        self.repo.set_initialized(&k_ps.get_name());
+        self.register_native(
+            "java/io/PrintStream.println:(Ljava/lang/String;)V",
+            crate::native_methods::java_io_PrintStream__println_string,
+        );
+        self.register_native(
+            "java/io/PrintStream.println:(I)V",
+            crate::native_methods::java_io_PrintStream__println_int,
+        );
+
+        // System's <clinit> never runs, so System.out has to be filled in here,
+        // otherwise calls through it would see a null receiver
+        let k_ps = self.lookup_klass(&k_ps.get_name());
+        let out_id = match self.allocate(&[], |heap| heap.try_allocate_obj(&k_ps)) {
+            Ok(id) => ObjId::of(id),
+            Err(e) => panic!("Error: System.out could not be allocated: {}", e),
+        };
+        let out_f = match k_sys.get_static_field_by_name_and_desc(
+            &"java/lang/System.out:Ljava/io/PrintStream;".to_string(),
+        ) {
+            Some(f) => f.clone(),
+            None => panic!("Error: No out field found on java/lang/System"),
+        };
+        self.repo
+            .put_static(k_sys.get_name(), out_f, JvmValue::ObjRef { val: out_id });
+    }
+
+    // Loads and initializes main_class, then calls its main(String[]) with args
+    pub fn run_main(&mut self, main_class: &str, args: Vec<String>) -> Result<(), RuntimeError> {
+        let i_callback = match self.interp {
+            Some(i_callback) => i_callback,
+            None => panic!("Error: VM must be bootstrapped before running {}", main_class),
+        };
+        let klass_name = main_class.replace('.', "/");
+        let k = self.lookup_klass(&klass_name);
+        self.ensure_initialized(&klass_name, i_callback)?;
+
+        let fq_meth = klass_name.clone() + ".main:([Ljava/lang/String;)V";
+        let main = match k.get_method_by_name_and_desc(&fq_meth) {
+            Some(m) => m.clone(),
+            None => panic!("Error: No main method found on {}", klass_name),
+        };
+
+        // Each String made so far is a root while the next is allocated
+        let k_jls = self.lookup_klass(&"java/lang/String".to_string());
+        let mut arg_ids = Vec::new();
+        for a in &args {
+            let roots: Vec<ObjId> = arg_ids.iter().map(|id| ObjId::of(*id)).collect();
+            arg_ids.push(self.allocate(&roots, |heap| heap.try_allocate_str(&k_jls, a))?);
+        }
+        let roots: Vec<ObjId> = arg_ids.iter().map(|id| ObjId::of(*id)).collect();
+        let args_id = self.allocate(&roots, |heap| heap.try_allocate_obj_arr(&k_jls, arg_ids.clone()))?;
+
+        let mut vars = InterpLocalVars::with_capacity(main.get_local_var_size() as usize);
+        vars.store(0, JvmValue::ObjRef { val: ObjId::of(args_id) });
+        i_callback(self, &main, &mut vars)?;
+        Ok(())
+    }
+}
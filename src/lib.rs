@@ -1,580 +1,1459 @@
 #![deny(unreachable_patterns)]
 
+use ocelotter_runtime::call_site::{CallSite, ConcatPart};
 use ocelotter_runtime::constant_pool::*;
+use ocelotter_runtime::decode;
+use ocelotter_runtime::decode::Instruction;
+use ocelotter_runtime::descriptor;
 use ocelotter_runtime::interp_stack::InterpEvalStack;
 use ocelotter_runtime::klass_repo::SharedKlassRepo;
+use ocelotter_runtime::object::OtObj;
+use ocelotter_runtime::otfield::OtField;
 use ocelotter_runtime::otklass::OtKlass;
 use ocelotter_runtime::otmethod::OtMethod;
+use ocelotter_runtime::runtime_error::{RuntimeError, ThrownException};
+use ocelotter_runtime::simple_heap::SharedSimpleHeap;
+use ocelotter_runtime::vm_context::VmContext;
 use ocelotter_runtime::*;
 
-pub mod opcode;
+pub use ocelotter_runtime::opcode;
 use opcode::*;
 
 pub fn exec_method(
-    repo: &mut SharedKlassRepo,
+    context: &mut VmContext,
     meth: &OtMethod,
     lvt: &mut InterpLocalVars,
-) -> Option<JvmValue> {
+) -> Result<Option<JvmValue>, RuntimeError> {
     dbg!(meth.clone());
     // dbg!(meth.get_flags());
-    if meth.is_native() {
-        // Explicit type hint here to document the type of n_f
-        let n_f: fn(&InterpLocalVars) -> Option<JvmValue> = meth.get_native_code().expect(
-            &format!("Native code not found {}", meth.get_fq_name_desc()),
-        );
-
-        // FIXME Parameter passing
-        n_f(lvt)
+    context.profile_invocation(meth);
+    if meth.is_abstract() {
+        return Err(RuntimeError::AbstractMethodError {
+            method: meth.get_fq_name_desc(),
+        });
+    }
+    // A native registered for a method with a Java body is an intrinsic, and runs instead
+    if let Some(n_f) = context.lookup_native(&meth.get_fq_name_desc()) {
+        n_f(context, lvt.as_slice())
+    } else if meth.is_native() {
+        panic!("Native code not found {}", meth.get_fq_name_desc())
     } else {
-        exec_bytecode_method(repo, meth.get_klass_name(), &meth.get_code(), lvt)
+        context.verify(meth)?;
+        exec_frame(
+            context,
+            meth.get_klass_name(),
+            &meth.get_fq_name_desc(),
+            &meth.get_exec_code(),
+            Some(meth),
+            meth.get_max_stack(),
+            lvt,
+        )
     }
 }
 
+// Runs raw bytecode that doesn't belong to a method, so there is no declared max_stack
 pub fn exec_bytecode_method(
-    repo: &mut SharedKlassRepo,
+    context: &mut VmContext,
     klass_name: String,
     instr: &Vec<u8>,
     lvt: &mut InterpLocalVars,
-) -> Option<JvmValue> {
-    let mut current = 0;
-    let mut eval = InterpEvalStack::of();
+) -> Result<Option<JvmValue>, RuntimeError> {
+    // With no method to name, traces show just the klass
+    let meth_name = klass_name.clone();
+    exec_frame(context, klass_name, &meth_name, instr, None, u16::MAX, lvt)
+}
 
-    loop {
-        // let my_klass_name = klass_name.clone();
-        let ins: u8 = *instr
-            .get(current)
-            .expect(&format!("Byte {} has no value", current));
+// Registers the frame with the context for as long as it runs, however it exits
+fn exec_frame(
+    context: &mut VmContext,
+    klass_name: String,
+    meth_name: &str,
+    instr: &Vec<u8>,
+    meth: Option<&OtMethod>,
+    max_stack: u16,
+    lvt: &mut InterpLocalVars,
+) -> Result<Option<JvmValue>, RuntimeError> {
+    let method_name = match meth {
+        Some(meth) => meth.get_name(),
+        None => String::new(),
+    };
+    context.check_frame_limit()?;
+    context.enter_frame(klass_name.clone(), method_name);
+    let ret = exec_bytecode(context, klass_name, meth_name, instr, meth, max_stack, lvt);
+    context.leave_frame();
+    ret
+}
 
-        current += 1;
+//////////// DISPATCH
 
-        // dbg!(ins);
-        match ins {
-            Opcode::ACONST_NULL => eval.aconst_null(),
+// Where the interpreter loop goes once a handler has run
+enum Flow {
+    Next,
+    // To the instruction with this index, which isn't its pc
+    Jump(usize),
+    Return(Option<JvmValue>),
+}
 
-            Opcode::ALOAD => {
-                eval.push(lvt.load(instr[current]));
-                current += 1;
-            }
-            Opcode::ALOAD_0 => eval.push(lvt.load(0)),
+// Everything a handler can see of the running method
+struct Frame<'a> {
+    context: &'a mut VmContext,
+    klass_name: String,
+    // None for bytecode run outside of any method
+    meth: Option<&'a OtMethod>,
+    instr: &'a [u8],
+    code: &'a [Instruction],
+    // The index in code of the instruction being run
+    idx: usize,
+    // The byte after its opcode in instr, i.e. its first operand, if it has any
+    current: usize,
+    eval: InterpEvalStack,
+    lvt: &'a mut InterpLocalVars,
+}
 
-            Opcode::ALOAD_1 => eval.push(lvt.load(1)),
+impl<'a> Frame<'a> {
+    fn insn(&self) -> &Instruction {
+        &self.code[self.idx]
+    }
 
-            Opcode::ARETURN => break Some(eval.pop()),
-            Opcode::ASTORE => {
-                lvt.store(instr[current], eval.pop());
-                current += 1;
-            }
-            Opcode::ASTORE_0 => lvt.store(0, eval.pop()),
+    // The local var index of the typed loads and stores
+    fn local_idx(&self) -> u16 {
+        self.insn().operand as u16
+    }
 
-            Opcode::ASTORE_1 => lvt.store(1, eval.pop()),
+    fn branch_if(&self, taken: bool) -> Flow {
+        if taken {
+            Flow::Jump(self.insn().operand as usize)
+        } else {
+            Flow::Next
+        }
+    }
 
-            Opcode::BIPUSH => {
-                eval.iconst(instr[current] as i32);
-                current += 1;
-            }
-            Opcode::DADD => eval.dadd(),
+    fn line_for_pc(&self, pc: usize) -> Option<u16> {
+        self.meth.and_then(|meth| meth.line_for_pc(pc as u16))
+    }
 
-            Opcode::DCONST_0 => eval.dconst(0.0),
+    // The index of the instruction that handles an exception thrown at pc, if any.
+    // Handlers are tried in the order the exception table lists them
+    fn find_handler(&self, pc: usize, obj_id: ObjId) -> Option<usize> {
+        let meth = self.meth?;
+        let actual = klass_name_of_obj(self.context, obj_id);
+        let handler = meth.get_exception_table().iter().find(|h| {
+            (h.start_pc as usize..h.end_pc as usize).contains(&pc)
+                && match &h.catch_type {
+                    None => true,
+                    Some(catch_type) => self.context.get_repo().is_assignable_from(&actual, catch_type),
+                }
+        })?;
+        match self.code.binary_search_by_key(&(handler.handler_pc as usize), |i| i.pc) {
+            Ok(idx) => Some(idx),
+            Err(_) => panic!(
+                "Exception handler at {} is not an instruction in {}",
+                handler.handler_pc,
+                meth.get_fq_name_desc()
+            ),
+        }
+    }
+
+    // Makes the exception object for an error the VM raised at pc, which is thrown
+    // from there as if by an athrow
+    fn raise(&mut self, pc: usize, e: RuntimeError) -> RuntimeError {
+        let (exception_klass, msg) = match e.as_exception() {
+            Some(exception) => exception,
+            None => return e,
+        };
+        let k = match self.context.try_lookup_klass(&exception_klass.to_string()) {
+            Ok(Some(k)) => k,
+            // Without the stubs bootstrapped there's nothing to throw
+            _ => return e,
+        };
+        let roots = frame_refs(&self.eval, self.lvt);
+        let obj_id = match self.context.allocate(&roots, |heap| heap.try_allocate_obj(&k)) {
+            Ok(id) => ObjId::of(id),
+            Err(oom) => return oom,
+        };
+        self.context.set_exception_message(obj_id, msg.clone());
+        self.context.set_frame_line(self.line_for_pc(pc));
+        RuntimeError::Thrown {
+            exception: ThrownException {
+                obj_id,
+                klass_name: k.get_name(),
+                msg: Some(msg),
+                trace: self.context.build_stack_trace(),
+            },
+        }
+    }
+}
 
-            Opcode::DCONST_1 => eval.dconst(1.0),
+type Handler = fn(&mut Frame) -> Result<Flow, RuntimeError>;
+
+// Indexed by opcode. The simple opcodes that dominate loops get a handler of their
+// own, and anything else goes to exec_general
+static DISPATCH: [Handler; 256] = dispatch_table();
+
+const fn dispatch_table() -> [Handler; 256] {
+    let mut t: [Handler; 256] = [exec_general; 256];
+    t[Opcode::NOP as usize] = op_nop;
+
+    t[Opcode::ACONST_NULL as usize] = op_aconst_null;
+    t[Opcode::ICONST_M1 as usize] = op_iconst::<-1>;
+    t[Opcode::ICONST_0 as usize] = op_iconst::<0>;
+    t[Opcode::ICONST_1 as usize] = op_iconst::<1>;
+    t[Opcode::ICONST_2 as usize] = op_iconst::<2>;
+    t[Opcode::ICONST_3 as usize] = op_iconst::<3>;
+    t[Opcode::ICONST_4 as usize] = op_iconst::<4>;
+    t[Opcode::ICONST_5 as usize] = op_iconst::<5>;
+    t[Opcode::LCONST_0 as usize] = op_lconst::<0>;
+    t[Opcode::LCONST_1 as usize] = op_lconst::<1>;
+    t[Opcode::FCONST_0 as usize] = op_fconst::<0>;
+    t[Opcode::FCONST_1 as usize] = op_fconst::<1>;
+    t[Opcode::FCONST_2 as usize] = op_fconst::<2>;
+    t[Opcode::DCONST_0 as usize] = op_dconst::<0>;
+    t[Opcode::DCONST_1 as usize] = op_dconst::<1>;
+    t[Opcode::BIPUSH as usize] = op_ipush;
+    t[Opcode::SIPUSH as usize] = op_ipush;
+
+    t[Opcode::ILOAD as usize] = op_iload;
+    t[Opcode::ILOAD_0 as usize] = op_iload_n::<0>;
+    t[Opcode::ILOAD_1 as usize] = op_iload_n::<1>;
+    t[Opcode::ILOAD_2 as usize] = op_iload_n::<2>;
+    t[Opcode::ILOAD_3 as usize] = op_iload_n::<3>;
+    t[Opcode::LLOAD as usize] = op_lload;
+    t[Opcode::LLOAD_0 as usize] = op_lload_n::<0>;
+    t[Opcode::LLOAD_1 as usize] = op_lload_n::<1>;
+    t[Opcode::LLOAD_2 as usize] = op_lload_n::<2>;
+    t[Opcode::LLOAD_3 as usize] = op_lload_n::<3>;
+    t[Opcode::FLOAD as usize] = op_fload;
+    t[Opcode::FLOAD_0 as usize] = op_fload_n::<0>;
+    t[Opcode::FLOAD_1 as usize] = op_fload_n::<1>;
+    t[Opcode::FLOAD_2 as usize] = op_fload_n::<2>;
+    t[Opcode::FLOAD_3 as usize] = op_fload_n::<3>;
+    t[Opcode::DLOAD as usize] = op_dload;
+    t[Opcode::DLOAD_0 as usize] = op_dload_n::<0>;
+    t[Opcode::DLOAD_1 as usize] = op_dload_n::<1>;
+    t[Opcode::DLOAD_2 as usize] = op_dload_n::<2>;
+    t[Opcode::DLOAD_3 as usize] = op_dload_n::<3>;
+    t[Opcode::ALOAD as usize] = op_aload;
+    t[Opcode::ALOAD_0 as usize] = op_aload_n::<0>;
+    t[Opcode::ALOAD_1 as usize] = op_aload_n::<1>;
+
+    t[Opcode::ISTORE as usize] = op_istore;
+    t[Opcode::ISTORE_0 as usize] = op_istore_n::<0>;
+    t[Opcode::ISTORE_1 as usize] = op_istore_n::<1>;
+    t[Opcode::ISTORE_2 as usize] = op_istore_n::<2>;
+    t[Opcode::ISTORE_3 as usize] = op_istore_n::<3>;
+    t[Opcode::LSTORE as usize] = op_lstore;
+    t[Opcode::LSTORE_0 as usize] = op_lstore_n::<0>;
+    t[Opcode::LSTORE_1 as usize] = op_lstore_n::<1>;
+    t[Opcode::LSTORE_2 as usize] = op_lstore_n::<2>;
+    t[Opcode::LSTORE_3 as usize] = op_lstore_n::<3>;
+    t[Opcode::FSTORE as usize] = op_fstore;
+    t[Opcode::FSTORE_0 as usize] = op_fstore_n::<0>;
+    t[Opcode::FSTORE_1 as usize] = op_fstore_n::<1>;
+    t[Opcode::FSTORE_2 as usize] = op_fstore_n::<2>;
+    t[Opcode::FSTORE_3 as usize] = op_fstore_n::<3>;
+    t[Opcode::DSTORE as usize] = op_dstore;
+    t[Opcode::DSTORE_0 as usize] = op_dstore_n::<0>;
+    t[Opcode::DSTORE_1 as usize] = op_dstore_n::<1>;
+    t[Opcode::DSTORE_2 as usize] = op_dstore_n::<2>;
+    t[Opcode::DSTORE_3 as usize] = op_dstore_n::<3>;
+    t[Opcode::ASTORE as usize] = op_astore;
+    t[Opcode::ASTORE_0 as usize] = op_astore_n::<0>;
+    t[Opcode::ASTORE_1 as usize] = op_astore_n::<1>;
+    t[Opcode::IINC as usize] = op_iinc;
+
+    t[Opcode::POP as usize] = op_pop;
+    t[Opcode::POP2 as usize] = op_pop2;
+    t[Opcode::DUP as usize] = op_dup;
+    t[Opcode::DUP_X1 as usize] = op_dup_x1;
+    t[Opcode::SWAP as usize] = op_swap;
+
+    t[Opcode::IADD as usize] = op_iadd;
+    t[Opcode::ISUB as usize] = op_isub;
+    t[Opcode::IMUL as usize] = op_imul;
+    t[Opcode::IDIV as usize] = op_idiv;
+    t[Opcode::IREM as usize] = op_irem;
+    t[Opcode::INEG as usize] = op_ineg;
+    t[Opcode::IAND as usize] = op_iand;
+    t[Opcode::IOR as usize] = op_ior;
+    t[Opcode::LDIV as usize] = op_ldiv;
+    t[Opcode::LREM as usize] = op_lrem;
+    t[Opcode::DADD as usize] = op_dadd;
+    t[Opcode::DSUB as usize] = op_dsub;
+    t[Opcode::I2D as usize] = op_i2d;
+
+    t[Opcode::GOTO as usize] = op_goto;
+    t[Opcode::GOTO_W as usize] = op_goto;
+    t[Opcode::IF_ICMPEQ as usize] = op_if_icmp::<{ Opcode::IF_ICMPEQ }>;
+    t[Opcode::IF_ICMPNE as usize] = op_if_icmp::<{ Opcode::IF_ICMPNE }>;
+    t[Opcode::IF_ICMPLT as usize] = op_if_icmp::<{ Opcode::IF_ICMPLT }>;
+    t[Opcode::IF_ICMPGE as usize] = op_if_icmp::<{ Opcode::IF_ICMPGE }>;
+    t[Opcode::IF_ICMPGT as usize] = op_if_icmp::<{ Opcode::IF_ICMPGT }>;
+    t[Opcode::IF_ICMPLE as usize] = op_if_icmp::<{ Opcode::IF_ICMPLE }>;
+    t[Opcode::IF_ACMPEQ as usize] = op_if_acmpeq;
+    t[Opcode::IF_ACMPNE as usize] = op_if_acmpne;
+    t[Opcode::IFNULL as usize] = op_ifnull;
+    t[Opcode::IFNONNULL as usize] = op_ifnonnull;
+
+    t[Opcode::IRETURN as usize] = op_typed_return::<{ Opcode::IRETURN }>;
+    t[Opcode::LRETURN as usize] = op_typed_return::<{ Opcode::LRETURN }>;
+    t[Opcode::FRETURN as usize] = op_typed_return::<{ Opcode::FRETURN }>;
+    t[Opcode::DRETURN as usize] = op_typed_return::<{ Opcode::DRETURN }>;
+    t[Opcode::ARETURN as usize] = op_typed_return::<{ Opcode::ARETURN }>;
+    t[Opcode::RETURN as usize] = op_return;
+    t
+}
 
-            Opcode::DLOAD => {
-                eval.push(lvt.load(instr[current]));
-                current += 1;
+fn exec_bytecode(
+    context: &mut VmContext,
+    klass_name: String,
+    meth_name: &str,
+    instr: &Vec<u8>,
+    meth: Option<&OtMethod>,
+    max_stack: u16,
+    lvt: &mut InterpLocalVars,
+) -> Result<Option<JvmValue>, RuntimeError> {
+    // Raw bytecode, and any method whose code didn't decode, is decoded for this run only
+    let decoded_here;
+    let code = match meth.and_then(|m| m.get_decoded()) {
+        Some(code) => code,
+        None => match decode::decode(instr) {
+            Ok(code) => {
+                decoded_here = code;
+                &decoded_here
             }
+            Err((pc, reason)) => {
+                return Err(RuntimeError::VerifyError {
+                    msg: format!("{} at {} in {}", reason, pc, klass_name),
+                })
+            }
+        },
+    };
+    let mut f = Frame {
+        context,
+        klass_name,
+        meth,
+        instr,
+        code,
+        idx: 0,
+        current: 0,
+        eval: InterpEvalStack::with_max_stack(max_stack),
+        lvt,
+    };
 
-            Opcode::DLOAD_0 => eval.push(lvt.load(0)),
+    loop {
+        let (ins, pc) = match f.code.get(f.idx) {
+            Some(insn) => (insn.opcode, insn.pc),
+            None => panic!("Byte {} has no value", f.instr.len()),
+        };
+
+        f.context.trace(meth_name, pc, ins, f.eval.depth());
+        f.context.profile_instruction(ins);
+        f.context.count_step()?;
+        if may_run_bytecode(ins) {
+            f.context.set_frame_roots(frame_refs(&f.eval, f.lvt));
+            f.context.set_frame_line(f.line_for_pc(pc));
+        }
+        f.current = pc + 1;
+
+        let result = match DISPATCH[ins as usize](&mut f) {
+            Err(e) if e.as_exception().is_some() => Err(f.raise(pc, e)),
+            result => result,
+        };
+        let flow = match result {
+            Ok(flow) => flow,
+            // Either thrown here or by something this frame called
+            Err(RuntimeError::Thrown { exception }) => {
+                match f.find_handler(pc, exception.obj_id) {
+                    Some(handler) => {
+                        let obj_id = exception.obj_id;
+                        f.context.record_stack_trace(obj_id, exception.trace);
+                        // The handler starts with just the exception on the stack
+                        f.eval.clear();
+                        f.eval.push(JvmValue::ObjRef { val: obj_id });
+                        Flow::Jump(handler)
+                    }
+                    None => break Err(RuntimeError::Thrown { exception }),
+                }
+            }
+            Err(e) => break Err(e),
+        };
+
+        // Checked before acting on the flow, so a returning instruction's fault isn't lost
+        if let Err(RuntimeError::VerifyError { msg }) = f.eval.check() {
+            break Err(RuntimeError::VerifyError {
+                msg: format!("{} in {}", msg, f.klass_name),
+            });
+        }
+        match flow {
+            Flow::Next => f.idx += 1,
+            Flow::Jump(idx) => f.idx = idx,
+            Flow::Return(ret) => break Ok(ret),
+        }
+    }
+}
 
-            Opcode::DLOAD_1 => eval.push(lvt.load(1)),
+//////////// HANDLERS
 
-            Opcode::DLOAD_2 => eval.push(lvt.load(2)),
+fn op_nop(_f: &mut Frame) -> Result<Flow, RuntimeError> {
+    Ok(Flow::Next)
+}
 
-            Opcode::DLOAD_3 => eval.push(lvt.load(3)),
+fn op_aconst_null(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.aconst_null();
+    Ok(Flow::Next)
+}
 
-            Opcode::DRETURN => break Some(eval.pop()),
-            Opcode::DSTORE => {
-                lvt.store(instr[current], eval.pop());
-                current += 1;
-            }
-            Opcode::DSTORE_0 => lvt.store(0, eval.pop()),
+fn op_iconst<const V: i32>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.iconst(V);
+    Ok(Flow::Next)
+}
 
-            Opcode::DSTORE_1 => lvt.store(1, eval.pop()),
+fn op_lconst<const V: i64>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.lconst(V);
+    Ok(Flow::Next)
+}
 
-            Opcode::DSTORE_2 => lvt.store(2, eval.pop()),
+fn op_fconst<const V: u8>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.fconst(V as f32);
+    Ok(Flow::Next)
+}
 
-            Opcode::DSTORE_3 => lvt.store(3, eval.pop()),
+fn op_dconst<const V: u8>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.dconst(V as f64);
+    Ok(Flow::Next)
+}
 
-            Opcode::DSUB => eval.dsub(),
+// bipush and sipush, whose operand was sign extended when it was decoded
+fn op_ipush(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.iconst(f.insn().operand);
+    Ok(Flow::Next)
+}
 
-            Opcode::DUP => eval.dup(),
+fn op_iload(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.eval.push(f.lvt.load_int(idx)?);
+    Ok(Flow::Next)
+}
 
-            Opcode::DUP_X1 => eval.dupX1(),
+fn op_iload_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.push(f.lvt.load_int(N)?);
+    Ok(Flow::Next)
+}
 
-            Opcode::GETFIELD => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
+fn op_lload(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.eval.push(f.lvt.load_long(idx)?);
+    Ok(Flow::Next)
+}
 
-                let recvp: JvmValue = eval.pop();
-                let obj_id = match recvp {
-                    JvmValue::ObjRef { val: v } => v,
-                    _ => panic!("Not an object ref at {}", (current - 1)),
-                };
-                let heap = HEAP.lock().unwrap();
-                let obj = heap.get_obj(obj_id).clone();
-                let getf = repo.lookup_instance_field(&klass_name, cp_lookup);
+fn op_lload_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.push(f.lvt.load_long(N)?);
+    Ok(Flow::Next)
+}
 
-                let ret = obj.get_field_value(getf.get_offset() as usize);
-                eval.push(ret);
-            }
-            Opcode::GETSTATIC => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
+fn op_fload(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.eval.push(f.lvt.load_float(idx)?);
+    Ok(Flow::Next)
+}
 
-                let getf = repo.lookup_static_field(&klass_name, cp_lookup).clone();
-                let klass = repo.lookup_klass(&getf.get_klass_name()).clone();
+fn op_fload_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.push(f.lvt.load_float(N)?);
+    Ok(Flow::Next)
+}
 
-                let ret = klass.get_static_field_value(&getf);
-                eval.push(ret.clone());
-            }
-            Opcode::GOTO => {
-                current += ((instr[current] as usize) << 8) + instr[current + 1] as usize
-            }
+fn op_dload(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.eval.push(f.lvt.load_double(idx)?);
+    Ok(Flow::Next)
+}
 
-            Opcode::I2D => eval.i2d(),
+fn op_dload_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.push(f.lvt.load_double(N)?);
+    Ok(Flow::Next)
+}
 
-            Opcode::IADD => eval.iadd(),
+fn op_aload(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.eval.push(f.lvt.load_ref(idx)?);
+    Ok(Flow::Next)
+}
 
-            Opcode::IALOAD => {
-                let pos_to_load = match eval.pop() {
-                    JvmValue::Int { val: v } => v,
-                    _ => panic!("Non-int seen on stack during IASTORE at {}", current - 1),
-                };
-                let arrayid = match eval.pop() {
-                    JvmValue::ObjRef { val: v } => v,
-                    _ => panic!("Non-objref seen on stack during IASTORE at {}", current - 1),
-                };
-                dbg!(arrayid.clone());
-
-                let unwrapped_val = match HEAP.lock().unwrap().get_obj(arrayid) {
-                    ocelotter_runtime::object::OtObj::vm_arr_int {
-                        id: _,
-                        mark: _,
-                        klassid: _,
-                        length: _,
-                        elements: elts,
-                    } => elts[pos_to_load as usize],
-                    _ => panic!("Non-int[] seen on stack during IASTORE at {}", current - 1),
-                };
-                eval.push(JvmValue::Int { val: unwrapped_val });
-            }
+fn op_aload_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.push(f.lvt.load_ref(N)?);
+    Ok(Flow::Next)
+}
 
-            Opcode::IAND => eval.iand(),
+fn op_istore(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.lvt.store_int(idx, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::IASTORE => {
-                let val_to_store = match eval.pop() {
-                    JvmValue::Int { val: v } => v,
-                    _ => panic!("Non-int seen on stack during IASTORE at {}", current - 1),
-                };
-                let pos_to_store = match eval.pop() {
-                    JvmValue::Int { val: v } => v,
-                    _ => panic!("Non-int seen on stack during IASTORE at {}", current - 1),
-                };
-                let obj_id = match eval.pop() {
-                    JvmValue::ObjRef { val: v } => v,
-                    _ => panic!("Non-objref seen on stack during IASTORE at {}", current - 1),
-                };
+fn op_istore_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.lvt.store_int(N, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-                HEAP.lock()
-                    .unwrap()
-                    .iastore(obj_id, pos_to_store, val_to_store);
-            }
+fn op_lstore(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.lvt.store_long(idx, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::ICONST_0 => eval.iconst(0),
+fn op_lstore_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.lvt.store_long(N, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::ICONST_1 => eval.iconst(1),
+fn op_fstore(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.lvt.store_float(idx, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::ICONST_2 => eval.iconst(2),
+fn op_fstore_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.lvt.store_float(N, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::ICONST_3 => eval.iconst(3),
+fn op_dstore(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.lvt.store_double(idx, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::ICONST_4 => eval.iconst(4),
+fn op_dstore_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.lvt.store_double(N, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::ICONST_5 => eval.iconst(5),
+fn op_astore(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let idx = f.local_idx();
+    f.lvt.store_ref(idx, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::ICONST_M1 => eval.iconst(-1),
+fn op_astore_n<const N: u16>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.lvt.store_ref(N, f.eval.pop())?;
+    Ok(Flow::Next)
+}
 
-            Opcode::IDIV => eval.idiv(),
+fn op_iinc(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let (idx, incr) = (f.insn().operand as u16, f.insn().operand2 as i16);
+    f.lvt.iinc(idx, incr)?;
+    Ok(Flow::Next)
+}
 
-            Opcode::IF_ICMPEQ => {
-                let jump_to = (instr[current] as usize) << 8 + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i == j
-                }) {
-                    current += jump_to;
-                } else {
-                    current += 2;
-                }
-            }
-            Opcode::IF_ICMPGT => {
-                let jump_to = (instr[current] as usize) << 8 + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i > j
-                }) {
-                    current += jump_to;
-                } else {
-                    current += 2;
-                }
-            }
+fn op_pop(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.pop();
+    Ok(Flow::Next)
+}
 
-            Opcode::IF_ICMPLT => {
-                let jump_to = (instr[current] as usize) << 8 + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i < j
-                }) {
-                    current += jump_to;
-                } else {
-                    current += 2;
-                }
-            }
-            Opcode::IF_ICMPNE => {
-                let jump_to = (instr[current] as usize) << 8 + instr[current + 1] as usize;
-                if massage_to_int_and_compare(eval.pop(), eval.pop(), |i: i32, j: i32| -> bool {
-                    i == j
-                }) {
-                    current += 2;
-                } else {
-                    current += jump_to;
-                }
-            }
-            // Opcode::IFEQ => {
-            //     let jump_to = (instr[current] as usize) << 8 + instr[current + 1] as usize;
-            //     let i = match eval.pop() {
-
-            //     }
-            //     if == 0 {
-            //         current += jump_to;
-            //     } else {
-            //         current += 2;
-            //     }
-            // }    ,
-            // Opcode::IFGE => {
-            //     v = eval.pop();
-            //     jump_to = ((int) instr[current++] << 8) + (int) instr[current++];
-            //     if (v.value >= 0L) {
-            //         current += jump_to - 1; // The -1 is necessary as we've already inc'd current
-            //     }
-            // } ,
-            // Opcode::IFGT => {
-            //     v = eval.pop();
-            //     jump_to = ((int) instr[current++] << 8) + (int) instr[current++];
-            //     if (v.value > 0L) {
-            //         current += jump_to - 1; // The -1 is necessary as we've already inc'd current
-            //     }
-            // },
-            // Opcode::IFLE => {
-            //     v = eval.pop();
-            //     jump_to = ((int) instr[current++] << 8) + (int) instr[current++];
-            //     if (v.value <= 0L) {
-            //         current += jump_to - 1; // The -1 is necessary as we've already inc'd current
-            //     }
-            // },
-            // Opcode::IFLT => {
-            //     v = eval.pop();
-            //     jump_to = ((int) instr[current++] << 8) + (int) instr[current++];
-            //     if (v.value < 0L) {
-            //         current += jump_to - 1; // The -1 is necessary as we've already inc'd current
-            //     }
-            // },
-            // Opcode::IFNE => {
-            //     v = eval.pop();
-            //     jump_to = ((int) instr[current] << 8) + (int) instr[current + 1];
-            //     if (v.value != 0L) {
-            //         current += jump_to - 1;  // The -1 is necessary as we've already inc'd current
-            //     }
-            // },
-            Opcode::IFNONNULL => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-
-                match eval.pop() {
-                    JvmValue::ObjRef { val: v } => {
-                        if v > 0 {
-                            current += jump_to;
-                        } else {
-                            current += 2;
-                        }
-                    }
-                    _ => panic!(
-                        "Value not of reference type found for IFNULL at {}",
-                        (current - 1)
-                    ),
-                };
-            }
-            Opcode::IFNULL => {
-                let jump_to = ((instr[current] as usize) << 8) + instr[current + 1] as usize;
-
-                match eval.pop() {
-                    JvmValue::ObjRef { val: v } => {
-                        if v == 0 {
-                            current += jump_to;
-                        } else {
-                            current += 2;
-                        }
-                    }
-                    _ => panic!(
-                        "Value not of reference type found for IFNULL at {}",
-                        (current - 1)
-                    ),
-                };
-            }
-            Opcode::IINC => {
-                lvt.iinc(instr[current], instr[current + 1]);
-                current += 2;
-            }
+fn op_pop2(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let _discard: JvmValue = f.eval.pop();
+    // FIXME Change to type match
+    // if (discard.type == JVMType.J || discard.type == JVMType.D) {
 
-            Opcode::ILOAD => {
-                eval.push(lvt.load(instr[current]));
-                current += 1
-            }
+    // }
+    f.eval.pop();
+    Ok(Flow::Next)
+}
 
-            Opcode::ILOAD_0 => eval.push(lvt.load(0)),
+fn op_dup(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.dup();
+    Ok(Flow::Next)
+}
 
-            Opcode::ILOAD_1 => eval.push(lvt.load(1)),
+fn op_dup_x1(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.dup_x1();
+    Ok(Flow::Next)
+}
 
-            Opcode::ILOAD_2 => eval.push(lvt.load(2)),
+fn op_swap(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let val1 = f.eval.pop();
+    let val2 = f.eval.pop();
+    f.eval.push(val1);
+    f.eval.push(val2);
+    Ok(Flow::Next)
+}
 
-            Opcode::ILOAD_3 => eval.push(lvt.load(3)),
+fn op_iadd(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.iadd();
+    Ok(Flow::Next)
+}
 
-            Opcode::IMUL => eval.imul(),
+fn op_isub(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.isub();
+    Ok(Flow::Next)
+}
 
-            Opcode::INEG => eval.ineg(),
+fn op_imul(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.imul();
+    Ok(Flow::Next)
+}
 
-            Opcode::INVOKESPECIAL => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-                dispatch_invoke(repo, current_klass, cp_lookup, &mut eval, 1);
-            }
-            Opcode::INVOKESTATIC => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-                // dbg!(current_klass.clone());
-                dispatch_invoke(repo, current_klass, cp_lookup, &mut eval, 0);
-            }
-            Opcode::INVOKEVIRTUAL => {
-                // FIXME DOES NOT ACTUALLY DO VIRTUAL LOOKUP YET
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-                dbg!(current_klass.clone());
-                dispatch_invoke(repo, current_klass, cp_lookup, &mut eval, 1);
-            }
-            Opcode::IOR => eval.ior(),
+fn op_idiv(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.idiv()?;
+    Ok(Flow::Next)
+}
 
-            Opcode::IREM => eval.irem(),
+fn op_irem(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.irem()?;
+    Ok(Flow::Next)
+}
 
-            Opcode::IRETURN => break Some(eval.pop()),
-            Opcode::ISTORE => {
-                lvt.store(instr[current], eval.pop());
-                current += 1;
-            }
-            Opcode::ISTORE_0 => lvt.store(0, eval.pop()),
+fn op_ineg(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.ineg();
+    Ok(Flow::Next)
+}
 
-            Opcode::ISTORE_1 => lvt.store(1, eval.pop()),
+fn op_iand(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.iand();
+    Ok(Flow::Next)
+}
 
-            Opcode::ISTORE_2 => lvt.store(2, eval.pop()),
+fn op_ior(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.ior();
+    Ok(Flow::Next)
+}
 
-            Opcode::ISTORE_3 => lvt.store(3, eval.pop()),
+fn op_ldiv(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.ldiv()?;
+    Ok(Flow::Next)
+}
 
-            Opcode::ISUB => eval.isub(),
-            Opcode::L2I => {
-                match eval.pop() {
-                    JvmValue::Long { val: v } => eval.push(JvmValue::Int { val: v as i32 }),
-                    _ => panic!("Value not of long type found for L2I at {}", (current - 1)),
-                };
-            }
-            Opcode::LDC => {
-                let cp_lookup = instr[current] as u16;
-                current += 1;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-
-                match current_klass.lookup_cp(cp_lookup) {
-                    // FIXME Actually look up the class object properly
-                    CpEntry::class { idx: _ } => eval.aconst_null(),
-                    CpEntry::double { val: dcon } => eval.dconst(dcon),
-                    CpEntry::integer { val: icon } => eval.iconst(icon),
-                    // FIXME Actually look up the class object properly
-                    CpEntry::string { idx: _ } => eval.aconst_null(),
-                    _ => panic!(
-                        "Non-handled entry found in LDC op {} at CP index {}",
-                        current_klass.get_name(),
-                        cp_lookup
-                    ),
-                }
-            }
-            // FIXME TEMP
-            Opcode::MONITORENTER => {
-                eval.pop();
-            }
-            // FIXME TEMP
-            Opcode::MONITOREXIT => {
-                eval.pop();
-            }
-            Opcode::NEW => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-                let current_klass = repo.lookup_klass(&klass_name).clone();
-
-                let alloc_klass_name = match current_klass.lookup_cp(cp_lookup) {
-                    // FIXME Find class name from constant pool of the current class
-                    CpEntry::class { idx } => current_klass.cp_as_string(idx), // "DUMMY_CLASS".to_string(),
-                    _ => panic!(
-                        "Non-class found in {} at CP index {}",
-                        current_klass.get_name(),
-                        cp_lookup
-                    ),
-                };
-                dbg!(alloc_klass_name.clone());
-                let object_klass = repo.lookup_klass(&alloc_klass_name).clone();
+fn op_lrem(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.lrem()?;
+    Ok(Flow::Next)
+}
 
-                let obj_id = HEAP.lock().unwrap().allocate_obj(&object_klass);
-                eval.push(JvmValue::ObjRef { val: obj_id });
-            }
-            Opcode::NEWARRAY => {
-                let arr_type = instr[current];
-                current += 1;
-
-                // FIXME Other primitive array types needed
-                let arr_id = match arr_type {
-                    // boolean: 4
-                    // char: 5
-                    // float: 6
-                    // double: 7
-                    // byte: 8
-                    // short: 9
-                    // int: 10
-                    // long: 11
-                    10 => match eval.pop() {
-                        JvmValue::Int { val: arr_size } => {
-                            HEAP.lock().unwrap().allocate_int_arr(arr_size)
-                        }
-                        _ => panic!("Not an int on the stack at {}", (current - 1)),
-                    },
-                    _ => panic!("Unsupported primitive array type at {}", (current - 1)),
-                };
+fn op_dadd(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.dadd();
+    Ok(Flow::Next)
+}
 
-                eval.push(JvmValue::ObjRef { val: arr_id });
-            }
+fn op_dsub(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.dsub();
+    Ok(Flow::Next)
+}
 
-            Opcode::NOP => {
-                ();
-            }
+fn op_i2d(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    f.eval.i2d();
+    Ok(Flow::Next)
+}
+
+// goto and goto_w, which only differ in how wide their offset was
+fn op_goto(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    Ok(Flow::Jump(f.insn().operand as usize))
+}
 
-            Opcode::POP => {
-                eval.pop();
+fn op_if_icmp<const OP: u8>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let taken = match OP {
+        Opcode::IF_ICMPEQ => f.eval.if_icmpeq(),
+        Opcode::IF_ICMPNE => f.eval.if_icmpne(),
+        Opcode::IF_ICMPLT => f.eval.if_icmplt(),
+        Opcode::IF_ICMPGE => f.eval.if_icmpge(),
+        Opcode::IF_ICMPGT => f.eval.if_icmpgt(),
+        Opcode::IF_ICMPLE => f.eval.if_icmple(),
+        _ => unreachable!("Not an if_icmp opcode: {}", OP),
+    };
+    Ok(f.branch_if(taken))
+}
+
+fn op_if_acmpeq(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let same = massage_to_ref_and_compare(f.eval.pop(), f.eval.pop());
+    Ok(f.branch_if(same))
+}
+
+fn op_if_acmpne(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let same = massage_to_ref_and_compare(f.eval.pop(), f.eval.pop());
+    Ok(f.branch_if(!same))
+}
+
+fn op_ifnull(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    match f.eval.pop() {
+        JvmValue::ObjRef { val: v } => Ok(f.branch_if(v.is_null())),
+        _ => panic!(
+            "Value not of reference type found for IFNULL at {}",
+            (f.current - 1)
+        ),
+    }
+}
+
+fn op_ifnonnull(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    match f.eval.pop() {
+        JvmValue::ObjRef { val: v } => Ok(f.branch_if(!v.is_null())),
+        _ => panic!(
+            "Value not of reference type found for IFNONNULL at {}",
+            (f.current - 1)
+        ),
+    }
+}
+
+fn op_typed_return<const OP: u8>(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    Ok(Flow::Return(Some(typed_return(f.eval.pop(), OP, f.current))))
+}
+
+fn op_return(_f: &mut Frame) -> Result<Flow, RuntimeError> {
+    Ok(Flow::Return(None))
+}
+
+// The opcodes that need the context or the heap, and the ones that aren't supported
+fn exec_general(f: &mut Frame) -> Result<Flow, RuntimeError> {
+    let meth = f.meth;
+    let context = &mut *f.context;
+    let klass_name = &f.klass_name;
+    let instr = f.instr;
+    let eval = &mut f.eval;
+    let lvt = &mut *f.lvt;
+    let mut current = f.current;
+    let ins = instr[current - 1];
+
+    match ins {
+        Opcode::AALOAD => {
+            let pos_to_load = match eval.pop() {
+                JvmValue::Int { val: v } => v,
+                _ => panic!("Non-int seen on stack during AALOAD at {}", current - 1),
+            };
+            let arr = array_ref(eval.pop(), ins, current - 1);
+            let elt = HEAP.lock().unwrap().aaload(arr, pos_to_load)?;
+            eval.push(JvmValue::ObjRef { val: elt });
+        }
+        Opcode::AASTORE => {
+            let val_to_store = array_ref(eval.pop(), ins, current - 1);
+            let pos_to_store = match eval.pop() {
+                JvmValue::Int { val: v } => v,
+                _ => panic!("Non-int seen on stack during AASTORE at {}", current - 1),
+            };
+            let arr = array_ref(eval.pop(), ins, current - 1);
+            // null can be stored in any array
+            let from = match val_to_store {
+                ObjId::NULL => None,
+                obj_id => Some(klass_name_of_obj(context, obj_id)),
+            };
+            let repo = context.get_repo();
+            HEAP.lock().unwrap().aastore(arr, pos_to_store, val_to_store, |component_id| {
+                let to = match repo.try_lookup_klass_by_id(component_id) {
+                    Some(k) => k.get_name(),
+                    None => panic!("No klass found for component {} of array {}", component_id, arr),
+                };
+                match from {
+                    Some(from) if !repo.is_assignable_from(&from, &to) => {
+                        Err(RuntimeError::ArrayStoreException { from, to })
+                    }
+                    _ => Ok(()),
+                }
+            })?;
+        }
+        Opcode::ANEWARRAY => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+            let arr_size = match eval.pop() {
+                JvmValue::Int { val: arr_size } => arr_size,
+                _ => panic!("Not an int on the stack at {}", (current - 3)),
+            };
+            // A component named by descriptor, e.g. [I, is an array. There are no array
+            // klasses, so an array of arrays is made as an Object[]
+            let mut elt_klass_name = cp_klass_name(context, klass_name, cp_lookup);
+            if elt_klass_name.starts_with('[') {
+                elt_klass_name = "java/lang/Object".to_string();
             }
-            Opcode::POP2 => {
-                let _discard: JvmValue = eval.pop();
-                // FIXME Change to type match
-                // if (discard.type == JVMType.J || discard.type == JVMType.D) {
+            let elt_klass = context.lookup_klass(&elt_klass_name).clone();
+            let arr_id = allocate_or_collect(context, eval, lvt, |heap| {
+                heap.try_allocate_null_obj_arr(&elt_klass, arr_size)
+            })?;
+            eval.push(JvmValue::ObjRef { val: ObjId::of(arr_id) });
+        }
+        Opcode::ARRAYLENGTH => {
+            let arr = array_ref(eval.pop(), ins, current - 1);
+            let len = HEAP.lock().unwrap().array_length(arr)?;
+            eval.iconst(len);
+        }
+        Opcode::ATHROW => {
+            let obj_id = deref(eval.pop(), "throw exception")?;
+            // The trace is taken here, as Java fills one in when the exception is made
+            context.set_frame_line(meth.and_then(|m| m.line_for_pc((current - 1) as u16)));
+            return Err(RuntimeError::Thrown {
+                exception: ThrownException {
+                    obj_id,
+                    klass_name: klass_name_of_obj(context, obj_id),
+                    // Kept when one the VM raised is thrown again
+                    msg: context.get_exception_message(obj_id).cloned(),
+                    trace: context.build_stack_trace(),
+                },
+            });
+        }
 
-                // }
-                eval.pop();
+        Opcode::CHECKCAST => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+            match eval.pop() {
+                // null can be cast to anything
+                JvmValue::ObjRef { val: ObjId::NULL } => eval.aconst_null(),
+                JvmValue::ObjRef { val: obj_id } => {
+                    let target = cp_klass_name(context, klass_name, cp_lookup);
+                    let actual = klass_name_of_obj(context, obj_id);
+                    if !context.get_repo().is_assignable_from(&actual, &target) {
+                        return Err(RuntimeError::ClassCastException {
+                            from: actual,
+                            to: target,
+                        });
+                    }
+                    eval.push(JvmValue::ObjRef { val: obj_id });
+                }
+                v => panic!("Non-reference value {:?} found for CHECKCAST at {}", v, current - 3),
             }
-            Opcode::PUTFIELD => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
+        }
+        Opcode::GETFIELD => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
 
-                let val = eval.pop();
+            let getf = context.get_repo().lookup_instance_field(klass_name, cp_lookup);
+            let action = format!("read field {}", getf.get_fq_name_desc());
+            let obj_id = deref(eval.pop(), &action)?.get_id();
+            let offset = instance_field_offset(context, &getf);
 
-                let recvp: JvmValue = eval.pop();
-                let obj_id = match recvp {
-                    JvmValue::ObjRef { val: v } => v,
-                    _ => panic!("Not an object ref at {}", (current - 1)),
-                };
+            let ret = HEAP.lock().unwrap().get_field(obj_id, offset);
+            eval.push(ret);
+        }
+        Opcode::GETSTATIC => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+
+            let field_klass_name = static_field_klass_name(context, klass_name, cp_lookup);
+            context.ensure_initialized(&field_klass_name, exec_method)?;
+            let getf = context.get_repo().lookup_static_field(klass_name, cp_lookup).clone();
+            let klass = context.lookup_klass(&getf.get_klass_name()).clone();
 
-                let putf = repo.lookup_instance_field(&klass_name, cp_lookup);
+            let ret = klass.get_static_field_value(&getf);
+            eval.push(ret.clone());
+        }
+        Opcode::BALOAD => {
+            let pos_to_load = match eval.pop() {
+                JvmValue::Int { val: v } => v,
+                _ => panic!("Non-int seen on stack during BALOAD at {}", current - 1),
+            };
+            let arr = array_ref(eval.pop(), ins, current - 1);
+            let val = HEAP.lock().unwrap().baload(arr, pos_to_load)?;
+            eval.push(JvmValue::Int { val });
+        }
+        Opcode::BASTORE => {
+            let val_to_store = match eval.pop() {
+                JvmValue::Int { val: v } => v,
+                _ => panic!("Non-int seen on stack during BASTORE at {}", current - 1),
+            };
+            let pos_to_store = match eval.pop() {
+                JvmValue::Int { val: v } => v,
+                _ => panic!("Non-int seen on stack during BASTORE at {}", current - 1),
+            };
+            let arr = array_ref(eval.pop(), ins, current - 1);
+            HEAP.lock().unwrap().bastore(arr, pos_to_store, val_to_store)?;
+        }
+        Opcode::IALOAD => {
+            let pos_to_load = match eval.pop() {
+                JvmValue::Int { val: v } => v,
+                _ => panic!("Non-int seen on stack during IALOAD at {}", current - 1),
+            };
+            let arr = array_ref(eval.pop(), ins, current - 1);
+            let val = HEAP.lock().unwrap().iaload(arr, pos_to_load)?;
+            eval.push(JvmValue::Int { val });
+        }
+
+        Opcode::IASTORE => {
+            let val_to_store = match eval.pop() {
+                JvmValue::Int { val: v } => v,
+                _ => panic!("Non-int seen on stack during IASTORE at {}", current - 1),
+            };
+            let pos_to_store = match eval.pop() {
+                JvmValue::Int { val: v } => v,
+                _ => panic!("Non-int seen on stack during IASTORE at {}", current - 1),
+            };
+            let arr = array_ref(eval.pop(), ins, current - 1);
+            HEAP.lock().unwrap().iastore(arr, pos_to_store, val_to_store)?;
+        }
 
-                HEAP.lock().unwrap().put_field(obj_id, putf, val);
+        // Opcode::IFEQ => {
+        //     let jump_to = (instr[current] as usize) << 8 + instr[current + 1] as usize;
+        //     let i = match eval.pop() {
+
+        //     }
+        //     if == 0 {
+        //         current += jump_to;
+        //     } else {
+        //         current += 2;
+        //     }
+        // }    ,
+        // Opcode::IFGE => {
+        //     v = eval.pop();
+        //     jump_to = ((int) instr[current++] << 8) + (int) instr[current++];
+        //     if (v.value >= 0L) {
+        //         current += jump_to - 1; // The -1 is necessary as we've already inc'd current
+        //     }
+        // } ,
+        // Opcode::IFGT => {
+        //     v = eval.pop();
+        //     jump_to = ((int) instr[current++] << 8) + (int) instr[current++];
+        //     if (v.value > 0L) {
+        //         current += jump_to - 1; // The -1 is necessary as we've already inc'd current
+        //     }
+        // },
+        // Opcode::IFLE => {
+        //     v = eval.pop();
+        //     jump_to = ((int) instr[current++] << 8) + (int) instr[current++];
+        //     if (v.value <= 0L) {
+        //         current += jump_to - 1; // The -1 is necessary as we've already inc'd current
+        //     }
+        // },
+        // Opcode::IFLT => {
+        //     v = eval.pop();
+        //     jump_to = ((int) instr[current++] << 8) + (int) instr[current++];
+        //     if (v.value < 0L) {
+        //         current += jump_to - 1; // The -1 is necessary as we've already inc'd current
+        //     }
+        // },
+        // Opcode::IFNE => {
+        //     v = eval.pop();
+        //     jump_to = ((int) instr[current] << 8) + (int) instr[current + 1];
+        //     if (v.value != 0L) {
+        //         current += jump_to - 1;  // The -1 is necessary as we've already inc'd current
+        //     }
+        // },
+        Opcode::INSTANCEOF => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+            match eval.pop() {
+                // null is never an instance of anything
+                JvmValue::ObjRef { val: ObjId::NULL } => eval.iconst(0),
+                JvmValue::ObjRef { val: obj_id } => {
+                    let target = cp_klass_name(context, klass_name, cp_lookup);
+                    let actual = klass_name_of_obj(context, obj_id);
+                    if context.get_repo().is_assignable_from(&actual, &target) {
+                        eval.iconst(1);
+                    } else {
+                        eval.iconst(0);
+                    }
+                }
+                v => panic!("Non-reference value {:?} found for INSTANCEOF at {}", v, current - 3),
             }
-            Opcode::PUTSTATIC => {
-                let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
-                current += 2;
-
-                let puts = repo.lookup_static_field(&klass_name, cp_lookup);
-                let klass_name = puts.get_klass_name();
-                // FIXME IMPL IS BROKEN
-                repo.put_static(klass_name, puts, eval.pop());
+        }
+        Opcode::INVOKEDYNAMIC => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            // Skip the two trailing zero bytes
+            current += 4;
+            // Call sites are per instruction, so the pc is needed to tell them apart
+            let meth_name = match meth {
+                Some(m) => m.get_fq_name_desc(),
+                None => klass_name.clone(),
+            };
+            let current_klass = context.lookup_klass(klass_name).clone();
+            let site = match context.call_site(&meth_name, current - 5, &current_klass, cp_lookup) {
+                Some(site) => site.clone(),
+                None => {
+                    return Err(RuntimeError::UnsupportedOpcodeError {
+                        opcode: Opcode::INVOKEDYNAMIC,
+                        pc: current - 5,
+                    })
+                }
+            };
+            invoke_dynamic(context, &site, eval, lvt)?;
+        }
+        Opcode::INVOKEINTERFACE => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            // Skip the count operand and the trailing zero byte
+            current += 4;
+            let current_klass = context.lookup_klass(klass_name).clone();
+            dispatch_invoke_interface(context, current_klass, cp_lookup, eval)?;
+        }
+        Opcode::INVOKESPECIAL => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+            let current_klass = context.lookup_klass(klass_name).clone();
+            dispatch_invoke(context, current_klass, cp_lookup, eval, 1)?;
+        }
+        Opcode::INVOKESTATIC => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+            let current_klass = context.lookup_klass(klass_name).clone();
+            // dbg!(current_klass.clone());
+            dispatch_invoke(context, current_klass, cp_lookup, eval, 0)?;
+        }
+        Opcode::INVOKEVIRTUAL => {
+            // FIXME DOES NOT ACTUALLY DO VIRTUAL LOOKUP YET
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+            let current_klass = context.lookup_klass(klass_name).clone();
+            dbg!(current_klass.clone());
+            dispatch_invoke(context, current_klass, cp_lookup, eval, 1)?;
+        }
+        Opcode::L2I => {
+            match eval.pop() {
+                JvmValue::Long { val: v } => eval.push(JvmValue::Int { val: v as i32 }),
+                _ => panic!("Value not of long type found for L2I at {}", (current - 1)),
+            };
+        }
+        Opcode::LDC => {
+            let cp_lookup = instr[current] as u16;
+            current += 1;
+            let current_klass = context.lookup_klass(klass_name).clone();
+
+            match current_klass.lookup_cp(cp_lookup) {
+                // FIXME Actually look up the class object properly
+                CpEntry::class { idx: _ } => eval.aconst_null(),
+                CpEntry::double { val: dcon } => eval.dconst(dcon),
+                CpEntry::integer { val: icon } => eval.iconst(icon),
+                CpEntry::string { idx } => {
+                    let s = context.intern_string(&current_klass.cp_as_string(idx))?;
+                    eval.push(JvmValue::ObjRef { val: s });
+                }
+                _ => panic!(
+                    "Non-handled entry found in LDC op {} at CP index {}",
+                    current_klass.get_name(),
+                    cp_lookup
+                ),
             }
-            Opcode::RETURN => break None,
-            Opcode::SIPUSH => {
-                let vtmp = ((instr[current] as i32) << 8) + instr[current + 1] as i32;
-                eval.iconst(vtmp);
-                current += 2;
+        }
+        Opcode::MONITORENTER => {
+            let obj_id = deref(eval.pop(), "enter synchronized block")?.get_id();
+            HEAP.lock().unwrap().monitor_enter(obj_id);
+        }
+        Opcode::MONITOREXIT => {
+            let obj_id = deref(eval.pop(), "exit synchronized block")?.get_id();
+            if HEAP.lock().unwrap().monitor_exit(obj_id).is_none() {
+                return Err(RuntimeError::IllegalMonitorState { obj_id });
             }
-            Opcode::SWAP => {
-                let val1 = eval.pop();
-                let val2 = eval.pop();
-                eval.push(val1);
-                eval.push(val2);
+        }
+        Opcode::NEW => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+            let current_klass = context.lookup_klass(klass_name).clone();
+
+            let alloc_klass_name = match current_klass.lookup_cp(cp_lookup) {
+                // FIXME Find class name from constant pool of the current class
+                CpEntry::class { idx } => current_klass.cp_as_string(idx), // "DUMMY_CLASS".to_string(),
+                _ => panic!(
+                    "Non-class found in {} at CP index {}",
+                    current_klass.get_name(),
+                    cp_lookup
+                ),
+            };
+            dbg!(alloc_klass_name.clone());
+            context.ensure_initialized(&alloc_klass_name, exec_method)?;
+            let object_klass = context.lookup_klass(&alloc_klass_name).clone();
+
+            let obj_id = allocate_or_collect(context, eval, lvt, |heap| {
+                heap.try_allocate_obj(&object_klass)
+            })?;
+            eval.push(JvmValue::ObjRef { val: ObjId::of(obj_id) });
+        }
+        Opcode::NEWARRAY => {
+            let arr_type = instr[current];
+            current += 1;
+
+            let arr_size = match eval.pop() {
+                JvmValue::Int { val: arr_size } => arr_size,
+                _ => panic!("Not an int on the stack at {}", (current - 1)),
+            };
+            // FIXME Other primitive array types needed
+            let arr_id = match arr_type {
+                // boolean: 4
+                // char: 5
+                // float: 6
+                // double: 7
+                // byte: 8
+                // short: 9
+                // int: 10
+                // long: 11
+                4 | 8 => allocate_or_collect(context, eval, lvt, |heap| {
+                    heap.try_allocate_byte_arr(arr_size, arr_type == 4)
+                })?,
+                10 => allocate_or_collect(context, eval, lvt, |heap| {
+                    heap.try_allocate_int_arr(arr_size)
+                })?,
+                _ => panic!("Unsupported primitive array type at {}", (current - 1)),
+            };
+
+            eval.push(JvmValue::ObjRef { val: ObjId::of(arr_id) });
+        }
+
+        Opcode::PUTFIELD => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+
+            let val = eval.pop();
+
+            let putf = context.get_repo().lookup_instance_field(klass_name, cp_lookup);
+            let action = format!("assign field {}", putf.get_fq_name_desc());
+            let obj_id = deref(eval.pop(), &action)?.get_id();
+            let offset = instance_field_offset(context, &putf);
+
+            HEAP.lock().unwrap().put_field(obj_id, offset, putf.narrow(val));
+        }
+        Opcode::PUTSTATIC => {
+            let cp_lookup = ((instr[current] as u16) << 8) + instr[current + 1] as u16;
+            current += 2;
+
+            let field_klass_name = static_field_klass_name(context, klass_name, cp_lookup);
+            context.ensure_initialized(&field_klass_name, exec_method)?;
+            let puts = context.get_repo().lookup_static_field(klass_name, cp_lookup);
+            let klass_name = puts.get_klass_name();
+            let val = puts.narrow(eval.pop());
+            context.get_repo().put_static(klass_name, puts, val);
+        }
+        Opcode::WIDE => {
+            let widened = instr[current];
+            let idx = ((instr[current + 1] as u16) << 8) + instr[current + 2] as u16;
+            current += 3;
+            match widened {
+                Opcode::ALOAD => eval.push(lvt.load_ref(idx)?),
+                Opcode::DLOAD => eval.push(lvt.load_double(idx)?),
+                Opcode::FLOAD => eval.push(lvt.load_float(idx)?),
+                Opcode::ILOAD => eval.push(lvt.load_int(idx)?),
+                Opcode::LLOAD => eval.push(lvt.load_long(idx)?),
+                Opcode::ASTORE => lvt.store_ref(idx, eval.pop())?,
+                Opcode::DSTORE => lvt.store_double(idx, eval.pop())?,
+                Opcode::FSTORE => lvt.store_float(idx, eval.pop())?,
+                Opcode::ISTORE => lvt.store_int(idx, eval.pop())?,
+                Opcode::LSTORE => lvt.store_long(idx, eval.pop())?,
+                Opcode::IINC => {
+                    let incr = ((instr[current] as i16) << 8) + instr[current + 1] as i16;
+                    lvt.iinc(idx, incr)?;
+                    current += 2;
+                }
+                Opcode::RET => {
+                    return Err(RuntimeError::UnsupportedOpcodeError {
+                        opcode: Opcode::RET,
+                        pc: current - 4,
+                    })
+                }
+                _ => panic!(
+                    "Unsupported opcode {} after WIDE at position {}",
+                    widened,
+                    (current - 5)
+                ),
             }
-            // Disallowed opcodes
-            Opcode::BREAKPOINT => break Some(JvmValue::Boolean { val: false }),
-            Opcode::IMPDEP1 => break Some(JvmValue::Boolean { val: false }),
-            Opcode::IMPDEP2 => break Some(JvmValue::Boolean { val: false }),
-            Opcode::JSR => break Some(JvmValue::Boolean { val: false }),
-            Opcode::JSR_W => break Some(JvmValue::Boolean { val: false }),
-            Opcode::RET => break Some(JvmValue::Boolean { val: false }),
-
-            _ => panic!(
-                "Illegal opcode byte: {} encountered at position {}. Stopping.",
-                ins,
-                (current - 1)
-            ),
         }
+        // Reserved for debuggers and the VM's own use, so never valid in a class file
+        Opcode::BREAKPOINT | Opcode::IMPDEP1 | Opcode::IMPDEP2 => {
+            return Err(RuntimeError::IllegalOpcode {
+                opcode: ins,
+                pc: current - 1,
+            })
+        }
+        // Subroutines are rejected rather than implemented. javac stopped emitting
+        // them for finally blocks in Java 6, and version 51+ class files can't use them
+        Opcode::JSR | Opcode::JSR_W | Opcode::RET => {
+            return Err(RuntimeError::UnsupportedOpcodeError {
+                opcode: ins,
+                pc: current - 1,
+            })
+        }
+        _ if !Opcode::is_defined(ins) => {
+            return Err(RuntimeError::IllegalOpcode {
+                opcode: ins,
+                pc: current - 1,
+            })
+        }
+
+        _ => panic!(
+            "Unimplemented opcode {} encountered at position {}. Stopping.",
+            Opcode::mnemonic(ins),
+            (current - 1)
+        ),
     }
+
+    f.current = current;
+    Ok(Flow::Next)
 }
 
-fn massage_to_int_and_compare(v1: JvmValue, v2: JvmValue, f: fn(i: i32, j: i32) -> bool) -> bool {
-    match v1 {
-        JvmValue::Int { val: i } => match v2 {
-            JvmValue::Int { val: i1 } => f(i, i1),
-            _ => panic!("Values found to have differing type for IF_ICMP*"),
-        },
-        _ => panic!("Values found to have the wrong type for IF_ICMP*"),
+// The name of the klass referred to by a CP class entry in the current klass
+fn cp_klass_name(context: &mut VmContext, klass_name: &String, cp_lookup: u16) -> String {
+    let current_klass = context.lookup_klass(klass_name);
+    match current_klass.lookup_cp(cp_lookup) {
+        CpEntry::class { idx } => current_klass.cp_as_string(idx),
+        _ => panic!(
+            "Non-class entry found in {} at CP index {}",
+            klass_name, cp_lookup
+        ),
     }
 }
 
+fn klass_name_of_obj(context: &VmContext, obj_id: ObjId) -> String {
+    match context.klass_name_of_obj(obj_id) {
+        Some(name) => name,
+        None => panic!("No klass found for object {}", obj_id),
+    }
+}
+
+// The klass named by a fieldref, which needn't be loaded yet
+fn static_field_klass_name(context: &mut VmContext, klass_name: &String, cp_lookup: u16) -> String {
+    let fq_name_desc = context.lookup_klass(klass_name).cp_as_string(cp_lookup);
+    SharedKlassRepo::klass_name_from_fq(&fq_name_desc)
+}
+
+// Position of f among its klass's instance fields, which is how objects store them
+fn instance_field_offset(context: &mut VmContext, f: &OtField) -> usize {
+    context
+        .lookup_klass(&f.get_klass_name())
+        .get_instance_field_offset(f)
+}
+
+// The handle behind a reference that's about to be used, e.g. to read a field.
+// action describes that use for the NullPointerException message
+fn deref(v: JvmValue, action: &str) -> Result<ObjId, RuntimeError> {
+    match v {
+        JvmValue::ObjRef { val: ObjId::NULL } => Err(RuntimeError::null_pointer(action)),
+        JvmValue::ObjRef { val } => Ok(val),
+        _ => panic!("Non-reference value {:?} found, trying to {}", v, action),
+    }
+}
+
+// The heap's array accessors do the null check, so this only unwraps the ObjRef
+fn array_ref(v: JvmValue, ins: u8, pc: usize) -> ObjId {
+    match v {
+        JvmValue::ObjRef { val } => val,
+        _ => panic!("Non-reference value {:?} found for {} at {}", v, Opcode::mnemonic(ins), pc),
+    }
+}
+
+// Invokes run the callee, and these can all run a <clinit>
+fn may_run_bytecode(ins: u8) -> bool {
+    matches!(
+        ins,
+        Opcode::INVOKEINTERFACE
+            | Opcode::INVOKESPECIAL
+            | Opcode::INVOKESTATIC
+            | Opcode::INVOKEVIRTUAL
+            | Opcode::GETSTATIC
+            | Opcode::PUTSTATIC
+            | Opcode::NEW
+    )
+}
+
+fn frame_refs(eval: &InterpEvalStack, lvt: &InterpLocalVars) -> Vec<ObjId> {
+    eval.as_slice()
+        .iter()
+        .chain(lvt.as_slice())
+        .filter_map(|v| match v {
+            JvmValue::ObjRef { val } if !val.is_null() => Some(*val),
+            _ => None,
+        })
+        .collect()
+}
+
+// On hitting the heap limit, collects garbage and tries once more
+fn allocate_or_collect<F>(
+    context: &mut VmContext,
+    eval: &InterpEvalStack,
+    lvt: &InterpLocalVars,
+    alloc: F,
+) -> Result<usize, RuntimeError>
+where
+    F: Fn(&mut SharedSimpleHeap) -> Result<usize, RuntimeError>,
+{
+    context.allocate(&frame_refs(eval, lvt), alloc)
+}
+
+// The typed return opcodes must find a value of the matching type on top of the stack.
+// Narrow types (boolean, byte, short, char) are returned via ireturn
+fn typed_return(val: JvmValue, ret_op: u8, current: usize) -> JvmValue {
+    let ty = match ret_op {
+        Opcode::IRETURN => 'I',
+        Opcode::LRETURN => 'J',
+        Opcode::FRETURN => 'F',
+        Opcode::DRETURN => 'D',
+        _ => 'A',
+    };
+    if !val.conforms_to(ty) {
+        panic!(
+            "Value {:?} of the wrong type found for {} at {}",
+            val,
+            Opcode::mnemonic(ret_op),
+            current - 1
+        );
+    }
+    val
+}
+
+fn massage_to_ref_and_compare(v1: JvmValue, v2: JvmValue) -> bool {
+    match (v1, v2) {
+        (JvmValue::ObjRef { val: r1 }, JvmValue::ObjRef { val: r2 }) => r1 == r2,
+        _ => panic!("Values found to have the wrong type for IF_ACMP*"),
+    }
+}
+
+
 fn dispatch_invoke(
-    repo: &mut SharedKlassRepo,
+    context: &mut VmContext,
     current_klass: OtKlass,
     cp_lookup: u16,
     eval: &mut InterpEvalStack,
     additional_args: u8,
-) -> () {
-    let fq_name_desc = current_klass.cp_as_string(cp_lookup);
-    let klz_idx = match current_klass.lookup_cp(cp_lookup) {
-        CpEntry::methodref { clz_idx, nt_idx: _ } => clz_idx,
+) -> Result<(), RuntimeError> {
+    let callee = context.lookup_method_by_cp(&current_klass, cp_lookup);
+    // Only invokestatic (which has no receiver) triggers initialization
+    if additional_args == 0 {
+        context.ensure_initialized(&callee.get_klass_name(), exec_method)?;
+    }
+
+    let args = pop_call_args(eval, &callee.get_desc(), additional_args > 0);
+    if additional_args > 0 {
+        let action = format!("invoke {}", callee.get_fq_name_desc());
+        deref(args[0].1.clone(), &action)?;
+    }
+    let mut vars = frame_of(&callee, args);
+    // Explicit use of match expression to be clear about the semantics
+    match exec_method(context, &callee, &mut vars)? {
+        Some(val) => eval.push(val),
+        None => (),
+    }
+    Ok(())
+}
+
+// Resolves against the receiver's actual klass rather than the interface named at the call site
+fn dispatch_invoke_interface(
+    context: &mut VmContext,
+    current_klass: OtKlass,
+    cp_lookup: u16,
+    eval: &mut InterpEvalStack,
+) -> Result<(), RuntimeError> {
+    let nt_idx = match current_klass.lookup_cp(cp_lookup) {
+        CpEntry::interface_methodref { clz_idx: _, nt_idx } => nt_idx,
         _ => panic!(
-            "Non-methodref found in {} at CP index {}",
+            "Non-interface methodref found in {} at CP index {}",
             current_klass.get_name(),
             cp_lookup
         ),
     };
-    let dispatch_klass_name = current_klass.cp_as_string(klz_idx);
+    let name_desc = current_klass.cp_as_string(nt_idx);
+
+    let args = pop_call_args(eval, &name_desc, true);
+    let action = format!("invoke interface method {}", name_desc);
+    let obj_id = deref(args[0].1.clone(), &action)?;
+    let receiver_klass_name = klass_name_of_obj(context, obj_id);
+    let callee = match context
+        .get_repo()
+        .try_lookup_method_interface(&receiver_klass_name, &name_desc)
+    {
+        Some(m) => m,
+        None => {
+            return Err(RuntimeError::AbstractMethodError {
+                method: receiver_klass_name + "." + &name_desc,
+            })
+        }
+    };
 
-    let callee = repo.lookup_method_exact(&dispatch_klass_name, fq_name_desc);
+    let mut vars = frame_of(&callee, args);
+    if let Some(val) = exec_method(context, &callee, &mut vars)? {
+        eval.push(val);
+    }
+    Ok(())
+}
 
-    // FIXME - General setup requires call args from the stack
-    let mut vars = InterpLocalVars::of(255);
-    if additional_args > 0 {
-        vars.store(0, eval.pop());
+// Pops a call's arguments (and receiver, if any) off the caller's stack, paired with
+// the local var slot each one lands in. The receiver, if present, comes first
+fn pop_call_args(
+    eval: &mut InterpEvalStack,
+    name_desc: &str,
+    has_receiver: bool,
+) -> Vec<(u16, JvmValue)> {
+    let sizes = descriptor::arg_slot_sizes(name_desc);
+    let mut slot: u16 = has_receiver as u16 + sizes.iter().sum::<u16>();
+    let mut out = Vec::new();
+    for size in sizes.iter().rev() {
+        slot -= size;
+        out.push((slot, eval.pop()));
     }
-    // Explicit use of match expression to be clear about the semantics
-    match exec_method(repo, &callee, &mut vars) {
-        Some(val) => eval.push(val),
-        None => (),
+    if has_receiver {
+        out.push((0, eval.pop()));
+    }
+    out.reverse();
+    out
+}
+
+//////////// INVOKEDYNAMIC
+
+// Runs a linked call site. String concatenation is the only kind there is
+fn invoke_dynamic(
+    context: &mut VmContext,
+    site: &CallSite,
+    eval: &mut InterpEvalStack,
+    lvt: &InterpLocalVars,
+) -> Result<(), RuntimeError> {
+    match site {
+        CallSite::StringConcat { name_desc, parts } => {
+            let mut args = pop_call_args(eval, name_desc, false).into_iter();
+            let mut out = String::new();
+            for part in parts {
+                match part {
+                    ConcatPart::Literal(s) => out.push_str(s),
+                    ConcatPart::Arg(ty) => match args.next() {
+                        Some((_, v)) => out.push_str(&concat_arg(context, ty, v)),
+                        None => panic!("Too few arguments on the stack for {}", name_desc),
+                    },
+                }
+            }
+            let k_jls = context.lookup_klass(&"java/lang/String".to_string()).clone();
+            let s = allocate_or_collect(context, eval, lvt, |heap| heap.try_allocate_str(&k_jls, &out))?;
+            eval.push(JvmValue::ObjRef { val: ObjId::of(s) });
+        }
+    }
+    Ok(())
+}
+
+// A concat argument as String.valueOf() would render it. Narrow values have been
+// promoted to ints on the stack, so the descriptor type says how to show them
+fn concat_arg(context: &VmContext, ty: &str, v: JvmValue) -> String {
+    match (ty, v) {
+        ("Z", JvmValue::Int { val }) => (val != 0).to_string(),
+        ("C", JvmValue::Int { val }) => match std::char::from_u32(val as u32 & 0xffff) {
+            Some(c) => c.to_string(),
+            None => char::REPLACEMENT_CHARACTER.to_string(),
+        },
+        (_, JvmValue::Int { val }) => val.to_string(),
+        (_, JvmValue::Long { val }) => val.to_string(),
+        (_, JvmValue::Float { val }) => java_fp_string(val),
+        (_, JvmValue::Double { val }) => java_fp_string(val),
+        (_, JvmValue::ObjRef { val: ObjId::NULL }) => "null".to_string(),
+        (_, JvmValue::ObjRef { val: id }) => {
+            let heap = HEAP.lock().unwrap();
+            match heap.get_obj(id.get_id()) {
+                OtObj::vm_str { .. } => heap.get_obj(id.get_id()).get_str_value(),
+                obj => obj.describe(context.get_repo()),
+            }
+        }
+        (_, v) => panic!("Unexpected value {:?} for concat argument of type {}", v, ty),
+    }
+}
+
+// Java prints plain decimals between 10^-3 and 10^7 and scientific notation, always
+// with a fractional part (e.g. 1.0E10), outside it
+fn java_fp_string<T>(v: T) -> String
+where
+    T: Into<f64> + Copy + std::fmt::Debug + std::fmt::UpperExp,
+{
+    let d: f64 = v.into();
+    if d.is_nan() {
+        "NaN".to_string()
+    } else if d.is_infinite() {
+        if d > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if d == 0.0 || (1e-3..1e7).contains(&d.abs()) {
+        format!("{:?}", v)
+    } else {
+        let sci = format!("{:E}", v);
+        match sci.find('E') {
+            Some(e) if !sci[..e].contains('.') => format!("{}.0{}", &sci[..e], &sci[e..]),
+            _ => sci,
+        }
+    }
+}
+
+fn frame_of(callee: &OtMethod, args: Vec<(u16, JvmValue)>) -> InterpLocalVars {
+    let mut vars = InterpLocalVars::with_capacity(callee.get_local_var_size() as usize);
+    for (slot, val) in args {
+        vars.store(slot, val);
     }
+    vars
 }
 
 // fn parse_class(bytes: Vec<u8>, fname: String) -> OtKlass {
@@ -1,28 +1,46 @@
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
 use std::path::Path;
+use std::rc::Rc;
 
 use super::*;
 
+use ocelotter_runtime::code_builder::CodeBuilder;
 use ocelotter_runtime::constant_pool::ACC_PUBLIC;
+use ocelotter_runtime::klass_builder::KlassBuilder;
+use ocelotter_runtime::otmethod;
+use ocelotter_runtime::runtime_error::StackTraceElement;
 use ocelotter_util::file_to_bytes;
 
 // Helper fns
 
-fn init_repo() -> SharedKlassRepo {
-    let mut repo = SharedKlassRepo::of();
-    repo.bootstrap(exec_method);
-    repo
+fn init_context() -> VmContext {
+    let mut context = VmContext::of();
+    context.bootstrap(exec_method);
+    context
 }
 
 fn execute_simple_bytecode(buf: &Vec<u8>) -> JvmValue {
-    let mut repo = init_repo();
+    let mut context = init_context();
     let mut lvt = InterpLocalVars::of(10); // FIXME
-    exec_bytecode_method(&mut repo, "DUMMY".to_string(), &buf, &mut lvt).unwrap_or_else(|| {
+    exec_bytecode_method(&mut context, "DUMMY".to_string(), buf, &mut lvt)
+        .unwrap()
+        .unwrap_or_else(|| {
         JvmValue::ObjRef {
-            val: 0, // object::OtObj::get_null(),
+            val: ObjId::NULL, // object::OtObj::get_null(),
         }
     })
 }
 
+// The klass and message of the exception that ret is the throwing of
+fn thrown(ret: Result<Option<JvmValue>, RuntimeError>) -> (String, Option<String>) {
+    match ret {
+        Err(RuntimeError::Thrown { exception }) => (exception.klass_name, exception.msg),
+        r => panic!("Expected an exception to be thrown, got {:?}", r),
+    }
+}
+
 fn simple_parse_klass(cname: String) -> OtKlass {
     let mut path = "./resources/test/".to_string();
     path.push_str(&cname);
@@ -133,6 +151,38 @@ fn bc_idiv_works() {
     assert_eq!(1, ret);
 }
 
+#[test]
+fn bc_division_by_zero_throws() {
+    let mut context = init_context();
+    for op in &[opcode::Opcode::IDIV, opcode::Opcode::IREM] {
+        let buf = vec![
+            opcode::Opcode::ICONST_5,
+            opcode::Opcode::ICONST_0,
+            *op,
+            opcode::Opcode::IRETURN,
+        ];
+        let mut lvt = InterpLocalVars::of(10);
+        let ret = exec_bytecode_method(&mut context, "DivTest".to_string(), &buf, &mut lvt);
+        assert_eq!(
+            ("java/lang/ArithmeticException".to_string(), Some("/ by zero".to_string())),
+            thrown(ret)
+        );
+    }
+
+    let buf = vec![
+        opcode::Opcode::LCONST_1,
+        opcode::Opcode::LCONST_0,
+        opcode::Opcode::LREM,
+        opcode::Opcode::LRETURN,
+    ];
+    let mut lvt = InterpLocalVars::of(10);
+    let ret = exec_bytecode_method(&mut context, "DivTest".to_string(), &buf, &mut lvt);
+    assert_eq!(
+        "java/lang/ArithmeticException: / by zero\n\tat DivTest(Unknown Source)",
+        ret.unwrap_err().to_string()
+    );
+}
+
 #[test]
 fn bc_iconst_dup_nop_pop() {
     let buf = vec![
@@ -198,7 +248,7 @@ fn bc_ifnonnull() {
         opcode::Opcode::ACONST_NULL,
         opcode::Opcode::IFNONNULL,
         0,
-        4,
+        5,
         opcode::Opcode::POP,
         opcode::Opcode::ICONST_2,
         opcode::Opcode::IRETURN,
@@ -213,6 +263,19 @@ fn bc_ifnonnull() {
     assert_eq!(2, ret);
 }
 
+// execute_simple_bytecode turns a missing return value into null too, so look at
+// the raw result
+#[test]
+fn bc_aconst_null_returns_null_ref() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(1);
+    let buf = vec![Opcode::ACONST_NULL, Opcode::ARETURN];
+    match exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt) {
+        Ok(Some(JvmValue::ObjRef { val })) => assert!(val.is_null()),
+        other => panic!("Expected a null reference, got {:?}", other),
+    }
+}
+
 #[test]
 fn bc_ifnull() {
     let buf = vec![
@@ -220,7 +283,7 @@ fn bc_ifnull() {
         opcode::Opcode::ACONST_NULL,
         opcode::Opcode::IFNULL,
         0,
-        4,
+        5,
         opcode::Opcode::POP,
         opcode::Opcode::ICONST_2,
         opcode::Opcode::IRETURN,
@@ -244,7 +307,7 @@ fn bc_ifeq() {
         Opcode::ICONST_2,
         Opcode::IF_ICMPEQ,
         0,
-        3,
+        4,
         Opcode::ICONST_4,
         // Opcode::GOTO,
         // 0,
@@ -270,7 +333,7 @@ fn bc_goto() {
         opcode::Opcode::IADD,
         opcode::Opcode::GOTO,
         0,
-        3,
+        4,
         0xff,
         opcode::Opcode::IRETURN,
     ];
@@ -284,120 +347,2003 @@ fn bc_goto() {
     assert_eq!(2, ret);
 }
 
-/////////////////////////////////////////////////////////////////
-//
-// Tests that actually load classes
+#[test]
+fn bc_goto_backwards() {
+    let buf = vec![
+        opcode::Opcode::GOTO,
+        0,
+        4,
+        opcode::Opcode::IRETURN,
+        opcode::Opcode::ICONST_3,
+        opcode::Opcode::GOTO,
+        0xff,
+        0xfe,
+    ];
+    let ret = match execute_simple_bytecode(&buf) {
+        JvmValue::Int { val: i } => i,
+        _ => {
+            println!("Unexpected, non-integer value encountered");
+            0
+        }
+    };
+    assert_eq!(3, ret);
+}
 
 #[test]
-fn interp_invoke_simple() {
-    let mut repo = init_repo();
-    let k = simple_parse_klass("SampleInvoke".to_string());
-    repo.add_klass(&k);
+fn bc_if_icmplt_compares_in_spec_order() {
+    // 2 < 3, so branch to return 1
+    let buf = vec![
+        Opcode::ICONST_2,
+        Opcode::ICONST_3,
+        Opcode::IF_ICMPLT,
+        0,
+        5,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+        Opcode::ICONST_1,
+        Opcode::IRETURN,
+    ];
+    assert_eq!(JvmValue::Int { val: 1 }, execute_simple_bytecode(&buf));
 
-    {
-        let fq_meth = "SampleInvoke.bar:()I";
-        let meth = k
-            .get_method_by_name_and_desc(&fq_meth.to_string())
-            .expect(&format!("{} not found", fq_meth));
-        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+    let mut buf = buf.clone();
+    buf[2] = Opcode::IF_ICMPGT;
+    assert_eq!(JvmValue::Int { val: 0 }, execute_simple_bytecode(&buf));
+}
+
+#[test]
+fn bc_if_acmpeq_same_ref() {
+    let buf = vec![
+        Opcode::ICONST_1,
+        Opcode::NEWARRAY,
+        10,
+        Opcode::DUP,
+        Opcode::IF_ACMPEQ,
+        0,
+        5,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+        Opcode::ICONST_1,
+        Opcode::IRETURN,
+    ];
+    let ret = match execute_simple_bytecode(&buf) {
+        JvmValue::Int { val: i } => i,
+        _ => {
+            println!("Unexpected, non-integer value encountered");
+            0
+        }
+    };
+    assert_eq!(1, ret);
+}
+
+#[test]
+fn bc_if_acmpeq_distinct_refs() {
+    let buf = vec![
+        Opcode::ICONST_1,
+        Opcode::NEWARRAY,
+        10,
+        Opcode::ICONST_1,
+        Opcode::NEWARRAY,
+        10,
+        Opcode::IF_ACMPEQ,
+        0,
+        5,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+        Opcode::ICONST_1,
+        Opcode::IRETURN,
+    ];
+    let ret = match execute_simple_bytecode(&buf) {
+        JvmValue::Int { val: i } => i,
+        _ => {
+            println!("Unexpected, non-integer value encountered");
+            0
+        }
+    };
+    assert_eq!(0, ret);
+}
+
+#[test]
+fn interp_object_equals_is_identity() {
+    let mut context = init_context();
+    let k_obj = context.lookup_klass(&"java/lang/Object".to_string());
+    let equals = k_obj
+        .get_method_by_name_and_desc(&"java/lang/Object.equals:(Ljava/lang/Object;)Z".to_string())
+        .expect("java/lang/Object.equals not found")
+        .clone();
+    let a = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k_obj));
+    let b = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k_obj));
 
+    for (this, other, expected) in &[(a, a, 1), (a, b, 0), (a, ObjId::NULL, 0)] {
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
-        let ret2 = match ret {
-            JvmValue::Int { val: i } => i,
-            _ => panic!("Error executing SampleInvoke.bar:()I - non-int value returned"),
-        };
-        assert_eq!(7, ret2);
+        vars.store(0, JvmValue::ObjRef { val: *this });
+        vars.store(1, JvmValue::ObjRef { val: *other });
+        let ret = exec_method(&mut context, &equals, &mut vars);
+        assert_eq!(Ok(Some(JvmValue::Int { val: *expected })), ret);
     }
 
-    {
-        let fq_meth = "SampleInvoke.foo:()I";
-        let meth = k
-            .get_method_by_name_and_desc(&fq_meth.to_string())
-            .expect(&format!("{} not found", fq_meth));
-        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+    // null == null, via if_acmpeq
+    let buf = vec![
+        Opcode::ACONST_NULL,
+        Opcode::ACONST_NULL,
+        Opcode::IF_ACMPEQ,
+        0,
+        5,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+        Opcode::ICONST_1,
+        Opcode::IRETURN,
+    ];
+    assert_eq!(JvmValue::Int { val: 1 }, execute_simple_bytecode(&buf));
+}
 
-        let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
-        let ret2 = match ret {
-            JvmValue::Int { val: i } => i,
-            _ => panic!("Error executing SampleInvoke.foo:()I - non-int value returned"),
-        };
-        assert_eq!(9, ret2);
+// Calls a boxing or unboxing method of a wrapper klass, with a single arg
+fn call_wrapper(context: &mut VmContext, klass_name: &str, name_desc: &str, arg: JvmValue) -> JvmValue {
+    let meth = context
+        .get_repo()
+        .lookup_method_exact(&klass_name.to_string(), format!("{}.{}", klass_name, name_desc));
+    let mut vars = InterpLocalVars::of(2);
+    vars.store(0, arg);
+    match exec_method(context, &meth, &mut vars) {
+        Ok(Some(v)) => v,
+        r => panic!("Unexpected result {:?} from {}.{}", r, klass_name, name_desc),
     }
 }
 
 #[test]
-fn interp_iffer() {
-    let mut repo = init_repo();
-    let k = simple_parse_klass("Iffer".to_string());
-    repo.add_klass(&k);
+fn interp_integer_value_of_caches_small_values() {
+    let mut context = init_context();
+    let value_of = "valueOf:(I)Ljava/lang/Integer;";
+    let mut box_int = |i: i32| call_wrapper(&mut context, "java/lang/Integer", value_of, JvmValue::Int { val: i });
 
-    {
-        let fq_meth = "Iffer.baz:()I";
-        let meth = k
-            .get_method_by_name_and_desc(&fq_meth.to_string())
-            .expect(&format!("{} not found", fq_meth));
-        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+    assert_eq!(box_int(100), box_int(100));
+    assert_eq!(box_int(-128), box_int(-128));
+    assert_eq!(box_int(127), box_int(127));
+    assert_ne!(box_int(1000), box_int(1000));
+    assert_ne!(box_int(128), box_int(128));
+    assert_ne!(box_int(-129), box_int(-129));
+}
 
-        let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
-        let ret2 = match ret {
-            JvmValue::Int { val: i } => i,
-            _ => panic!("Error executing Iffer.baz:()I - non-int value returned"),
-        };
-        assert_eq!(3, ret2);
+#[test]
+fn interp_boxes_unbox_to_their_value() {
+    let mut context = init_context();
+    for (klass_name, value_of, x_value, v) in &[
+        ("java/lang/Integer", "valueOf:(I)Ljava/lang/Integer;", "intValue:()I", JvmValue::Int { val: 1000 }),
+        ("java/lang/Long", "valueOf:(J)Ljava/lang/Long;", "longValue:()J", JvmValue::Long { val: 1 << 40 }),
+        ("java/lang/Double", "valueOf:(D)Ljava/lang/Double;", "doubleValue:()D", JvmValue::Double { val: 2.5 }),
+        ("java/lang/Boolean", "valueOf:(Z)Ljava/lang/Boolean;", "booleanValue:()Z", JvmValue::Int { val: 1 }),
+    ] {
+        let boxed = call_wrapper(&mut context, klass_name, value_of, v.clone());
+        assert_eq!(v.clone(), call_wrapper(&mut context, klass_name, x_value, boxed));
+    }
+
+    // There are only ever two Booleans
+    let value_of = "valueOf:(Z)Ljava/lang/Boolean;";
+    let t1 = call_wrapper(&mut context, "java/lang/Boolean", value_of, JvmValue::Int { val: 1 });
+    let t2 = call_wrapper(&mut context, "java/lang/Boolean", value_of, JvmValue::Int { val: 1 });
+    let f = call_wrapper(&mut context, "java/lang/Boolean", value_of, JvmValue::Int { val: 0 });
+    assert_eq!(t1, t2);
+    assert_ne!(t1, f);
+}
+
+fn concat_method(context: &mut VmContext, name_desc: &str) -> OtMethod {
+    let k = simple_parse_klass("Concat".to_string());
+    context.get_repo_mut().add_klass(&k);
+    context
+        .get_repo()
+        .lookup_method_exact(&"Concat".to_string(), "Concat.".to_string() + name_desc)
+}
+
+fn str_value(v: JvmValue) -> String {
+    match v {
+        JvmValue::ObjRef { val } => HEAP.lock().unwrap().get_obj(val.get_id()).get_str_value(),
+        _ => panic!("Non-reference value {:?} found where a String was expected", v),
     }
 }
 
 #[test]
-fn interp_array_set() {
-    let mut repo = init_repo();
-    let k = simple_parse_klass("ArraySimple".to_string());
-    repo.add_klass(&k);
+fn interp_string_concat_uses_string_builder() {
+    let mut context = init_context();
+    let meth = concat_method(&mut context, "xEquals:(I)Ljava/lang/String;");
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::Int { val: 42 });
+    let ret = exec_method(&mut context, &meth, &mut vars).unwrap().unwrap();
+    assert_eq!("x=42", str_value(ret));
 
-    {
-        let fqname = "ArraySimple.baz:()I".to_string();
-        let meth = k.get_method_by_name_and_desc(&fqname).unwrap();
+    let meth = concat_method(&mut context, "greet:(Ljava/lang/String;)Ljava/lang/String;");
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::ObjRef { val: context.intern_string("World").unwrap() });
+    let ret = exec_method(&mut context, &meth, &mut vars).unwrap().unwrap();
+    assert_eq!("Hello, World!", str_value(ret));
 
-        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+    // A null String is appended as "null"
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::ObjRef { val: ObjId::NULL });
+    let ret = exec_method(&mut context, &meth, &mut vars).unwrap().unwrap();
+    assert_eq!("Hello, null!", str_value(ret));
+}
 
-        let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
-        let ret2 = match ret {
-            JvmValue::Int { val: i } => i,
-            _ => panic!("Error executing {} - non-int value returned", fqname),
-        };
-        assert_eq!(7, ret2);
+#[test]
+fn interp_string_constants_are_interned() {
+    let mut context = init_context();
+    let meth = concat_method(&mut context, "constant:()Ljava/lang/String;");
+    let first = exec_method(&mut context, &meth, &mut InterpLocalVars::of(1)).unwrap().unwrap();
+    let second = exec_method(&mut context, &meth, &mut InterpLocalVars::of(1)).unwrap().unwrap();
+    assert_eq!(first, second);
+    assert_eq!("x=", str_value(first));
+}
+
+fn run_indy_concat(context: &mut VmContext, name_desc: &str, args: Vec<JvmValue>) -> String {
+    let k = simple_parse_klass("IndyConcat".to_string());
+    context.get_repo_mut().add_klass(&k);
+    let meth = context
+        .get_repo()
+        .lookup_method_exact(&"IndyConcat".to_string(), "IndyConcat.".to_string() + name_desc);
+    let mut vars = InterpLocalVars::of(meth.get_local_var_size());
+    let mut slot = 0;
+    for arg in args {
+        let wide = matches!(arg, JvmValue::Long { .. } | JvmValue::Double { .. });
+        vars.store(slot, arg);
+        slot += if wide { 2 } else { 1 };
     }
+    str_value(exec_method(context, &meth, &mut vars).unwrap().unwrap())
 }
 
 #[test]
-fn interp_field_set() {
-    let mut repo = init_repo();
-    let k = simple_parse_klass("FieldHaver".to_string());
-    repo.add_klass(&k);
+fn interp_invokedynamic_concatenates_strings() {
+    let mut context = init_context();
+    let name = JvmValue::ObjRef { val: context.intern_string("Ann").unwrap() };
+    let args = vec![
+        name,
+        JvmValue::Int { val: 30 },
+        JvmValue::Long { val: 1 << 40 },
+        JvmValue::Int { val: 1 },
+        JvmValue::Int { val: 'A' as i32 },
+        JvmValue::Double { val: 2.5 },
+    ];
+    assert_eq!(
+        "name=Ann, age=30, id=1099511627776, ok=true, grade=A, score=2.5",
+        run_indy_concat(&mut context, "describe:(Ljava/lang/String;IJZCD)Ljava/lang/String;", args)
+    );
 
-    {
-        let fqname = "FieldHaver.main2:([Ljava/lang/String;)I".to_string();
-        let meth = k.get_method_by_name_and_desc(&fqname).unwrap();
+    // The tag chars themselves come through as constants, not placeholders
+    let args = vec![JvmValue::Int { val: 7 }];
+    assert_eq!("\u{1}7\u{2}", run_indy_concat(&mut context, "withTags:(I)Ljava/lang/String;", args));
+}
 
-        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+#[test]
+fn interp_invokedynamic_bootstraps_once_per_call_site() {
+    let mut context = init_context();
+    let tags = "withTags:(I)Ljava/lang/String;";
+    run_indy_concat(&mut context, tags, vec![JvmValue::Int { val: 1 }]);
+    assert_eq!(1, context.get_bootstraps_run());
+    run_indy_concat(&mut context, tags, vec![JvmValue::Int { val: 2 }]);
+    assert_eq!(1, context.get_bootstraps_run());
+
+    // The concat in the loop is linked on the first time round only
+    let ret = run_indy_concat(&mut context, "count:(I)Ljava/lang/String;", vec![JvmValue::Int { val: 5 }]);
+    assert_eq!("01234", ret);
+    assert_eq!(2, context.get_bootstraps_run());
+}
+
+#[test]
+fn interp_invokedynamic_tries_an_unsupported_bootstrap_once() {
+    let mut context = init_context();
+    let k = simple_parse_klass("IndyLambda".to_string());
+    context.get_repo_mut().add_klass(&k);
+    let meth = k
+        .get_method_by_name_and_desc(&"IndyLambda.make:()Ljava/lang/Runnable;".to_string())
+        .expect("IndyLambda.make not found");
+    let expected = RuntimeError::UnsupportedOpcodeError {
+        opcode: Opcode::INVOKEDYNAMIC,
+        pc: 0,
+    };
+    for _ in 0..2 {
+        let mut vars = InterpLocalVars::of(1);
+        assert_eq!(Err(expected.clone()), exec_method(&mut context, meth, &mut vars));
+    }
+    assert_eq!(1, context.get_bootstraps_run());
+}
+
+#[test]
+fn interp_invokedynamic_formats_floats_like_java() {
+    let mut context = init_context();
+    let join_desc = "join:(Ljava/lang/String;Ljava/lang/String;F)Ljava/lang/String;";
+    let mut join = |f: f32| {
+        let a = JvmValue::ObjRef { val: context.intern_string("<").unwrap() };
+        let args = vec![a, JvmValue::ObjRef { val: ObjId::NULL }, JvmValue::Float { val: f }];
+        run_indy_concat(&mut context, join_desc, args)
+    };
+    assert_eq!("<1.5null", join(1.5));
+    assert_eq!("<-0.0null", join(-0.0));
+    assert_eq!("<1.0E10null", join(1e10));
+    assert_eq!("<1.5E-4null", join(1.5e-4));
+    assert_eq!("<NaNnull", join(f32::NAN));
+    assert_eq!("<-Infinitynull", join(f32::NEG_INFINITY));
+}
+
+fn run_array_store(context: &mut VmContext, name: &str) -> Result<Option<JvmValue>, RuntimeError> {
+    let k = simple_parse_klass("ArrayStore".to_string());
+    context.get_repo_mut().add_klass(&k);
+    let meth = context.get_repo().lookup_method_exact(
+        &"ArrayStore".to_string(),
+        format!("ArrayStore.{}:()Ljava/lang/Object;", name),
+    );
+    exec_method(context, &meth, &mut InterpLocalVars::of(2))
+}
+
+#[test]
+fn interp_aastore_checks_component_type() {
+    let mut context = init_context();
+
+    // String into Object[]
+    let ret = run_array_store(&mut context, "storeString").unwrap().unwrap();
+    assert_eq!("s", str_value(ret));
+
+    // null into String[]
+    let ret = run_array_store(&mut context, "storeNull");
+    assert_eq!(Ok(Some(JvmValue::ObjRef { val: ObjId::NULL })), ret);
+
+    // Integer into String[]
+    let ret = run_array_store(&mut context, "storeInteger");
+    assert_eq!(
+        (
+            "java/lang/ArrayStoreException".to_string(),
+            Some("java/lang/Integer cannot be stored in an array of java/lang/String".to_string())
+        ),
+        thrown(ret)
+    );
+
+    // Arrays into arrays of arrays
+    match run_array_store(&mut context, "storeRow") {
+        Ok(Some(JvmValue::ObjRef { val })) => {
+            assert_eq!(Ok(5), HEAP.lock().unwrap().iaload(val, 1))
+        }
+        other => panic!("Expected the stored row, got {:?}", other),
+    }
+    let ret = run_array_store(&mut context, "storeStrings").unwrap().unwrap();
+    assert_eq!("s", str_value(ret));
+
+    // The same store, with a handler
+    match run_array_store(&mut context, "catchStore") {
+        Ok(Some(JvmValue::ObjRef { val })) if !val.is_null() => assert_eq!(
+            Some("java/lang/ArrayStoreException".to_string()),
+            context.klass_name_of_obj(val)
+        ),
+        other => panic!("Expected the caught exception, got {:?}", other),
+    }
+}
 
+#[test]
+fn bc_if_acmpne_null_and_non_null() {
+    let buf = vec![
+        Opcode::ACONST_NULL,
+        Opcode::ICONST_1,
+        Opcode::NEWARRAY,
+        10,
+        Opcode::IF_ACMPNE,
+        0,
+        5,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+        Opcode::ICONST_1,
+        Opcode::IRETURN,
+    ];
+    let ret = match execute_simple_bytecode(&buf) {
+        JvmValue::Int { val: i } => i,
+        _ => {
+            println!("Unexpected, non-integer value encountered");
+            0
+        }
+    };
+    assert_eq!(1, ret);
+}
+
+#[test]
+fn bc_ifnull_non_null() {
+    let buf = vec![
+        Opcode::ICONST_1,
+        Opcode::NEWARRAY,
+        10,
+        Opcode::IFNULL,
+        0,
+        5,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+        Opcode::ICONST_1,
+        Opcode::IRETURN,
+    ];
+    let ret = match execute_simple_bytecode(&buf) {
+        JvmValue::Int { val: i } => i,
+        _ => {
+            println!("Unexpected, non-integer value encountered");
+            0
+        }
+    };
+    assert_eq!(0, ret);
+}
+
+#[test]
+fn bc_trace_records_each_instruction() {
+    let mut context = init_context();
+    let trace = Rc::new(RefCell::new(Vec::new()));
+    let recorder = trace.clone();
+    context.set_trace_callback(Some(Box::new(move |_, pc, opcode, depth| {
+        recorder.borrow_mut().push((pc, opcode, depth))
+    })));
+
+    let buf = vec![
+        Opcode::ICONST_1,
+        Opcode::BIPUSH,
+        3,
+        Opcode::IADD,
+        Opcode::IRETURN,
+    ];
+    let mut lvt = InterpLocalVars::of(10);
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 4 })), ret);
+    assert_eq!(
+        vec![
+            (0, Opcode::ICONST_1, 0),
+            (1, Opcode::BIPUSH, 1),
+            (3, Opcode::IADD, 2),
+            (4, Opcode::IRETURN, 1),
+        ],
+        *trace.borrow()
+    );
+
+    // Nothing more is recorded once the callback is removed
+    context.set_trace_callback(None);
+    let mut lvt = InterpLocalVars::of(10);
+    exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt).unwrap();
+    assert_eq!(4, trace.borrow().len());
+}
+
+#[test]
+fn interp_profile_counts_instructions_and_calls() {
+    // int sum = 0; for (int i = 0; i != 10; i++) { sum += i; } return sum;
+    let mut c = CodeBuilder::new();
+    let top = c.new_label();
+    let done = c.new_label();
+    c.iconst(0)
+        .istore(0)
+        .iconst(0)
+        .istore(1)
+        .bind(top)
+        .iload(1)
+        .iconst(10)
+        .branch(Opcode::IF_ICMPEQ, done)
+        .iload(0)
+        .iload(1)
+        .iadd()
+        .istore(0)
+        .iinc(1, 1)
+        .goto(top)
+        .bind(done)
+        .iload(0)
+        .ireturn();
+    let k = KlassBuilder::new("Summer")
+        .add_method("sum", "()I", ACC_PUBLIC | ACC_STATIC, c.build())
+        .build();
+    let meth = k
+        .get_method_by_name_and_desc(&"Summer.sum:()I".to_string())
+        .expect("Summer.sum:()I not found")
+        .clone();
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    // Nothing is collected until profiling is switched on
+    let mut vars = InterpLocalVars::of(5);
+    exec_method(&mut context, &meth, &mut vars).unwrap();
+    assert!(context.profile_report().is_none());
+
+    context.set_profiling(true);
+    for _ in 0..2 {
         let mut vars = InterpLocalVars::of(5);
-        let ret = match exec_method(&mut repo, &meth, &mut vars).unwrap() {
-            JvmValue::Int { val: i } => i,
-            _ => panic!("Error executing {} - non-int value returned", fqname),
-        };
-        assert_eq!(7, ret);
+        let ret = exec_method(&mut context, &meth, &mut vars);
+        assert_eq!(Ok(Some(JvmValue::Int { val: 45 })), ret);
     }
+
+    let report = context.profile_report().expect("No profile collected");
+    // 4 to set up, 10 passes of 9 round the loop, then 5 more to leave it and return
+    assert_eq!(2 * (4 + 10 * 9 + 5), report.instructions);
+    assert!(report.opcodes.contains(&("iadd".to_string(), 20)));
+    assert_eq!(("iload_1".to_string(), 42), report.opcodes[0]);
+    assert_eq!(vec![("Summer.sum:()I".to_string(), 2)], report.methods);
+}
+
+#[test]
+fn interp_trace_names_the_running_method() {
+    let k = KlassBuilder::new("Tiny")
+        .add_method(
+            "run",
+            "()I",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![Opcode::ICONST_2, Opcode::ICONST_3, Opcode::IMUL, Opcode::IRETURN],
+        )
+        .build();
+    let meth = k
+        .get_method_by_name_and_desc(&"Tiny.run:()I".to_string())
+        .expect("Tiny.run:()I not found")
+        .clone();
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    let trace = Rc::new(RefCell::new(Vec::new()));
+    let recorder = trace.clone();
+    context.set_trace_callback(Some(Box::new(move |meth_name, _, opcode, _| {
+        recorder
+            .borrow_mut()
+            .push(format!("{} {}", meth_name, Opcode::mnemonic(opcode)))
+    })));
+
+    let mut vars = InterpLocalVars::of(5);
+    let ret = exec_method(&mut context, &meth, &mut vars);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 6 })), ret);
+    assert_eq!(
+        vec![
+            "Tiny.run:()I iconst_2",
+            "Tiny.run:()I iconst_3",
+            "Tiny.run:()I imul",
+            "Tiny.run:()I ireturn",
+        ],
+        *trace.borrow()
+    );
+}
+
+// Dog's constant pool holds #2 Dog, #4 Animal and #6 Pet
+fn init_pet_hierarchy(context: &mut VmContext) -> (usize, usize) {
+    let repo = context.get_repo_mut();
+    repo.add_klass(&KlassBuilder::new("Pet").build());
+    repo.add_klass(&KlassBuilder::new("Animal").build());
+    repo.add_klass(&KlassBuilder::new("Dog").with_super("Animal").with_interface("Pet").build());
+    // Ids are assigned on the repo's copy
+    let animal = context.lookup_klass(&"Animal".to_string());
+    let dog = context.lookup_klass(&"Dog".to_string());
+    let mut heap = HEAP.lock().unwrap();
+    (heap.allocate_obj(&animal), heap.allocate_obj(&dog))
+}
+
+#[test]
+fn bc_checkcast() {
+    let mut context = init_context();
+    let (animal_id, dog_id) = init_pet_hierarchy(&mut context);
+
+    let casts = vec![
+        Opcode::ALOAD_0,
+        Opcode::CHECKCAST,
+        0,
+        4,
+        Opcode::CHECKCAST,
+        0,
+        6,
+        Opcode::ARETURN,
+    ];
+    let mut lvt = InterpLocalVars::of(10);
+    lvt.store(0, JvmValue::ObjRef { val: ObjId::of(dog_id) });
+    let ret = exec_bytecode_method(&mut context, "Dog".to_string(), &casts, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::ObjRef { val: ObjId::of(dog_id) })), ret);
+
+    let bad_cast = vec![Opcode::ALOAD_0, Opcode::CHECKCAST, 0, 2, Opcode::ARETURN];
+    lvt.store(0, JvmValue::ObjRef { val: ObjId::of(animal_id) });
+    let ret = exec_bytecode_method(&mut context, "Dog".to_string(), &bad_cast, &mut lvt);
+    assert_eq!(
+        (
+            "java/lang/ClassCastException".to_string(),
+            Some("Animal cannot be cast to Dog".to_string())
+        ),
+        thrown(ret)
+    );
+
+    lvt.store(0, JvmValue::ObjRef { val: ObjId::NULL });
+    let ret = exec_bytecode_method(&mut context, "Dog".to_string(), &bad_cast, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::ObjRef { val: ObjId::NULL })), ret);
+}
+
+#[test]
+fn bc_instanceof() {
+    let mut context = init_context();
+    let (animal_id, dog_id) = init_pet_hierarchy(&mut context);
+    let is_pet = vec![Opcode::ALOAD_0, Opcode::INSTANCEOF, 0, 6, Opcode::IRETURN];
+
+    let mut lvt = InterpLocalVars::of(10);
+    lvt.store(0, JvmValue::ObjRef { val: ObjId::of(dog_id) });
+    let ret = exec_bytecode_method(&mut context, "Dog".to_string(), &is_pet, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 1 })), ret);
+
+    lvt.store(0, JvmValue::ObjRef { val: ObjId::of(animal_id) });
+    let ret = exec_bytecode_method(&mut context, "Dog".to_string(), &is_pet, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 0 })), ret);
+
+    lvt.store(0, JvmValue::ObjRef { val: ObjId::NULL });
+    let ret = exec_bytecode_method(&mut context, "Dog".to_string(), &is_pet, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 0 })), ret);
+}
+
+#[test]
+fn bc_wide_reaches_locals_past_255() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(400);
+    lvt.store(300, JvmValue::Int { val: 5 });
+
+    // wide iinc 300, -1000; wide iload 300; wide istore 399; wide iload 399; ireturn
+    let buf = vec![
+        Opcode::WIDE,
+        Opcode::IINC,
+        1,
+        44,
+        0xfc,
+        0x18,
+        Opcode::WIDE,
+        Opcode::ILOAD,
+        1,
+        44,
+        Opcode::WIDE,
+        Opcode::ISTORE,
+        1,
+        143,
+        Opcode::WIDE,
+        Opcode::ILOAD,
+        1,
+        143,
+        Opcode::IRETURN,
+    ];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: -995 })), ret);
+    assert_eq!(JvmValue::Int { val: -995 }, lvt.load(399));
+}
+
+#[test]
+fn bc_dup_x1_matches_spec() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(5);
+    // ..., 1, 2 -> ..., 2, 1, 2, so the subtractions give 2 - (1 - 2)
+    let buf = vec![
+        Opcode::ICONST_1,
+        Opcode::ICONST_2,
+        Opcode::DUP_X1,
+        Opcode::ISUB,
+        Opcode::ISUB,
+        Opcode::IRETURN,
+    ];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 3 })), ret);
+}
+
+#[test]
+fn bc_wide_operands_are_16_bit() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(300);
+    lvt.store(256, JvmValue::Int { val: 1 });
+    lvt.store(1, JvmValue::Int { val: 99 });
+
+    // wide iinc 256, 0x1234; wide iload 256 (not iload 1); ireturn
+    let buf = vec![
+        Opcode::WIDE,
+        Opcode::IINC,
+        0x01,
+        0x00,
+        0x12,
+        0x34,
+        Opcode::WIDE,
+        Opcode::ILOAD,
+        0x01,
+        0x00,
+        Opcode::IRETURN,
+    ];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 0x1235 })), ret);
+
+    let buf = vec![Opcode::NOP, Opcode::WIDE, Opcode::RET, 0x01, 0x00];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(
+        Err(RuntimeError::UnsupportedOpcodeError {
+            opcode: Opcode::RET,
+            pc: 1
+        }),
+        ret
+    );
+}
+
+#[test]
+fn bc_shorthand_constants() {
+    let cases = vec![
+        (vec![Opcode::ICONST_M1, Opcode::IRETURN], JvmValue::Int { val: -1 }),
+        (vec![Opcode::ICONST_0, Opcode::IRETURN], JvmValue::Int { val: 0 }),
+        (vec![Opcode::ICONST_1, Opcode::IRETURN], JvmValue::Int { val: 1 }),
+        (vec![Opcode::ICONST_2, Opcode::IRETURN], JvmValue::Int { val: 2 }),
+        (vec![Opcode::ICONST_3, Opcode::IRETURN], JvmValue::Int { val: 3 }),
+        (vec![Opcode::ICONST_4, Opcode::IRETURN], JvmValue::Int { val: 4 }),
+        (vec![Opcode::ICONST_5, Opcode::IRETURN], JvmValue::Int { val: 5 }),
+        (vec![Opcode::LCONST_0, Opcode::LRETURN], JvmValue::Long { val: 0 }),
+        (vec![Opcode::LCONST_1, Opcode::LRETURN], JvmValue::Long { val: 1 }),
+        (vec![Opcode::FCONST_0, Opcode::FRETURN], JvmValue::Float { val: 0.0 }),
+        (vec![Opcode::FCONST_1, Opcode::FRETURN], JvmValue::Float { val: 1.0 }),
+        (vec![Opcode::FCONST_2, Opcode::FRETURN], JvmValue::Float { val: 2.0 }),
+        (vec![Opcode::DCONST_0, Opcode::DRETURN], JvmValue::Double { val: 0.0 }),
+        (vec![Opcode::DCONST_1, Opcode::DRETURN], JvmValue::Double { val: 1.0 }),
+        // The push operands are signed
+        (vec![Opcode::BIPUSH, 0x7f, Opcode::IRETURN], JvmValue::Int { val: 127 }),
+        (vec![Opcode::BIPUSH, 0x80, Opcode::IRETURN], JvmValue::Int { val: -128 }),
+        (vec![Opcode::SIPUSH, 0x01, 0x2c, Opcode::IRETURN], JvmValue::Int { val: 300 }),
+        (vec![Opcode::SIPUSH, 0xff, 0xfe, Opcode::IRETURN], JvmValue::Int { val: -2 }),
+    ];
+    for (buf, expected) in cases {
+        assert_eq!(expected, execute_simple_bytecode(&buf), "{:?}", buf);
+    }
+}
+
+#[test]
+fn bc_store_past_max_locals_is_an_error() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::with_capacity(3);
+    let buf = vec![Opcode::ICONST_1, Opcode::ISTORE_3, Opcode::ICONST_1, Opcode::IRETURN];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(
+        Err(RuntimeError::LocalVarOutOfRange {
+            idx: 3,
+            max_locals: 3
+        }),
+        ret
+    );
+}
+
+#[test]
+fn bc_monitor_recursion_count() {
+    let mut context = init_context();
+    let k_jls = context
+        .get_repo()
+        .lookup_klass(&"java/lang/String".to_string());
+    let obj_id = HEAP.lock().unwrap().allocate_str(&k_jls, "lock");
+    let mut lvt = InterpLocalVars::of(10);
+    lvt.store(0, JvmValue::ObjRef { val: ObjId::of(obj_id) });
+
+    let enter_twice = vec![
+        Opcode::ALOAD_0,
+        Opcode::MONITORENTER,
+        Opcode::ALOAD_0,
+        Opcode::MONITORENTER,
+        Opcode::RETURN,
+    ];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &enter_twice, &mut lvt);
+    assert_eq!(Ok(None), ret);
+    assert_eq!(2, HEAP.lock().unwrap().get_obj(obj_id).get_lock_count());
+
+    let exit_twice = vec![
+        Opcode::ALOAD_0,
+        Opcode::MONITOREXIT,
+        Opcode::ALOAD_0,
+        Opcode::MONITOREXIT,
+        Opcode::RETURN,
+    ];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &exit_twice, &mut lvt);
+    assert_eq!(Ok(None), ret);
+    assert_eq!(0, HEAP.lock().unwrap().get_obj(obj_id).get_lock_count());
+
+    // Unbalanced exit
+    let exit_again = vec![Opcode::ALOAD_0, Opcode::MONITOREXIT, Opcode::RETURN];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &exit_again, &mut lvt);
+    assert_eq!(
+        (
+            "java/lang/IllegalMonitorStateException".to_string(),
+            Some(format!("object {}", obj_id))
+        ),
+        thrown(ret)
+    );
+}
+
+#[test]
+fn interp_unbalanced_monitorexit_can_be_caught() {
+    // aload_0; monitorexit; iconst_0; ireturn; then a handler that returns -1
+    let code = vec![
+        Opcode::ALOAD_0,
+        Opcode::MONITOREXIT,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+        Opcode::POP,
+        Opcode::ICONST_M1,
+        Opcode::IRETURN,
+    ];
+    let k = KlassBuilder::new("Unlocker")
+        .add_method("exit", "(Ljava/lang/Object;)I", ACC_PUBLIC | ACC_STATIC, code)
+        .build();
+    let mut meth = k
+        .get_method_by_name_and_desc(&"Unlocker.exit:(Ljava/lang/Object;)I".to_string())
+        .expect("Unlocker.exit not found")
+        .clone();
+    meth.set_exception_table(vec![otmethod::ExceptionHandler {
+        start_pc: 0,
+        end_pc: 4,
+        handler_pc: 4,
+        catch_type: Some("java/lang/IllegalMonitorStateException".to_string()),
+    }]);
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    let k_obj = context.lookup_klass(&"java/lang/Object".to_string());
+    let obj = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k_obj));
+    let mut vars = InterpLocalVars::of(1);
+    vars.store(0, JvmValue::ObjRef { val: obj });
+    assert_eq!(Ok(Some(JvmValue::Int { val: -1 })), exec_method(&mut context, &meth, &mut vars));
+}
+
+/////////////////////////////////////////////////////////////////
+//
+// Tests that actually load classes
+
+#[test]
+fn interp_failed_clinit_leaves_klass_unusable() {
+    let mut context = init_context();
+    let k = simple_parse_klass("BadInit".to_string());
+    context.get_repo_mut().add_klass(&k);
+    let meth = k
+        .get_method_by_name_and_desc(&"BadInit.get:()I".to_string())
+        .expect("BadInit.get:()I not found");
+
+    // The first use sees what <clinit> threw
+    let mut vars = InterpLocalVars::of(1);
+    let ret = exec_method(&mut context, meth, &mut vars);
+    assert_eq!(
+        ("java/lang/ArithmeticException".to_string(), Some("/ by zero".to_string())),
+        thrown(ret)
+    );
+    assert!(!context.get_repo().is_initialized(&"BadInit".to_string()));
+
+    // Later ones don't run it again
+    let mut vars = InterpLocalVars::of(1);
+    match exec_method(&mut context, meth, &mut vars) {
+        Err(RuntimeError::NoClassDefFoundError { klass_name, msg: _ }) => assert_eq!("BadInit", klass_name),
+        other => panic!("Expected a NoClassDefFoundError, got {:?}", other),
+    }
+}
+
+#[test]
+fn interp_invoke_simple() {
+    let mut context = init_context();
+    let k = simple_parse_klass("SampleInvoke".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    {
+        let fq_meth = "SampleInvoke.bar:()I";
+        let meth = k
+            .get_method_by_name_and_desc(&fq_meth.to_string())
+            .expect(&format!("{} not found", fq_meth));
+        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+
+        let mut vars = InterpLocalVars::of(5);
+        let ret = exec_method(&mut context, meth, &mut vars).unwrap().unwrap();
+        let ret2 = match ret {
+            JvmValue::Int { val: i } => i,
+            _ => panic!("Error executing SampleInvoke.bar:()I - non-int value returned"),
+        };
+        assert_eq!(7, ret2);
+    }
+
+    {
+        let fq_meth = "SampleInvoke.foo:()I";
+        let meth = k
+            .get_method_by_name_and_desc(&fq_meth.to_string())
+            .expect(&format!("{} not found", fq_meth));
+        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+
+        let mut vars = InterpLocalVars::of(5);
+        let ret = exec_method(&mut context, meth, &mut vars).unwrap().unwrap();
+        let ret2 = match ret {
+            JvmValue::Int { val: i } => i,
+            _ => panic!("Error executing SampleInvoke.foo:()I - non-int value returned"),
+        };
+        assert_eq!(9, ret2);
+    }
+}
+
+#[test]
+fn interp_invoke_caches_call_site() {
+    let mut context = init_context();
+    let k = simple_parse_klass("SampleInvoke".to_string());
+    context.get_repo_mut().add_klass(&k);
+    let baseline = context.get_repo().cached_method_count();
+
+    let fq_meth = "SampleInvoke.foo:()I";
+    let meth = k
+        .get_method_by_name_and_desc(&fq_meth.to_string())
+        .unwrap_or_else(|| panic!("{} not found", fq_meth));
+
+    for _i in 0..1000 {
+        let mut vars = InterpLocalVars::of(5);
+        match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+            JvmValue::Int { val: i } => assert_eq!(9, i),
+            _ => panic!("Error executing {} - non-int value returned", fq_meth),
+        };
+    }
+    // The single invokestatic site in foo() is resolved once and then reused
+    assert_eq!(baseline + 1, context.get_repo().cached_method_count());
+}
+
+#[test]
+fn interp_void_and_int_returns() {
+    let mut context = init_context();
+    let k = simple_parse_klass("Main3".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    {
+        // The constructor chains to Object.<init> and returns nothing
+        let fq_meth = "Main3.<init>:()V";
+        let meth = k
+            .get_method_by_name_and_desc(&fq_meth.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fq_meth));
+        let obj_id = HEAP.lock().unwrap().allocate_obj(&k);
+        let mut vars = InterpLocalVars::of(5);
+        vars.store(0, JvmValue::ObjRef { val: ObjId::of(obj_id) });
+        assert!(exec_method(&mut context, meth, &mut vars).unwrap().is_none());
+    }
+
+    {
+        let buf = vec![Opcode::BIPUSH, 17, Opcode::IRETURN];
+        assert_eq!(JvmValue::Int { val: 17 }, execute_simple_bytecode(&buf));
+    }
+}
+
+#[test]
+#[should_panic]
+fn bc_areturn_rejects_int() {
+    let buf = vec![Opcode::ICONST_1, Opcode::ARETURN];
+    execute_simple_bytecode(&buf);
+}
+
+// Writer that lets a test keep hold of the output after handing it to the VM
+#[derive(Clone)]
+struct CapturedOutput {
+    buf: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Write for CapturedOutput {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.borrow_mut().write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn interp_println_writes_to_context_output() {
+    let mut context = init_context();
+    let captured = CapturedOutput {
+        buf: Rc::new(RefCell::new(Vec::new())),
+    };
+    context.set_out(Box::new(captured.clone()));
+
+    let k_jls = context
+        .get_repo()
+        .lookup_klass(&"java/lang/String".to_string());
+    let s_id = HEAP.lock().unwrap().allocate_str(&k_jls, "Hello World");
+    let print_stream = JvmValue::ObjRef { val: ObjId::NULL };
+
+    let println_str = context
+        .lookup_native("java/io/PrintStream.println:(Ljava/lang/String;)V")
+        .expect("println(String) not registered");
+    let args = vec![print_stream.clone(), JvmValue::ObjRef { val: ObjId::of(s_id) }];
+    assert!(println_str(&mut context, &args).unwrap().is_none());
+
+    let println_int = context
+        .lookup_native("java/io/PrintStream.println:(I)V")
+        .expect("println(int) not registered");
+    let args = vec![print_stream, JvmValue::Int { val: 42 }];
+    assert!(println_int(&mut context, &args).unwrap().is_none());
+
+    let out = String::from_utf8(captured.buf.borrow().clone()).unwrap();
+    assert_eq!("Hello World\n42\n", out);
+}
+
+#[test]
+fn run_main_prints_its_args() {
+    let mut context = init_context();
+    let captured = CapturedOutput {
+        buf: Rc::new(RefCell::new(Vec::new())),
+    };
+    context.set_out(Box::new(captured.clone()));
+    context
+        .get_class_loader_mut()
+        .add_dir(Path::new("./resources/test/main"));
+
+    let args = vec!["first".to_string(), "second".to_string()];
+    assert_eq!(Ok(()), context.run_main("HelloMain", args));
+
+    let out = String::from_utf8(captured.buf.borrow().clone()).unwrap();
+    assert_eq!("first\n2\n", out);
+}
+
+#[test]
+fn interp_loads_klasses_on_demand() {
+    let mut context = init_context();
+    context
+        .get_class_loader_mut()
+        .add_dir(Path::new("./resources/test/lazy"));
+    assert!(context
+        .get_repo()
+        .try_lookup_klass(&"LazyCallee".to_string())
+        .is_none());
+
+    let k = context.lookup_klass(&"LazyCaller".to_string());
+    let fq_meth = "LazyCaller.run:()I";
+    let meth = k
+        .get_method_by_name_and_desc(&fq_meth.to_string())
+        .unwrap_or_else(|| panic!("{} not found", fq_meth));
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+        JvmValue::Int { val: i } => assert_eq!(42, i),
+        _ => panic!("Error executing {} - non-int value returned", fq_meth),
+    };
+
+    // The invokestatic pulled in the callee
+    assert!(context
+        .get_repo()
+        .try_lookup_klass(&"LazyCallee".to_string())
+        .is_some());
+    assert!(context.try_lookup_klass(&"NotOnClasspath".to_string()).unwrap().is_none());
+}
+
+#[test]
+fn interp_invokeinterface_dispatches_to_receiver() {
+    let mut context = init_context();
+    context
+        .get_class_loader_mut()
+        .add_dir(Path::new("./resources/test/iface"));
+    // Neither implementation is mentioned by name at the call site
+    context.lookup_klass(&"Greeter".to_string());
+    context.lookup_klass(&"EnglishGreeter".to_string());
+
+    let k = context.lookup_klass(&"GreeterCaller".to_string());
+    for (fq_meth, expected) in &[("GreeterCaller.run:()I", 21), ("GreeterCaller.runDefault:()I", 42)] {
+        let meth = k
+            .get_method_by_name_and_desc(&fq_meth.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fq_meth));
+        let mut vars = InterpLocalVars::of(5);
+        match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+            JvmValue::Int { val: i } => assert_eq!(*expected, i),
+            _ => panic!("Error executing {} - non-int value returned", fq_meth),
+        };
+    }
+}
+
+#[test]
+fn interp_abstract_method_is_not_run() {
+    let mut context = init_context();
+    context
+        .get_class_loader_mut()
+        .add_dir(Path::new("./resources/test/iface"));
+    let k = context.lookup_klass(&"Greeter".to_string());
+    let meth = k
+        .get_method_by_name_and_desc(&"Greeter.greet:()I".to_string())
+        .expect("Greeter.greet:()I not found");
+
+    let mut vars = InterpLocalVars::of(5);
+    assert_eq!(
+        Err(RuntimeError::AbstractMethodError {
+            method: "Greeter.greet:()I".to_string()
+        }),
+        exec_method(&mut context, meth, &mut vars)
+    );
+
+    // A caller gets it as an AbstractMethodError it can catch
+    let k = context.lookup_klass(&"GreeterCaller".to_string());
+    let meth = k
+        .get_method_by_name_and_desc(&"GreeterCaller.runMute:()I".to_string())
+        .expect("GreeterCaller.runMute:()I not found");
+    let mut vars = InterpLocalVars::of(5);
+    assert_eq!(Ok(Some(JvmValue::Int { val: -1 })), exec_method(&mut context, meth, &mut vars));
+}
+
+#[test]
+fn interp_get_class_returns_interned_class_object() {
+    let mut context = init_context();
+    context
+        .get_class_loader_mut()
+        .add_dir(Path::new("./resources/test/reflect"));
+    let k = context.lookup_klass(&"GetClassName".to_string());
+
+    let meth = k
+        .get_method_by_name_and_desc(&"GetClassName.name:()Ljava/lang/String;".to_string())
+        .expect("GetClassName.name:()Ljava/lang/String; not found");
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+        JvmValue::ObjRef { val: id } => assert_eq!(
+            "GetClassName",
+            HEAP.lock().unwrap().get_obj(id.get_id()).get_str_value()
+        ),
+        v => panic!("Error executing GetClassName.name - non-reference {:?} returned", v),
+    };
+
+    // Every instance of a klass shares one Class object
+    let meth = k
+        .get_method_by_name_and_desc(&"GetClassName.sameClass:()Z".to_string())
+        .expect("GetClassName.sameClass:()Z not found");
+    let mut vars = InterpLocalVars::of(5);
+    assert_eq!(
+        Some(JvmValue::Int { val: 1 }),
+        exec_method(&mut context, meth, &mut vars).unwrap()
+    );
+    let class_obj = context.get_class_object(&"GetClassName".to_string()).unwrap();
+    assert_eq!(
+        Some(&"GetClassName".to_string()),
+        context.klass_name_of_class_object(class_obj)
+    );
+}
+
+#[test]
+fn interp_clinit_runs_once_on_first_use() {
+    let mut context = init_context();
+    context
+        .get_class_loader_mut()
+        .add_dir(Path::new("./resources/test/clinit"));
+    let k = context.lookup_klass(&"StaticInit".to_string());
+    assert!(!context.get_repo().is_initialized(&"StaticInit".to_string()));
+
+    for fq_meth in &["StaticInit.read:()I", "StaticInit.read:()I"] {
+        let meth = k
+            .get_method_by_name_and_desc(&fq_meth.to_string())
+            .unwrap_or_else(|| panic!("{} not found", fq_meth));
+        let mut vars = InterpLocalVars::of(5);
+        match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+            JvmValue::Int { val: i } => assert_eq!(22, i),
+            _ => panic!("Error executing {} - non-int value returned", fq_meth),
+        };
+    }
+    assert!(context.get_repo().is_initialized(&"StaticInit".to_string()));
+
+    let fq_meth = "StaticInit.timesInitialized:()I";
+    let meth = k
+        .get_method_by_name_and_desc(&fq_meth.to_string())
+        .unwrap_or_else(|| panic!("{} not found", fq_meth));
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+        JvmValue::Int { val: i } => assert_eq!(1, i),
+        _ => panic!("Error executing {} - non-int value returned", fq_meth),
+    };
+}
+
+#[test]
+fn interp_max_stack_is_enforced() {
+    let k = KlassBuilder::new("Overflow")
+        .add_method(
+            "two",
+            "()I",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![Opcode::ICONST_1, Opcode::ICONST_1, Opcode::IADD, Opcode::IRETURN],
+        )
+        .build();
+    let mut meth = k
+        .get_method_by_name_and_desc(&"Overflow.two:()I".to_string())
+        .expect("Overflow.two:()I not found")
+        .clone();
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    meth.set_max_stack(2);
+    let mut vars = InterpLocalVars::of(5);
+    let ret = exec_method(&mut context, &meth, &mut vars);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 2 })), ret);
+
+    meth.set_max_stack(1);
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut context, &meth, &mut vars) {
+        Err(RuntimeError::VerifyError { msg: _ }) => (),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+}
+
+#[test]
+fn interp_step_limit_stops_an_infinite_loop() {
+    // while (true) {}
+    let mut c = CodeBuilder::new();
+    let top = c.new_label();
+    c.bind(top).op(Opcode::NOP).goto(top);
+    let buf = c.build();
+
+    let mut context = init_context();
+    context.set_max_steps(1000);
+    let mut lvt = InterpLocalVars::of(1);
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(
+        Err(RuntimeError::ExecutionLimitExceeded { limit: "steps", max: 1000 }),
+        ret
+    );
+    assert_eq!(1001, context.get_steps());
+
+    // Setting the limit again starts a fresh budget
+    context.set_max_steps(3);
+    assert_eq!(0, context.get_steps());
+    let code = vec![Opcode::ICONST_1, Opcode::ICONST_2, Opcode::IADD, Opcode::IRETURN];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &code, &mut lvt);
+    assert_eq!(
+        Err(RuntimeError::ExecutionLimitExceeded { limit: "steps", max: 3 }),
+        ret
+    );
+}
+
+#[test]
+fn interp_frame_limit_stops_unbounded_recursion() {
+    // static void down() { down(); }
+    let b = KlassBuilder::new("Deep");
+    let base = b.next_cp_index();
+    let utf8 = |s: &str| CpEntry::utf8 { val: s.to_string() };
+    let down_ref = base + 3;
+    let k = b
+        .add_constant(utf8("down"))
+        .add_constant(utf8("()V"))
+        .add_constant(CpEntry::name_and_type { name_idx: base, type_idx: base + 1 })
+        .add_constant(CpEntry::methodref { clz_idx: 2, nt_idx: base + 2 })
+        .add_method(
+            "down",
+            "()V",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![Opcode::INVOKESTATIC, 0, down_ref as u8, Opcode::RETURN],
+        )
+        .build();
+    let meth = k
+        .get_method_by_name_and_desc(&"Deep.down:()V".to_string())
+        .expect("Deep.down:()V not found")
+        .clone();
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    context.set_max_frames(50);
+    let steps_before = context.get_steps();
+    let ret = exec_method(&mut context, &meth, &mut InterpLocalVars::of(1));
+    assert_eq!(
+        Err(RuntimeError::ExecutionLimitExceeded { limit: "frames", max: 50 }),
+        ret
+    );
+    // Each call made one step before the one that couldn't be entered
+    assert_eq!(50, context.get_steps() - steps_before);
+    assert!(context.build_stack_trace().is_empty());
+}
+
+#[test]
+fn interp_stack_underflow_is_a_verify_error() {
+    let k = KlassBuilder::new("Underflow")
+        .add_method(
+            "add",
+            "()I",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![Opcode::ICONST_1, Opcode::IADD, Opcode::IRETURN],
+        )
+        .build();
+    let meth = k
+        .get_method_by_name_and_desc(&"Underflow.add:()I".to_string())
+        .expect("Underflow.add:()I not found")
+        .clone();
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    // The verifier rejects the method before it runs...
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut context, &meth, &mut vars) {
+        Err(RuntimeError::VerifyError { msg }) => {
+            assert_eq!("Operand stack underflow at pc 1 in Underflow.add:()I", msg)
+        }
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+
+    // ...but raw bytecode isn't verified, so the underflow is caught as it happens
+    let mut vars = InterpLocalVars::of(5);
+    match exec_bytecode_method(&mut context, "Underflow".to_string(), &meth.get_code(), &mut vars) {
+        Err(RuntimeError::VerifyError { msg }) => {
+            assert_eq!("Operand stack underflow in Underflow", msg)
+        }
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+}
+
+#[test]
+fn interp_returning_from_an_empty_stack_is_a_verify_error() {
+    let mut context = init_context();
+    let mut vars = InterpLocalVars::of(1);
+    match exec_bytecode_method(&mut context, "Empty".to_string(), &vec![Opcode::IRETURN], &mut vars) {
+        Err(RuntimeError::VerifyError { msg }) => assert_eq!("Operand stack underflow in Empty", msg),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+}
+
+#[test]
+fn interp_ill_typed_method_is_not_run() {
+    let k = KlassBuilder::new("IllTyped")
+        .add_method(
+            "add",
+            "()I",
+            ACC_PUBLIC | ACC_STATIC,
+            vec![Opcode::DCONST_1, Opcode::DCONST_1, Opcode::IADD, Opcode::IRETURN],
+        )
+        .build();
+    let meth = k
+        .get_method_by_name_and_desc(&"IllTyped.add:()I".to_string())
+        .expect("IllTyped.add:()I not found")
+        .clone();
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut context, &meth, &mut vars) {
+        Err(RuntimeError::VerifyError { msg }) => assert_eq!(
+            "Expected Int on the stack, found Double at pc 2 in IllTyped.add:()I",
+            msg
+        ),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+}
+
+#[test]
+fn bc_goto_w_branches_past_the_16_bit_range() {
+    // goto_w +40000; iload_0; ireturn; nop ...; bipush 42; istore_0; goto_w to the iload_0
+    let mut buf = vec![Opcode::GOTO_W, 0, 0, 0x9c, 0x40, Opcode::ILOAD_0, Opcode::IRETURN];
+    buf.resize(40000, Opcode::NOP);
+    buf.extend_from_slice(&[Opcode::BIPUSH, 42, Opcode::ISTORE_0]);
+    // At 40003, so back 39998 to 5
+    buf.extend_from_slice(&[Opcode::GOTO_W, 0xff, 0xff, 0x63, 0xc2]);
+
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(5);
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 42 })), ret);
+}
+
+#[test]
+fn interp_jsr_is_unsupported() {
+    // jsr +4; iconst_0; ireturn; astore_1; ret 1
+    let code = vec![
+        Opcode::JSR,
+        0,
+        4,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+        Opcode::ASTORE_1,
+        Opcode::RET,
+        1,
+    ];
+    let k = KlassBuilder::new("Subroutine")
+        .add_method("run", "()I", ACC_PUBLIC | ACC_STATIC, code.clone())
+        .build();
+    let meth = k
+        .get_method_by_name_and_desc(&"Subroutine.run:()I".to_string())
+        .expect("Subroutine.run:()I not found")
+        .clone();
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    let expected = RuntimeError::UnsupportedOpcodeError {
+        opcode: Opcode::JSR,
+        pc: 0,
+    };
+    let mut vars = InterpLocalVars::of(5);
+    assert_eq!(Err(expected.clone()), exec_method(&mut context, &meth, &mut vars));
+    assert_eq!(
+        "Unsupported opcode jsr at 0: jsr/ret subroutines are deprecated, recompile for Java 6 or later",
+        format!("{}", expected)
+    );
+
+    // Unverified bytecode gets the same error from the interpreter
+    let mut vars = InterpLocalVars::of(5);
+    assert_eq!(
+        Err(expected),
+        exec_bytecode_method(&mut context, "Subroutine".to_string(), &code, &mut vars)
+    );
+}
+
+fn stub_current_time_millis(_ctx: &mut VmContext, _args: &[JvmValue]) -> Result<Option<JvmValue>, RuntimeError> {
+    Ok(Some(JvmValue::Long { val: 42 }))
+}
+
+#[test]
+fn interp_registered_native_is_dispatched() {
+    let mut context = init_context();
+    // Replaces the implementation installed during bootstrap
+    context.register_native(
+        "java/lang/System.currentTimeMillis:()J",
+        stub_current_time_millis,
+    );
+    let k = simple_parse_klass("Main3".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    let fq_meth = "Main3.main2:([Ljava/lang/String;)I";
+    let meth = k
+        .get_method_by_name_and_desc(&fq_meth.to_string())
+        .unwrap_or_else(|| panic!("{} not found", fq_meth));
+
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+        JvmValue::Int { val: i } => assert_eq!(42, i),
+        _ => panic!("Error executing {} - non-int value returned", fq_meth),
+    };
+}
+
+#[test]
+fn interp_iffer() {
+    let mut context = init_context();
+    let k = simple_parse_klass("Iffer".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    {
+        let fq_meth = "Iffer.baz:()I";
+        let meth = k
+            .get_method_by_name_and_desc(&fq_meth.to_string())
+            .expect(&format!("{} not found", fq_meth));
+        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+
+        let mut vars = InterpLocalVars::of(5);
+        let ret = exec_method(&mut context, meth, &mut vars).unwrap().unwrap();
+        let ret2 = match ret {
+            JvmValue::Int { val: i } => i,
+            _ => panic!("Error executing Iffer.baz:()I - non-int value returned"),
+        };
+        assert_eq!(3, ret2);
+    }
+}
+
+#[test]
+fn interp_array_set() {
+    let mut context = init_context();
+    let k = simple_parse_klass("ArraySimple".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    {
+        let fqname = "ArraySimple.baz:()I".to_string();
+        let meth = k.get_method_by_name_and_desc(&fqname).unwrap();
+
+        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+
+        let mut vars = InterpLocalVars::of(5);
+        let ret = exec_method(&mut context, meth, &mut vars).unwrap().unwrap();
+        let ret2 = match ret {
+            JvmValue::Int { val: i } => i,
+            _ => panic!("Error executing {} - non-int value returned", fqname),
+        };
+        assert_eq!(7, ret2);
+    }
+}
+
+#[test]
+fn interp_field_set() {
+    let mut context = init_context();
+    let k = simple_parse_klass("FieldHaver".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    {
+        let fqname = "FieldHaver.main2:([Ljava/lang/String;)I".to_string();
+        let meth = k.get_method_by_name_and_desc(&fqname).unwrap();
+
+        assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
+
+        let mut vars = InterpLocalVars::of(5);
+        let ret = match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+            JvmValue::Int { val: i } => i,
+            _ => panic!("Error executing {} - non-int value returned", fqname),
+        };
+        assert_eq!(7, ret);
+    }
+}
+
+#[test]
+fn interp_narrow_fields_load_as_ints() {
+    let mut context = init_context();
+    let k = simple_parse_klass("NarrowFields".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    let fqname = "NarrowFields.main2:([Ljava/lang/String;)I".to_string();
+    let meth = k.get_method_by_name_and_desc(&fqname).unwrap();
+    let mut vars = InterpLocalVars::of(5);
+    let ret = exec_method(&mut context, meth, &mut vars).unwrap();
+    assert_eq!(Some(JvmValue::Int { val: 7 + 1 + 97 + 2 }), ret);
+}
+
+#[test]
+fn interp_field_store_narrows() {
+    let mut context = init_context();
+    let k = simple_parse_klass("NarrowFields".to_string());
+    context.get_repo_mut().add_klass(&k);
+    let k = context.lookup_klass(&"NarrowFields".to_string());
+    let field_idx = |field: &str| {
+        k.cp_iter()
+            .find(|(i, _)| matches!(k.resolve_fieldref(*i), Ok((_, name, _)) if name == field))
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| panic!("No fieldref for NarrowFields.{}", field))
+    };
+    // javac always narrows with i2b before a putfield, so store the raw int
+    let store_and_load = |idx: u16, v: i16| {
+        vec![
+            Opcode::ALOAD_0,
+            Opcode::SIPUSH,
+            (v >> 8) as u8,
+            v as u8,
+            Opcode::PUTFIELD,
+            (idx >> 8) as u8,
+            idx as u8,
+            Opcode::ALOAD_0,
+            Opcode::GETFIELD,
+            (idx >> 8) as u8,
+            idx as u8,
+            Opcode::IRETURN,
+        ]
+    };
+    let obj_id = HEAP.lock().unwrap().allocate_obj(&k);
+    let mut lvt = InterpLocalVars::of(5);
+    lvt.store(0, JvmValue::ObjRef { val: ObjId::of(obj_id) });
+
+    // 300 is 0x12c, of which only 0x2c fits in a byte
+    let code = store_and_load(field_idx("b"), 300);
+    let ret = exec_bytecode_method(&mut context, "NarrowFields".to_string(), &code, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 44 })), ret);
+
+    // Only the low bit of a boolean survives
+    let code = store_and_load(field_idx("z"), 2);
+    let ret = exec_bytecode_method(&mut context, "NarrowFields".to_string(), &code, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 0 })), ret);
+
+    let code = store_and_load(field_idx("s"), -2);
+    let ret = exec_bytecode_method(&mut context, "NarrowFields".to_string(), &code, &mut lvt);
+    assert_eq!(Ok(Some(JvmValue::Int { val: -2 })), ret);
+}
+
+#[test]
+fn interp_runs_folded_constants() {
+    let mut c = CodeBuilder::new();
+    c.iconst(2).iconst(3).iadd().iconst(100).imul().ireturn();
+    let k = KlassBuilder::new("Folder")
+        .add_method("run", "()I", ACC_PUBLIC | ACC_STATIC, c.build())
+        .build();
+    let mut context = init_context();
+    context.set_constant_folding(true);
+    context.get_repo_mut().add_klass(&k);
+
+    let meth = context
+        .get_repo()
+        .lookup_method_exact(&"Folder".to_string(), "Folder.run:()I".to_string());
+    // 500 needs a sipush, which fits in the space the five instructions took up
+    assert_eq!(
+        vec![Opcode::SIPUSH, 0x01, 0xf4, Opcode::NOP, Opcode::NOP, Opcode::NOP, Opcode::IRETURN],
+        meth.get_exec_code()
+    );
+    assert_eq!(c.build(), meth.get_code());
+
+    let mut vars = InterpLocalVars::of(5);
+    let ret = exec_method(&mut context, &meth, &mut vars);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 500 })), ret);
+}
+
+#[test]
+fn interp_runs_loop_from_decoded_code() {
+    // int n = 0; for (int i = 0; i < 1000; i++) n += 2; return n;
+    let mut c = CodeBuilder::new();
+    let (top, done) = (c.new_label(), c.new_label());
+    c.iconst(0)
+        .istore(0)
+        .iconst(0)
+        .istore(1)
+        .bind(top)
+        .iload(1)
+        .iconst(1000)
+        .if_icmpge(done)
+        .iinc(0, 2)
+        .iinc(1, 1)
+        .goto(top)
+        .bind(done)
+        .iload(0)
+        .ireturn();
+    let k = KlassBuilder::new("Looper")
+        .add_method("run", "()I", ACC_PUBLIC | ACC_STATIC, c.build())
+        .build();
+    let mut context = init_context();
+    context.get_repo_mut().add_klass(&k);
+
+    let meth = context
+        .get_repo()
+        .lookup_method_exact(&"Looper".to_string(), "Looper.run:()I".to_string());
+    let decoded = meth.get_decoded().expect("Looper.run wasn't decoded");
+    assert_eq!(12, decoded.len());
+
+    for _ in 0..2 {
+        let mut vars = InterpLocalVars::of(2);
+        let ret = exec_method(&mut context, &meth, &mut vars);
+        assert_eq!(Ok(Some(JvmValue::Int { val: 2000 })), ret);
+    }
+}
+
+#[test]
+fn interp_rejects_code_that_does_not_decode() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(1);
+    let buf = vec![Opcode::SIPUSH, 0, 1, Opcode::GOTO, 0xff, 0xfe, Opcode::IRETURN];
+    match exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt) {
+        Err(RuntimeError::VerifyError { msg }) => assert!(msg.contains("at 3"), "{}", msg),
+        other => panic!("Expected a VerifyError, got {:?}", other),
+    }
+}
+
+fn catcher_method(context: &mut VmContext, name_desc: &str) -> OtMethod {
+    let k = simple_parse_klass("Catcher".to_string());
+    context.get_repo_mut().add_klass(&k);
+    context
+        .get_repo()
+        .lookup_method_exact(&"Catcher".to_string(), "Catcher.".to_string() + name_desc)
+}
+
+fn trace_frames(trace: &[StackTraceElement]) -> Vec<(&str, &str, Option<u16>)> {
+    trace
+        .iter()
+        .map(|e| (e.klass_name.as_str(), e.method_name.as_str(), e.line))
+        .collect()
+}
+
+#[test]
+fn interp_exception_caught_by_caller() {
+    let mut context = init_context();
+    let meth = catcher_method(&mut context, "outer:()Ljava/lang/Object;");
+
+    // outer() returns the exception it caught from inner()
+    let mut vars = InterpLocalVars::of(1);
+    let exception = match exec_method(&mut context, &meth, &mut vars) {
+        Ok(Some(JvmValue::ObjRef { val })) if !val.is_null() => val,
+        other => panic!("Expected the caught exception, got {:?}", other),
+    };
+    assert_eq!(Some("java/lang/Exception".to_string()), context.klass_name_of_obj(exception));
+
+    let trace = context.get_stack_trace(exception).expect("No trace recorded");
+    assert_eq!(
+        vec![("Catcher", "inner", Some(3)), ("Catcher", "outer", Some(9))],
+        trace_frames(trace)
+    );
+}
+
+#[test]
+fn interp_uncaught_exception_unwinds_every_frame() {
+    let mut context = init_context();
+    let meth = catcher_method(&mut context, "uncaught:()I");
+
+    let mut vars = InterpLocalVars::of(1);
+    match exec_method(&mut context, &meth, &mut vars) {
+        Err(RuntimeError::Thrown { exception }) => {
+            assert_eq!(
+                vec![("Catcher", "inner", Some(3)), ("Catcher", "uncaught", Some(17))],
+                trace_frames(&exception.trace)
+            );
+            assert!(context.get_stack_trace(exception.obj_id).is_none());
+        }
+        other => panic!("Expected an uncaught exception, got {:?}", other),
+    }
+}
+
+#[test]
+fn interp_uncaught_exception_prints_java_stack_trace() {
+    let mut context = init_context();
+    let meth = catcher_method(&mut context, "uncaught:()I");
+
+    let mut vars = InterpLocalVars::of(1);
+    let err = exec_method(&mut context, &meth, &mut vars).unwrap_err();
+    assert_eq!(
+        "java/lang/Exception\n\tat Catcher.inner(Catcher.java:3)\n\tat Catcher.uncaught(Catcher.java:17)",
+        err.to_string()
+    );
+    // Every frame has been left by the time the exception gets out
+    assert!(context.build_stack_trace().is_empty());
+}
+
+fn vm_exceptions_method(context: &mut VmContext, name_desc: &str) -> OtMethod {
+    let k = simple_parse_klass("VmExceptions".to_string());
+    context.get_repo_mut().add_klass(&k);
+    context
+        .get_repo()
+        .lookup_method_exact(&"VmExceptions".to_string(), "VmExceptions.".to_string() + name_desc)
+}
+
+#[test]
+fn interp_vm_exceptions_are_caught() {
+    let mut context = init_context();
+    let divide = vm_exceptions_method(&mut context, "divide:(I)I");
+    for (d, expected) in &[(2, 5), (0, -1)] {
+        let mut vars = InterpLocalVars::of(2);
+        vars.store(0, JvmValue::Int { val: *d });
+        assert_eq!(Ok(Some(JvmValue::Int { val: *expected })), exec_method(&mut context, &divide, &mut vars));
+    }
+
+    let at = vm_exceptions_method(&mut context, "at:(I)I");
+    for (i, expected) in &[(1, 2), (2, -1), (-1, -1)] {
+        let mut vars = InterpLocalVars::of(2);
+        vars.store(0, JvmValue::Int { val: *i });
+        assert_eq!(Ok(Some(JvmValue::Int { val: *expected })), exec_method(&mut context, &at, &mut vars));
+    }
+
+    let allocate = vm_exceptions_method(&mut context, "allocate:(I)I");
+    for (n, expected) in &[(3, 3), (-1, -1)] {
+        let mut vars = InterpLocalVars::of(2);
+        vars.store(0, JvmValue::Int { val: *n });
+        assert_eq!(
+            Ok(Some(JvmValue::Int { val: *expected })),
+            exec_method(&mut context, &allocate, &mut vars)
+        );
+    }
+
+    let cast = vm_exceptions_method(&mut context, "cast:(Ljava/lang/Object;)I");
+    let k_obj = context.lookup_klass(&"java/lang/Object".to_string());
+    let obj = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k_obj));
+    let s = context.intern_string("s").unwrap();
+    for (o, expected) in &[(s, 1), (obj, -1)] {
+        let mut vars = InterpLocalVars::of(2);
+        vars.store(0, JvmValue::ObjRef { val: *o });
+        assert_eq!(Ok(Some(JvmValue::Int { val: *expected })), exec_method(&mut context, &cast, &mut vars));
+    }
+
+    let length = vm_exceptions_method(&mut context, "length:([I)I");
+    let mut vars = InterpLocalVars::of(2);
+    vars.store(0, JvmValue::ObjRef { val: ObjId::NULL });
+    assert_eq!(Ok(Some(JvmValue::Int { val: -1 })), exec_method(&mut context, &length, &mut vars));
+
+    // Caught by a handler for their super
+    let length_or_divide = vm_exceptions_method(&mut context, "lengthOrDivide:([I)I");
+    let empty = ObjId::of(HEAP.lock().unwrap().allocate_int_arr(0));
+    for a in &[ObjId::NULL, empty] {
+        let mut vars = InterpLocalVars::of(2);
+        vars.store(0, JvmValue::ObjRef { val: *a });
+        assert_eq!(
+            Ok(Some(JvmValue::Int { val: -1 })),
+            exec_method(&mut context, &length_or_divide, &mut vars)
+        );
+    }
+}
+
+#[test]
+fn interp_caught_vm_exception_has_its_message() {
+    let mut context = init_context();
+    let meth = vm_exceptions_method(&mut context, "divideMessage:(I)Ljava/lang/String;");
+    let mut vars = InterpLocalVars::of(2);
+    vars.store(0, JvmValue::Int { val: 0 });
+    match exec_method(&mut context, &meth, &mut vars) {
+        Ok(Some(JvmValue::ObjRef { val })) if !val.is_null() => {
+            assert_eq!("/ by zero", HEAP.lock().unwrap().get_obj(val.get_id()).get_str_value())
+        }
+        other => panic!("Expected the exception's message, got {:?}", other),
+    }
+}
+
+#[test]
+fn interp_exception_handler_must_match_klass() {
+    let mut context = init_context();
+    let meth = catcher_method(&mut context, "picky:()I");
+
+    // The IllegalStateException handler comes first, but doesn't match
+    let mut vars = InterpLocalVars::of(1);
+    let ret = exec_method(&mut context, &meth, &mut vars);
+    assert_eq!(Ok(Some(JvmValue::Int { val: 2 })), ret);
+}
+
+#[test]
+fn interp_athrow_of_null_throws_npe() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(1);
+    let buf = vec![Opcode::ACONST_NULL, Opcode::ATHROW];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!("java/lang/NullPointerException", thrown(ret).0);
+}
+
+#[test]
+fn bc_nop_is_skipped() {
+    let buf = vec![Opcode::NOP, Opcode::ICONST_2, Opcode::NOP, Opcode::NOP, Opcode::IRETURN];
+    assert_eq!(JvmValue::Int { val: 2 }, execute_simple_bytecode(&buf));
+}
+
+#[test]
+fn bc_reserved_and_undefined_opcodes_are_illegal() {
+    for (opcode, pc) in &[(Opcode::IMPDEP2, 1), (Opcode::IMPDEP1, 1), (Opcode::BREAKPOINT, 1), (0xcb, 1)] {
+        let mut context = init_context();
+        let mut lvt = InterpLocalVars::of(1);
+        let buf = vec![Opcode::NOP, *opcode, Opcode::ICONST_1, Opcode::IRETURN];
+        let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+        assert_eq!(
+            Err(RuntimeError::IllegalOpcode {
+                opcode: *opcode,
+                pc: *pc
+            }),
+            ret
+        );
+    }
+
+    let err = RuntimeError::IllegalOpcode {
+        opcode: Opcode::IMPDEP2,
+        pc: 1,
+    };
+    assert_eq!("java/lang/VerifyError: illegal opcode 0xff (impdep2) at 1", err.to_string());
+}
+
+#[test]
+fn interp_field_access_on_null_throws() {
+    let mut context = init_context();
+    let k = simple_parse_klass("FieldHaver".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    let field_idx = k
+        .cp_iter()
+        .find(|(i, _)| matches!(k.resolve_fieldref(*i), Ok((_, name, _)) if name == "i"))
+        .map(|(i, _)| i)
+        .expect("No fieldref for FieldHaver.i");
+    let getfield = vec![
+        Opcode::ACONST_NULL,
+        Opcode::GETFIELD,
+        (field_idx >> 8) as u8,
+        field_idx as u8,
+        Opcode::IRETURN,
+    ];
+    let mut lvt = InterpLocalVars::of(5);
+    let ret = exec_bytecode_method(&mut context, "FieldHaver".to_string(), &getfield, &mut lvt);
+    assert_eq!(
+        (
+            "java/lang/NullPointerException".to_string(),
+            Some("Cannot read field FieldHaver.i:I because the reference is null".to_string())
+        ),
+        thrown(ret)
+    );
+
+    let arraylength = vec![Opcode::ACONST_NULL, Opcode::ARRAYLENGTH, Opcode::IRETURN];
+    let ret = exec_bytecode_method(&mut context, "FieldHaver".to_string(), &arraylength, &mut lvt);
+    assert_eq!("java/lang/NullPointerException", thrown(ret).0);
+}
+
+#[test]
+fn bc_array_access_through_null_throws() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(1);
+    let iaload = vec![Opcode::ACONST_NULL, Opcode::ICONST_0, Opcode::IALOAD, Opcode::IRETURN];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &iaload, &mut lvt);
+    assert_eq!(
+        Some("Cannot load from int array because the reference is null".to_string()),
+        thrown(ret).1
+    );
+
+    let iastore = vec![
+        Opcode::ACONST_NULL,
+        Opcode::ICONST_0,
+        Opcode::ICONST_1,
+        Opcode::IASTORE,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+    ];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &iastore, &mut lvt);
+    assert_eq!(
+        Some("Cannot store to int array because the reference is null".to_string()),
+        thrown(ret).1
+    );
+}
+
+#[test]
+fn bc_array_index_out_of_bounds_throws() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(1);
+    // (new int[3])[3]
+    let iaload = vec![
+        Opcode::ICONST_3,
+        Opcode::NEWARRAY,
+        10,
+        Opcode::ICONST_3,
+        Opcode::IALOAD,
+        Opcode::IRETURN,
+    ];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &iaload, &mut lvt);
+    assert_eq!(
+        (
+            "java/lang/ArrayIndexOutOfBoundsException".to_string(),
+            Some("Index 3 out of bounds for length 3".to_string())
+        ),
+        thrown(ret)
+    );
+
+    // (new int[3])[-1] = 1
+    let iastore = vec![
+        Opcode::ICONST_3,
+        Opcode::NEWARRAY,
+        10,
+        Opcode::ICONST_M1,
+        Opcode::ICONST_1,
+        Opcode::IASTORE,
+        Opcode::ICONST_0,
+        Opcode::IRETURN,
+    ];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &iastore, &mut lvt);
+    assert_eq!(
+        "java/lang/ArrayIndexOutOfBoundsException: Index -1 out of bounds for length 3\n\tat DUMMY(Unknown Source)",
+        ret.unwrap_err().to_string()
+    );
+
+    // In bounds, the last element round trips
+    let last = vec![
+        Opcode::ICONST_3,
+        Opcode::NEWARRAY,
+        10,
+        Opcode::DUP,
+        Opcode::ICONST_2,
+        Opcode::BIPUSH,
+        42,
+        Opcode::IASTORE,
+        Opcode::ICONST_2,
+        Opcode::IALOAD,
+        Opcode::IRETURN,
+    ];
+    assert_eq!(JvmValue::Int { val: 42 }, execute_simple_bytecode(&last));
+}
+
+// (new T[1])[0] = v, then reads it back, for T the newarray type
+fn byte_array_round_trip(arr_type: u8, v: i16) -> JvmValue {
+    let buf = vec![
+        Opcode::ICONST_1,
+        Opcode::NEWARRAY,
+        arr_type,
+        Opcode::DUP,
+        Opcode::ICONST_0,
+        Opcode::SIPUSH,
+        (v >> 8) as u8,
+        v as u8,
+        Opcode::BASTORE,
+        Opcode::ICONST_0,
+        Opcode::BALOAD,
+        Opcode::IRETURN,
+    ];
+    execute_simple_bytecode(&buf)
+}
+
+#[test]
+fn bc_baload_sign_extends_bytes() {
+    // byte[]
+    assert_eq!(JvmValue::Int { val: -1 }, byte_array_round_trip(8, -1));
+    assert_eq!(JvmValue::Int { val: -128 }, byte_array_round_trip(8, -128));
+    assert_eq!(JvmValue::Int { val: 127 }, byte_array_round_trip(8, 127));
+    // 200 truncates to 0xc8, which is -56
+    assert_eq!(JvmValue::Int { val: -56 }, byte_array_round_trip(8, 200));
+}
+
+#[test]
+fn bc_bastore_masks_booleans() {
+    // boolean[] keeps only the low bit
+    assert_eq!(JvmValue::Int { val: 1 }, byte_array_round_trip(4, 1));
+    assert_eq!(JvmValue::Int { val: 0 }, byte_array_round_trip(4, 2));
+    assert_eq!(JvmValue::Int { val: 1 }, byte_array_round_trip(4, -1));
+    assert_eq!(JvmValue::Int { val: 1 }, byte_array_round_trip(4, 0x103));
+}
+
+#[test]
+fn bc_newarray_of_negative_size_throws() {
+    let mut context = init_context();
+    let mut lvt = InterpLocalVars::of(1);
+    let buf = vec![Opcode::ICONST_M1, Opcode::NEWARRAY, 10, Opcode::ARRAYLENGTH, Opcode::IRETURN];
+    let ret = exec_bytecode_method(&mut context, "DUMMY".to_string(), &buf, &mut lvt);
+    assert_eq!(
+        ("java/lang/NegativeArraySizeException".to_string(), Some("-1".to_string())),
+        thrown(ret)
+    );
+
+    // A zero length array is fine
+    let buf = vec![Opcode::ICONST_0, Opcode::NEWARRAY, 10, Opcode::ARRAYLENGTH, Opcode::IRETURN];
+    assert_eq!(JvmValue::Int { val: 0 }, execute_simple_bytecode(&buf));
+}
+
+#[test]
+fn interp_field_write_seen_through_alias() {
+    let mut context = init_context();
+    let k = simple_parse_klass("Aliaser".to_string());
+    context.get_repo_mut().add_klass(&k);
+
+    // a and b are separate copies of the same ObjRef
+    let fqname = "Aliaser.run:()I";
+    let meth = k
+        .get_method_by_name_and_desc(&fqname.to_string())
+        .unwrap_or_else(|| panic!("{} not found", fqname));
+    let mut vars = InterpLocalVars::of(5);
+    match exec_method(&mut context, meth, &mut vars).unwrap().unwrap() {
+        JvmValue::Int { val: i } => assert_eq!(8, i),
+        _ => panic!("Error executing {} - non-int value returned", fqname),
+    };
 }
 
 #[test]
 fn interp_system_current_timemillis() {
-    let mut repo = init_repo();
+    let mut context = init_context();
     let k = simple_parse_klass("Main3".to_string());
-    repo.add_klass(&k);
+    context.get_repo_mut().add_klass(&k);
 
     {
         let fqname = "Main3.main2:([Ljava/lang/String;)I";
@@ -407,13 +2353,13 @@ fn interp_system_current_timemillis() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut context, meth, &mut vars).unwrap().unwrap();
         let ctm1 = match ret {
             JvmValue::Int { val: i } => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
         };
         vars = InterpLocalVars::of(5);
-        let opt_ret = exec_method(&mut repo, &meth, &mut vars);
+        let opt_ret = exec_method(&mut context, meth, &mut vars).unwrap();
         let ret2 = match opt_ret {
             Some(value) => value,
             None => panic!("Error executing {} - no value returned", fqname),
@@ -429,9 +2375,9 @@ fn interp_system_current_timemillis() {
 #[test]
 #[ignore]
 fn interp_class_based_addition() {
-    let mut repo = init_repo();
+    let mut context = init_context();
     let k = simple_parse_klass("AddFieldInteger".to_string());
-    repo.add_klass(&k);
+    context.get_repo_mut().add_klass(&k);
 
     {
         let fqname = "AddFieldInteger.main2:([Ljava/lang/String;)I".to_string();
@@ -440,7 +2386,7 @@ fn interp_class_based_addition() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut context, meth, &mut vars).unwrap().unwrap();
         let ret2 = match ret {
             JvmValue::Int { val: i } => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
@@ -451,9 +2397,9 @@ fn interp_class_based_addition() {
 
 #[test]
 fn interp_ldc_based_addition() {
-    let mut repo = init_repo();
+    let mut context = init_context();
     let k = simple_parse_klass("AddLdc".to_string());
-    repo.add_klass(&k);
+    context.get_repo_mut().add_klass(&k);
 
     {
         let fqname = "AddLdc.main2:([Ljava/lang/String;)I".to_string();
@@ -462,7 +2408,7 @@ fn interp_ldc_based_addition() {
         assert_eq!(ACC_PUBLIC | ACC_STATIC, meth.get_flags());
 
         let mut vars = InterpLocalVars::of(5);
-        let ret = exec_method(&mut repo, &meth, &mut vars).unwrap();
+        let ret = exec_method(&mut context, meth, &mut vars).unwrap().unwrap();
         let ret2 = match ret {
             JvmValue::Int { val: i } => i,
             _ => panic!("Error executing {} - non-int value returned", fqname),
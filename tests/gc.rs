@@ -0,0 +1,189 @@
+// The heap is global, so collecting it frees whatever any other running test
+// still holds. These tests get a binary of their own, and take turns
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use lazy_static::lazy_static;
+
+use ocelotter::{exec_bytecode_method, exec_method};
+use ocelotter_runtime::opcode::Opcode;
+use ocelotter_runtime::runtime_error::RuntimeError;
+use ocelotter_runtime::klass_parser::OtKlassParser;
+use ocelotter_runtime::vm_context::VmContext;
+use ocelotter_runtime::*;
+use ocelotter_util::file_to_bytes;
+
+lazy_static! {
+    static ref HEAP_USER: Mutex<()> = Mutex::new(());
+}
+
+// A failed test mustn't stop the rest from running
+fn take_turn() -> MutexGuard<'static, ()> {
+    HEAP_USER.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn init_context() -> VmContext {
+    let mut context = VmContext::of();
+    context.bootstrap(exec_method);
+    // Whatever earlier tests left behind is garbage now, so the counts start clean
+    context.gc(&[]);
+    context
+}
+
+fn add_test_klass(context: &mut VmContext, name: &str) {
+    let path = format!("./resources/test/{}.class", name);
+    let bytes = file_to_bytes(Path::new(&path)).unwrap_or_else(|_| panic!("Error reading {}", name));
+    let mut parser = OtKlassParser::of(bytes, format!("{}.class", name));
+    parser.parse();
+    context.get_repo_mut().add_klass(&parser.klass());
+}
+
+// Fills the heap with unreachable objects, up to a limit of max_objects
+fn fill_with_garbage(context: &mut VmContext, max_objects: usize) {
+    let k = context.lookup_klass(&"java/lang/Object".to_string());
+    let mut heap = HEAP.lock().unwrap();
+    while heap.live_count() < max_objects {
+        heap.allocate_obj(&k);
+    }
+    heap.set_max_objects(Some(max_objects));
+}
+
+fn clear_limit() {
+    HEAP.lock().unwrap().set_max_objects(None);
+}
+
+#[test]
+fn vm_allocations_collect_a_full_heap() {
+    let _turn = take_turn();
+    let mut context = init_context();
+    let live = HEAP.lock().unwrap().live_count();
+    fill_with_garbage(&mut context, live + 5);
+
+    let s = context.intern_string("Interned");
+    let class_obj = context.get_class_object(&"java/lang/Object".to_string());
+    clear_limit();
+
+    let heap = HEAP.lock().unwrap();
+    assert!(heap.is_valid(s.unwrap()));
+    assert!(heap.is_valid(class_obj.unwrap()));
+    // The garbage went to make room for them
+    assert_eq!(live + 2, heap.live_count());
+}
+
+#[test]
+fn gc_keeps_what_the_vm_refers_to() {
+    let _turn = take_turn();
+    let mut context = init_context();
+    let k = context.lookup_klass(&"java/lang/Object".to_string());
+
+    let statics = context.get_repo().static_refs();
+    assert!(!statics.is_empty(), "System.out should be a static root");
+    let interned = context.intern_string("Interned").unwrap();
+    let value_of = context
+        .lookup_native("java/lang/Integer.valueOf:(I)Ljava/lang/Integer;")
+        .expect("Integer.valueOf() not registered");
+    let boxed = match value_of(&mut context, &[JvmValue::Int { val: 5 }]) {
+        Ok(Some(JvmValue::ObjRef { val })) => val,
+        other => panic!("Expected a box, got {:?}", other),
+    };
+    context.enter_frame("Test".to_string(), "run".to_string());
+    let framed = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k));
+    context.set_frame_roots(vec![framed]);
+    let passed = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k));
+    let garbage = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k));
+
+    assert!(context.gc(&[passed]) >= 1);
+    {
+        let heap = HEAP.lock().unwrap();
+        assert!(statics.iter().all(|s| heap.is_valid(*s)));
+        for kept in &[interned, boxed, framed, passed] {
+            assert!(heap.is_valid(*kept), "{} was collected", kept);
+        }
+        assert!(!heap.is_valid(garbage));
+    }
+
+    // Once its frame has gone, so has the frame's root
+    context.leave_frame();
+    assert_eq!(2, context.gc(&[]));
+    let heap = HEAP.lock().unwrap();
+    assert!(!heap.is_valid(framed));
+    assert!(!heap.is_valid(passed));
+    assert!(heap.is_valid(boxed));
+}
+
+#[test]
+fn interp_collects_when_the_heap_limit_is_hit() {
+    let _turn = take_turn();
+    let mut context = init_context();
+    add_test_klass(&mut context, "Churn");
+    let meth = context
+        .get_repo()
+        .lookup_method_exact(&"Churn".to_string(), "Churn.churn:(I)I".to_string());
+
+    // Far more garbage than fits, so the loop only gets through by collecting
+    let live = HEAP.lock().unwrap().live_count();
+    fill_with_garbage(&mut context, live + 10);
+    let mut vars = InterpLocalVars::of(4);
+    vars.store(0, JvmValue::Int { val: 100 });
+    let ret = exec_method(&mut context, &meth, &mut vars);
+    let live_after = HEAP.lock().unwrap().live_count();
+    clear_limit();
+
+    // The local and the static each kept their array
+    assert_eq!(Some(JvmValue::Int { val: 42 + 99 }), ret.unwrap());
+    assert!(live_after <= live + 10);
+}
+
+#[test]
+fn reused_ids_lose_the_message_of_a_collected_exception() {
+    let _turn = take_turn();
+    let mut context = init_context();
+    let buf = vec![Opcode::ICONST_1, Opcode::ICONST_0, Opcode::IDIV, Opcode::IRETURN];
+    let mut vars = InterpLocalVars::of(1);
+    let raised = match exec_bytecode_method(&mut context, "DivTest".to_string(), &buf, &mut vars) {
+        Err(RuntimeError::Thrown { exception }) => exception.obj_id,
+        other => panic!("Expected an ArithmeticException, got {:?}", other),
+    };
+    assert_eq!(Some(&"/ by zero".to_string()), context.get_exception_message(raised));
+
+    // The exception was the only garbage, so the next object gets its id
+    assert_eq!(1, context.gc(&[]));
+    let k = context.lookup_klass(&"java/lang/Exception".to_string());
+    let made = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k));
+    assert_eq!(raised, made);
+    assert_eq!(None, context.get_exception_message(made));
+    let get_message = context
+        .lookup_native("java/lang/Throwable.getMessage:()Ljava/lang/String;")
+        .expect("Throwable.getMessage() not registered");
+    assert_eq!(
+        Ok(Some(JvmValue::ObjRef { val: ObjId::NULL })),
+        get_message(&mut context, &[JvmValue::ObjRef { val: made }])
+    );
+}
+
+#[test]
+fn collected_exceptions_lose_their_stack_trace() {
+    let _turn = take_turn();
+    let mut context = init_context();
+    add_test_klass(&mut context, "Catcher");
+    let meth = context
+        .get_repo()
+        .lookup_method_exact(&"Catcher".to_string(), "Catcher.outer:()Ljava/lang/Object;".to_string());
+
+    // outer() catches the exception, so its trace is recorded, then hands it back
+    let mut vars = InterpLocalVars::of(1);
+    let caught = match exec_method(&mut context, &meth, &mut vars) {
+        Ok(Some(JvmValue::ObjRef { val })) if !val.is_null() => val,
+        other => panic!("Expected the caught exception, got {:?}", other),
+    };
+    context.gc(&[caught]);
+    assert!(context.get_stack_trace(caught).is_some());
+
+    // Once nothing refers to it, the id can go to a new exception, with no trace
+    assert_eq!(1, context.gc(&[]));
+    assert!(context.get_stack_trace(caught).is_none());
+    let k = context.lookup_klass(&"java/lang/Exception".to_string());
+    let made = ObjId::of(HEAP.lock().unwrap().allocate_obj(&k));
+    assert_eq!(caught, made);
+    assert!(context.get_stack_trace(made).is_none());
+}
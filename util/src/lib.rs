@@ -4,6 +4,8 @@ use zip::ZipArchive;
 use std::io::{Read, Seek};
 use zip::result::ZipResult;
 
+pub use zip::result::ZipError;
+
 pub fn file_to_bytes(path: &Path) -> Result<Vec<u8>, std::io::Error> {
     File::open(path).and_then(|mut file| {
         let mut bytes = Vec::new();
@@ -38,12 +40,23 @@ impl<R: Read + Seek> Iterator for ZipFiles<R> {
 
 impl ZipFiles<File> {
     pub fn new(file_name: &str) -> ZipFiles<File> {
-        let file = File::open(&file_name)
-            .expect(&format!("Couldn't open file {}", &file_name));
-
-        let archive = ZipArchive::new(file)
-            .expect(&format!("Problem reading archive {}", &file_name));
+        ZipFiles::open(file_name)
+            .expect(&format!("Problem reading archive {}", &file_name))
+    }
 
-        ZipFiles { i: 0, archive }
+    pub fn open(file_name: &str) -> ZipResult<ZipFiles<File>> {
+        let file = File::open(&file_name)?;
+        let archive = ZipArchive::new(file)?;
+        Ok(ZipFiles { i: 0, archive })
     }
 }
+
+// Reads a single named entry, e.g. a/b/C.class, out of a zip or jar file
+pub fn zip_entry_to_bytes(file_name: &str, entry_name: &str) -> ZipResult<Vec<u8>> {
+    let file = File::open(&file_name)?;
+    let mut archive = ZipArchive::new(file)?;
+    let mut entry = archive.by_name(entry_name)?;
+    let mut content = vec![];
+    entry.read_to_end(&mut content)?;
+    Ok(content)
+}